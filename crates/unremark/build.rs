@@ -0,0 +1,25 @@
+fn main() {
+    // The messages are hand-written prost structs, so this needs no protoc
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+
+        let method = |name: &str, route_name: &str, input_type: &str, output_type: &str| {
+            Method::builder()
+                .name(name)
+                .route_name(route_name)
+                .input_type(format!("crate::grpc::{}", input_type))
+                .output_type(format!("crate::grpc::{}", output_type))
+                .codec_path("tonic::codec::ProstCodec")
+        };
+        let service = Service::builder()
+            .name("Unremark")
+            .package("unremark.v1")
+            .method(method("detect_comments", "DetectComments", "SourceFile", "DetectCommentsResponse").build())
+            .method(method("analyze_sources", "AnalyzeSources", "AnalyzeRequest", "FileResult").server_streaming().build())
+            .build();
+
+        Builder::new().compile(&[service]);
+        println!("cargo:rerun-if-changed=build.rs");
+    }
+}
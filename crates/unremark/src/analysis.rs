@@ -1,247 +1,999 @@
-use crate::types::{ApiError, CommentInfo, CommentAnalysis, AnalysisResult, Language, Cache, CacheEntry};
-use crate::api::make_api_request;
-use crate::comment_detection::detect_comments;
-use crate::utils::remove_redundant_comments;
-use std::path::PathBuf;
-use std::fs;
+use crate::types::{
+    AccuracyAnalysis, ApiError, ApiSettings, BatchAnalysis, CommentInfo, CommentAnalysis, CommentExplanation, AnalysisResult, AnalysisOptions, Language,
+    Cache, CacheEntry, Provider,
+};
+use crate::baseline::subtract_findings;
+use crate::blame::annotate_blame;
+use crate::api::{
+    accuracy_format, accuracy_prompt, batch_redundancy_format, batch_redundancy_prompt, chat_messages, configure_network, make_api_request,
+    redundancy_format, redundancy_prompt,
+};
+use crate::config::{NetworkConfig, PromptExample};
+use crate::replay::FixtureMode;
+use crate::comment_detection::detect_comments_with;
+use crate::heuristics::{
+    apply_rules, find_outdated_comments, flag_duplicate_comments, flag_regex_comments, prefilter_comments, rules_for,
+    useful_comment_reason, CommentSite,
+    DUPLICATE_COMMENT_RULE, MISLEADING_COMMENT_RULE, OUTDATED_COMMENT_RULE, REDUNDANT_COMMENT_RULE,
+};
+use crate::plugins::{apply_policies, flag_policy_comments, Verdict};
+use crate::classifier::CLASSIFIER_RULE;
+use crate::services::proxy::{AnalysisService, ProxyAnalysisService};
+use crate::todos::is_todo_comment;
+use crate::utils::{annotate_redundant_comments, decode_source, encode_source, is_annotated, remove_redundant_comments, write_atomically};
+use crate::verify::verify_fix;
+use crate::markdown::fenced_sources;
+use crate::timings::{timed, Phase};
+use crate::telemetry::metrics;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use futures::future::join_all;
-use std::time::{Duration, Instant};
-use tree_sitter::Parser;
-use log::{debug, error, info};
+use futures::stream::Stream;
+use futures::StreamExt;
+use std::time::Instant;
+use tree_sitter::{Node, Parser};
+use tracing::{debug, error, info, instrument, warn};
 use std::time::SystemTime;
-use parking_lot;
 
 
 pub async fn analyze_file(path: &PathBuf, fix: bool, cache: &parking_lot::RwLock<Cache>) -> AnalysisResult {
-    let canonical_path = path.canonicalize().unwrap_or_else(|_| path.clone());
+    analyze_file_with_options(path, fix, cache, &AnalysisOptions::default()).await
+}
+
+/// Analyzes the files concurrently, at most `options.file_concurrency` at a
+/// time, yielding each result as soon as it's ready rather than in the order
+/// of `paths`.
+pub fn analyze_files<'a>(
+    paths: &'a [PathBuf],
+    fix: bool,
+    cache: &'a parking_lot::RwLock<Cache>,
+    options: &'a AnalysisOptions,
+) -> impl Stream<Item = AnalysisResult> + 'a {
+    let analyses = paths.iter().map(move |path| analyze_file_with_options(path, fix, cache, options));
+    futures::stream::iter(analyses).buffer_unordered(options.file_concurrency.max(1))
+}
+
+#[instrument(name = "file", skip_all, fields(path = %path.display()))]
+pub async fn analyze_file_with_options(
+    path: &PathBuf,
+    fix: bool,
+    cache: &parking_lot::RwLock<Cache>,
+    options: &AnalysisOptions,
+) -> AnalysisResult {
+    let canonical_path = tokio::fs::canonicalize(path).await.unwrap_or_else(|_| path.clone());
     let path_str = canonical_path.to_string_lossy().to_string();
 
+    let metadata = tokio::fs::metadata(path).await.ok();
+    if let (Some(limit), Some(metadata)) = (options.max_file_size, &metadata) {
+        if metadata.len() > limit {
+            debug!("Skipping {}, {} bytes is over the limit of {}", path.display(), metadata.len(), limit);
+            return AnalysisResult {
+                path: path.clone(),
+                warnings: vec![format!("skipped, {} bytes is over the limit of {}", metadata.len(), limit)],
+                ..Default::default()
+            };
+        }
+    }
+
     // Get file's last modified time
-    let last_modified = fs::metadata(path)
-        .and_then(|m| m.modified())
+    let last_modified = metadata
+        .and_then(|m| m.modified().ok())
         .map(|t| t.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default())
         .map(|d| d.as_secs())
         .unwrap_or(0);
 
-    let source_code = match std::fs::read_to_string(path) {
-        Ok(code) => code,
-        Err(_) => return AnalysisResult {
+    let decoded = tokio::fs::read(path).await
+        .map_err(|e| format!("failed to read the file: {}", e))
+        .and_then(|bytes| decode_source(&bytes));
+    let (source_code, encoding): (Arc<str>, _) = match decoded {
+        Ok((code, encoding)) => (code.into(), encoding),
+        Err(e) => return AnalysisResult {
             path: path.clone(),
-            redundant_comments: vec![],
-            errors: vec![],
+            errors: vec![e],
+            ..Default::default()
         },
     };
 
-    // Check cache first
-    let redundant_comments = {
-        let cache_read = cache.read();
-        if let Some(entry) = cache_read.entries.get(&path_str) {
-            if entry.last_modified == last_modified {
-                entry.redundant_comments.clone()
-            } else {
-                drop(cache_read);
-                let analysis = analyze_source(&source_code, path).await;
-                // Update cache
-                let mut cache_write = cache.write();
-                cache_write.entries.insert(
+    // Offline results are incomplete, so they neither come from nor go into the cache
+    let cached = if options.offline {
+        None
+    } else {
+        cache.read().entries.get(&path_str)
+            .filter(|entry| entry.last_modified == last_modified && entry.rules_key == options.cache_key())
+            .map(|entry| (entry.redundant_comments.clone(), entry.flagged_comments.clone(), entry.scored_comments.clone(), vec![], vec![]))
+    };
+
+    let from_cache = cached.is_some();
+    let (redundant_comments, flagged_comments, mut scored_comments, warnings, mut errors) = match cached {
+        Some(cached) => {
+            metrics().record_cache_hit();
+            cached
+        }
+        None => {
+            metrics().record_file();
+            let analysis = analyze_source(Arc::clone(&source_code), path, options).await;
+            // Syntax errors are usually mid-edit, and files that couldn't be
+            // fully analyzed are retried on the next run, so neither is cached
+            if !options.offline && analysis.warnings.is_empty() && analysis.errors.is_empty() {
+                cache.write().entries.insert(
                     path_str,
                     CacheEntry {
                         last_modified,
                         redundant_comments: analysis.redundant_comments.clone(),
+                        flagged_comments: analysis.flagged_comments.clone(),
+                        scored_comments: analysis.scored_comments.clone(),
+                        rules_key: options.cache_key(),
                     },
                 );
-                analysis.redundant_comments
             }
-        } else {
-            drop(cache_read);
-            let analysis = analyze_source(&source_code, path).await;
-            // Update cache
-            let mut cache_write = cache.write();
-            cache_write.entries.insert(
-                path_str,
-                CacheEntry {
-                    last_modified,
-                    redundant_comments: analysis.redundant_comments.clone(),
-                },
-            );
-            analysis.redundant_comments
+            (analysis.redundant_comments, analysis.flagged_comments, analysis.scored_comments, analysis.warnings, analysis.errors)
         }
     };
+    let mut redundant_comments = apply_severities(redundant_comments, options);
+    let mut flagged_comments = apply_severities(flagged_comments, options);
+
+    // The cache keeps every finding, whichever lines the current run cares about
+    if let Some(changed_lines) = &options.changed_lines {
+        redundant_comments.retain(|comment| changed_lines.touches(&canonical_path, comment));
+        flagged_comments.retain(|comment| changed_lines.touches(&canonical_path, comment));
+        scored_comments.retain(|comment| changed_lines.touches(&canonical_path, comment));
+    }
+    if let Some(merge_base) = &options.merge_base {
+        if let Some(base_source) = merge_base.source_of(&canonical_path) {
+            let known = analyze_source(base_source.into(), path, options).await;
+            redundant_comments = subtract_findings(redundant_comments, &known.redundant_comments);
+            flagged_comments = subtract_findings(flagged_comments, &known.flagged_comments);
+        }
+    }
+
+    // Blame before fixing, while the lines still match the committed file
+    if options.blame {
+        annotate_blame(path, &mut redundant_comments);
+        annotate_blame(path, &mut flagged_comments);
+    }
 
-    // Apply fixes if requested
-    if fix && !redundant_comments.is_empty() {
-        let updated_source = remove_redundant_comments(&source_code, &redundant_comments);
-        if let Err(e) = std::fs::write(path, updated_source) {
-            error!("Failed to write changes to {}: {}", path.display(), e);
+    // Apply fixes if requested, or mark the comments to fix for review, but
+    // only in files whose analysis finished
+    if (fix || options.annotate) && !redundant_comments.is_empty() && errors.is_empty() {
+        let fix_start = Instant::now();
+        let updated_source = if fix {
+            remove_redundant_comments(&source_code, &redundant_comments)
+        } else {
+            annotate_redundant_comments(&source_code, &redundant_comments)
+        };
+        let language = options.language.or_else(|| Language::detect(path, &source_code));
+        let verified = match language {
+            Some(language) if fix && options.verify => verify_fix(&source_code, &updated_source, &redundant_comments, language),
+            _ => Ok(()),
+        };
+        match verified.map(|_| encode_source(&updated_source, encoding)) {
+            Ok(Ok(bytes)) => {
+                // Once started, the write finishes even if this analysis is cancelled
+                let target = path.clone();
+                let written = tokio::task::spawn_blocking(move || write_atomically(&target, &bytes)).await;
+                if let Ok(Err(e)) = written {
+                    error!("Failed to write changes to {}: {}", path.display(), e);
+                }
+            }
+            Ok(Err(e)) => error!("Failed to write changes to {}: {}", path.display(), e),
+            Err(e) => {
+                warn!("Not fixing {}: {}", path.display(), e);
+                errors.push(format!("fix failed verification, file left unchanged: {}", e));
+            }
+        }
+        if let Some(timings) = &options.timings {
+            timings.record(Phase::Fix, fix_start.elapsed());
         }
     }
 
     AnalysisResult {
         path: path.clone(),
         redundant_comments,
-        errors: vec![],
+        flagged_comments,
+        errors,
+        warnings,
+        scored_comments,
+        cached: from_cache,
+        source: options.keep_source.then(|| source_code.to_string()),
     }
 }
 
-pub async fn analyze_source(source_code: &str, path: &PathBuf) -> AnalysisResult {
-    let language = match path.extension()
-        .and_then(|ext| ext.to_str())
-        .and_then(Language::from_extension) {
+/// Analyzes the source of the file at `path`, shared rather than copied
+/// with the analysis on the blocking pool.
+pub async fn analyze_source(source_code: Arc<str>, path: &Path, options: &AnalysisOptions) -> AnalysisResult {
+    if options.markdown && path.extension().is_some_and(|ext| ext == "md") {
+        return analyze_markdown(&source_code, path, options).await;
+    }
+    let language = match options.language.or_else(|| Language::detect(path, &source_code)) {
             Some(lang) => lang,
             None => return AnalysisResult {
-                path: path.clone(),
-                redundant_comments: vec![],
-                errors: vec![],
+                path: path.to_path_buf(),
+                errors: vec!["unsupported file type".to_string()],
+                ..Default::default()
             },
     };
 
+    let source = options.keep_source.then(|| source_code.to_string());
+    match find_comments(source_code, language, options).await {
+        Ok(findings) => AnalysisResult {
+            path: path.to_path_buf(),
+            redundant_comments: apply_severities(findings.redundant_comments, options),
+            flagged_comments: apply_severities(findings.flagged_comments, options),
+            errors: findings.errors,
+            warnings: findings.warnings,
+            scored_comments: findings.scored_comments,
+            cached: false,
+            source,
+        },
+        Err(e) => AnalysisResult {
+            path: path.to_path_buf(),
+            errors: vec![e],
+            ..Default::default()
+        },
+    }
+}
+
+/// Analyzes the fenced code blocks of a Markdown file, each language's
+/// blocks as one source, reporting the comments at their lines in the file.
+async fn analyze_markdown(markdown: &str, path: &Path, options: &AnalysisOptions) -> AnalysisResult {
+    let mut result = AnalysisResult {
+        path: path.to_path_buf(),
+        source: options.keep_source.then(|| markdown.to_string()),
+        ..Default::default()
+    };
+    for (language, source_code) in fenced_sources(markdown) {
+        match find_comments(source_code.into(), language, options).await {
+            Ok(findings) => {
+                result.redundant_comments.extend(apply_severities(findings.redundant_comments, options));
+                result.flagged_comments.extend(apply_severities(findings.flagged_comments, options));
+                result.warnings.extend(findings.warnings.into_iter().map(|warning| format!("{} block: {}", language, warning)));
+                result.errors.extend(findings.errors.into_iter().map(|error| format!("{} block: {}", language, error)));
+                result.scored_comments.extend(findings.scored_comments);
+            }
+            Err(e) => result.errors.push(format!("{} block: {}", language, e)),
+        }
+    }
+    result.redundant_comments.sort_by_key(|comment| comment.line_number);
+    result.flagged_comments.sort_by_key(|comment| comment.line_number);
+    result.scored_comments.sort_by_key(|comment| comment.line_number);
+    result
+}
+
+/// What the local checks make of a file, before anything is sent to the API.
+pub(crate) struct LocalAnalysis {
+    pub(crate) comments: Vec<CommentInfo>,
+    pub(crate) redundant_comments: Vec<CommentInfo>,
+    /// Comments the local checks couldn't settle, for the API
+    pub(crate) undecided: Vec<CommentInfo>,
+    pub(crate) flagged_comments: Vec<CommentInfo>,
+    /// Syntax errors the parser recovered from, whose comments were skipped
+    pub(crate) syntax_errors: Vec<String>,
+}
+
+/// What's reported for a source.
+struct Findings {
+    redundant_comments: Vec<CommentInfo>,
+    flagged_comments: Vec<CommentInfo>,
+    warnings: Vec<String>,
+    /// Comments skipped while the circuit breaker was open, so the file is
+    /// analyzed again on the next run
+    errors: Vec<String>,
+    scored_comments: Vec<CommentInfo>,
+}
+
+/// The redundant and flagged comments of the source. Parsing and the local
+/// checks are CPU-bound, so they run on the blocking pool, where large files
+/// don't stall the async workers and files are parsed on every core. Only the
+/// API calls run on the async runtime.
+async fn find_comments(
+    source_code: Arc<str>,
+    language: Language,
+    options: &AnalysisOptions,
+) -> Result<Findings, String> {
+    let local = {
+        let options = options.clone();
+        tokio::task::spawn_blocking(move || analyze_locally(&source_code, language, &options))
+    };
+    let local = match local.await {
+        Ok(local) => local,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    };
+    let local = local?;
+
+    let api_start = Instant::now();
+    let (redundant_comments, judged, api_errors) = with_api_scores(local.redundant_comments, local.undecided, options).await;
+    let mut flagged_comments = local.flagged_comments;
+    flagged_comments.retain(|comment| !redundant_comments.iter().any(|r| r.line_number == comment.line_number));
+    flagged_comments.extend(flag_misleading_comments(&local.comments, &redundant_comments, options).await);
+    if let Some(timings) = &options.timings {
+        timings.record(Phase::Api, api_start.elapsed());
+    }
+    flagged_comments.sort_by_key(|comment| comment.line_number);
+    let scored_comments = if options.report_all {
+        score_comments(&local.comments, &redundant_comments, judged, options)
+    } else {
+        vec![]
+    };
+    let (skipped, api_errors): (Vec<ApiError>, Vec<ApiError>) = api_errors.into_iter()
+        .partition(|error| matches!(error, ApiError::CircuitOpen(_)));
+    let mut warnings = local.syntax_errors;
+    warnings.extend(unanswered_warnings(&api_errors));
+    let errors = unanswered_warnings(&skipped);
+    Ok(Findings { redundant_comments, flagged_comments, warnings, errors, scored_comments })
+}
+
+/// One message per distinct error of the API's answers, with how many
+/// comments it left unanalyzed.
+fn unanswered_warnings(errors: &[ApiError]) -> Vec<String> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for error in errors {
+        let message = error.to_string();
+        match counts.iter_mut().find(|(other, _)| *other == message) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((message, 1)),
+        }
+    }
+    counts.into_iter()
+        .map(|(message, count)| match count {
+            1 => format!("1 comment wasn't analyzed: {}", message),
+            count => format!("{} comments weren't analyzed: {}", count, message),
+        })
+        .collect()
+}
+
+/// Every analyzable comment with its usefulness score: the API's for the
+/// comments it judged, and for the ones the local checks found redundant,
+/// the less sure they were the higher. Comments the local checks kept have
+/// no score, they were never in question.
+fn score_comments(
+    comments: &[CommentInfo],
+    redundant_comments: &[CommentInfo],
+    judged: Vec<CommentInfo>,
+    options: &AnalysisOptions,
+) -> Vec<CommentInfo> {
+    comments.iter()
+        .filter(|comment| is_analyzable(comment, options))
+        .map(|comment| {
+            let same = |other: &&CommentInfo| other.line_number == comment.line_number && other.text == comment.text;
+            if let Some(judged) = judged.iter().find(same) {
+                return judged.clone();
+            }
+            match redundant_comments.iter().find(same) {
+                Some(redundant) => CommentInfo {
+                    quality_score: redundant.quality_score
+                        .or(Some(redundant.confidence.map_or(0, |confidence| ((1.0 - confidence) * 100.0).round() as u8))),
+                    ..redundant.clone()
+                },
+                None => comment.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Parses the source and runs every local check on it, or says why it
+/// couldn't be analyzed. Syntax errors the parser recovers from only take
+/// out the comments within them, the rest of the file is still analyzed.
+pub(crate) fn analyze_locally(source_code: &str, language: Language, options: &AnalysisOptions) -> Result<LocalAnalysis, String> {
     let mut parser = Parser::new();
-    if parser.set_language(&language.get_tree_sitter_language()).is_err() {
-        return AnalysisResult {
-            path: path.clone(),
-            redundant_comments: vec![],
-            errors: vec![],
-        };
+    parser.set_language(&language.get_tree_sitter_language())
+        .map_err(|e| format!("failed to load the {} grammar: {}", language, e))?;
+    let timings = options.timings.as_deref();
+    let tree = timed(timings, Phase::Parse, || crate::sfc::parse(&mut parser, source_code, language))
+        .ok_or_else(|| format!("failed to parse the file as {}", language))?;
+    let mut error_lines = Vec::new();
+    syntax_error_lines(tree.root_node(), &mut error_lines);
+
+    let mut comments = timed(timings, Phase::Detect, || {
+        detect_comments_with(source_code, language, &options.context_budget).unwrap_or_default()
+    });
+    // The context of comments in a broken region can't be trusted
+    comments.retain(|comment| !error_lines.iter().any(|lines| lines.contains(&comment.line_number)));
+    let syntax_errors = error_lines.iter()
+        .map(|lines| match (lines.start(), lines.end()) {
+            (start, end) if start == end => format!("syntax error at line {}, comments there weren't analyzed", start),
+            (start, end) => format!("syntax error in lines {}-{}, comments there weren't analyzed", start, end),
+        })
+        .collect();
+
+    let (redundant_comments, undecided, flagged_comments) = timed(timings, Phase::Heuristics, || {
+        let (redundant_comments, undecided) = triage_comments(source_code, comments.clone(), options);
+        let flagged_comments = flag_comments_for_review(source_code, language, &comments, &redundant_comments, options);
+        (redundant_comments, undecided, flagged_comments)
+    });
+    Ok(LocalAnalysis { comments, redundant_comments, undecided, flagged_comments, syntax_errors })
+}
+
+/// The lines (1-based) of each error or missing node in the tree, in source order.
+fn syntax_error_lines(node: Node, lines: &mut Vec<RangeInclusive<usize>>) {
+    if node.is_error() || node.is_missing() {
+        let range = node.start_position().row + 1..=node.end_position().row + 1;
+        // Overlapping errors, e.g. a missing token right after an error, are reported once
+        match lines.last_mut() {
+            Some(last) if *range.start() <= *last.end() => *last = *last.start()..=(*last.end()).max(*range.end()),
+            _ => lines.push(range),
+        }
+        return;
+    }
+    if node.has_error() {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            syntax_error_lines(child, lines);
+        }
     }
+}
 
-    let tree = match parser.parse(source_code, None) {
-        Some(tree) => tree,
-        None => return AnalysisResult {
-            path: path.clone(),
-            redundant_comments: vec![],
-            errors: vec![],
-        },
+/// Sets each finding's severity from its rule and confidence, as configured in the options.
+fn apply_severities(comments: Vec<CommentInfo>, options: &AnalysisOptions) -> Vec<CommentInfo> {
+    comments.into_iter()
+        .map(|mut comment| {
+            comment.severity = options.rules.finding_severity(&comment);
+            comment
+        })
+        .collect()
+}
+
+/// Runs the local heuristic rules over the comments and sends whatever they
+/// don't flag to the API, unless running offline. TODO-style comments,
+/// license headers and comments ignored by a regex rule are skipped entirely
+/// so `--fix` never removes them.
+pub async fn analyze_detected_comments(source_code: &str, comments: Vec<CommentInfo>, options: &AnalysisOptions) -> Vec<CommentInfo> {
+    let (redundant_comments, undecided) = triage_comments(source_code, comments, options);
+    with_api_verdicts(redundant_comments, undecided, options).await
+}
+
+/// Settles what the local checks can (plugins, heuristic rules, prefilter and
+/// classifier), returning the redundant comments and the ones left undecided.
+fn triage_comments(source_code: &str, comments: Vec<CommentInfo>, options: &AnalysisOptions) -> (Vec<CommentInfo>, Vec<CommentInfo>) {
+    let comments: Vec<CommentInfo> = comments.into_iter()
+        .filter(|comment| is_analyzable(comment, options))
+        .collect();
+    // Comments marked by --annotate and left marked in review are settled
+    let (annotated, comments): (Vec<CommentInfo>, Vec<CommentInfo>) = comments.into_iter()
+        .partition(|comment| is_annotated(&comment.text));
+    let (mut redundant_comments, comments) = apply_policies(comments, &options.policies, &options.rules);
+    redundant_comments.extend(annotated.into_iter().map(|comment| CommentInfo {
+        explanation: Some("marked as likely redundant by --annotate".to_string()),
+        rule_id: Some(REDUNDANT_COMMENT_RULE.to_string()),
+        ..comment
+    }));
+    let (flagged_by_rules, remaining) = apply_rules(source_code, comments, &rules_for(options));
+    redundant_comments.extend(flagged_by_rules);
+    debug!("Heuristics flagged {} comments, {} left for the API", redundant_comments.len(), remaining.len());
+
+    let remaining = if options.prefilter {
+        prefilter_comments(source_code, remaining).0
+    } else {
+        remaining
     };
 
-    if tree.root_node().has_error() {
-        return AnalysisResult {
-            path: path.clone(),
-            redundant_comments: vec![],
-            errors: vec![],
-        };
+    let remaining = match &options.classifier {
+        Some(classifier) if options.rules.is_enabled(CLASSIFIER_RULE) => {
+            let (flagged_by_classifier, undecided) = classifier.classify(source_code, remaining);
+            redundant_comments.extend(flagged_by_classifier);
+            undecided
+        }
+        _ => remaining,
+    };
+    (redundant_comments, remaining)
+}
+
+/// Adds the API's verdicts on the undecided comments, unless running offline.
+async fn with_api_verdicts(
+    mut redundant_comments: Vec<CommentInfo>,
+    undecided: Vec<CommentInfo>,
+    options: &AnalysisOptions,
+) -> Vec<CommentInfo> {
+    if !options.offline && options.rules.is_enabled(REDUNDANT_COMMENT_RULE) && !undecided.is_empty() {
+        redundant_comments.extend(analyze_comments_with(undecided, &options.api).await.unwrap_or_default());
+        redundant_comments.sort_by_key(|comment| comment.line_number);
     }
+    redundant_comments
+}
 
-    let comments = detect_comments(source_code, language).unwrap_or_default();
-    let redundant_comments = analyze_comments(comments).await.unwrap_or_default();
+/// `with_api_verdicts` that also returns every comment the API judged, with
+/// its explanation and score, redundant or not, and the errors of the
+/// requests that went unanswered.
+async fn with_api_scores(
+    mut redundant_comments: Vec<CommentInfo>,
+    undecided: Vec<CommentInfo>,
+    options: &AnalysisOptions,
+) -> (Vec<CommentInfo>, Vec<CommentInfo>, Vec<ApiError>) {
+    let mut judged_comments = Vec::new();
+    let mut errors = Vec::new();
+    if !options.offline && options.rules.is_enabled(REDUNDANT_COMMENT_RULE) && !undecided.is_empty() {
+        let (judged, judge_errors) = judge_comments(undecided, &options.api).await;
+        errors = judge_errors;
+        for (comment, is_redundant) in judged {
+            if is_redundant {
+                redundant_comments.push(comment.clone());
+            }
+            judged_comments.push(comment);
+        }
+        redundant_comments.sort_by_key(|comment| comment.line_number);
+    }
+    (redundant_comments, judged_comments, errors)
+}
 
-    AnalysisResult {
-        path: path.clone(),
-        redundant_comments,
-        errors: vec![],
+/// TODO-style comments, license headers and comments ignored by a regex rule
+/// are left out of every check that could report them.
+pub(crate) fn is_analyzable(comment: &CommentInfo, options: &AnalysisOptions) -> bool {
+    !is_todo_comment(&comment.text) && !comment.is_license() && !options.rules.ignores(&comment.text)
+}
+
+/// Comments that contradict their code, when `check_accuracy` is on. Comments
+/// already reported as redundant aren't checked, they're going away anyway.
+async fn flag_misleading_comments(
+    comments: &[CommentInfo],
+    redundant_comments: &[CommentInfo],
+    options: &AnalysisOptions,
+) -> Vec<CommentInfo> {
+    if !options.check_accuracy || options.offline || !options.rules.is_enabled(MISLEADING_COMMENT_RULE) {
+        return vec![];
+    }
+    let candidates: Vec<CommentInfo> = comments.iter()
+        .filter(|comment| is_analyzable(comment, options))
+        .filter(|comment| !redundant_comments.iter().any(|r| r.line_number == comment.line_number))
+        .cloned()
+        .collect();
+    if candidates.is_empty() {
+        return vec![];
     }
+    check_comment_accuracy_with(candidates, &options.api).await
 }
 
-pub async fn analyze_comments(comments: Vec<CommentInfo>) -> Result<Vec<CommentInfo>, String> {
-    let client = reqwest::Client::builder()
+/// Comments worth a second look (possibly outdated, duplicated, matching a
+/// regex rule or plugin), leaving out the ones already reported as redundant.
+fn flag_comments_for_review(
+    source_code: &str,
+    language: Language,
+    comments: &[CommentInfo],
+    redundant_comments: &[CommentInfo],
+    options: &AnalysisOptions,
+) -> Vec<CommentInfo> {
+    let comments: Vec<CommentInfo> = comments.iter()
+        .filter(|comment| !options.rules.ignores(&comment.text))
+        .cloned()
+        .collect();
+
+    let mut flagged_comments = Vec::new();
+    if options.rules.is_enabled(OUTDATED_COMMENT_RULE) {
+        flagged_comments.extend(find_outdated_comments(source_code, language));
+    }
+    if options.rules.is_enabled(DUPLICATE_COMMENT_RULE) {
+        flagged_comments.extend(flag_duplicate_comments(&comments, options.min_duplicate_occurrences));
+    }
+    flagged_comments.extend(flag_regex_comments(&comments, &options.rules));
+    flagged_comments.extend(flag_policy_comments(&comments, &options.policies, &options.rules));
+    flagged_comments.retain(|comment| !options.rules.ignores(&comment.text));
+    flagged_comments.retain(|comment| !redundant_comments.iter().any(|r| r.line_number == comment.line_number));
+    flagged_comments.sort_by_key(|comment| comment.line_number);
+    flagged_comments
+}
+
+/// What the analysis makes of the comment on `line` (1-based) of the source,
+/// and why. The whole file goes through the local checks, but only that
+/// comment is sent to the API.
+pub async fn explain_comment(
+    source_code: &str,
+    language: Language,
+    line: usize,
+    options: &AnalysisOptions,
+) -> Result<CommentExplanation, String> {
+    let local = analyze_locally(source_code, language, options)?;
+    let comment = local.comments.iter()
+        .find(|comment| (comment.line_number..comment.line_number + comment.text.lines().count().max(1)).contains(&line))
+        .cloned()
+        .ok_or_else(|| format!("no comment at line {}", line))?;
+    let same = |other: &&CommentInfo| other.line_number == comment.line_number;
+    let decided = |verdict, found: CommentInfo| Ok(CommentExplanation {
+        verdict,
+        comment: apply_severities(vec![found], options).remove(0),
+    });
+
+    if let Some(reason) = skip_reason(&comment, options) {
+        return Ok(kept(comment, reason));
+    }
+    if let Some(found) = local.redundant_comments.iter().find(same) {
+        return decided(Verdict::Redundant, found.clone());
+    }
+    let undecided = local.undecided.iter().any(|other| other.line_number == comment.line_number);
+    let asks_api = !options.offline && options.rules.is_enabled(REDUNDANT_COMMENT_RULE);
+    let mut api_explanation = None;
+    if undecided && asks_api {
+        let (is_redundant, explanation) = ask_redundancy(comment.clone(), &options.api).await.map_err(|e| e.to_string())?;
+        if is_redundant {
+            return decided(Verdict::Redundant, CommentInfo {
+                explanation,
+                rule_id: Some(REDUNDANT_COMMENT_RULE.to_string()),
+                ..comment
+            });
+        }
+        api_explanation = explanation;
+    }
+    if let Some(found) = local.flagged_comments.iter().find(same) {
+        return decided(Verdict::Flag, found.clone());
+    }
+    if let Some(found) = flag_misleading_comments(std::slice::from_ref(&comment), &[], options).await.into_iter().next() {
+        return decided(Verdict::Flag, found);
+    }
+
+    let lines: Vec<&str> = source_code.lines().collect();
+    let reason = match useful_comment_reason(&CommentSite::new(&comment, &lines)) {
+        Some(reason) if options.prefilter && !undecided => format!("it {}", reason),
+        _ if undecided && asks_api => api_explanation.unwrap_or_else(|| "the API didn't find it redundant".to_string()),
+        _ if undecided => "the local rules didn't flag it, and it wasn't sent to the API".to_string(),
+        _ => "the local checks kept it".to_string(),
+    };
+    Ok(kept(comment, reason))
+}
+
+/// Why a comment is never reported, for the ones `is_analyzable` leaves out.
+fn skip_reason(comment: &CommentInfo, options: &AnalysisOptions) -> Option<String> {
+    if is_todo_comment(&comment.text) {
+        Some("TODO-style comments are never reported".to_string())
+    } else if comment.is_license() {
+        Some("license headers are never reported".to_string())
+    } else if options.rules.ignores(&comment.text) {
+        Some("a regex rule in the config ignores it".to_string())
+    } else {
+        None
+    }
+}
+
+fn kept(comment: CommentInfo, reason: String) -> CommentExplanation {
+    CommentExplanation {
+        verdict: Verdict::Keep,
+        comment: CommentInfo { explanation: Some(reason), ..comment },
+    }
+}
+
+/// The provider's redundancy verdict on one comment, with its explanation
+/// either way. The proxy only returns redundant comments, so a kept comment
+/// comes back without one.
+async fn ask_redundancy(comment: CommentInfo, api: &ApiSettings) -> Result<(bool, Option<String>), ApiError> {
+    if api.provider == Provider::Proxy {
+        let redundant_comments = try_analyze_comments(vec![comment], api).await?;
+        return Ok(match redundant_comments.into_iter().next() {
+            Some(redundant) => (true, redundant.explanation),
+            None => (false, None),
+        });
+    }
+    let line_number = comment.line_number;
+    let (_, result) = request_all(vec![comment], redundancy_prompt, &redundancy_format(), &api.examples, api).await.remove(0);
+    let analysis = parse_response::<CommentAnalysis>(&result?)?;
+    check_line(analysis.comment_line_number, line_number)?;
+    Ok((analysis.is_redundant, Some(analysis.explanation)))
+}
+
+/// HTTP client for the API checks, keeping idle connections for reuse.
+pub(crate) fn api_client(network: &NetworkConfig) -> Result<reqwest::Client, ApiError> {
+    let builder = reqwest::Client::builder()
         .pool_max_idle_per_host(10)
-        .pool_idle_timeout(None)
-        .timeout(Duration::from_secs(30))
+        .pool_idle_timeout(None);
+    configure_network(builder, network)?
         .build()
-        .unwrap();
-    let openai_api_key = std::env::var("OPENAI_API_KEY").expect("OpenAI API key not set");
+        .map_err(|e| ApiError::Other(format!("Failed to build API client: {}", e)))
+}
+
+/// Sends every comment (or batch of comments) to the API with the given
+/// prompt, after the examples, for an answer in the given format,
+/// concurrently, with at most `api.concurrency` requests in flight.
+async fn request_all<T>(
+    comments: Vec<T>,
+    prompt: fn(&T) -> String,
+    response_format: &serde_json::Value,
+    examples: &[PromptExample],
+    api: &ApiSettings,
+) -> Vec<(T, Result<serde_json::Value, ApiError>)> {
+    let client = match api.client.clone().map_or_else(|| api_client(&api.network), Ok) {
+        Ok(client) => client,
+        Err(err) => return comments.into_iter().map(|comment| (comment, Err(err.clone()))).collect(),
+    };
+    // Replayed requests never reach the API, so they need no key
+    let replaying = api.fixtures.as_ref().is_some_and(|fixtures| fixtures.mode() == FixtureMode::Replay);
+    let Some(openai_api_key) = api.resolved_api_key().or_else(|| replaying.then(String::new)) else {
+        return comments.into_iter()
+            .map(|comment| (comment, Err(ApiError::Other(format!("no API key for {}", api.provider)))))
+            .collect();
+    };
     let openai = Arc::new(client);
+    let concurrency = api.concurrency.unwrap_or(comments.len()).max(1);
     
     let start_time = Instant::now();
-    debug!("Starting concurrent analysis of {} comments", comments.len());
+    debug!("Starting {} concurrent requests", comments.len());
 
-    // Create all API request futures at once
-    let futures: Vec<_> = comments.into_iter()
+    let requests = comments.into_iter()
         .map(|comment| {
             let openai = Arc::clone(&openai);
             let api_key = openai_api_key.clone();
             async move {
-                let result = make_api_request(&openai, &api_key, &comment).await;
+                let result = make_api_request(&openai, api, &api_key, &chat_messages(&prompt(&comment), examples), response_format).await;
                 (comment, result)
             }
-        })
-        .collect();
+        });
 
-    // Execute all API requests concurrently
-    let results = join_all(futures).await;
+    let results: Vec<_> = futures::stream::iter(requests).buffered(concurrency).collect().await;
     
     let duration = start_time.elapsed();
     debug!("Completed analysis of {} comments in {:.2} seconds", 
         results.len(),
         duration.as_secs_f64()
     );
+    results
+}
 
-    // Process results and filter redundant comments
-    let futures: Vec<_> = results.into_iter()
-        .map(|(comment, api_result)| async move {
-            match api_result {
-                Ok(json) => {
-                    if let Some(content) = json["choices"][0]["message"]["content"].as_str() {
-                        if let Ok(analysis) = serde_json::from_str::<CommentAnalysis>(content) {
-                            if analysis.comment_line_number == comment.line_number && analysis.is_redundant {
-                                info!("Found redundant comment: {}", analysis.explanation);
-                                let mut comment = comment;
-                                comment.explanation = Some(analysis.explanation);
-                                return Some(comment);
-                            }
+fn log_api_error(comment: &CommentInfo, err: &ApiError) {
+    error!("Error analyzing comment '{}': {}", comment.text, err);
+    match err {
+        ApiError::RateLimit(msg) => {
+            error!("Rate limit exceeded. Consider reducing concurrent requests. Details: {}", msg);
+        },
+        ApiError::Timeout(msg) => {
+            error!("Request timed out. The API may be experiencing high latency. Details: {}", msg);
+        },
+        ApiError::Network(msg) => {
+            error!("Network error. Please check your internet connection. Details: {}", msg);
+        },
+        ApiError::CircuitOpen(msg) => {
+            error!("Skipped, the circuit breaker is open. Details: {}", msg);
+        },
+        ApiError::Other(msg) => {
+            error!("Unexpected error occurred. Details: {}", msg);
+        },
+    }
+}
+
+/// The model's answer to a prompt, parsed from the chat completion. Answers
+/// that are refused, cut short or not in the requested shape are errors,
+/// rather than comments quietly left out.
+fn parse_response<T: serde::de::DeserializeOwned>(json: &serde_json::Value) -> Result<T, ApiError> {
+    let choice = &json["choices"][0];
+    if let Some(refusal) = choice["message"]["refusal"].as_str() {
+        return Err(ApiError::Other(format!("the model refused to answer: {}", refusal)));
+    }
+    if choice["finish_reason"] == "length" {
+        return Err(ApiError::Other("the model's answer was cut short, raise max_tokens in [generation]".to_string()));
+    }
+    let content = choice["message"]["content"].as_str()
+        .ok_or_else(|| ApiError::Other("the API's answer has no content".to_string()))?;
+    serde_json::from_str(content)
+        .map_err(|e| ApiError::Other(format!("the model's answer doesn't match the schema: {}", e)))
+}
+
+/// The probability of each `is_redundant` answer in the response, from the
+/// logprobs of its tokens when they were asked for. The answer's own text can
+/// hold `true` or `false` too, so only the values right after the key count.
+fn verdict_probabilities(json: &serde_json::Value) -> Vec<f64> {
+    let Some(tokens) = json["choices"][0]["logprobs"]["content"].as_array() else {
+        return vec![];
+    };
+    let mut text = String::new();
+    let mut probabilities = Vec::new();
+    for token in tokens {
+        let value = token["token"].as_str().unwrap_or_default();
+        if matches!(value.trim(), "true" | "false") && text.trim_end().ends_with("\"is_redundant\":") {
+            if let Some(logprob) = token["logprob"].as_f64() {
+                probabilities.push(logprob.exp());
+            }
+        }
+        text.push_str(value);
+    }
+    probabilities
+}
+
+/// Checks the model answered about the comment it was asked about.
+fn check_line(answered: usize, asked: usize) -> Result<(), ApiError> {
+    match answered == asked {
+        true => Ok(()),
+        false => Err(ApiError::Other(format!("the model answered about line {} instead of line {}", answered, asked))),
+    }
+}
+
+pub async fn analyze_comments(comments: Vec<CommentInfo>) -> Result<Vec<CommentInfo>, String> {
+    analyze_comments_with(comments, &ApiSettings::default()).await
+}
+
+/// Asks the configured provider which of the comments are redundant. Comments
+/// whose request failed are logged and left out.
+pub async fn analyze_comments_with(comments: Vec<CommentInfo>, api: &ApiSettings) -> Result<Vec<CommentInfo>, String> {
+    Ok(find_redundant_comments(comments, api).await.0)
+}
+
+/// Like `analyze_comments_with`, but fails with the first error instead of
+/// leaving out the comments whose request failed.
+pub async fn try_analyze_comments(comments: Vec<CommentInfo>, api: &ApiSettings) -> Result<Vec<CommentInfo>, ApiError> {
+    let (redundant_comments, errors) = find_redundant_comments(comments, api).await;
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(redundant_comments),
+    }
+}
+
+/// The comments the provider finds redundant, and the errors of the requests that failed.
+async fn find_redundant_comments(comments: Vec<CommentInfo>, api: &ApiSettings) -> (Vec<CommentInfo>, Vec<ApiError>) {
+    let (judged, errors) = judge_comments(comments, api).await;
+    let redundant_comments = judged.into_iter()
+        .filter_map(|(comment, is_redundant)| is_redundant.then_some(comment))
+        .collect();
+    (redundant_comments, errors)
+}
+
+/// Each comment the provider answered for, with whether it's redundant and
+/// the explanation and usefulness score it gave, and the errors of the
+/// requests that failed. The proxy only returns the redundant comments, so
+/// the rest come back as sent.
+async fn judge_comments(comments: Vec<CommentInfo>, api: &ApiSettings) -> (Vec<(CommentInfo, bool)>, Vec<ApiError>) {
+    if api.provider == Provider::Proxy {
+        if let Err(err) = api.breaker.check() {
+            return (vec![], vec![err; comments.len()]);
+        }
+        let service = ProxyAnalysisService::from_settings(api);
+        let sent = comments.clone();
+        let response = service.analyze_comments_with_proxy(comments).await;
+        api.breaker.record(&response);
+        return match response {
+            Ok(redundant_comments) => {
+                let redundant_comments: Vec<CommentInfo> = redundant_comments.into_iter()
+                    .map(|comment| {
+                        // Proxies that don't echo the span get it back from the comment sent
+                        let byte_range = comment.byte_range.clone().or_else(|| sent.iter()
+                            .find(|sent| sent.line_number == comment.line_number && sent.text == comment.text)
+                            .and_then(|sent| sent.byte_range.clone()));
+                        CommentInfo {
+                            rule_id: Some(REDUNDANT_COMMENT_RULE.to_string()),
+                            byte_range,
+                            ..comment
+                        }
+                    })
+                    .collect();
+                let kept = sent.into_iter()
+                    .filter(|sent| !redundant_comments.iter().any(|r| r.line_number == sent.line_number && r.text == sent.text))
+                    .map(|comment| (comment, false))
+                    .collect::<Vec<_>>();
+                let mut judged: Vec<(CommentInfo, bool)> = redundant_comments.into_iter().map(|comment| (comment, true)).collect();
+                judged.extend(kept);
+                judged.sort_by_key(|(comment, _)| comment.line_number);
+                (judged, vec![])
+            }
+            Err(err) => {
+                error!("Error analyzing comments through the proxy: {}", err);
+                (vec![], vec![err])
+            }
+        };
+    }
+
+    if api.batch_size > 1 {
+        return judge_batches(comments, api).await;
+    }
+    let mut judged = Vec::new();
+    let mut errors = Vec::new();
+    for (comment, api_result) in request_all(comments, redundancy_prompt, &redundancy_format(), &api.examples, api).await {
+        let answer = api_result.and_then(|json| {
+            let mut analysis = parse_response::<CommentAnalysis>(&json)?;
+            check_line(analysis.comment_line_number, comment.line_number)?;
+            if let Some(&confidence) = verdict_probabilities(&json).first() {
+                analysis.confidence = Some(confidence);
+            }
+            Ok(analysis)
+        });
+        match answer {
+            Ok(analysis) => judged.push(with_verdict(comment, analysis)),
+            Err(err) => {
+                log_api_error(&comment, &err);
+                errors.push(err);
+            }
+        }
+    }
+    (judged, errors)
+}
+
+/// `judge_comments` asking about up to `api.batch_size` comments per
+/// request. Each answer is matched to its comment by line, so the model
+/// leaving one out only fails that comment.
+async fn judge_batches(comments: Vec<CommentInfo>, api: &ApiSettings) -> (Vec<(CommentInfo, bool)>, Vec<ApiError>) {
+    let batches: Vec<Vec<CommentInfo>> = comments.chunks(api.batch_size).map(<[CommentInfo]>::to_vec).collect();
+    let prompt = |batch: &Vec<CommentInfo>| batch_redundancy_prompt(batch);
+    let mut judged = Vec::new();
+    let mut errors = Vec::new();
+    for (batch, api_result) in request_all(batches, prompt, &batch_redundancy_format(), &api.examples, api).await {
+        let answer = api_result.and_then(|json| {
+            let mut batch = parse_response::<BatchAnalysis>(&json)?;
+            // The verdicts come in the order of the answers
+            for (analysis, confidence) in batch.analyses.iter_mut().zip(verdict_probabilities(&json)) {
+                analysis.confidence = Some(confidence);
+            }
+            Ok(batch)
+        });
+        match answer {
+            Ok(BatchAnalysis { mut analyses }) => {
+                for comment in batch {
+                    match analyses.iter().position(|analysis| analysis.comment_line_number == comment.line_number) {
+                        Some(index) => judged.push(with_verdict(comment, analyses.remove(index))),
+                        None => {
+                            let err = ApiError::Other(format!("the model left out the comment on line {}", comment.line_number));
+                            log_api_error(&comment, &err);
+                            errors.push(err);
                         }
                     }
-                },
-                Err(err) => {
-                    error!("Error analyzing comment '{}': {}", comment.text, err);
-                    match err {
-                        ApiError::RateLimit(msg) => {
-                            error!("Rate limit exceeded. Consider reducing concurrent requests. Details: {}", msg);
-                        },
-                        ApiError::Timeout(msg) => {
-                            error!("Request timed out. The API may be experiencing high latency. Details: {}", msg);
-                        },
-                        ApiError::Network(msg) => {
-                            error!("Network error. Please check your internet connection. Details: {}", msg);
-                        },
-                        ApiError::Other(msg) => {
-                            error!("Unexpected error occurred. Details: {}", msg);
-                        },
-                    }
                 }
             }
-            None
+            Err(err) => {
+                for comment in &batch {
+                    log_api_error(comment, &err);
+                    errors.push(err.clone());
+                }
+            }
+        }
+    }
+    (judged, errors)
+}
+
+/// The comment with the model's explanation and score, flagged when it's redundant.
+fn with_verdict(mut comment: CommentInfo, analysis: CommentAnalysis) -> (CommentInfo, bool) {
+    if analysis.is_redundant {
+        info!("Found redundant comment: {}", analysis.explanation);
+        comment.rule_id = Some(REDUNDANT_COMMENT_RULE.to_string());
+    }
+    comment.explanation = Some(analysis.explanation);
+    comment.quality_score = analysis.usefulness_score.map(|score| score.clamp(0.0, 100.0).round() as u8);
+    comment.confidence = analysis.confidence.map(|confidence| confidence.clamp(0.0, 1.0) as f32);
+    (comment, analysis.is_redundant)
+}
+
+/// Asks the API whether each comment still describes its code, returning the
+/// ones that contradict it as `misleading-comment` findings.
+pub async fn check_comment_accuracy(comments: Vec<CommentInfo>) -> Vec<CommentInfo> {
+    check_comment_accuracy_with(comments, &ApiSettings::default()).await
+}
+
+/// `check_comment_accuracy` against the configured provider. The proxy only
+/// answers the redundancy question, so there's nothing to report through it.
+pub async fn check_comment_accuracy_with(comments: Vec<CommentInfo>, api: &ApiSettings) -> Vec<CommentInfo> {
+    if api.provider == Provider::Proxy {
+        warn!("The proxy can't check comment accuracy, skipping {} comments", comments.len());
+        return vec![];
+    }
+    request_all(comments, accuracy_prompt, &accuracy_format(), &[], api).await
+        .into_iter()
+        .filter_map(|(comment, api_result)| match api_result.and_then(|json| parse_response(&json)) {
+            Ok(analysis) => misleading_finding(comment, analysis),
+            Err(err) => {
+                log_api_error(&comment, &err);
+                None
+            }
         })
-        .collect();
+        .collect()
+}
 
-    Ok(join_all(futures).await.into_iter().filter_map(|x| x).collect())
+fn misleading_finding(comment: CommentInfo, analysis: AccuracyAnalysis) -> Option<CommentInfo> {
+    if analysis.comment_line_number != comment.line_number || analysis.is_accurate {
+        return None;
+    }
+    info!("Found misleading comment: {}", analysis.explanation);
+    let mut comment = comment;
+    comment.explanation = Some(analysis.explanation);
+    comment.rule_id = Some(MISLEADING_COMMENT_RULE.to_string());
+    Some(comment)
 }
 
 // Note: this is used by the LSP server to analyze the current file
 pub async fn analyze_current_file(source_code: &str, language: Language) -> AnalysisResult {
-    let mut parser = Parser::new();
-    if parser.set_language(&language.get_tree_sitter_language()).is_err() {
-        return AnalysisResult {
-            path: PathBuf::new(),
-            redundant_comments: vec![],
-            errors: vec![],
-        };
-    }
-
-    let tree = match parser.parse(source_code, None) {
-        Some(tree) => tree,
-        None => return AnalysisResult {
+    let options = AnalysisOptions::default();
+    match find_comments(source_code.into(), language, &options).await {
+        Ok(findings) => AnalysisResult {
             path: PathBuf::new(),
-            redundant_comments: vec![],
+            redundant_comments: apply_severities(findings.redundant_comments, &options),
+            flagged_comments: apply_severities(findings.flagged_comments, &options),
             errors: vec![],
+            warnings: findings.warnings,
+            scored_comments: findings.scored_comments,
+            cached: false,
+            source: None,
+        },
+        Err(e) => AnalysisResult {
+            errors: vec![e],
+            ..Default::default()
         },
-    };
-
-    if tree.root_node().has_error() {
-        return AnalysisResult {
-            path: PathBuf::new(),
-            redundant_comments: vec![],
-            errors: vec![],
-        };
-    }
-
-    let comments = detect_comments(source_code, language).unwrap_or_default();
-    let redundant_comments = analyze_comments(comments).await.unwrap_or_default();
-
-    AnalysisResult {
-        path: PathBuf::new(),
-        redundant_comments,
-        errors: vec![],
     }
 }
 
@@ -251,10 +1003,12 @@ mod tests {
     use super::*;
     use crate::types::ApiError;
     use crate::constants::CACHE_FILE_NAME;
+    use crate::replay::test_fixtures;
     use crate::utils::get_cache_path;
     
     use std::collections::HashMap;
     use std::fs;
+    use std::time::Duration;
     use reqwest::StatusCode;
     use tempfile::TempDir;
     use tokio::time::sleep;
@@ -268,6 +1022,14 @@ mod tests {
         (temporary_directory, cache_path)
     }
 
+    /// Options answering the API's requests with the responses recorded for these tests.
+    fn recorded_options() -> AnalysisOptions {
+        AnalysisOptions {
+            api: ApiSettings { fixtures: Some(test_fixtures()), ..Default::default() },
+            ..Default::default()
+        }
+    }
+
     fn clear_cache() {
         if let Ok(cache_path) = get_cache_path().canonicalize() {
             debug!("Clearing cache at: {}", cache_path.display());
@@ -286,7 +1048,7 @@ mod tests {
         let test_file = temporary_directory.path().join("test.py");
         fs::write(&test_file, "# Test comment\ndef test():\n    pass").unwrap();
 
-        let result1 = analyze_file(&test_file, false, &cache).await;
+        let result1 = analyze_file_with_options(&test_file, false, &cache, &recorded_options()).await;
         cache.write().save_to_path(&cache_path);
         assert!(!result1.redundant_comments.is_empty(), "Should find redundant comments");
 
@@ -294,7 +1056,7 @@ mod tests {
         assert!(!cache_contents.is_empty(), "Cache file should not be empty");
 
         let cache2 = Arc::new(parking_lot::RwLock::new(Cache::load_from_path(&cache_path)));
-        let result2 = analyze_file(&test_file, false, &cache2).await;
+        let result2 = analyze_file_with_options(&test_file, false, &cache2, &recorded_options()).await;
 
         assert_eq!(
             result1.redundant_comments.len(),
@@ -313,7 +1075,7 @@ mod tests {
         let test_file = temporary_directory.path().join("test.py");
         fs::write(&test_file, "# This is a test file\ndef calculate_sum(a, b):\n    return a + b").unwrap();
 
-        let result1 = analyze_file(&test_file, false, &cache).await;
+        let result1 = analyze_file_with_options(&test_file, false, &cache, &recorded_options()).await;
         cache.write().save_to_path(&cache_path);
 
         // Modify the file with a useful comment
@@ -321,7 +1083,7 @@ mod tests {
         fs::write(&test_file, "# This function uses integer arithmetic for precise calculations\ndef calculate_sum(a, b):\n    return a + b").unwrap();
 
         let cache2 = Arc::new(parking_lot::RwLock::new(Cache::load_from_path(&cache_path)));
-        let result2 = analyze_file(&test_file, false, &cache2).await;
+        let result2 = analyze_file_with_options(&test_file, false, &cache2, &recorded_options()).await;
 
         assert_ne!(
             result1.redundant_comments.len(),
@@ -341,7 +1103,7 @@ mod tests {
         let initial_content = "# This is a test file\ndef calculate_sum(a, b):\n    # Adds two numbers together\n    return a + b";
         fs::write(&test_file, initial_content).unwrap();
 
-        let result = analyze_file(&test_file, true, &cache).await;
+        let result = analyze_file_with_options(&test_file, true, &cache, &recorded_options()).await;
         
         let updated_content = fs::read_to_string(&test_file).unwrap();
         assert_ne!(initial_content, updated_content, "Fix command should modify the file");
@@ -361,12 +1123,12 @@ mod tests {
         let initial_content = "# Another test comment\ndef calculate_sum(a, b):\n    # Performs addition\n    return a + b";
         fs::write(&test_file, initial_content).unwrap();
 
-        let result1 = analyze_file(&test_file, false, &cache).await;
+        let result1 = analyze_file_with_options(&test_file, false, &cache, &recorded_options()).await;
         cache.write().save_to_path(&cache_path);
         assert!(!result1.redundant_comments.is_empty(), "Should find redundant comments");
 
         let cache2 = Arc::new(parking_lot::RwLock::new(Cache::load_from_path(&cache_path)));
-        let result2 = analyze_file(&test_file, true, &cache2).await;
+        let result2 = analyze_file_with_options(&test_file, true, &cache2, &recorded_options()).await;
 
         let final_content = fs::read_to_string(&test_file).unwrap();
         assert_ne!(initial_content, final_content, "Fix command should work with cached results");
@@ -400,7 +1162,7 @@ struct Point {
 "#;
         fs::write(&test_file, initial_content).unwrap();
 
-        let analysis_result = analyze_file(&test_file, false, &cache).await;
+        let analysis_result = analyze_file_with_options(&test_file, false, &cache, &recorded_options()).await;
         assert!(!analysis_result.redundant_comments.is_empty(), "Should identify redundant comments in Rust code");
         
         let comment_texts: Vec<&str> = analysis_result.redundant_comments
@@ -412,7 +1174,7 @@ struct Point {
         assert!(comment_texts.contains(&"// Adds two numbers together"), "Should detect redundant function comment");
         assert!(comment_texts.contains(&"// Returns the sum"), "Should detect redundant inline comment");
 
-        let fix_result = analyze_file(&test_file, true, &cache).await;
+        let fix_result = analyze_file_with_options(&test_file, true, &cache, &recorded_options()).await;
         assert!(!fix_result.redundant_comments.is_empty(), "Should still report the redundant comments");
 
         let final_content = fs::read_to_string(&test_file).unwrap();
@@ -458,7 +1220,7 @@ struct DocumentedStruct {
 "#;
         fs::write(&test_file, initial_content).unwrap();
 
-        let analysis_result = analyze_file(&test_file, false, &cache).await;
+        let analysis_result = analyze_file_with_options(&test_file, false, &cache, &recorded_options()).await;
         
         assert_eq!(analysis_result.redundant_comments.len(), 1, "Should only detect one redundant comment");
         assert_eq!(
@@ -467,7 +1229,7 @@ struct DocumentedStruct {
             "Should only detect the non-doc comment as redundant"
         );
 
-        let fix_result = analyze_file(&test_file, true, &cache).await;
+        let fix_result = analyze_file_with_options(&test_file, true, &cache, &recorded_options()).await;
         assert!(!fix_result.redundant_comments.is_empty(), "Should still report the redundant comments");
         let final_content = fs::read_to_string(&test_file).unwrap();
         assert!(final_content.contains("//! Module-level documentation"), "Should preserve module doc comments");
@@ -513,7 +1275,7 @@ class Point:
 "#;
         fs::write(&test_file, initial_content).unwrap();
 
-        let analysis_result = analyze_file(&test_file, false, &cache).await;
+        let analysis_result = analyze_file_with_options(&test_file, false, &cache, &recorded_options()).await;
         
         let comment_texts: Vec<&str> = analysis_result.redundant_comments
             .iter()
@@ -528,7 +1290,7 @@ class Point:
         assert!(!comment_texts.iter().any(|&c| c.contains("Function level docstring")), "Should not detect function docstring");
         assert!(!comment_texts.iter().any(|&c| c.contains("Class level docstring")), "Should not detect class docstring");
 
-        let fix_result = analyze_file(&test_file, true, &cache).await;
+        let fix_result = analyze_file_with_options(&test_file, true, &cache, &recorded_options()).await;
         assert!(!fix_result.redundant_comments.is_empty(), "Should still report the redundant comments");
         
         let final_content = fs::read_to_string(&test_file).unwrap();
@@ -562,7 +1324,7 @@ function calculateSum(a, b) {
 "#;
         fs::write(&test_file, initial_content).unwrap();
 
-        let analysis_result = analyze_file(&test_file, false, &cache).await;
+        let analysis_result = analyze_file_with_options(&test_file, false, &cache, &recorded_options()).await;
         
         let comment_texts: Vec<&str> = analysis_result.redundant_comments
             .iter()
@@ -576,7 +1338,7 @@ function calculateSum(a, b) {
         assert!(!comment_texts.iter().any(|&c| c.contains("@fileoverview")), "Should not detect JSDoc module comment");
         assert!(!comment_texts.iter().any(|&c| c.contains("Function documentation")), "Should not detect JSDoc function comment");
 
-        let fix_result = analyze_file(&test_file, true, &cache).await;
+        let fix_result = analyze_file_with_options(&test_file, true, &cache, &recorded_options()).await;
         assert!(!fix_result.redundant_comments.is_empty(), "Should still report the redundant comments");
         
         let final_content = fs::read_to_string(&test_file).unwrap();
@@ -616,7 +1378,7 @@ interface Shape {
 "#;
         fs::write(&test_file, initial_content).unwrap();
 
-        let analysis_result = analyze_file(&test_file, false, &cache).await;
+        let analysis_result = analyze_file_with_options(&test_file, false, &cache, &recorded_options()).await;
         
         let comment_texts: Vec<&str> = analysis_result.redundant_comments
             .iter()
@@ -631,7 +1393,7 @@ interface Shape {
         assert!(!comment_texts.iter().any(|&c| c.contains("Function documentation")), "Should not detect TSDoc function comment");
         assert!(!comment_texts.iter().any(|&c| c.contains("Interface documentation")), "Should not detect TSDoc interface comment");
 
-        let fix_result = analyze_file(&test_file, true, &cache).await;
+        let fix_result = analyze_file_with_options(&test_file, true, &cache, &recorded_options()).await;
         assert!(!fix_result.redundant_comments.is_empty(), "Should still report the redundant comments");
         
         let final_content = fs::read_to_string(&test_file).unwrap();
@@ -641,6 +1403,62 @@ interface Shape {
         assert!(!final_content.contains("// This is a redundant file comment"), "Should remove redundant comment");
     }
 
+    #[test]
+    fn test_misleading_finding_from_accuracy_response() {
+        let comment = CommentInfo { text: "// Sorts ascending".to_string(), line_number: 4, ..Default::default() };
+        let response = |is_accurate: bool, line: usize| json!({
+            "choices": [{ "message": { "content": json!({
+                "is_accurate": is_accurate,
+                "comment_line_number": line,
+                "comment_text": "// Sorts ascending",
+                "explanation": "The code sorts in descending order",
+            }).to_string() } }]
+        });
+
+        let analysis = parse_response(&response(false, 4)).unwrap();
+        let finding = misleading_finding(comment.clone(), analysis).unwrap();
+        assert_eq!(finding.rule_id.as_deref(), Some(MISLEADING_COMMENT_RULE));
+        assert_eq!(finding.explanation.as_deref(), Some("The code sorts in descending order"));
+
+        assert!(misleading_finding(comment.clone(), parse_response(&response(true, 4)).unwrap()).is_none());
+        assert!(misleading_finding(comment, parse_response(&response(false, 9)).unwrap()).is_none());
+        assert!(parse_response::<AccuracyAnalysis>(&json!({ "choices": [] })).is_err());
+    }
+
+    #[test]
+    fn test_verdict_probabilities_from_logprobs() {
+        let token = |token: &str, logprob: f64| json!({ "token": token, "logprob": logprob });
+        let response = json!({ "choices": [{ "logprobs": { "content": [
+            token("{\"", 0.0), token("is_redundant", 0.0), token("\":", 0.0), token(" true", 0.5_f64.ln()),
+            token(",\"", 0.0), token("explanation", 0.0), token("\":\"", 0.0), token("false", -3.0),
+            token("\",\"is_redundant\":", 0.0), token("false", 0.0),
+        ] } }] });
+        let probabilities = verdict_probabilities(&response);
+        assert_eq!(probabilities.len(), 2);
+        assert!((probabilities[0] - 0.5).abs() < 1e-9, "{:?}", probabilities);
+        assert_eq!(probabilities[1], 1.0);
+        assert!(verdict_probabilities(&json!({ "choices": [{}] })).is_empty());
+    }
+
+    #[test]
+    fn test_answers_outside_the_schema_are_errors() {
+        let answer = |message: serde_json::Value, finish_reason: &str| json!({
+            "choices": [{ "message": message, "finish_reason": finish_reason }]
+        });
+        let error = |json: serde_json::Value| parse_response::<CommentAnalysis>(&json).unwrap_err().to_string();
+
+        assert!(error(answer(json!({ "content": "{\"is_redundant\": \"yes\"}" }), "stop")).contains("doesn't match the schema"));
+        assert!(error(answer(json!({ "content": null, "refusal": "I can't help with that" }), "stop")).contains("I can't help with that"));
+        assert!(error(answer(json!({ "content": "{\"is_redun" }), "length")).contains("cut short"));
+        assert!(check_line(3, 4).unwrap_err().to_string().contains("line 3 instead of line 4"));
+
+        let errors = vec![ApiError::Other("off".to_string()), ApiError::Other("off".to_string()), ApiError::Other("cut".to_string())];
+        assert_eq!(unanswered_warnings(&errors), vec![
+            "2 comments weren't analyzed: API error: off".to_string(),
+            "1 comment wasn't analyzed: API error: cut".to_string(),
+        ]);
+    }
+
     #[tokio::test]
     async fn test_rate_limit_handling() {
         let mock_server = MockServer::start().await;
@@ -685,7 +1503,7 @@ interface Shape {
             text: "// Test comment".to_string(),
             line_number: 1,
             context: "Test context".to_string(),
-            explanation: None,
+            ..Default::default()
         };
 
         let result = make_test_api_request(
@@ -698,6 +1516,364 @@ interface Shape {
         assert!(result.is_ok(), "Request should succeed after retries: {:?}", result);
     }
 
+    #[tokio::test]
+    async fn test_analyze_files_streams_every_file() {
+        let temporary_directory = TempDir::new().unwrap();
+        let paths: Vec<PathBuf> = ["a.rs", "b.py"].iter()
+            .map(|name| temporary_directory.path().join(name))
+            .collect();
+        fs::write(&paths[0], "fn main() {\n    // Constructor\n    let x = 1;\n}\n").unwrap();
+        fs::write(&paths[1], "def f():\n    return 1\n").unwrap();
+
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+        let mut results: Vec<AnalysisResult> = analyze_files(&paths, false, &cache, &options).collect().await;
+        results.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].redundant_comments.len(), 1);
+        assert!(results[1].redundant_comments.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_open_breaker_still_filters_to_changed_lines() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("main.rs");
+        fs::write(&file, "fn first() {\n    // Constructor\n    let x = 1;\n}\n\nfn second() {\n    // Constructor\n    let y = 1;\n    // Keep this in step with the server\n    call(y);\n}\n").unwrap();
+        let root = dir.path().canonicalize().unwrap();
+
+        let breaker = crate::circuit_breaker::CircuitBreaker::new(&crate::config::CircuitBreakerConfig { max_failures: 1, cooldown_seconds: None });
+        breaker.record::<()>(&Err(ApiError::Other("down".to_string())));
+        let options = AnalysisOptions {
+            api: ApiSettings { api_key: Some("key".to_string()), breaker: Arc::new(breaker), ..Default::default() },
+            changed_lines: Some(Arc::new(crate::changes::ChangedLines::from_diff("+++ b/main.rs\n@@ -7,1 +7,1 @@\n", &root))),
+            ..Default::default()
+        };
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+        let result = analyze_file_with_options(&file, false, &cache, &options).await;
+
+        assert_eq!(result.errors.len(), 1, "{:?}", result.errors);
+        let lines: Vec<usize> = result.redundant_comments.iter().map(|comment| comment.line_number).collect();
+        assert_eq!(lines, vec![7]);
+        assert_eq!(result.redundant_comments[0].severity, Some(crate::types::Severity::Warning));
+        // Retried on the next run
+        assert!(cache.read().entries.is_empty());
+    }
+
+    #[test]
+    fn test_analyze_locally_leaves_undecided_comments_for_the_api() {
+        let source = "fn main() {\n    // Constructor\n    let x = 1;\n    // Keep this in step with the server\n    call(x);\n}\n";
+        let local = analyze_locally(source, Language::Rust, &AnalysisOptions::default()).unwrap();
+
+        assert_eq!(local.comments.len(), 2);
+        assert_eq!(local.redundant_comments.len(), 1);
+        assert_eq!(local.redundant_comments[0].line_number, 2);
+        assert_eq!(local.undecided.len(), 1);
+        assert_eq!(local.undecided[0].line_number, 4);
+
+    }
+
+    #[test]
+    fn test_analyze_locally_skips_only_the_broken_region() {
+        let source = "fn first() {\n    // Constructor\n    let x = ;\n}\n\nfn second() {\n    // Constructor\n    let y = 1;\n}\n";
+        let local = analyze_locally(source, Language::Rust, &AnalysisOptions::default()).unwrap();
+
+        assert_eq!(local.syntax_errors, vec!["syntax error at line 3, comments there weren't analyzed"]);
+        let lines: Vec<usize> = local.redundant_comments.iter().map(|comment| comment.line_number).collect();
+        assert_eq!(lines, vec![2, 7]);
+
+        let source = "fn broken() {\n    x.(\n    // Constructor\n    ]];\n}\n";
+        let local = analyze_locally(source, Language::Rust, &AnalysisOptions::default()).unwrap();
+        assert!(local.syntax_errors[0].starts_with("syntax error in lines"), "{:?}", local.syntax_errors);
+        assert!(local.comments.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_files_that_cant_be_analyzed_report_why() {
+        let dir = TempDir::new().unwrap();
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+
+        let missing = dir.path().join("missing.py");
+        let result = analyze_file_with_options(&missing, false, &cache, &options).await;
+        assert!(result.errors[0].starts_with("failed to read the file"), "{}", result.errors[0]);
+
+        let result = analyze_source("text".into(), Path::new("notes.txt"), &options).await;
+        assert_eq!(result.errors, vec!["unsupported file type"]);
+    }
+
+    #[tokio::test]
+    async fn test_fixing_a_latin1_file_keeps_its_encoding() {
+        let dir = TempDir::new().unwrap();
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+
+        let path = dir.path().join("caf\u{e9}.py");
+        fs::write(&path, b"def f():\n    # Constructor\n    return 'cr\xe8me'\n").unwrap();
+        let result = analyze_file_with_options(&path, true, &cache, &options).await;
+
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        assert_eq!(result.redundant_comments.len(), 1);
+        assert_eq!(fs::read(&path).unwrap(), b"def f():\n    return 'cr\xe8me'\n");
+    }
+
+    #[tokio::test]
+    async fn test_explain_comment_offline() {
+        let source = "fn main() {\n    // Constructor\n    let x = 1;\n    // TODO: drop this\n    // Keep this in step with the server\n    call(x);\n}\n";
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+
+        let explained = explain_comment(source, Language::Rust, 2, &options).await.unwrap();
+        assert_eq!(explained.verdict, Verdict::Redundant);
+        assert!(explained.comment.rule_id.is_some());
+
+        let explained = explain_comment(source, Language::Rust, 4, &options).await.unwrap();
+        assert_eq!(explained.verdict, Verdict::Keep);
+        assert_eq!(explained.comment.explanation.as_deref(), Some("TODO-style comments are never reported"));
+
+        let explained = explain_comment(source, Language::Rust, 5, &options).await.unwrap();
+        assert_eq!(explained.verdict, Verdict::Keep);
+        assert!(explained.comment.explanation.unwrap().contains("wasn't sent to the API"));
+
+        let error = explain_comment(source, Language::Rust, 3, &options).await.unwrap_err();
+        assert_eq!(error, "no comment at line 3");
+    }
+
+    #[tokio::test]
+    async fn test_annotate_then_fix() {
+        let dir = TempDir::new().unwrap();
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+        let path = dir.path().join("main.rs");
+        fs::write(&path, "fn main() {\n    // Constructor\n    let x = 1;\n    // Keep this in step with the server\n    call(x);\n}\n").unwrap();
+
+        let options = AnalysisOptions { offline: true, annotate: true, ..Default::default() };
+        let result = analyze_file_with_options(&path, false, &cache, &options).await;
+        assert_eq!(result.redundant_comments.len(), 1);
+        let annotated = fs::read_to_string(&path).unwrap();
+        assert!(annotated.contains("// [unremark: likely redundant] Constructor"), "{}", annotated);
+
+        // A reviewer disagreed with nothing, and marked one more comment by hand
+        fs::write(&path, annotated.replace("// Keep", "// [unremark: likely redundant] Keep")).unwrap();
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+        let result = analyze_file_with_options(&path, true, &cache, &options).await;
+        assert_eq!(result.redundant_comments.len(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fn main() {\n    let x = 1;\n    call(x);\n}\n");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_analyze_source_on_a_multi_threaded_runtime() {
+        let source = "fn main() {\n    // Constructor\n    let x = 1;\n}\n";
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+        let result = analyze_source(source.into(), Path::new("main.rs"), &options).await;
+        assert_eq!(result.redundant_comments.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_files_over_the_size_limit_are_skipped() {
+        let temporary_directory = TempDir::new().unwrap();
+        let path = temporary_directory.path().join("main.rs");
+        fs::write(&path, "fn main() {\n    // Constructor\n    let x = 1;\n}\n").unwrap();
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+
+        let options = AnalysisOptions { offline: true, max_file_size: Some(10), ..Default::default() };
+        let result = analyze_file_with_options(&path, true, &cache, &options).await;
+        assert!(result.redundant_comments.is_empty());
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].contains("over the limit of 10"), "{}", result.warnings[0]);
+        assert!(fs::read_to_string(&path).unwrap().contains("// Constructor"));
+
+        let options = AnalysisOptions { offline: true, max_file_size: None, ..Default::default() };
+        let result = analyze_file_with_options(&path, false, &cache, &options).await;
+        assert_eq!(result.redundant_comments.len(), 1);
+        assert!(result.warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_verified_fix_is_written() {
+        let temporary_directory = TempDir::new().unwrap();
+        let path = temporary_directory.path().join("main.rs");
+        fs::write(&path, "fn main() {\n    // Constructor\n    let x = 1;\n}\n").unwrap();
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+
+        let options = AnalysisOptions { offline: true, verify: true, ..Default::default() };
+        let result = analyze_file_with_options(&path, true, &cache, &options).await;
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fn main() {\n    let x = 1;\n}\n");
+    }
+
+    #[tokio::test]
+    async fn test_markdown_code_blocks_are_analyzed_in_place() {
+        let temporary_directory = TempDir::new().unwrap();
+        let path = temporary_directory.path().join("README.md");
+        let markdown = "# Example\n\n// Constructor\n\n```rust\nfn main() {\n    // Constructor\n    let x = 1;\n}\n```\n\n```console\n// Constructor\n```\n";
+        fs::write(&path, markdown).unwrap();
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+        let result = analyze_file_with_options(&path, false, &cache, &options).await;
+        assert_eq!(result.errors, vec!["unsupported file type"]);
+
+        let options = AnalysisOptions { offline: true, markdown: true, ..Default::default() };
+        let result = analyze_file_with_options(&path, true, &cache, &options).await;
+        let lines: Vec<usize> = result.redundant_comments.iter().map(|comment| comment.line_number).collect();
+        assert_eq!(lines, vec![7]);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Example\n\n// Constructor\n\n```rust\nfn main() {\n    let x = 1;\n}\n```\n\n```console\n// Constructor\n```\n",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_language_option_overrides_the_file_name() {
+        let temporary_directory = TempDir::new().unwrap();
+        let path = temporary_directory.path().join("Jenkinsfile");
+        fs::write(&path, "// Constructor\nconst x = 1;\n").unwrap();
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+        let result = analyze_file_with_options(&path, false, &cache, &options).await;
+        assert_eq!(result.errors, vec!["unsupported file type"]);
+
+        let options = AnalysisOptions { offline: true, language: Some(Language::JavaScript), ..Default::default() };
+        let result = analyze_file_with_options(&path, false, &cache, &options).await;
+        assert_eq!(result.redundant_comments.len(), 1);
+        assert_ne!(options.cache_key(), AnalysisOptions::default().cache_key());
+    }
+
+    #[tokio::test]
+    async fn test_analyze_files_with_bounded_concurrency() {
+        let temporary_directory = TempDir::new().unwrap();
+        let paths: Vec<PathBuf> = (0..10)
+            .map(|i| temporary_directory.path().join(format!("file{}.rs", i)))
+            .collect();
+        for path in &paths {
+            fs::write(path, "fn main() {\n    // Constructor\n    let x = 1;\n}\n").unwrap();
+        }
+
+        let cache = parking_lot::RwLock::new(Cache { entries: HashMap::new() });
+        // Zero is treated as one at a time rather than stalling
+        for file_concurrency in [0, 1, 3] {
+            let options = AnalysisOptions { offline: true, file_concurrency, ..Default::default() };
+            let results: Vec<AnalysisResult> = analyze_files(&paths, false, &cache, &options).collect().await;
+            assert_eq!(results.len(), 10);
+            assert!(results.iter().all(|result| result.redundant_comments.len() == 1));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_api_settings_choose_endpoint_key_and_model() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(wiremock::matchers::header("authorization", "Bearer configured-key"))
+            .and(wiremock::matchers::body_partial_json(json!({ "model": "gpt-test" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{
+                    "message": {
+                        "content": "{\"is_redundant\": true, \"comment_line_number\": 1, \"comment_text\": \"// Add\", \"explanation\": \"Says what the code says\"}"
+                    }
+                }]
+            })))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let api = ApiSettings {
+            api_key: Some("configured-key".to_string()),
+            model: "gpt-test".to_string(),
+            endpoint: Some(format!("{}/v1", mock_server.uri())),
+            concurrency: Some(1),
+            ..Default::default()
+        };
+        let comments = vec![
+            CommentInfo { text: "// Add".to_string(), line_number: 1, ..Default::default() },
+            CommentInfo { text: "// Add".to_string(), line_number: 1, ..Default::default() },
+        ];
+
+        let redundant = analyze_comments_with(comments, &api).await.unwrap();
+        assert_eq!(redundant.len(), 2);
+        assert_eq!(redundant[0].rule_id.as_deref(), Some(REDUNDANT_COMMENT_RULE));
+    }
+
+    #[tokio::test]
+    async fn test_batched_comments_are_matched_by_line() {
+        let mock_server = MockServer::start().await;
+        let analysis = |line: usize, is_redundant: bool| json!({
+            "is_redundant": is_redundant,
+            "comment_line_number": line,
+            "comment_text": "",
+            "explanation": "Because",
+            "usefulness_score": if is_redundant { 5 } else { 80 },
+        });
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(wiremock::matchers::body_partial_json(json!({ "response_format": { "json_schema": { "name": "comment_analyses" } } })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{
+                    "message": { "content": json!({ "analyses": [analysis(3, false), analysis(1, true)] }).to_string() }
+                }]
+            })))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let api = ApiSettings {
+            api_key: Some("key".to_string()),
+            endpoint: Some(format!("{}/v1", mock_server.uri())),
+            batch_size: 3,
+            ..Default::default()
+        };
+        let comment = |line: usize| CommentInfo { text: format!("// line {}", line), line_number: line, ..Default::default() };
+        let (judged, errors) = judge_comments(vec![comment(1), comment(2), comment(3), comment(4)], &api).await;
+
+        let verdicts: Vec<(usize, bool)> = judged.iter().map(|(comment, redundant)| (comment.line_number, *redundant)).collect();
+        assert_eq!(verdicts, vec![(1, true), (3, false)]);
+        assert_eq!(judged[1].0.quality_score, Some(80));
+        let errors: Vec<String> = errors.iter().map(ApiError::to_string).collect();
+        assert_eq!(errors, vec![
+            "API error: the model left out the comment on line 2".to_string(),
+            "API error: the model left out the comment on line 4".to_string(),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_report_all_scores_every_analyzed_comment() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "choices": [{
+                    "message": {
+                        "content": "{\"is_redundant\": false, \"comment_line_number\": 3, \"comment_text\": \"// Retry\", \"explanation\": \"Says why\", \"usefulness_score\": 82}"
+                    }
+                }]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let source = "fn main() {\n    // Constructor\n    // Retry while the lock is held elsewhere\n    let x = 1;\n}\n";
+        let options = AnalysisOptions {
+            report_all: true,
+            prefilter: false,
+            api: ApiSettings {
+                api_key: Some("test-key".to_string()),
+                endpoint: Some(format!("{}/v1", mock_server.uri())),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = analyze_source(source.into(), Path::new("main.rs"), &options).await;
+
+        assert_eq!(result.redundant_comments.len(), 1);
+        let scores: Vec<(usize, Option<u8>)> = result.scored_comments.iter()
+            .map(|comment| (comment.line_number, comment.quality_score))
+            .collect();
+        assert_eq!(scores, vec![(2, Some(0)), (3, Some(82))]);
+        assert_eq!(result.scored_comments[1].explanation.as_deref(), Some("Says why"));
+
+        let options = AnalysisOptions { report_all: false, ..options };
+        assert!(analyze_source(source.into(), Path::new("main.rs"), &options).await.scored_comments.is_empty());
+    }
+
     async fn make_test_api_request(
         client: &reqwest::Client,
         api_key: &str,
@@ -714,7 +1890,7 @@ interface Shape {
             }
 
             let message = serde_json::json!({
-                "model": "ft:gpt-4o-mini-2024-07-18:personal:unremark:Aq45wBQq",
+                "model": crate::constants::OPENAI_MODEL,
                 "messages": [{
                     "role": "user",
                     "content": format!(
@@ -796,4 +1972,4 @@ interface Shape {
 
         Err(ApiError::Other("Maximum retries exceeded".to_string()))
     }
-}
\ No newline at end of file
+}
@@ -1,105 +1,601 @@
-use crate::types::{CommentInfo, ApiError};
-use reqwest::StatusCode;
-use std::time::Duration;
+use crate::config::{NetworkConfig, PromptExample};
+use crate::services::proxy::{AnalysisService, ProxyAnalysisService};
+use crate::telemetry::{metrics, Outcome};
+use crate::types::{ApiError, ApiSettings, CommentInfo, Provider};
+use crate::utils::estimate_tokens;
+use crate::constants::MAX_RETRY_DELAY;
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, ClientBuilder, Identity, Proxy, StatusCode};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::time::sleep;
-use log::debug;
+use tracing::{debug, instrument, Span};
 
-pub(crate) async fn make_api_request(
-    client: &reqwest::Client,
-    api_key: &str,
-    comment: &CommentInfo,
-) -> Result<serde_json::Value, ApiError> {
-    let max_retries = 3;
-    let mut retry_delay = Duration::from_millis(1000);
+/// Asks whether a comment is redundant next to its code.
+pub(crate) fn redundancy_prompt(comment: &CommentInfo) -> String {
+    format!(
+        "Comment: '{}'\nContext: '{}'\nLine Number: {}\nIs this comment redundant or useful? Please respond with a JSON object containing the following fields: is_redundant, comment_line_number, comment_text, explanation, usefulness_score (0 to 100, how much the comment helps a reader of the code), confidence (0 to 1, how sure you are of is_redundant)",
+        comment.text,
+        comment.context,
+        comment.line_number
+    )
+}
+
+/// The chat messages asking `prompt`, after each example as a redundancy
+/// question with its answer.
+pub(crate) fn chat_messages(prompt: &str, examples: &[PromptExample]) -> Vec<serde_json::Value> {
+    let mut messages = Vec::with_capacity(examples.len() * 2 + 1);
+    for example in examples {
+        let comment = CommentInfo {
+            text: example.comment.clone(),
+            context: example.context.clone(),
+            line_number: 1,
+            ..Default::default()
+        };
+        let explanation = example.explanation.clone().unwrap_or_else(|| {
+            if example.redundant { "It only repeats what the code says" } else { "It tells the reader something the code doesn't" }.to_string()
+        });
+        let answer = serde_json::json!({
+            "is_redundant": example.redundant,
+            "comment_line_number": 1,
+            "comment_text": example.comment,
+            "explanation": explanation,
+            "usefulness_score": if example.redundant { 10 } else { 90 },
+            "confidence": 1.0,
+        });
+        messages.push(serde_json::json!({ "role": "user", "content": redundancy_prompt(&comment) }));
+        messages.push(serde_json::json!({ "role": "assistant", "content": answer.to_string() }));
+    }
+    messages.push(serde_json::json!({ "role": "user", "content": prompt }));
+    messages
+}
+
+/// Asks whether a comment still describes what its code actually does.
+pub(crate) fn accuracy_prompt(comment: &CommentInfo) -> String {
+    format!(
+        "Comment: '{}'\nContext: '{}'\nLine Number: {}\nDoes this comment accurately describe the code next to it, or does it contradict what the code does? Please respond with a JSON object containing the following fields: is_accurate, comment_line_number, comment_text, explanation",
+        comment.text,
+        comment.context,
+        comment.line_number
+    )
+}
+
+/// Asks whether each of the comments is redundant, in one prompt.
+pub(crate) fn batch_redundancy_prompt(comments: &[CommentInfo]) -> String {
+    let listed: Vec<String> = comments.iter()
+        .map(|comment| format!("Comment: '{}'\nContext: '{}'\nLine Number: {}", comment.text, comment.context, comment.line_number))
+        .collect();
+    format!(
+        "{}\n\nIs each of these comments redundant or useful? Please respond with a JSON object whose analyses field holds one object per comment, in the same order, containing the following fields: is_redundant, comment_line_number, comment_text, explanation, usefulness_score (0 to 100, how much the comment helps a reader of the code), confidence (0 to 1, how sure you are of is_redundant)",
+        listed.join("\n\n")
+    )
+}
+
+/// Has the model answer the redundancy prompt in `CommentAnalysis`'s shape.
+pub(crate) fn redundancy_format() -> serde_json::Value {
+    answer_format("comment_analysis", redundancy_schema())
+}
+
+/// Has the model answer the batch prompt in `BatchAnalysis`'s shape.
+pub(crate) fn batch_redundancy_format() -> serde_json::Value {
+    answer_format("comment_analyses", object_schema(serde_json::json!({
+        "analyses": { "type": "array", "items": redundancy_schema() },
+    })))
+}
+
+/// Has the model answer the accuracy prompt in `AccuracyAnalysis`'s shape.
+pub(crate) fn accuracy_format() -> serde_json::Value {
+    answer_format("accuracy_analysis", object_schema(serde_json::json!({
+        "is_accurate": { "type": "boolean" },
+        "comment_line_number": { "type": "integer" },
+        "comment_text": { "type": "string" },
+        "explanation": { "type": "string" },
+    })))
+}
+
+fn redundancy_schema() -> serde_json::Value {
+    object_schema(serde_json::json!({
+        "is_redundant": { "type": "boolean" },
+        "comment_line_number": { "type": "integer" },
+        "comment_text": { "type": "string" },
+        "explanation": { "type": "string" },
+        "usefulness_score": { "type": "integer", "description": "0 to 100, how much the comment helps a reader of the code" },
+        "confidence": { "type": "number", "description": "0 to 1, how sure the answer is of is_redundant" },
+    }))
+}
+
+/// A strict JSON schema `response_format` for the schema.
+fn answer_format(name: &str, schema: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "type": "json_schema",
+        "json_schema": { "name": name, "strict": true, "schema": schema },
+    })
+}
+
+/// The schema of an object with all of `properties`, and nothing else.
+fn object_schema(properties: serde_json::Value) -> serde_json::Value {
+    let required: Vec<&String> = properties.as_object().map(|properties| properties.keys().collect()).unwrap_or_default();
+    serde_json::json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// How failed requests are retried: up to `max_attempts` tries, waiting
+/// `initial_delay` before the second and `multiplier` times longer before
+/// each one after, up to `MAX_RETRY_DELAY`. A response's `Retry-After` sets
+/// the next wait instead, whichever backend sent it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub multiplier: f64,
+    /// Fraction of each wait randomly added or taken off, so requests that
+    /// failed together aren't all retried at the same moment
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_millis(1000),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before the `retry`th retry, 1 for the first.
+    fn delay(&self, retry: u32) -> Duration {
+        self.delay_with(retry, fastrand::f64())
+    }
+
+    /// `delay` with `random`, in `0.0..1.0`, placing it within the jitter.
+    fn delay_with(&self, retry: u32, random: f64) -> Duration {
+        let exponent = i32::try_from(retry.saturating_sub(1)).unwrap_or(i32::MAX);
+        let backoff = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let jittered = backoff * (1.0 + self.jitter * (2.0 * random - 1.0));
+        Duration::try_from_secs_f64(jittered.max(0.0)).map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+    }
+}
+
+/// How long a response asks to wait before retrying: OpenAI's
+/// `retry-after-ms`, or `Retry-After` in seconds or as an HTTP date. Capped
+/// at `MAX_RETRY_DELAY` like the backoff, so no response stalls a run.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+    let delay = match header("retry-after-ms").and_then(|value| value.parse::<f64>().ok()) {
+        Some(millis) => Duration::try_from_secs_f64(millis / 1000.0).ok(),
+        None => {
+            let value = header("retry-after")?;
+            match value.parse::<f64>() {
+                Ok(seconds) => Duration::try_from_secs_f64(seconds).ok(),
+                Err(_) => httpdate::parse_http_date(value).ok()
+                    .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default()),
+            }
+        }
+    };
+    delay.map(|delay| delay.min(MAX_RETRY_DELAY))
+}
+
+/// Told when each rate-limited request will be retried, e.g. for an editor
+/// to show that the analysis is waiting on the API.
+pub type RateLimitSender = tokio::sync::mpsc::UnboundedSender<SystemTime>;
+
+/// Applies `network` to an HTTP client: trusting the extra CA certificates,
+/// presenting the client certificate, and going through the proxy. Unreadable
+/// or invalid files are reported rather than silently ignored.
+pub(crate) fn configure_network(mut builder: ClientBuilder, network: &NetworkConfig) -> Result<ClientBuilder, ApiError> {
+    let read = |path: &Path, what: &str| {
+        std::fs::read(path).map_err(|e| ApiError::Other(format!("Cannot read {} {}: {}", what, path.display(), e)))
+    };
+
+    if let Some(path) = &network.ca_bundle {
+        let certificates = Certificate::from_pem_bundle(&read(path, "CA bundle")?)
+            .map_err(|e| ApiError::Other(format!("Invalid CA bundle {}: {}", path.display(), e)))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    match (&network.client_cert, &network.client_key) {
+        (Some(cert), Some(key)) => {
+            let identity = Identity::from_pkcs8_pem(&read(cert, "client certificate")?, &read(key, "client key")?)
+                .map_err(|e| ApiError::Other(format!("Invalid client certificate {}: {}", cert.display(), e)))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => return Err(ApiError::Other("client_cert and client_key must be set together".to_string())),
+    }
+    if let Some(url) = &network.https_proxy {
+        let proxy = Proxy::all(url)
+            .map_err(|e| ApiError::Other(format!("Invalid proxy {}: {}", url, e)))?;
+        builder = builder.proxy(proxy);
+    }
+    Ok(builder)
+}
+
+/// Sends the request built by `request` until it succeeds or `policy` gives
+/// up, mapping the last failure to an `ApiError`. Waits on rate limits are
+/// announced on `rate_limited`, when given.
+#[instrument(name = "api_request", level = "debug", skip_all, fields(attempts, status))]
+pub(crate) async fn send_with_retry(
+    policy: &RetryPolicy,
+    rate_limited: Option<&RateLimitSender>,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, ApiError> {
+    let max_retries = policy.max_attempts.max(1);
+    // Asked for by the last response, in place of the backoff
+    let mut requested_delay = None;
 
     for attempt in 0..max_retries {
+        Span::current().record("attempts", attempt + 1);
         if attempt > 0 {
             debug!("Retrying request (attempt {}/{})", attempt + 1, max_retries);
-            sleep(retry_delay).await;
-            retry_delay *= 2;
+            sleep(requested_delay.take().unwrap_or_else(|| policy.delay(attempt))).await;
         }
+        let last_attempt = attempt == max_retries - 1;
 
-        let message = serde_json::json!({
-            "model": "ft:gpt-4o-mini-2024-07-18:personal:unremark:Aq45wBQq",
-            "messages": [{
-                "role": "user",
-                "content": format!(
-                    "Comment: '{}'\nContext: '{}'\nLine Number: {}\nIs this comment redundant or useful? Please respond with a JSON object containing the following fields: is_redundant, comment_line_number, comment_text, explanation",
-                    comment.text,
-                    comment.context,
-                    comment.line_number
-                )
-            }],
-            "max_tokens": 500,
-            "temperature": 0.0,
-            "top_p": 1.0,
-            "n": 1,
-            "stream": false
-        });
-
-        match client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", api_key))
-            .json(&message)
-            .send()
-            .await
-        {
+        let sent = Instant::now();
+        let response = request().send().await;
+        let outcome = match &response {
+            Ok(response) if response.status().is_success() => Outcome::Success,
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => Outcome::RateLimited,
+            _ => Outcome::Error,
+        };
+        metrics().record_api_request(outcome, sent.elapsed());
+        match response {
             Ok(response) => {
+                Span::current().record("status", response.status().as_u16());
+                requested_delay = retry_after(response.headers());
                 match response.status() {
-                    StatusCode::OK => {
-                        return response.json().await.map_err(|e| {
-                            ApiError::Other(format!("Failed to parse response: {}", e))
-                        });
-                    }
+                    status if status.is_success() => return Ok(response),
                     StatusCode::TOO_MANY_REQUESTS => {
-                        if attempt == max_retries - 1 {
+                        if last_attempt {
                             return Err(ApiError::RateLimit(
                                 "Rate limit exceeded after all retries".to_string(),
                             ));
                         }
-                        if let Some(retry_after) = response.headers()
-                            .get("retry-after")
-                            .and_then(|h| h.to_str().ok())
-                            .and_then(|s| s.parse::<u64>().ok())
-                        {
-                            retry_delay = Duration::from_secs(retry_after);
+                        let delay = *requested_delay.get_or_insert_with(|| policy.delay(attempt + 1));
+                        if let Some(rate_limited) = rate_limited {
+                            // Nobody listening anymore is no reason to fail the request
+                            let _ = rate_limited.send(SystemTime::now() + delay);
                         }
-                        continue;
                     }
                     status => {
-                        if attempt == max_retries - 1 {
+                        if last_attempt {
                             return Err(ApiError::Other(
                                 format!("Request failed with status: {}", status),
                             ));
                         }
-                        continue;
                     }
                 }
             }
             Err(e) => {
-                if e.is_timeout() {
-                    if attempt == max_retries - 1 {
-                        return Err(ApiError::Timeout(
-                            "Request timed out after all retries".to_string(),
-                        ));
-                    }
+                if !last_attempt {
+                    continue;
+                }
+                return Err(if e.is_timeout() {
+                    ApiError::Timeout("Request timed out after all retries".to_string())
                 } else if e.is_connect() {
-                    if attempt == max_retries - 1 {
-                        return Err(ApiError::Network(
-                            "Failed to connect after all retries".to_string(),
-                        ));
-                    }
+                    ApiError::Network("Failed to connect after all retries".to_string())
                 } else {
-                    if attempt == max_retries - 1 {
-                        return Err(ApiError::Other(
-                            format!("Request failed: {}", e),
-                        ));
-                    }
-                }
-                continue;
+                    ApiError::Other(format!("Request failed: {}", e))
+                });
             }
         }
     }
 
     Err(ApiError::Other("Maximum retries exceeded".to_string()))
-}
\ No newline at end of file
+}
+
+pub(crate) async fn make_api_request(
+    client: &reqwest::Client,
+    api: &ApiSettings,
+    api_key: &str,
+    messages: &[serde_json::Value],
+    response_format: &serde_json::Value,
+) -> Result<serde_json::Value, ApiError> {
+    let mut message = serde_json::json!({
+        "model": api.model,
+        "messages": messages,
+        "response_format": response_format,
+        "max_tokens": api.generation.max_tokens,
+        "temperature": api.generation.temperature,
+        "top_p": api.generation.top_p,
+        "n": 1,
+        "stream": false
+    });
+    let prompt_tokens: usize = messages.iter()
+        .filter_map(|message| message["content"].as_str())
+        .map(estimate_tokens)
+        .sum();
+    // OpenRouter moves on to these when the model can't answer
+    if api.provider == Provider::OpenRouter && !api.openrouter.fallback_models.is_empty() {
+        message["models"] = serde_json::json!(api.openrouter.fallback_models);
+    }
+    if api.generation.logprobs {
+        message["logprobs"] = serde_json::json!(true);
+    }
+    if let Some(replayed) = api.fixtures.as_ref().and_then(|fixtures| fixtures.replay(&message)) {
+        return replayed;
+    }
+    api.breaker.check()?;
+    let _in_flight = api.limiter.acquire(prompt_tokens + api.generation.max_tokens as usize).await;
+
+    let response = send_with_retry(&api.retry, api.rate_limited.as_ref(), || {
+        let request = client
+            .post(format!("{}/chat/completions", api.base_url()))
+            .timeout(api.timeout)
+            .header("Authorization", format!("Bearer {}", api_key))
+            .json(&message);
+        match api.provider {
+            Provider::OpenRouter => api.openrouter.headers().into_iter()
+                .fold(request, |request, (name, value)| request.header(name, value)),
+            _ => request,
+        }
+    }).await;
+    api.breaker.record(&response);
+    let json: serde_json::Value = response?.json().await.map_err(|e| {
+        ApiError::Other(format!("Failed to parse response: {}", e))
+    })?;
+    let usage = &json["usage"];
+    let (prompt_tokens, completion_tokens) = (
+        usage["prompt_tokens"].as_u64().unwrap_or_default(),
+        usage["completion_tokens"].as_u64().unwrap_or_default(),
+    );
+    metrics().record_tokens(prompt_tokens, completion_tokens);
+    api.usage.record(prompt_tokens, completion_tokens);
+    if let Some(fixtures) = &api.fixtures {
+        fixtures.record(&message, &json);
+    }
+    Ok(json)
+}
+
+/// Checks that the provider can be reached and takes the credentials, with a
+/// request that costs nothing: listing the models, or sending the proxy an
+/// empty batch. Failures aren't retried, so a diagnosis stays quick.
+pub async fn check_connection(api: &ApiSettings) -> Result<(), ApiError> {
+    let once = RetryPolicy { max_attempts: 1, ..api.retry };
+    if api.provider == Provider::Proxy {
+        let service = ProxyAnalysisService { retry: once, ..ProxyAnalysisService::from_settings(api) };
+        return service.analyze_comments_with_proxy(vec![]).await.map(|_| ());
+    }
+
+    let Some(api_key) = api.resolved_api_key() else {
+        return Err(ApiError::Other(format!("no API key for {}", api.provider)));
+    };
+    let client = configure_network(reqwest::Client::builder(), &api.network)?
+        .build()
+        .map_err(|e| ApiError::Other(format!("Failed to build API client: {}", e)))?;
+    send_with_retry(&once, None, || {
+        client
+            .get(format!("{}/models", api.base_url()))
+            .timeout(api.timeout)
+            .header("Authorization", format!("Bearer {}", api_key))
+    }).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn configure(network: &NetworkConfig) -> Result<reqwest::Client, ApiError> {
+        configure_network(reqwest::Client::builder(), network)?
+            .build()
+            .map_err(|e| ApiError::Other(e.to_string()))
+    }
+
+    #[test]
+    fn test_default_network_builds() {
+        assert!(configure(&NetworkConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn test_missing_ca_bundle_is_an_error() {
+        let network = NetworkConfig {
+            ca_bundle: Some(PathBuf::from("/nonexistent/ca.pem")),
+            ..Default::default()
+        };
+        let err = configure(&network).unwrap_err();
+        assert!(err.to_string().contains("Cannot read CA bundle /nonexistent/ca.pem"));
+    }
+
+    #[test]
+    fn test_invalid_client_certificate_is_an_error() {
+        let temp_dir = TempDir::new().unwrap();
+        let cert = temp_dir.path().join("client.pem");
+        let key = temp_dir.path().join("client.key");
+        std::fs::write(&cert, "not a certificate").unwrap();
+        std::fs::write(&key, "not a key").unwrap();
+
+        let network = NetworkConfig {
+            client_cert: Some(cert),
+            client_key: Some(key),
+            ..Default::default()
+        };
+        let err = configure(&network).unwrap_err();
+        assert!(err.to_string().contains("Invalid client certificate"));
+    }
+
+    #[test]
+    fn test_client_cert_requires_key() {
+        let network = NetworkConfig {
+            client_cert: Some(PathBuf::from("client.pem")),
+            ..Default::default()
+        };
+        assert!(configure(&network).is_err());
+    }
+
+    #[test]
+    fn test_https_proxy() {
+        let network = NetworkConfig {
+            https_proxy: Some("http://proxy.example.com:3128".to_string()),
+            ..Default::default()
+        };
+        assert!(configure(&network).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_check_connection() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .and(header("Authorization", "Bearer good"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "data": [] })))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/models"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&mock_server)
+            .await;
+
+        let api = |api_key: &str| ApiSettings {
+            api_key: Some(api_key.to_string()),
+            endpoint: Some(mock_server.uri()),
+            ..Default::default()
+        };
+        assert!(check_connection(&api("good")).await.is_ok());
+        let err = check_connection(&api("bad")).await.unwrap_err();
+        assert!(err.to_string().contains("401"), "{}", err);
+    }
+
+    #[test]
+    fn test_examples_come_before_the_question() {
+        let examples = [PromptExample {
+            comment: "// increment i".to_string(),
+            context: "i += 1;".to_string(),
+            redundant: true,
+            explanation: None,
+        }];
+        let messages = chat_messages("Is it redundant?", &examples);
+        let roles: Vec<&str> = messages.iter().map(|message| message["role"].as_str().unwrap()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+        assert!(messages[0]["content"].as_str().unwrap().contains("Comment: '// increment i'\nContext: 'i += 1;'"));
+        let answer: serde_json::Value = serde_json::from_str(messages[1]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(answer["is_redundant"], true);
+        assert_eq!(messages[2]["content"], "Is it redundant?");
+    }
+
+    #[test]
+    fn test_answer_formats_require_every_field() {
+        let format = redundancy_format();
+        let schema = &format["json_schema"]["schema"];
+        assert_eq!(format["json_schema"]["strict"], true);
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(schema["required"].as_array().unwrap().len(), schema["properties"].as_object().unwrap().len());
+        assert!(accuracy_format()["json_schema"]["schema"]["required"].as_array().unwrap().contains(&"is_accurate".into()));
+        let items = &batch_redundancy_format()["json_schema"]["schema"]["properties"]["analyses"]["items"];
+        assert_eq!(items, schema);
+    }
+
+    #[tokio::test]
+    async fn test_openrouter_requests_carry_its_headers_and_fallbacks() {
+        use wiremock::matchers::{body_partial_json, header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(header("HTTP-Referer", "https://github.com/software-trizzey/unremark"))
+            .and(header("X-Title", "unremark"))
+            .and(body_partial_json(serde_json::json!({
+                "model": "meta-llama/llama-3.1-8b-instruct",
+                "models": ["openai/gpt-4o-mini"],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "choices": [] })))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let api = ApiSettings {
+            provider: Provider::OpenRouter,
+            model: "meta-llama/llama-3.1-8b-instruct".to_string(),
+            endpoint: Some(mock_server.uri()),
+            openrouter: crate::config::OpenRouterConfig {
+                fallback_models: vec!["openai/gpt-4o-mini".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(make_api_request(&reqwest::Client::new(), &api, "key", &chat_messages("prompt", &[]), &redundancy_format()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_are_announced() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(429).insert_header("retry-after", "0"))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let policy = RetryPolicy { max_attempts: 2, initial_delay: Duration::from_millis(10), ..Default::default() };
+        let client = reqwest::Client::new();
+        let before = SystemTime::now();
+        send_with_retry(&policy, Some(&sender), || client.get(mock_server.uri())).await.unwrap();
+
+        let retry_at = receiver.try_recv().unwrap();
+        assert!(retry_at >= before);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_backoff_grows_with_jitter_up_to_the_cap() {
+        let policy = RetryPolicy { initial_delay: Duration::from_secs(1), multiplier: 3.0, jitter: 0.5, ..Default::default() };
+        assert_eq!(policy.delay_with(1, 0.5), Duration::from_secs(1));
+        assert_eq!(policy.delay_with(3, 0.5), Duration::from_secs(9));
+        assert_eq!(policy.delay_with(2, 0.0), Duration::from_millis(1500));
+        assert_eq!(policy.delay_with(2, 1.0), Duration::from_millis(4500));
+        assert_eq!(policy.delay_with(30, 0.5), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_retry_after_in_any_format() {
+        let headers = |name: &'static str, value: String| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(retry_after(&headers("retry-after", "2".to_string())), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(&headers("retry-after-ms", "250".to_string())), Some(Duration::from_millis(250)));
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let delay = retry_after(&headers("retry-after", later)).unwrap();
+        assert!(delay > Duration::from_secs(20) && delay <= Duration::from_secs(30), "{:?}", delay);
+        let earlier = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(120));
+        assert_eq!(retry_after(&headers("retry-after", earlier)), Some(Duration::ZERO));
+        assert_eq!(retry_after(&headers("retry-after", "soon".to_string())), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_retry_after_is_capped() {
+        let headers = |name: &'static str, value: String| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(retry_after(&headers("retry-after", "86400".to_string())), Some(MAX_RETRY_DELAY));
+        assert_eq!(retry_after(&headers("retry-after-ms", "86400000".to_string())), Some(MAX_RETRY_DELAY));
+        let tomorrow = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(86400));
+        assert_eq!(retry_after(&headers("retry-after", tomorrow)), Some(MAX_RETRY_DELAY));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::changes::{git, repository_root};
+use crate::types::CommentInfo;
+use std::path::{Path, PathBuf};
+
+/// The commit where the current branch forked from another, whose findings
+/// count as already known.
+#[derive(Debug, Clone)]
+pub struct MergeBase {
+    root: PathBuf,
+    pub commit: String,
+}
+
+impl MergeBase {
+    /// Finds the merge base of `HEAD` and `revision` for the repository containing `path`.
+    pub fn find(path: &Path, revision: &str) -> Result<Self, String> {
+        let root = repository_root(path)?;
+        let commit = git(&root, &["merge-base", "HEAD", revision])?.trim().to_string();
+        Ok(Self { root, commit })
+    }
+
+    /// The file's contents at the merge base, if it existed then. `path` must be canonical.
+    pub fn source_of(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).ok()?.to_string_lossy().replace('\\', "/");
+        git(&self.root, &["show", &format!("{}:{}", self.commit, relative)]).ok()
+    }
+}
+
+fn same_finding(a: &CommentInfo, b: &CommentInfo) -> bool {
+    a.rule_id == b.rule_id && a.text.trim() == b.text.trim()
+}
+
+/// Drops the findings already present in the baseline. Line numbers shift as
+/// code moves, so findings match by rule and comment text, each baseline
+/// finding cancelling out at most one current one.
+pub fn subtract_findings(current: Vec<CommentInfo>, baseline: &[CommentInfo]) -> Vec<CommentInfo> {
+    let mut unmatched: Vec<&CommentInfo> = baseline.iter().collect();
+    current.into_iter()
+        .filter(|finding| match unmatched.iter().position(|known| same_finding(known, finding)) {
+            Some(index) => {
+                unmatched.swap_remove(index);
+                false
+            }
+            None => true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finding(text: &str, line_number: usize, rule_id: &str) -> CommentInfo {
+        CommentInfo { text: text.to_string(), line_number, rule_id: Some(rule_id.to_string()), ..Default::default() }
+    }
+
+    #[test]
+    fn test_subtract_findings_matches_moved_comments() {
+        let baseline = vec![finding("// Constructor", 3, "boilerplate-comment"), finding("// x", 8, "name-echo")];
+        let current = vec![
+            finding("// Constructor", 10, "boilerplate-comment"),
+            finding("// Constructor", 20, "boilerplate-comment"),
+            finding("// x", 12, "duplicate-comment"),
+        ];
+
+        let new = subtract_findings(current, &baseline);
+        assert_eq!(new.len(), 2);
+        assert_eq!(new[0].line_number, 20);
+        assert_eq!(new[1].rule_id.as_deref(), Some("duplicate-comment"));
+    }
+}
@@ -2,7 +2,52 @@
 use pyo3::prelude::*;
 
 #[cfg(feature = "python")]
-use crate::types::CommentInfo;
+use crate::types::{AnalysisOptions, AnalysisResult, ApiError, ApiSettings, Cache, CommentInfo, Language, Provider};
+
+#[cfg(feature = "python")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "python")]
+use std::sync::{mpsc, Mutex};
+
+#[cfg(feature = "python")]
+use futures::StreamExt;
+
+// Raised for API failures, all under `unremark.errors.AnalysisError`
+#[cfg(feature = "python")]
+pyo3::create_exception!(unremark.errors, AnalysisError, pyo3::exceptions::PyException, "The analysis could not be completed.");
+#[cfg(feature = "python")]
+pyo3::create_exception!(unremark.errors, RateLimitError, AnalysisError, "The API kept rate limiting the requests.");
+#[cfg(feature = "python")]
+pyo3::create_exception!(unremark.errors, TimeoutError, AnalysisError, "The API did not answer in time.");
+#[cfg(feature = "python")]
+pyo3::create_exception!(unremark.errors, NetworkError, AnalysisError, "The API could not be reached.");
+
+#[cfg(feature = "python")]
+impl From<ApiError> for PyErr {
+    fn from(err: ApiError) -> Self {
+        match err {
+            ApiError::RateLimit(msg) => RateLimitError::new_err(msg),
+            ApiError::Timeout(msg) => TimeoutError::new_err(msg),
+            ApiError::Network(msg) | ApiError::CircuitOpen(msg) => NetworkError::new_err(msg),
+            ApiError::Other(msg) => AnalysisError::new_err(msg),
+        }
+    }
+}
+
+/// The `unremark.errors` submodule, importable with `from unremark.errors import ...`.
+#[cfg(feature = "python")]
+pub fn register_errors(parent: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = parent.py();
+    let errors = PyModule::new(py, "errors")?;
+    errors.add("AnalysisError", py.get_type::<AnalysisError>())?;
+    errors.add("RateLimitError", py.get_type::<RateLimitError>())?;
+    errors.add("TimeoutError", py.get_type::<TimeoutError>())?;
+    errors.add("NetworkError", py.get_type::<NetworkError>())?;
+    parent.add_submodule(&errors)?;
+    py.import("sys")?.getattr("modules")?.set_item("unremark.errors", errors)?;
+    Ok(())
+}
 
 #[cfg(feature = "python")]
 #[pyclass]
@@ -16,6 +61,42 @@ pub struct PyCommentInfo {
     context: String,
     #[pyo3(get)]
     explanation: Option<String>,
+    #[pyo3(get)]
+    rule_id: Option<String>,
+    #[pyo3(get)]
+    severity: Option<String>,
+    #[pyo3(get)]
+    quality_score: Option<u8>,
+}
+
+#[cfg(feature = "python")]
+impl From<CommentInfo> for PyCommentInfo {
+    fn from(comment: CommentInfo) -> Self {
+        Self {
+            text: comment.text,
+            line_number: comment.line_number,
+            context: comment.context,
+            explanation: comment.explanation,
+            rule_id: comment.rule_id,
+            severity: comment.severity.map(|severity| severity.to_string()),
+            quality_score: comment.quality_score,
+        }
+    }
+}
+
+#[cfg(feature = "python")]
+impl From<PyCommentInfo> for CommentInfo {
+    fn from(comment: PyCommentInfo) -> Self {
+        Self {
+            text: comment.text,
+            line_number: comment.line_number,
+            context: comment.context,
+            explanation: comment.explanation,
+            rule_id: comment.rule_id,
+            quality_score: comment.quality_score,
+            ..Default::default()
+        }
+    }
 }
 
 #[cfg(feature = "python")]
@@ -24,7 +105,7 @@ impl PyCommentInfo {
     #[new]
     #[pyo3(signature = (text, line_number, context, explanation=None))]
     fn new(text: String, line_number: usize, context: String, explanation: Option<String>) -> Self {
-        Self { text, line_number, context, explanation }
+        Self { text, line_number, context, explanation, rule_id: None, severity: None, quality_score: None }
     }
 
     fn __repr__(&self) -> String {
@@ -43,29 +124,319 @@ impl PyCommentInfo {
     }
 }
 
+/// Settings for the analysis functions, instead of environment variables.
 #[cfg(feature = "python")]
-#[pyfunction]
-pub fn py_analyze_comments(comments: Vec<PyCommentInfo>) -> PyResult<Vec<PyCommentInfo>> {
-    let rust_comments = comments.into_iter()
-        .map(|c| CommentInfo {
-            text: c.text,
-            line_number: c.line_number,
-            context: c.context,
-            explanation: c.explanation,
+#[pyclass(name = "Config")]
+#[derive(Clone)]
+pub struct PyConfig {
+    /// Falls back to `OPENAI_API_KEY`
+    #[pyo3(get, set)]
+    api_key: Option<String>,
+    /// `openai`, `openrouter`, or `proxy` for an unremark proxy server
+    #[pyo3(get, set)]
+    provider: String,
+    #[pyo3(get, set)]
+    model: String,
+    /// Base URL of the provider's API, when not the default one
+    #[pyo3(get, set)]
+    endpoint: Option<String>,
+    /// Most API requests in flight at once, unlimited when None
+    #[pyo3(get, set)]
+    concurrency: Option<usize>,
+    /// Only run the local rules, never call the API
+    #[pyo3(get, set)]
+    offline: bool,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl PyConfig {
+    #[new]
+    #[pyo3(signature = (api_key=None, provider="openai".to_string(), model=None, endpoint=None, concurrency=None, offline=false))]
+    fn new(
+        api_key: Option<String>,
+        provider: String,
+        model: Option<String>,
+        endpoint: Option<String>,
+        concurrency: Option<usize>,
+        offline: bool,
+    ) -> PyResult<Self> {
+        let model = model.unwrap_or_else(|| {
+            provider.parse::<Provider>().map_or(crate::constants::OPENAI_MODEL, |provider| provider.default_model()).to_string()
+        });
+        let config = Self {
+            api_key,
+            provider,
+            model,
+            endpoint,
+            concurrency,
+            offline,
+        };
+        config.api_settings()?;
+        Ok(config)
+    }
+
+    fn __repr__(&self) -> String {
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| "None".to_string());
+        format!(
+            "Config(api_key={}, provider='{}', model='{}', endpoint={}, concurrency={}, offline={})",
+            or_none(self.api_key.as_ref().map(|_| "'***'".to_string())),
+            self.provider,
+            self.model,
+            or_none(self.endpoint.as_ref().map(|endpoint| format!("'{}'", endpoint))),
+            or_none(self.concurrency.map(|concurrency| concurrency.to_string())),
+            if self.offline { "True" } else { "False" }
+        )
+    }
+}
+
+#[cfg(feature = "python")]
+impl PyConfig {
+    fn api_settings(&self) -> PyResult<ApiSettings> {
+        let provider: Provider = self.provider.parse().map_err(pyo3::exceptions::PyValueError::new_err)?;
+        Ok(ApiSettings {
+            api_key: self.api_key.clone(),
+            provider,
+            model: self.model.clone(),
+            endpoint: self.endpoint.clone(),
+            concurrency: self.concurrency,
+            ..Default::default()
         })
-        .collect();
-
-    let redundant_comments = tokio::runtime::Runtime::new()
-        .unwrap()
-        .block_on(crate::analysis::analyze_comments(rust_comments))
-        .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e.to_string()))?;
-
-    Ok(redundant_comments.into_iter()
-        .map(|c| PyCommentInfo {
-            text: c.text,
-            line_number: c.line_number,
-            context: c.context,
-            explanation: c.explanation,
+    }
+
+    fn analysis_options(config: Option<&Self>, offline: bool) -> PyResult<AnalysisOptions> {
+        Ok(AnalysisOptions {
+            offline: offline || config.is_some_and(|config| config.offline),
+            api: config.map(Self::api_settings).transpose()?.unwrap_or_default(),
+            ..Default::default()
         })
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(signature = (comments, config=None))]
+pub fn py_analyze_comments(
+    py: Python<'_>,
+    comments: Vec<PyCommentInfo>,
+    config: Option<PyConfig>,
+) -> PyResult<Vec<PyCommentInfo>> {
+    let rust_comments = comments.into_iter().map(CommentInfo::from).collect();
+    let api = config.as_ref().map(PyConfig::api_settings).transpose()?.unwrap_or_default();
+
+    // Other Python threads keep running while the requests are out
+    let redundant_comments = py.allow_threads(|| {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(crate::analysis::try_analyze_comments(rust_comments, &api))
+    })?;
+
+    Ok(redundant_comments.into_iter().map(PyCommentInfo::from).collect())
+}
+
+/// The findings for one file, as returned by `analyze_file` and `analyze_directory`.
+#[cfg(feature = "python")]
+#[pyclass(name = "AnalysisResult")]
+#[derive(Clone)]
+pub struct PyAnalysisResult {
+    #[pyo3(get)]
+    path: String,
+    #[pyo3(get)]
+    redundant_comments: Vec<PyCommentInfo>,
+    #[pyo3(get)]
+    flagged_comments: Vec<PyCommentInfo>,
+    #[pyo3(get)]
+    errors: Vec<String>,
+    #[pyo3(get)]
+    warnings: Vec<String>,
+}
+
+#[cfg(feature = "python")]
+impl From<AnalysisResult> for PyAnalysisResult {
+    fn from(result: AnalysisResult) -> Self {
+        Self {
+            path: result.path.display().to_string(),
+            redundant_comments: result.redundant_comments.into_iter().map(PyCommentInfo::from).collect(),
+            flagged_comments: result.flagged_comments.into_iter().map(PyCommentInfo::from).collect(),
+            errors: result.errors,
+            warnings: result.warnings,
+        }
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl PyAnalysisResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "AnalysisResult(path='{}', redundant_comments={}, flagged_comments={}, errors={})",
+            self.path,
+            self.redundant_comments.len(),
+            self.flagged_comments.len(),
+            self.errors.len()
+        )
+    }
+}
+
+/// The supported source files under `root`, skipping what `.gitignore` does
+/// and anything matching one of the `ignore` globs.
+#[cfg(feature = "python")]
+fn collect_source_files(root: &Path, ignore: &[String]) -> PyResult<Vec<PathBuf>> {
+    let mut overrides = ignore::overrides::OverrideBuilder::new(root);
+    for pattern in ignore {
+        overrides.add(&format!("!{}", pattern))
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("Invalid ignore pattern {}: {}", pattern, e)))?;
+    }
+    let overrides = overrides.build()
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+
+    Ok(ignore::WalkBuilder::new(root)
+        .overrides(overrides)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+        .map(|entry| entry.into_path())
+        .filter(|path| Language::of_file(path).is_some())
         .collect())
-}
\ No newline at end of file
+}
+
+/// `options` analyzing at most `jobs` files at once, when given.
+#[cfg(feature = "python")]
+fn with_jobs(options: AnalysisOptions, jobs: Option<usize>) -> AnalysisOptions {
+    AnalysisOptions {
+        file_concurrency: jobs.unwrap_or(options.file_concurrency),
+        ..options
+    }
+}
+
+/// Analyzes the files with the on-disk cache, like the CLI does. Runs
+/// without the GIL, so other Python threads aren't held up meanwhile.
+#[cfg(feature = "python")]
+fn analyze_paths(py: Python<'_>, paths: &[PathBuf], fix: bool, options: &AnalysisOptions) -> Vec<PyAnalysisResult> {
+    py.allow_threads(|| {
+        let cache = parking_lot::RwLock::new(Cache::load());
+        let results: Vec<_> = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(
+                futures::stream::iter(paths.iter().map(|path| crate::analysis::analyze_file_with_options(path, fix, &cache, options)))
+                    .buffered(options.file_concurrency.max(1))
+                    .collect(),
+            );
+        cache.read().save();
+
+        results.into_iter().map(PyAnalysisResult::from).collect()
+    })
+}
+
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "analyze_file", signature = (path, fix=false, offline=false, config=None))]
+pub fn py_analyze_file(
+    py: Python<'_>,
+    path: PathBuf,
+    fix: bool,
+    offline: bool,
+    config: Option<PyConfig>,
+) -> PyResult<PyAnalysisResult> {
+    if !path.is_file() {
+        return Err(pyo3::exceptions::PyFileNotFoundError::new_err(format!("No such file: {}", path.display())));
+    }
+    let options = PyConfig::analysis_options(config.as_ref(), offline)?;
+    Ok(analyze_paths(py, &[path], fix, &options).remove(0))
+}
+
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "analyze_directory", signature = (path, ignore=vec![], fix=false, offline=false, config=None, jobs=None))]
+pub fn py_analyze_directory(
+    py: Python<'_>,
+    path: PathBuf,
+    ignore: Vec<String>,
+    fix: bool,
+    offline: bool,
+    config: Option<PyConfig>,
+    jobs: Option<usize>,
+) -> PyResult<Vec<PyAnalysisResult>> {
+    if !path.is_dir() {
+        return Err(pyo3::exceptions::PyNotADirectoryError::new_err(format!("Not a directory: {}", path.display())));
+    }
+    let options = with_jobs(PyConfig::analysis_options(config.as_ref(), offline)?, jobs);
+    let paths = py.allow_threads(|| collect_source_files(&path, &ignore))?;
+    Ok(analyze_paths(py, &paths, fix, &options))
+}
+
+/// Yields each file's `AnalysisResult` as soon as it's analyzed, from
+/// `iter_analyze_directory`. The analysis runs on a background thread.
+#[cfg(feature = "python")]
+#[pyclass(name = "DirectoryAnalysis")]
+pub struct PyDirectoryAnalysis {
+    receiver: Mutex<mpsc::Receiver<AnalysisResult>>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl PyDirectoryAnalysis {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> Option<PyAnalysisResult> {
+        // Waiting on the background thread mustn't block other Python threads
+        py.allow_threads(|| self.receiver.lock().unwrap().recv().ok().map(PyAnalysisResult::from))
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "iter_analyze_directory", signature = (path, ignore=vec![], fix=false, offline=false, config=None, jobs=None))]
+pub fn py_iter_analyze_directory(
+    py: Python<'_>,
+    path: PathBuf,
+    ignore: Vec<String>,
+    fix: bool,
+    offline: bool,
+    config: Option<PyConfig>,
+    jobs: Option<usize>,
+) -> PyResult<PyDirectoryAnalysis> {
+    if !path.is_dir() {
+        return Err(pyo3::exceptions::PyNotADirectoryError::new_err(format!("Not a directory: {}", path.display())));
+    }
+    let options = with_jobs(PyConfig::analysis_options(config.as_ref(), offline)?, jobs);
+    let paths = py.allow_threads(|| collect_source_files(&path, &ignore))?;
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let cache = parking_lot::RwLock::new(Cache::load());
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut results = std::pin::pin!(crate::analysis::analyze_files(&paths, fix, &cache, &options));
+            while let Some(result) = results.next().await {
+                // The iterator was dropped, nobody is waiting for the rest
+                if sender.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+        cache.read().save();
+    });
+
+    Ok(PyDirectoryAnalysis { receiver: Mutex::new(receiver) })
+}
+
+/// Removes the comments from `source`, the way `fix=True` does.
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "remove_redundant_comments")]
+pub fn py_remove_redundant_comments(source: &str, comments: Vec<PyCommentInfo>) -> String {
+    let comments: Vec<CommentInfo> = comments.into_iter().map(CommentInfo::from).collect();
+    crate::utils::remove_redundant_comments(source, &comments)
+}
+
+/// A unified diff of `path` that removes the comments, to preview a fix or
+/// post it as a suggestion. Empty when there is nothing to remove.
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "generate_patch")]
+pub fn py_generate_patch(path: PathBuf, source: &str, comments: Vec<PyCommentInfo>) -> String {
+    let comments: Vec<CommentInfo> = comments.into_iter().map(CommentInfo::from).collect();
+    crate::patch::generate_patch(&path, source, &comments)
+}
@@ -0,0 +1,161 @@
+use crate::types::CommentInfo;
+use tracing::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+pub(crate) const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BlameLine {
+    pub commit: String,
+    pub author: String,
+    pub author_time: u64,
+}
+
+/// When a comment was last modified, according to git blame.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CommentBlame {
+    pub commit: String,
+    pub author: String,
+    pub age_days: u64,
+}
+
+/// Who last changed each line of the file as it is on disk, keyed by line
+/// number, or `None` outside a git repository. Lines not committed yet are
+/// blamed on the all-zero commit, as `git blame` does.
+pub(crate) fn blame_file(path: &Path) -> Option<HashMap<usize, BlameLine>> {
+    let path = path.canonicalize().ok()?;
+    let blamed = git2::Repository::discover(&path).and_then(|repository| {
+        let workdir = repository.workdir()
+            .and_then(|workdir| workdir.canonicalize().ok())
+            .ok_or_else(|| git2::Error::from_str("bare repository"))?;
+        let relative = path.strip_prefix(&workdir).map_err(|_| git2::Error::from_str("outside the work tree"))?;
+        let contents = std::fs::read(&path).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+        let committed = repository.blame_file(relative, None)?;
+        let blame = committed.blame_buffer(&contents)?;
+
+        let now = now_seconds();
+        let mut lines = HashMap::new();
+        for hunk in blame.iter() {
+            let commit = hunk.final_commit_id();
+            let signature = hunk.final_signature().filter(|_| !commit.is_zero());
+            let line = BlameLine {
+                commit: commit.to_string(),
+                author: signature.as_ref()
+                    .map_or_else(|| "Not Committed Yet".to_string(), |signature| String::from_utf8_lossy(signature.name_bytes()).into_owned()),
+                author_time: signature.map_or(now, |signature| signature.when().seconds().max(0) as u64),
+            };
+            let start = hunk.final_start_line();
+            for number in start..start + hunk.lines_in_hunk() {
+                lines.insert(number, line.clone());
+            }
+        }
+        Ok(lines)
+    });
+
+    match blamed {
+        Ok(lines) => Some(lines),
+        Err(e) => {
+            debug!("git blame failed for {}: {}", path.display(), e.message());
+            None
+        }
+    }
+}
+
+pub(crate) fn now_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Annotates each comment with the most recent commit that touched one of its lines.
+fn annotate(comments: &mut [CommentInfo], blame: &HashMap<usize, BlameLine>, now: u64) {
+    for comment in comments {
+        let last_line = comment.line_number + comment.text.lines().count().saturating_sub(1);
+        comment.blame = (comment.line_number..=last_line)
+            .filter_map(|number| blame.get(&number))
+            .max_by_key(|line| line.author_time)
+            .map(|line| CommentBlame {
+                commit: line.commit.clone(),
+                author: line.author.clone(),
+                age_days: now.saturating_sub(line.author_time) / SECONDS_PER_DAY,
+            });
+    }
+}
+
+/// Adds `git blame` commit, author and age to the comments of a file. Files
+/// outside a git repository are left as they are.
+pub fn annotate_blame(path: &Path, comments: &mut [CommentInfo]) {
+    if comments.is_empty() {
+        return;
+    }
+    if let Some(blame) = blame_file(path) {
+        annotate(comments, &blame, now_seconds());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit_file(dir: &Path, name: &str, contents: &str) -> git2::Oid {
+        std::fs::write(dir.join(name), contents).unwrap();
+        let repository = git2::Repository::open(dir).unwrap();
+        let mut index = repository.index().unwrap();
+        index.add_path(Path::new(name)).unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::new("Ada Lovelace", "ada@example.com", &git2::Time::new(1700000000, 0)).unwrap();
+        repository.commit(Some("HEAD"), &signature, &signature, "Add file", &tree, &[]).unwrap()
+    }
+
+    #[test]
+    fn test_blame_file() {
+        let dir = tempfile::tempdir().unwrap();
+        git2::Repository::init(dir.path()).unwrap();
+        let commit = commit_file(dir.path(), "main rs.rs", "fn main() {\n    // TODO: remove\n}\n");
+        std::fs::write(dir.path().join("main rs.rs"), "fn main() {\n    // TODO: remove\n    run();\n}\n").unwrap();
+
+        let blame = blame_file(&dir.path().join("main rs.rs")).unwrap();
+
+        assert_eq!(blame.len(), 4);
+        assert_eq!(blame[&2], BlameLine {
+            commit: commit.to_string(),
+            author: "Ada Lovelace".to_string(),
+            author_time: 1700000000,
+        });
+        assert_eq!(blame[&3].commit, "0".repeat(40));
+        assert_eq!(blame[&3].author, "Not Committed Yet");
+        assert_eq!(blame[&4].commit, commit.to_string());
+    }
+
+    #[test]
+    fn test_blame_file_outside_a_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {}\n").unwrap();
+
+        assert!(blame_file(&dir.path().join("main.rs")).is_none());
+    }
+
+    #[test]
+    fn test_annotate_uses_latest_line_of_comment() {
+        let line = |author: &str, author_time| BlameLine { commit: "4b8e1f0c".to_string(), author: author.to_string(), author_time };
+        let blame = HashMap::from([
+            (1, line("Ada Lovelace", 1700000000)),
+            (2, line("Not Committed Yet", 1800000000)),
+        ]);
+        let mut comments = vec![
+            CommentInfo { text: "// a\n// b".to_string(), line_number: 2, ..Default::default() },
+            CommentInfo { text: "// c".to_string(), line_number: 9, ..Default::default() },
+        ];
+        annotate(&mut comments, &blame, 1800000000 + 3 * SECONDS_PER_DAY);
+
+        let blamed = comments[0].blame.as_ref().unwrap();
+        assert_eq!(blamed.author, "Not Committed Yet");
+        assert_eq!(blamed.age_days, 3);
+        assert!(comments[1].blame.is_none());
+    }
+}
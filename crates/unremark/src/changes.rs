@@ -0,0 +1,230 @@
+use crate::types::CommentInfo;
+use tracing::debug;
+use regex::Regex;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Lines added or modified since a git revision, keyed by canonical file path.
+#[derive(Debug, Clone, Default)]
+pub struct ChangedLines {
+    files: HashMap<PathBuf, Vec<RangeInclusive<usize>>>,
+}
+
+fn hunk_header_regex() -> &'static Regex {
+    static HUNK_HEADER: OnceLock<Regex> = OnceLock::new();
+    HUNK_HEADER.get_or_init(|| Regex::new(r"^@@ -\d+(?:,\d+)? \+(\d+)(?:,(\d+))? @@").unwrap())
+}
+
+pub(crate) fn git(directory: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C").arg(directory)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Failed to run git: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The top-level directory of the git repository containing `path`.
+pub fn repository_root(path: &Path) -> Result<PathBuf, String> {
+    let path = path.canonicalize().map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let directory = if path.is_file() { path.parent().unwrap_or(&path) } else { path.as_path() };
+    let root = PathBuf::from(git(directory, &["rev-parse", "--show-toplevel"])?.trim());
+    Ok(root.canonicalize().unwrap_or(root))
+}
+
+/// Undoes git's C-style quoting of paths with special characters, such as
+/// `"b/caf\303\251.rs"`. Unquoted paths come back unchanged.
+fn unquote_path(path: &str) -> String {
+    let Some(quoted) = path.strip_prefix('"').and_then(|path| path.strip_suffix('"')) else {
+        return path.to_string();
+    };
+
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut rest = quoted.bytes().peekable();
+    while let Some(byte) = rest.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        match rest.next() {
+            Some(digit @ b'0'..=b'7') => {
+                let mut value = digit - b'0';
+                for _ in 0..2 {
+                    if let Some(digit) = rest.next_if(|digit| (b'0'..=b'7').contains(digit)) {
+                        value = value.wrapping_mul(8) + (digit - b'0');
+                    }
+                }
+                bytes.push(value);
+            }
+            Some(b'a') => bytes.push(0x07),
+            Some(b'b') => bytes.push(0x08),
+            Some(b'f') => bytes.push(0x0c),
+            Some(b'n') => bytes.push(b'\n'),
+            Some(b'r') => bytes.push(b'\r'),
+            Some(b't') => bytes.push(b'\t'),
+            Some(b'v') => bytes.push(0x0b),
+            Some(other) => bytes.push(other),
+            None => bytes.push(b'\\'),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl ChangedLines {
+    /// Collects the lines under `path` that changed in the working tree since
+    /// `revision`, staged or not. Untracked files count as changed throughout.
+    pub fn since(path: &Path, revision: &str) -> Result<Self, String> {
+        let root = repository_root(path)?;
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let pathspec = path.to_string_lossy();
+
+        let diff = git(&root, &[
+            "-c", "core.quotePath=false", "diff", "--unified=0", "--no-color", "--no-ext-diff", "--src-prefix=a/", "--dst-prefix=b/",
+            revision, "--", &pathspec,
+        ])?;
+        let mut changed = Self::from_diff(&diff, &root);
+
+        let untracked = git(&root, &["-c", "core.quotePath=false", "ls-files", "--others", "--exclude-standard", "--full-name", "--", &pathspec])?;
+        for file in untracked.lines().filter(|line| !line.is_empty()) {
+            changed.files.insert(root.join(unquote_path(file)), vec![1..=usize::MAX]);
+        }
+
+        debug!("{} files changed since {}", changed.files.len(), revision);
+        Ok(changed)
+    }
+
+    /// Reads the line ranges of each hunk's new side out of a unified diff,
+    /// with paths relative to `root`. With `--unified=0` these are exactly the
+    /// added and modified lines.
+    pub fn from_diff(diff: &str, root: &Path) -> Self {
+        let mut files: HashMap<PathBuf, Vec<RangeInclusive<usize>>> = HashMap::new();
+        let mut current = None;
+
+        for line in diff.lines() {
+            if let Some(target) = line.strip_prefix("+++ ") {
+                let target = unquote_path(target.strip_suffix('\t').unwrap_or(target));
+                current = target.strip_prefix("b/").map(|file| root.join(file));
+            } else if let (Some(file), Some(hunk)) = (&current, hunk_header_regex().captures(line)) {
+                let start: usize = hunk[1].parse().unwrap_or(0);
+                let count: usize = hunk.get(2).map_or(1, |count| count.as_str().parse().unwrap_or(0));
+                // Pure deletions leave nothing behind to comment on
+                if count > 0 {
+                    files.entry(file.clone()).or_default().push(start..=start + count - 1);
+                }
+            }
+        }
+
+        Self { files }
+    }
+
+    pub fn contains_file(&self, path: &Path) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.files.contains_key(&path)
+    }
+
+    pub fn touches_line(&self, path: &Path, line_number: usize) -> bool {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.files.get(&path).is_some_and(|ranges| ranges.iter().any(|range| range.contains(&line_number)))
+    }
+
+    /// Whether the lines `first..=last` all fall within a single hunk. `path` must be canonical.
+    pub fn contains_lines(&self, path: &Path, first: usize, last: usize) -> bool {
+        self.files.get(path).is_some_and(|ranges| {
+            ranges.iter().any(|range| range.contains(&first) && range.contains(&last))
+        })
+    }
+
+    /// Whether any line of the comment was added or modified. `path` must be canonical.
+    pub fn touches(&self, path: &Path, comment: &CommentInfo) -> bool {
+        let last_line = comment.line_number + comment.text.lines().count().saturating_sub(1);
+        self.files.get(path).is_some_and(|ranges| {
+            ranges.iter().any(|range| *range.start() <= last_line && comment.line_number <= *range.end())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -3,0 +4,2 @@ fn main() {
++    // Added comment
++    let x = 1;
+@@ -10 +12 @@ fn other() {
+-    // old
++    // new
+@@ -20,3 +21,0 @@
+-    gone();
+diff --git a/old.py b/old.py
+deleted file mode 100644
+--- a/old.py
++++ /dev/null
+@@ -1,2 +0,0 @@
+-# removed
+-pass
+";
+
+    fn comment(text: &str, line_number: usize) -> CommentInfo {
+        CommentInfo { text: text.to_string(), line_number, ..Default::default() }
+    }
+
+    #[test]
+    fn test_parse_diff_collects_added_and_modified_lines() {
+        let root = Path::new("/repo");
+        let changed = ChangedLines::from_diff(DIFF, root);
+        let lib = root.join("src/lib.rs");
+
+        assert_eq!(changed.files.len(), 1);
+        assert_eq!(changed.files[&lib], vec![4..=5, 12..=12]);
+        assert!(changed.touches(&lib, &comment("// Added comment", 4)));
+        assert!(changed.touches(&lib, &comment("// new", 12)));
+        assert!(!changed.touches(&lib, &comment("// untouched", 8)));
+        assert!(!changed.touches(&root.join("other.rs"), &comment("// new", 12)));
+    }
+
+    #[test]
+    fn test_multi_line_comment_touched_by_any_line() {
+        let root = Path::new("/repo");
+        let changed = ChangedLines::from_diff(DIFF, root);
+        let block = comment("/*\n * Header\n * edited\n */", 10);
+
+        assert!(changed.touches(&root.join("src/lib.rs"), &block));
+    }
+
+    #[test]
+    fn test_parse_diff_unquotes_paths() {
+        let root = Path::new("/repo");
+        let diff = "\
+--- \"a/caf\\303\\251.rs\"
++++ \"b/caf\\303\\251.rs\"
+@@ -1 +1 @@
++// changed
+--- a/my file.rs\t
++++ b/my file.rs\t
+@@ -2 +2 @@
++// changed
+";
+        let changed = ChangedLines::from_diff(diff, root);
+
+        assert_eq!(changed.files[&root.join("café.rs")], vec![1..=1]);
+        assert_eq!(changed.files[&root.join("my file.rs")], vec![2..=2]);
+    }
+
+    #[test]
+    fn test_unquote_path() {
+        assert_eq!(unquote_path("b/src/lib.rs"), "b/src/lib.rs");
+        assert_eq!(unquote_path(r#""b/tab\there \"quoted\" back\\slash""#), "b/tab\there \"quoted\" back\\slash");
+    }
+}
@@ -0,0 +1,128 @@
+use crate::config::CircuitBreakerConfig;
+use crate::types::ApiError;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+
+/// Stops sending requests to the API once `max_failures` of them in a row
+/// failed, even after their retries, so a backend that's down costs one
+/// error per comment rather than a round of retries for each. It stays open
+/// for the rest of the run, or until the cooldown has passed, when requests
+/// are let through again and a single failure opens it back up. Clones of
+/// the `ApiSettings` holding it share it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    skipped: usize,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self { config: config.clone(), state: Mutex::default() }
+    }
+
+    /// A closed breaker with the same settings, for a new run.
+    pub(crate) fn restarted(&self) -> Self {
+        Self::new(&self.config)
+    }
+
+    /// Errs, counting the request as skipped, while the breaker is open.
+    pub(crate) fn check(&self) -> Result<(), ApiError> {
+        self.check_at(Instant::now())
+    }
+
+    fn check_at(&self, now: Instant) -> Result<(), ApiError> {
+        let mut state = self.state.lock();
+        let Some(opened_at) = state.opened_at else {
+            return Ok(());
+        };
+        let cooled_down = self.config.cooldown_seconds
+            .is_some_and(|cooldown| now.saturating_duration_since(opened_at) >= Duration::from_secs(cooldown));
+        if cooled_down {
+            state.opened_at = None;
+            return Ok(());
+        }
+        state.skipped += 1;
+        Err(ApiError::CircuitOpen(format!("the API failed {} times in a row", self.config.max_failures)))
+    }
+
+    /// Records how a request that was let through went.
+    pub(crate) fn record<T>(&self, result: &Result<T, ApiError>) {
+        self.record_at(result.is_ok(), Instant::now())
+    }
+
+    fn record_at(&self, succeeded: bool, now: Instant) {
+        let mut state = self.state.lock();
+        if succeeded {
+            state.consecutive_failures = 0;
+            return;
+        }
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.config.max_failures && state.opened_at.is_none() {
+            state.opened_at = Some(now);
+        }
+    }
+
+    /// How many requests weren't sent because the breaker was open.
+    pub fn skipped(&self) -> usize {
+        self.state.lock().skipped
+    }
+
+    /// How many failures in a row open the breaker.
+    pub fn max_failures(&self) -> u32 {
+        self.config.max_failures
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(&CircuitBreakerConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_after_consecutive_failures() {
+        let start = Instant::now();
+        let breaker = CircuitBreaker::new(&CircuitBreakerConfig { max_failures: 3, cooldown_seconds: None });
+        breaker.record_at(false, start);
+        breaker.record_at(false, start);
+        // A success in between starts the count over
+        breaker.record_at(true, start);
+        breaker.record_at(false, start);
+        breaker.record_at(false, start);
+        assert!(breaker.check_at(start).is_ok());
+        breaker.record_at(false, start);
+        let err = breaker.check_at(start + Duration::from_secs(3600)).unwrap_err();
+        assert_eq!(err.to_string(), "Circuit breaker open: the API failed 3 times in a row");
+        assert!(breaker.check_at(start).is_err());
+        assert_eq!(breaker.skipped(), 2);
+        assert_eq!(breaker.restarted().skipped(), 0);
+    }
+
+    #[test]
+    fn test_breaker_lets_requests_through_after_the_cooldown() {
+        let start = Instant::now();
+        let breaker = CircuitBreaker::new(&CircuitBreakerConfig { max_failures: 2, cooldown_seconds: Some(30) });
+        breaker.record_at(false, start);
+        breaker.record_at(false, start);
+        assert!(breaker.check_at(start + Duration::from_secs(29)).is_err());
+        assert!(breaker.check_at(start + Duration::from_secs(30)).is_ok());
+        // Still failing, so one more failure opens it again
+        breaker.record_at(false, start + Duration::from_secs(31));
+        assert!(breaker.check_at(start + Duration::from_secs(32)).is_err());
+        assert!(breaker.check_at(start + Duration::from_secs(61)).is_ok());
+        breaker.record_at(true, start + Duration::from_secs(62));
+        breaker.record_at(false, start + Duration::from_secs(63));
+        assert!(breaker.check_at(start + Duration::from_secs(64)).is_ok());
+    }
+}
@@ -0,0 +1,148 @@
+use crate::config::ClassifierConfig;
+use crate::heuristics::CommentSite;
+use crate::types::CommentInfo;
+use tracing::{debug, warn};
+use std::sync::Arc;
+
+#[cfg(feature = "classifier")]
+mod onnx;
+#[cfg(feature = "classifier")]
+mod wordpiece;
+
+#[cfg(feature = "classifier")]
+pub use onnx::OnnxClassifier;
+#[cfg(feature = "classifier")]
+pub use wordpiece::WordPiece;
+
+/// Rule ID for comments the local classifier is confident are redundant.
+pub const CLASSIFIER_RULE: &str = "local-classifier";
+
+/// A local model scoring how likely a comment is to be redundant.
+pub trait RedundancyScorer: Send + Sync + std::fmt::Debug {
+    /// Probability (0.0-1.0) that the comment is redundant next to `code`.
+    fn score(&self, comment: &str, code: &str) -> Result<f32, String>;
+}
+
+/// Sits between the heuristics and the LLM: confident verdicts are settled
+/// locally, only the uncertain middle is sent to the API.
+#[derive(Debug, Clone)]
+pub struct LocalClassifier {
+    pub scorer: Arc<dyn RedundancyScorer>,
+    /// Scores at or above this are reported as redundant
+    pub redundant_threshold: f32,
+    /// Scores at or below this are kept without asking the API
+    pub useful_threshold: f32,
+}
+
+impl LocalClassifier {
+    /// Returns the comments scored redundant and the ones left undecided.
+    pub fn classify(&self, source: &str, comments: Vec<CommentInfo>) -> (Vec<CommentInfo>, Vec<CommentInfo>) {
+        let lines: Vec<&str> = source.lines().collect();
+        let mut redundant = Vec::new();
+        let mut undecided = Vec::new();
+
+        for comment in comments {
+            let site = CommentSite::new(&comment, &lines);
+            let code = site.described_code().unwrap_or(&comment.context);
+            match self.scorer.score(&comment.text, code) {
+                Ok(score) if score >= self.redundant_threshold => {
+                    let mut comment = comment;
+                    comment.rule_id = Some(CLASSIFIER_RULE.to_string());
+                    comment.confidence = Some(score);
+                    comment.explanation = Some(format!("Local classifier scored the comment {:.2} likely redundant", score));
+                    redundant.push(comment);
+                }
+                Ok(score) if score <= self.useful_threshold => {
+                    debug!("Local classifier kept comment on line {} (score {:.2})", comment.line_number, score);
+                }
+                Ok(_) => undecided.push(comment),
+                Err(e) => {
+                    warn!("Local classifier failed on line {}: {}", comment.line_number, e);
+                    undecided.push(comment);
+                }
+            }
+        }
+
+        (redundant, undecided)
+    }
+}
+
+/// Loads the classifier configured in `unremark.toml`, if any.
+#[cfg(feature = "classifier")]
+pub fn load_classifier(config: Option<&ClassifierConfig>) -> Result<Option<LocalClassifier>, String> {
+    let Some(config) = config else {
+        return Ok(None);
+    };
+    let scorer = OnnxClassifier::load(&config.model, &config.vocab, config.max_length)?;
+    Ok(Some(LocalClassifier {
+        scorer: Arc::new(scorer),
+        redundant_threshold: config.redundant_threshold,
+        useful_threshold: config.useful_threshold,
+    }))
+}
+
+/// Loads the classifier configured in `unremark.toml`, if any.
+#[cfg(not(feature = "classifier"))]
+pub fn load_classifier(config: Option<&ClassifierConfig>) -> Result<Option<LocalClassifier>, String> {
+    match config {
+        Some(config) => Err(format!(
+            "cannot load classifier {}: unremark was built without the `classifier` feature",
+            config.model.display()
+        )),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scores by how many words the comment shares with the code.
+    #[derive(Debug)]
+    struct OverlapScorer;
+
+    impl RedundancyScorer for OverlapScorer {
+        fn score(&self, comment: &str, code: &str) -> Result<f32, String> {
+            let words: Vec<&str> = comment.split_whitespace().skip(1).collect();
+            let shared = words.iter().filter(|word| code.contains(*word)).count();
+            Ok(shared as f32 / words.len().max(1) as f32)
+        }
+    }
+
+    #[test]
+    fn test_classify_settles_confident_scores_locally() {
+        let classifier = LocalClassifier {
+            scorer: Arc::new(OverlapScorer),
+            redundant_threshold: 0.9,
+            useful_threshold: 0.1,
+        };
+        let source = "// total\nlet total = 1;\n// legacy quirk of billing\nlet x = 2;\n// x and billing\nlet x = 3;";
+        let comment = |text: &str, line_number| CommentInfo { text: text.to_string(), line_number, ..Default::default() };
+        let comments = vec![
+            comment("// total", 1),
+            comment("// legacy quirk of billing", 3),
+            comment("// x and billing", 5),
+        ];
+
+        let (redundant, undecided) = classifier.classify(source, comments);
+        assert_eq!(redundant.len(), 1);
+        assert_eq!(redundant[0].rule_id.as_deref(), Some(CLASSIFIER_RULE));
+        assert_eq!(redundant[0].confidence, Some(1.0));
+        assert_eq!(undecided.len(), 1);
+        assert_eq!(undecided[0].line_number, 5);
+    }
+
+    #[cfg(not(feature = "classifier"))]
+    #[test]
+    fn test_loading_classifier_requires_feature() {
+        let config = ClassifierConfig {
+            model: "model.onnx".into(),
+            vocab: "vocab.txt".into(),
+            redundant_threshold: 0.9,
+            useful_threshold: 0.1,
+            max_length: 128,
+        };
+        assert!(load_classifier(None).unwrap().is_none());
+        assert!(load_classifier(Some(&config)).unwrap_err().contains("classifier"));
+    }
+}
@@ -0,0 +1,89 @@
+use super::wordpiece::WordPiece;
+use super::RedundancyScorer;
+use std::path::Path;
+use std::sync::Arc;
+use tract_onnx::prelude::*;
+
+/// A small text-classification model (e.g. a distilled BERT) exported to ONNX.
+///
+/// The model takes `input_ids` and, optionally, `attention_mask` as `i64`
+/// tensors of shape `[1, max_length]`, fed `[CLS] comment [SEP] code [SEP]`,
+/// and returns either two logits (useful, redundant) or a single logit.
+pub struct OnnxClassifier {
+    model: Arc<TypedRunnableModel>,
+    tokenizer: WordPiece,
+    max_length: usize,
+    inputs: usize,
+}
+
+impl std::fmt::Debug for OnnxClassifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OnnxClassifier").field("max_length", &self.max_length).finish_non_exhaustive()
+    }
+}
+
+impl OnnxClassifier {
+    pub fn load(model_path: &Path, vocab_path: &Path, max_length: usize) -> Result<Self, String> {
+        let tokenizer = WordPiece::load(vocab_path)?;
+        let invalid = |e: TractError| format!("Invalid model {}: {}", model_path.display(), e);
+
+        let mut model = tract_onnx::onnx().model_for_path(model_path).map_err(invalid)?;
+        let inputs = model.input_outlets().map_err(invalid)?.len().min(2);
+        for input in 0..inputs {
+            model = model
+                .with_input_fact(input, i64::fact([1, max_length]).into())
+                .map_err(invalid)?;
+        }
+        let model = model.into_optimized()
+            .and_then(|model| model.into_runnable())
+            .map_err(invalid)?;
+
+        Ok(Self { model, tokenizer, max_length, inputs })
+    }
+}
+
+fn redundant_probability(logits: &[f32]) -> Option<f32> {
+    match logits {
+        [logit] => Some(1.0 / (1.0 + (-logit).exp())),
+        [useful, redundant, ..] => {
+            let max = useful.max(*redundant);
+            let (useful, redundant) = ((useful - max).exp(), (redundant - max).exp());
+            Some(redundant / (useful + redundant))
+        }
+        [] => None,
+    }
+}
+
+impl RedundancyScorer for OnnxClassifier {
+    fn score(&self, comment: &str, code: &str) -> Result<f32, String> {
+        let (ids, mask) = self.tokenizer.encode_pair(comment, code, self.max_length);
+        let tensor = |values: Vec<i64>| -> Result<TValue, String> {
+            tract_ndarray::Array2::from_shape_vec((1, self.max_length), values)
+                .map(|array| Tensor::from(array).into())
+                .map_err(|e| e.to_string())
+        };
+
+        let mut inputs = tvec![tensor(ids)?];
+        if self.inputs > 1 {
+            inputs.push(tensor(mask)?);
+        }
+        let outputs = self.model.run(inputs).map_err(|e| e.to_string())?;
+        let logits = outputs[0].to_plain_array_view::<f32>().map_err(|e| e.to_string())?;
+        let logits: Vec<f32> = logits.iter().copied().collect();
+
+        redundant_probability(&logits).ok_or_else(|| "model returned no logits".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redundant_probability() {
+        assert_eq!(redundant_probability(&[0.0]), Some(0.5));
+        assert!(redundant_probability(&[-4.0, 4.0]).unwrap() > 0.99);
+        assert!(redundant_probability(&[4.0, -4.0]).unwrap() < 0.01);
+        assert_eq!(redundant_probability(&[]), None);
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+const MAX_WORD_CHARS: usize = 100;
+
+/// BERT-style WordPiece tokenizer over a `vocab.txt` (one token per line,
+/// the line number being its ID), as used by distilled classifiers.
+#[derive(Debug, Clone)]
+pub struct WordPiece {
+    vocab: HashMap<String, i64>,
+    unknown: i64,
+    classify: i64,
+    separator: i64,
+    padding: i64,
+}
+
+impl WordPiece {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read vocabulary {}: {}", path.display(), e))?;
+        Self::from_tokens(contents.lines())
+    }
+
+    pub fn from_tokens<'a>(tokens: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let vocab: HashMap<String, i64> = tokens.into_iter()
+            .enumerate()
+            .map(|(id, token)| (token.trim_end().to_string(), id as i64))
+            .collect();
+        let special = |token: &str| vocab.get(token).copied().ok_or(format!("vocabulary has no {} token", token));
+
+        Ok(Self {
+            unknown: special("[UNK]")?,
+            classify: special("[CLS]")?,
+            separator: special("[SEP]")?,
+            padding: special("[PAD]")?,
+            vocab,
+        })
+    }
+
+    /// Lowercases, splits on whitespace and punctuation, then greedily matches
+    /// the longest known sub-words.
+    pub fn tokenize(&self, text: &str) -> Vec<i64> {
+        let lowercase = text.to_lowercase();
+        let mut words = Vec::new();
+        for chunk in lowercase.split_whitespace() {
+            let mut start = 0;
+            for (index, c) in chunk.char_indices() {
+                if c.is_ascii_punctuation() {
+                    if start < index {
+                        words.push(&chunk[start..index]);
+                    }
+                    words.push(&chunk[index..index + c.len_utf8()]);
+                    start = index + c.len_utf8();
+                }
+            }
+            if start < chunk.len() {
+                words.push(&chunk[start..]);
+            }
+        }
+
+        words.into_iter().flat_map(|word| self.word_pieces(word)).collect()
+    }
+
+    fn word_pieces(&self, word: &str) -> Vec<i64> {
+        if word.chars().count() > MAX_WORD_CHARS {
+            return vec![self.unknown];
+        }
+
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while start < word.len() {
+            let piece = word[start..].char_indices()
+                .map(|(offset, c)| start + offset + c.len_utf8())
+                .rev()
+                .find_map(|end| {
+                    let candidate = if start == 0 { word[..end].to_string() } else { format!("##{}", &word[start..end]) };
+                    self.vocab.get(&candidate).map(|id| (*id, end))
+                });
+            match piece {
+                Some((id, end)) => {
+                    pieces.push(id);
+                    start = end;
+                }
+                None => return vec![self.unknown],
+            }
+        }
+        pieces
+    }
+
+    /// Encodes `[CLS] first [SEP] second [SEP]`, truncating `second` (then
+    /// `first`) to fit and padding to `max_length`. Returns the input IDs and
+    /// the attention mask.
+    pub fn encode_pair(&self, first: &str, second: &str, max_length: usize) -> (Vec<i64>, Vec<i64>) {
+        let mut first = self.tokenize(first);
+        let mut second = self.tokenize(second);
+        let budget = max_length.saturating_sub(3);
+        second.truncate(budget.saturating_sub(first.len()));
+        first.truncate(budget);
+
+        let mut ids = vec![self.classify];
+        ids.extend(first);
+        ids.push(self.separator);
+        ids.extend(second);
+        ids.push(self.separator);
+        ids.truncate(max_length);
+
+        let mut mask = vec![1; ids.len()];
+        ids.resize(max_length, self.padding);
+        mask.resize(max_length, 0);
+        (ids, mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenizer() -> WordPiece {
+        WordPiece::from_tokens(["[PAD]", "[UNK]", "[CLS]", "[SEP]", "add", "##s", "two", "numbers", "(", ")", "a"]).unwrap()
+    }
+
+    #[test]
+    fn test_tokenize_splits_sub_words_and_punctuation() {
+        let tokenizer = tokenizer();
+        assert_eq!(tokenizer.tokenize("Adds two numbers"), vec![4, 5, 6, 7]);
+        assert_eq!(tokenizer.tokenize("add(a)"), vec![4, 8, 10, 9]);
+        assert_eq!(tokenizer.tokenize("zebra"), vec![1]);
+    }
+
+    #[test]
+    fn test_encode_pair_truncates_and_pads() {
+        let tokenizer = tokenizer();
+        let (ids, mask) = tokenizer.encode_pair("adds", "two numbers", 8);
+        assert_eq!(ids, vec![2, 4, 5, 3, 6, 7, 3, 0]);
+        assert_eq!(mask, vec![1, 1, 1, 1, 1, 1, 1, 0]);
+
+        let (ids, _) = tokenizer.encode_pair("adds two numbers", "add a", 6);
+        assert_eq!(ids, vec![2, 4, 5, 6, 3, 3]);
+    }
+
+    #[test]
+    fn test_requires_special_tokens() {
+        assert!(WordPiece::from_tokens(["[PAD]", "hello"]).is_err());
+    }
+}
@@ -1,49 +1,78 @@
 use crate::types::{CommentInfo, Language};
-use crate::utils::find_context;
+use crate::grammars::queried_comments;
+use crate::license::mark_license_headers;
+use crate::utils::{find_context, ContextBudget};
 
-use log::debug;
+use tracing::debug;
 use tree_sitter::{Node, Parser};
 
 pub fn detect_comments(source_code: &str, language: Language) -> Result<Vec<CommentInfo>, String> {
+    detect_comments_with(source_code, language, &ContextBudget::default())
+}
+
+/// Like `detect_comments`, with the code kept as each comment's context
+/// bounded by `budget`.
+pub fn detect_comments_with(source_code: &str, language: Language, budget: &ContextBudget) -> Result<Vec<CommentInfo>, String> {
     let mut parser = Parser::new();
     if parser.set_language(&language.get_tree_sitter_language()).is_err() {
         return Ok(vec![]);
     }
 
-    let tree = match parser.parse(source_code, None) {
+    let tree = match crate::sfc::parse(&mut parser, source_code, language) {
         Some(tree) => tree,
         None => return Ok(vec![]),
     };
 
-    if tree.root_node().has_error() {
-        return Ok(vec![]);
-    }
+    let root = tree.root_node();
+    // Loaded grammars may say which of their nodes are comments with a query
+    let queried = queried_comments(language, root, source_code);
+    let is_comment = |node: Node| match &queried {
+        Some(ids) => ids.contains(&node.id()),
+        None => is_comment_node(node),
+    };
+    let mut comments = collect_comments(root, source_code, budget, &is_comment);
+
+    let mut cursor = root.walk();
+    let first_code_line = root.children(&mut cursor)
+        .find(|child| !is_comment(*child))
+        .map(|child| child.start_position().row + 1);
+    mark_license_headers(&mut comments, first_code_line);
 
-    Ok(collect_comments(tree.root_node(), source_code))
+    Ok(comments)
 }
 
-fn collect_comments(node: Node, code: &str) -> Vec<CommentInfo> {
+fn collect_comments(node: Node, code: &str, budget: &ContextBudget, is_comment: &dyn Fn(Node) -> bool) -> Vec<CommentInfo> {
     let mut comments = Vec::new();
     let mut cursor = node.walk();
 
     for child in node.children(&mut cursor) {
         debug!("Node kind: {} at line {}", child.kind(), child.start_position().row + 1);
-        if child.kind().contains("comment") {
-            let comment_text = code[child.byte_range()].trim().to_string();
+        // Python docstrings are documentation, and strings rather than comments
+        if is_docstring(child) {
+            debug!("Skipping docstring on line {}", child.start_position().row + 1);
+            continue;
+        }
+        if is_comment(child) {
+            let raw_text = &code[child.byte_range()];
+            let comment_text = raw_text.trim().to_string();
+            let start = child.start_byte() + (raw_text.len() - raw_text.trim_start().len());
+            let byte_range = start..start + comment_text.len();
             
             // Skip documentation comments for all supported languages
             if comment_text.starts_with("///") ||    // Rust doc comments
                comment_text.starts_with("//!") ||    // Rust module doc comments
                comment_text.starts_with("/**") ||    // JSDoc/TSDoc/Rust block doc comments
-               comment_text.starts_with("/*!")  ||   // Rust module block doc comments
-               comment_text.starts_with("\"\"\"") || // Python docstrings
-               comment_text.starts_with("'''") {     // Python docstrings (alternative)
+               comment_text.starts_with("/*!") {     // Rust module block doc comments
                 debug!("Skipping doc comment: {}", comment_text);
                 continue;
             }
+            // A script's shebang line is parsed as a comment by some grammars
+            if child.start_byte() == 0 && comment_text.starts_with("#!") {
+                continue;
+            }
 
             let line_number = child.start_position().row + 1;
-            let context = find_context(child, code);
+            let context = find_context(child, code, budget);
 
             debug!("Found comment: '{}' of type '{}' on line {}", 
                 comment_text, child.kind(), line_number
@@ -53,10 +82,185 @@ fn collect_comments(node: Node, code: &str) -> Vec<CommentInfo> {
                 text: comment_text,
                 line_number,
                 context,
-                explanation: Some("This comment may be redundant".to_string())
+                explanation: Some("This comment may be redundant".to_string()),
+                byte_range: Some(byte_range),
+                ..Default::default()
             });
+            // Some grammars (Dart's) nest the comment's body as another comment
+            continue;
         }
-        comments.extend(collect_comments(child, code));
+        comments.extend(collect_comments(child, code, budget, is_comment));
     }
     comments
-} 
\ No newline at end of file
+}
+
+/// Whether the node is a comment. Grammars mostly call them `comment`,
+/// `line_comment` or `block_comment`; SQL's block comments are `marginalia`,
+/// and its `COMMENT ON` statements aren't comments at all.
+pub(crate) fn is_comment_node(node: Node) -> bool {
+    match node.kind() {
+        "marginalia" => true,
+        "comment_statement" | "keyword_comment" => false,
+        kind => kind.contains("comment"),
+    }
+}
+
+/// Whether the node is an Elixir `@moduledoc`, `@doc` or `@typedoc`
+/// attribute, documentation written as a string rather than a comment.
+pub(crate) fn is_doc_attribute(node: Node, code: &str) -> bool {
+    node.kind() == "unary_operator"
+        && code[node.byte_range()].starts_with('@')
+        && node.child_by_field_name("operand")
+            .filter(|operand| operand.kind() == "call")
+            .and_then(|call| call.child_by_field_name("target"))
+            .and_then(|target| target.utf8_text(code.as_bytes()).ok())
+            .is_some_and(|name| matches!(name, "moduledoc" | "doc" | "typedoc"))
+}
+
+/// Whether the node is a Python docstring: a string standing alone as the
+/// first statement of a module, class or function.
+pub(crate) fn is_docstring(node: Node) -> bool {
+    let is_lone_string = node.kind() == "expression_statement"
+        && node.named_child_count() == 1
+        && node.named_child(0).is_some_and(|child| matches!(child.kind(), "string" | "concatenated_string"));
+    if !is_lone_string {
+        return false;
+    }
+    let Some(parent) = node.parent() else {
+        return false;
+    };
+    let is_first = parent.named_child(0).is_some_and(|first| first.id() == node.id());
+    let documents = match parent.kind() {
+        "module" => true,
+        "block" => parent.parent().is_some_and(|owner| matches!(owner.kind(), "function_definition" | "class_definition")),
+        _ => false,
+    };
+    is_first && documents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_python_docstrings_are_found_by_position() {
+        let source = r#""Module docs."
+
+class Greeter:
+    '''Says hello.'''
+
+    def greet(self):
+        """
+        # Not a comment
+        """
+        "not a docstring"
+        # A comment
+        return "hello"
+"#;
+        let comments = detect_comments(source, Language::Python).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["# A comment"]);
+
+        let mut parser = Parser::new();
+        parser.set_language(&Language::Python.get_tree_sitter_language()).unwrap();
+        let tree = parser.parse(source, None).unwrap();
+        let mut docstrings = Vec::new();
+        let mut stack = vec![tree.root_node()];
+        while let Some(node) = stack.pop() {
+            if is_docstring(node) {
+                docstrings.push(node.start_position().row + 1);
+            }
+            let mut cursor = node.walk();
+            stack.extend(node.children(&mut cursor));
+        }
+        docstrings.sort();
+        assert_eq!(docstrings, vec![1, 4, 7]);
+    }
+
+    #[test]
+    fn test_c_and_cpp_comments() {
+        let source = "/// Counts the items.\n/** Also docs. */\nint count(int n) {\n    // increment i\n    for (int i = 0; i < n; i++) {} /* loop */\n    return n;\n}\n";
+        for language in [Language::C, Language::Cpp] {
+            let comments = detect_comments(source, language).unwrap();
+            let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+            assert_eq!(texts, vec!["// increment i", "/* loop */"], "{}", language);
+        }
+
+        let source = "namespace app {\n// Widget class\nclass Widget {\n  int size_; // the size\n};\n}\n";
+        let comments = detect_comments(source, Language::Cpp).unwrap();
+        assert_eq!(comments.iter().map(|comment| comment.line_number).collect::<Vec<_>>(), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_sql_comments() {
+        let source = "-- add column\nALTER TABLE users ADD COLUMN email TEXT; /* nullable */\nCOMMENT ON TABLE users IS 'People';\n";
+        let comments = detect_comments(source, Language::Sql).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["-- add column", "/* nullable */"]);
+    }
+
+    #[test]
+    fn test_hcl_comments() {
+        let source = "# Region\nvariable \"region\" {\n  // the default\n  default = \"us-east-1\" /* for now */\n}\n";
+        let comments = detect_comments(source, Language::Hcl).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["# Region", "// the default", "/* for now */"]);
+    }
+
+    #[test]
+    fn test_dart_comments_without_dartdoc() {
+        let source = "/// A counter.\nclass Counter {\n  /** The count. */\n  int count = 0; // start at zero\n\n  // Increment the count\n  void increment() => count++;\n}\n";
+        let comments = detect_comments(source, Language::Dart).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["// start at zero", "// Increment the count"]);
+    }
+
+    #[test]
+    fn test_elixir_doc_attributes_are_not_comments() {
+        let source = "defmodule Math do\n  @moduledoc \"\"\"\n  # Math helpers\n  \"\"\"\n\n  @doc \"Adds.\"\n  def add(a, b), do: a + b # sum\n\n  defp sub(a, b) do\n    # Subtract\n    a - b\n  end\nend\n";
+        let comments = detect_comments(source, Language::Elixir).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["# sum", "# Subtract"]);
+    }
+
+    #[test]
+    fn test_zig_doc_comments_are_kept() {
+        let source = "//! Math helpers.\nconst std = @import(\"std\");\n\n/// Adds two numbers.\npub fn add(a: i32, b: i32) i32 {\n    // Return the sum\n    return a + b; // sum\n}\n";
+        let comments = detect_comments(source, Language::Zig).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["// Return the sum", "// sum"]);
+    }
+
+    #[test]
+    fn test_haskell_haddock_comments_are_kept() {
+        let source = "-- | Adds two numbers.\nadd :: Int -> Int -> Int\nadd x y = x + y -- sum\n\n{-| Multiplies. -}\nmul :: Int -> Int -> Int\n{- block -}\nmul x y = x * y\n";
+        let comments = detect_comments(source, Language::Haskell).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["-- sum", "{- block -}"]);
+    }
+
+    #[test]
+    fn test_single_file_component_scripts() {
+        let source = "<template>\n  <!-- Greeting -->\n  <a href=\"https://example.com\">{{ msg }}</a>\n</template>\n\n<script setup lang=\"ts\">\n// Set the message\nconst msg: string = \"hi\" // hi\n</script>\n";
+        let comments = detect_comments(source, Language::Vue).unwrap();
+        let found: Vec<(usize, &str)> = comments.iter().map(|comment| (comment.line_number, comment.text.as_str())).collect();
+        assert_eq!(found, vec![(7, "// Set the message"), (8, "// hi")]);
+
+        let source = "<script context=\"module\">\n  /* Shared */\n  export const shared = 1;\n</script>\n\n<script>\n  // Count clicks\n  let count = 0;\n</script>\n\n<button on:click={() => count++}>{count}</button>\n";
+        let comments = detect_comments(source, Language::Svelte).unwrap();
+        let found: Vec<(usize, &str)> = comments.iter().map(|comment| (comment.line_number, comment.text.as_str())).collect();
+        assert_eq!(found, vec![(2, "/* Shared */"), (7, "// Count clicks")]);
+    }
+
+    #[test]
+    fn test_bash_comments_without_the_shebang() {
+        let source = "#!/bin/bash\n# Deploy the app\nset -e\necho \"# not a comment\" # say it\ncat <<EOF\n# heredoc\nEOF\n";
+        let comments = detect_comments(source, Language::Bash).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["# Deploy the app", "# say it"]);
+
+        let comments = detect_comments("#!/usr/bin/env python3\n# Print one\nprint(1)\n", Language::Python).unwrap();
+        let texts: Vec<&str> = comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(texts, vec!["# Print one"]);
+    }
+}
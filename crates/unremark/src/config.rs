@@ -0,0 +1,1111 @@
+use crate::constants::{
+    DEFAULT_MAX_CONSECUTIVE_FAILURES, DEFAULT_MAX_IN_FLIGHT, DEFAULT_OPENROUTER_REFERER, DEFAULT_OPENROUTER_TITLE, DEFAULT_PROXY_ENDPOINT, OPENAI_BASE_URL_ENV, OPENAI_MAX_OUTPUT_TOKENS,
+};
+use crate::heuristics::{default_severity, finding_severity, BUILTIN_RULE_IDS, REGEX_RULE};
+use crate::services::proxy::Compression;
+use crate::api::RetryPolicy;
+use crate::trust::TrustedWorkspaces;
+use crate::types::{ApiSettings, CommentInfo, Provider, Severity};
+use crate::utils::ContextBudget;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// File names looked up, in order, in the analyzed directory and its ancestors.
+pub const CONFIG_FILE_NAMES: &[&str] = &["unremark.toml", ".unremark.toml"];
+
+/// Project settings read from `unremark.toml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub license: LicenseConfig,
+    pub rules: RulesConfig,
+    pub plugins: Vec<PluginConfig>,
+    pub grammars: Vec<GrammarConfig>,
+    /// Extensions to analyze as a language, by its name, e.g. `mjs = "javascript"`
+    pub extensions: HashMap<String, String>,
+    pub classifier: Option<ClassifierConfig>,
+    pub openai: OpenAiConfig,
+    pub openrouter: OpenRouterConfig,
+    pub proxy: ProxyConfig,
+    pub network: NetworkConfig,
+    pub generation: GenerationConfig,
+    pub credentials: CredentialsConfig,
+    pub examples: Vec<PromptExample>,
+    pub rate_limit: RateLimitConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub retry: RetryConfig,
+    /// How much code is sent along with each comment
+    pub context: ContextBudget,
+}
+
+/// Where API keys come from, instead of a plaintext `.env` file. A project
+/// can name its own variable or command for the OpenAI key, taking
+/// precedence over `OPENAI_API_KEY`; past that (and `UNREMARK_PROXY_TOKEN`
+/// for the proxy), the helper is run as `<helper> get openai` (or `proxy`)
+/// and prints the key, and the keychain is read from the `unremark`
+/// service's entry for the provider (needs the `keychain` feature). The
+/// command and the helper are only run from a trusted workspace's config:
+///
+/// ```toml
+/// [credentials]
+/// api_key_env = "MY_TEAM_OPENAI_KEY"
+/// api_key_cmd = "op read op://team/openai/credential"
+/// helper = "unremark-credential-pass"
+/// keychain = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CredentialsConfig {
+    /// Variable holding this project's OpenAI key
+    pub api_key_env: Option<String>,
+    /// Command printing this project's OpenAI key
+    pub api_key_cmd: Option<String>,
+    /// Command printing the key, tried before the keychain
+    pub helper: Option<String>,
+    /// Look the key up in the macOS Keychain, Windows Credential Manager or Secret Service
+    pub keychain: bool,
+}
+
+/// How the model writes its answers, sent with every request to the API or
+/// the proxy. Deterministic by default; a higher `temperature` or lower
+/// `top_p` makes verdicts more varied. With `logprobs`, the confidence of
+/// each verdict is the probability the API gives its answer rather than the
+/// one the model states (the proxy doesn't support it):
+///
+/// ```toml
+/// [generation]
+/// max_tokens = 500
+/// temperature = 0.0
+/// top_p = 1.0
+/// logprobs = true
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GenerationConfig {
+    /// Longest answer, in tokens
+    pub max_tokens: u32,
+    pub temperature: f32,
+    pub top_p: f32,
+    #[serde(skip_serializing)]
+    pub logprobs: bool,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self { max_tokens: 500, temperature: 0.0, top_p: 1.0, logprobs: false }
+    }
+}
+
+impl GenerationConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Checks the settings are within what `provider` accepts. The proxy
+    /// caps answers to its own model's limit, so only OpenAI's is checked.
+    pub fn validate(&self, provider: Provider) -> Result<(), String> {
+        if self.max_tokens == 0 {
+            return Err("generation max_tokens must be at least 1".to_string());
+        }
+        if provider == Provider::OpenAi && self.max_tokens > OPENAI_MAX_OUTPUT_TOKENS {
+            return Err(format!("generation max_tokens must be at most {} for OpenAI", OPENAI_MAX_OUTPUT_TOKENS));
+        }
+        if !(0.0..=2.0).contains(&self.temperature) {
+            return Err("generation temperature must be between 0 and 2".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.top_p) {
+            return Err("generation top_p must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// TLS and proxy settings for every outbound request, for networks that
+/// intercept TLS or only allow traffic through a corporate proxy. The CA
+/// bundle and proxy are only taken from a trusted workspace's config:
+///
+/// ```toml
+/// [network]
+/// ca_bundle = "certs/corporate-ca.pem"
+/// client_cert = "certs/client.pem"
+/// client_key = "certs/client.key"
+/// https_proxy = "http://proxy.example.com:3128"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// PEM file of extra CA certificates to trust, relative to the config file
+    pub ca_bundle: Option<PathBuf>,
+    /// PEM certificate chain presented for mutual TLS, relative to the config file
+    pub client_cert: Option<PathBuf>,
+    /// PKCS#8 PEM private key of `client_cert`, relative to the config file
+    pub client_key: Option<PathBuf>,
+    /// Proxy for all requests, instead of `HTTPS_PROXY`/`HTTP_PROXY`
+    pub https_proxy: Option<String>,
+}
+
+impl NetworkConfig {
+    /// This section with `UNREMARK_CA_BUNDLE`, `UNREMARK_CLIENT_CERT`,
+    /// `UNREMARK_CLIENT_KEY` and `UNREMARK_HTTPS_PROXY` taking precedence.
+    pub fn with_env(&self) -> Self {
+        let path = |name: &str| std::env::var_os(name).map(PathBuf::from);
+        Self {
+            ca_bundle: path("UNREMARK_CA_BUNDLE").or_else(|| self.ca_bundle.clone()),
+            client_cert: path("UNREMARK_CLIENT_CERT").or_else(|| self.client_cert.clone()),
+            client_key: path("UNREMARK_CLIENT_KEY").or_else(|| self.client_key.clone()),
+            https_proxy: std::env::var("UNREMARK_HTTPS_PROXY").ok().or_else(|| self.https_proxy.clone()),
+        }
+    }
+}
+
+/// The model OpenAI's API is asked, and where it's reached, for
+/// OpenAI-compatible servers such as vLLM, LM Studio or LiteLLM. The key is
+/// sent to the base URL, so it's only taken from a trusted workspace's config:
+///
+/// ```toml
+/// [openai]
+/// model = "gpt-4o-mini"
+/// base_url = "http://localhost:8000/v1"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    /// The author's fine-tuned model when unset
+    pub model: Option<String>,
+    /// The URL `/chat/completions` and `/models` are appended to
+    pub base_url: Option<String>,
+}
+
+impl OpenAiConfig {
+    /// The base URL to use: `explicit` (a CLI flag or editor setting) first,
+    /// then `OPENAI_BASE_URL` and this file's setting. OpenAI's own API is
+    /// used when none is set.
+    pub fn resolve_base_url(&self, explicit: Option<&str>) -> Option<String> {
+        self.resolve_base_url_with(explicit, std::env::var(OPENAI_BASE_URL_ENV).ok())
+    }
+
+    fn resolve_base_url_with(&self, explicit: Option<&str>, environment: Option<String>) -> Option<String> {
+        explicit.map(str::to_string)
+            .or(environment)
+            .or_else(|| self.base_url.clone())
+            .filter(|url| !url.trim().is_empty())
+    }
+}
+
+/// Which models `--provider openrouter` asks, and how its requests are
+/// attributed on openrouter.ai. The fallback models are tried in order when
+/// the first is down, rate-limited or refuses the prompt:
+///
+/// ```toml
+/// [openrouter]
+/// model = "meta-llama/llama-3.1-8b-instruct"
+/// fallback_models = ["mistralai/mistral-small", "openai/gpt-4o-mini"]
+/// referer = "https://example.com/our-project"
+/// title = "Our project"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenRouterConfig {
+    /// `openai/gpt-4o-mini` when unset
+    pub model: Option<String>,
+    pub fallback_models: Vec<String>,
+    /// Sent as `HTTP-Referer`, unremark's repository when unset
+    pub referer: Option<String>,
+    /// Sent as `X-Title`, `unremark` when unset
+    pub title: Option<String>,
+}
+
+impl OpenRouterConfig {
+    /// The `HTTP-Referer` and `X-Title` headers of every request.
+    pub fn headers(&self) -> [(&'static str, &str); 2] {
+        [
+            ("HTTP-Referer", self.referer.as_deref().unwrap_or(DEFAULT_OPENROUTER_REFERER)),
+            ("X-Title", self.title.as_deref().unwrap_or(DEFAULT_OPENROUTER_TITLE)),
+        ]
+    }
+}
+
+/// How fast requests go to the API, across every file of a run. Unset
+/// limits aren't enforced, and at most 16 requests are in flight unless
+/// configured otherwise:
+///
+/// ```toml
+/// [rate_limit]
+/// requests_per_minute = 500
+/// tokens_per_minute = 200000
+/// max_in_flight = 8
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub requests_per_minute: Option<u32>,
+    /// Counting each request's prompt and the most tokens it may answer with
+    pub tokens_per_minute: Option<u32>,
+    pub max_in_flight: Option<usize>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self { requests_per_minute: None, tokens_per_minute: None, max_in_flight: Some(DEFAULT_MAX_IN_FLIGHT) }
+    }
+}
+
+/// How failed requests to the API and the proxy are retried: up to
+/// `max_attempts` tries, the first retry after `initial_delay_ms`, each one
+/// after that `multiplier` times later, give or take the `jitter` fraction.
+/// A `Retry-After` from the backend takes precedence:
+///
+/// ```toml
+/// [retry]
+/// max_attempts = 5
+/// initial_delay_ms = 500
+/// multiplier = 3.0
+/// jitter = 0.5
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub multiplier: f64,
+    pub jitter: f64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        let policy = RetryPolicy::default();
+        Self {
+            max_attempts: policy.max_attempts,
+            initial_delay_ms: policy.initial_delay.as_millis() as u64,
+            multiplier: policy.multiplier,
+            jitter: policy.jitter,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            initial_delay: std::time::Duration::from_millis(self.initial_delay_ms),
+            multiplier: self.multiplier,
+            jitter: self.jitter,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("retry max_attempts must be at least 1".to_string());
+        }
+        if !(1.0..).contains(&self.multiplier) {
+            return Err(format!("retry multiplier must be at least 1, got {}", self.multiplier));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!("retry jitter must be between 0 and 1, got {}", self.jitter));
+        }
+        Ok(())
+    }
+}
+
+/// When to stop sending requests to a failing API. After `max_failures`
+/// failed requests in a row (5 by default), the rest of the run's comments
+/// are skipped, or only until `cooldown_seconds` have passed when set:
+///
+/// ```toml
+/// [circuit_breaker]
+/// max_failures = 3
+/// cooldown_seconds = 60
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    pub max_failures: u32,
+    pub cooldown_seconds: Option<u64>,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self { max_failures: DEFAULT_MAX_CONSECUTIVE_FAILURES, cooldown_seconds: None }
+    }
+}
+
+/// Where the unremark proxy server is reached (from a trusted workspace's
+/// config only, as it gets the proxy token), and how requests to it are
+/// compressed (`gzip` by default, `zstd` or `none`):
+///
+/// ```toml
+/// [proxy]
+/// endpoint = "https://unremark.example.com"
+/// compression = "zstd"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub endpoint: Option<String>,
+    pub compression: Compression,
+}
+
+impl ProxyConfig {
+    /// The endpoint to use: `explicit` (a CLI flag or editor setting) first,
+    /// then `PROXY_ENDPOINT`, this file's setting and finally the default.
+    pub fn resolve_endpoint(&self, explicit: Option<&str>) -> String {
+        self.resolve_endpoint_with(explicit, std::env::var("PROXY_ENDPOINT").ok())
+    }
+
+    fn resolve_endpoint_with(&self, explicit: Option<&str>, environment: Option<String>) -> String {
+        explicit.map(str::to_string)
+            .or(environment)
+            .or_else(|| self.endpoint.clone())
+            .unwrap_or_else(|| DEFAULT_PROXY_ENDPOINT.to_string())
+    }
+}
+
+/// A local ONNX model scoring comments before they reach the API (needs the
+/// `classifier` feature):
+///
+/// ```toml
+/// [classifier]
+/// model = "models/comment-classifier.onnx"
+/// vocab = "models/vocab.txt"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClassifierConfig {
+    /// Path to the model, relative to the config file
+    pub model: PathBuf,
+    /// Path to the model's WordPiece vocabulary, relative to the config file
+    pub vocab: PathBuf,
+    /// Scores at or above this are reported as redundant without asking the API
+    #[serde(default = "default_redundant_threshold")]
+    pub redundant_threshold: f32,
+    /// Scores at or below this are kept without asking the API
+    #[serde(default = "default_useful_threshold")]
+    pub useful_threshold: f32,
+    /// Tokens fed to the model per comment and code pair
+    #[serde(default = "default_max_length")]
+    pub max_length: usize,
+}
+
+fn default_redundant_threshold() -> f32 {
+    0.9
+}
+
+fn default_useful_threshold() -> f32 {
+    0.1
+}
+
+fn default_max_length() -> usize {
+    128
+}
+
+/// A labeled comment shown to the model before each redundancy question, as
+/// an earlier question with its answer, to teach it a codebase's conventions.
+/// Not sent to the proxy, which writes its own prompts:
+///
+/// ```toml
+/// [[examples]]
+/// comment = "// SAFETY: the buffer outlives the handle"
+/// context = "unsafe { register(handle, buf.as_ptr()) }"
+/// redundant = false
+/// explanation = "Safety comments are required by our review policy"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PromptExample {
+    pub comment: String,
+    /// Code the comment sits next to
+    #[serde(default)]
+    pub context: String,
+    pub redundant: bool,
+    pub explanation: Option<String>,
+}
+
+/// A WASM comment policy to load (needs the `plugins` feature):
+///
+/// ```toml
+/// [[plugins]]
+/// path = "policies/house_style.wasm"
+/// id = "house-style"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    /// Path to the module, relative to the config file
+    pub path: PathBuf,
+    /// Rule ID of the plugin's findings, the file stem by default
+    pub id: Option<String>,
+}
+
+impl PluginConfig {
+    pub fn id(&self) -> &str {
+        self.id.as_deref()
+            .or_else(|| self.path.file_stem().and_then(|stem| stem.to_str()))
+            .unwrap_or("plugin")
+    }
+}
+
+/// A tree-sitter grammar to load for a language unremark doesn't support,
+/// compiled to a shared library (needs the `grammars` feature). Its files
+/// are recognized by their extensions, and its comments are the nodes the
+/// query captures, or the nodes whose kind contains `comment` without one.
+/// Only loaded from a trusted workspace's config:
+///
+/// ```toml
+/// [[grammars]]
+/// name = "gleam"
+/// path = "grammars/libtree-sitter-gleam.so"
+/// extensions = ["gleam"]
+/// comment_query = "(comment) @comment"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrammarConfig {
+    /// The language's name, as reported and matched by code fences
+    pub name: String,
+    /// Path to the library, relative to the config file
+    pub path: PathBuf,
+    pub extensions: Vec<String>,
+    /// Function returning the grammar, `tree_sitter_<name>` by default
+    pub symbol: Option<String>,
+    pub comment_query: Option<String>,
+}
+
+impl GrammarConfig {
+    pub fn symbol(&self) -> String {
+        self.symbol.clone().unwrap_or_else(|| format!("tree_sitter_{}", self.name.replace('-', "_")))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LicenseConfig {
+    /// Text every file's license header must contain, e.g.
+    /// `"SPDX-License-Identifier: MIT"`. When unset, any recognized license
+    /// header satisfies the check.
+    pub required_header: Option<String>,
+}
+
+/// Per-rule settings, keyed by rule ID, plus user-defined regex rules:
+///
+/// ```toml
+/// [rules.name-echo]
+/// severity = "info"
+///
+/// [rules.redundant-comment]
+/// enabled = false
+///
+/// [[rules.regex]]
+/// pattern = "^//\\s*auto-generated"
+/// action = "ignore"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RulesConfig {
+    regex: Vec<RegexRule>,
+    #[serde(flatten)]
+    rules: HashMap<String, RuleConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuleConfig {
+    pub enabled: bool,
+    pub severity: Option<Severity>,
+}
+
+impl Default for RuleConfig {
+    fn default() -> Self {
+        Self { enabled: true, severity: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegexAction {
+    /// Leave matching comments out of the analysis entirely
+    Ignore,
+    /// Report matching comments for review
+    Flag,
+}
+
+/// A house convention expressed as a pattern over the comment's text,
+/// delimiters included.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegexRule {
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    pub action: RegexAction,
+    /// Rule ID reported for flagged comments
+    #[serde(default = "default_regex_rule_id")]
+    pub id: String,
+    /// Explanation reported for flagged comments
+    pub message: Option<String>,
+    pub severity: Option<Severity>,
+}
+
+fn default_regex_rule_id() -> String {
+    REGEX_RULE.to_string()
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+impl RulesConfig {
+    pub fn set(&mut self, rule_id: &str, rule: RuleConfig) {
+        self.rules.insert(rule_id.to_string(), rule);
+    }
+
+    pub fn add_regex_rule(&mut self, rule: RegexRule) {
+        self.regex.push(rule);
+    }
+
+    pub fn regex_rules(&self) -> &[RegexRule] {
+        &self.regex
+    }
+
+    pub fn is_enabled(&self, rule_id: &str) -> bool {
+        self.rules.get(rule_id).is_none_or(|rule| rule.enabled)
+    }
+
+    /// Whether a regex rule says to leave this comment alone.
+    pub fn ignores(&self, comment_text: &str) -> bool {
+        self.regex.iter()
+            .any(|rule| rule.action == RegexAction::Ignore && rule.pattern.is_match(comment_text))
+    }
+
+    pub fn severity(&self, rule_id: &str) -> Severity {
+        self.configured_severity(rule_id).unwrap_or_else(|| default_severity(rule_id))
+    }
+
+    /// Severity of a finding: the one configured for its rule, or else the
+    /// rule's default, a level lower when the finding has low confidence.
+    pub fn finding_severity(&self, comment: &CommentInfo) -> Option<Severity> {
+        let rule_id = comment.rule_id.as_deref()?;
+        Some(self.configured_severity(rule_id).unwrap_or_else(|| finding_severity(rule_id, comment.confidence)))
+    }
+
+    fn configured_severity(&self, rule_id: &str) -> Option<Severity> {
+        self.rules.get(rule_id)
+            .and_then(|rule| rule.severity)
+            .or_else(|| self.regex.iter().find(|rule| rule.id == rule_id).and_then(|rule| rule.severity))
+    }
+
+    /// Identifies the settings that change which comments get reported, to
+    /// tell cached results computed under different settings apart.
+    pub fn cache_key(&self) -> String {
+        let mut disabled: Vec<&str> = self.rules.iter()
+            .filter(|(_, rule)| !rule.enabled)
+            .map(|(id, _)| id.as_str())
+            .collect();
+        disabled.sort_unstable();
+
+        let regex = self.regex.iter()
+            .map(|rule| format!("{:?}:{}:{}", rule.action, rule.id, rule.pattern.as_str()));
+        disabled.into_iter().map(str::to_string).chain(regex).collect::<Vec<_>>().join(",")
+    }
+
+    fn validate(&self, plugin_ids: &[&str]) -> Result<(), String> {
+        let is_known = |id: &str| {
+            BUILTIN_RULE_IDS.contains(&id) || plugin_ids.contains(&id) || self.regex.iter().any(|rule| rule.id == id)
+        };
+        match self.rules.keys().find(|id| !is_known(id)) {
+            Some(id) => Err(format!("unknown rule '{}', expected one of: {}", id, BUILTIN_RULE_IDS.join(", "))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Config {
+    /// API settings from the `[openai]`, `[openrouter]`, `[network]`, `[proxy]`, `[generation]` and
+    /// `[credentials]` sections, with `OPENAI_BASE_URL` and the `UNREMARK_*` network variables applied.
+    pub fn api_settings(&self) -> ApiSettings {
+        ApiSettings {
+            model: self.openai.model.clone().unwrap_or_else(|| Provider::OpenAi.default_model().to_string()),
+            endpoint: self.openai.resolve_base_url(None),
+            network: self.network.with_env(),
+            compression: self.proxy.compression,
+            generation: self.generation,
+            credentials: self.credentials.clone(),
+            openrouter: self.openrouter.clone(),
+            examples: self.examples.clone(),
+            limiter: std::sync::Arc::new(crate::rate_limit::RateLimiter::new(&self.rate_limit)),
+            breaker: std::sync::Arc::new(crate::circuit_breaker::CircuitBreaker::new(&self.circuit_breaker)),
+            retry: self.retry.policy(),
+            ..Default::default()
+        }
+    }
+
+    /// The API settings for `provider`, with its model and default endpoint.
+    pub fn api_settings_for(&self, provider: Provider) -> ApiSettings {
+        let api = self.api_settings();
+        match provider {
+            Provider::OpenAi => api,
+            Provider::Proxy => ApiSettings { provider, endpoint: None, ..api },
+            Provider::OpenRouter => ApiSettings {
+                provider,
+                endpoint: None,
+                model: self.openrouter.model.clone().unwrap_or_else(|| provider.default_model().to_string()),
+                ..api
+            },
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(contents).map_err(|e| e.to_string())?;
+        let plugin_ids: Vec<&str> = config.plugins.iter().map(PluginConfig::id).collect();
+        config.rules.validate(&plugin_ids)?;
+        // Limits that depend on the provider are checked once it's known
+        config.generation.validate(Provider::Proxy)?;
+        if [config.rate_limit.requests_per_minute, config.rate_limit.tokens_per_minute].contains(&Some(0))
+            || config.rate_limit.max_in_flight == Some(0) {
+            return Err("rate_limit values must be at least 1".to_string());
+        }
+        config.retry.validate()?;
+        if config.context.max_tokens == 0 {
+            return Err("context max_tokens must be at least 1".to_string());
+        }
+        if config.circuit_breaker.max_failures == 0 {
+            return Err("circuit_breaker max_failures must be at least 1".to_string());
+        }
+        if config.examples.iter().any(|example| example.comment.trim().is_empty()) {
+            return Err("examples need a comment".to_string());
+        }
+        if [&config.openai.model, &config.openrouter.model].into_iter().flatten().any(|model| model.trim().is_empty()) {
+            return Err("model must not be empty".to_string());
+        }
+        if let Some(classifier) = &config.classifier {
+            if classifier.useful_threshold > classifier.redundant_threshold {
+                return Err("classifier useful_threshold must not exceed redundant_threshold".to_string());
+            }
+        }
+        Ok(config)
+    }
+
+    /// Loads the config at `path`, see `load_from_path_with`.
+    pub fn load_from_path(path: &Path) -> Result<Self, String> {
+        Self::load_from_path_with(path, &TrustedWorkspaces::load())
+    }
+
+    /// Loads the config at `path`, leaving out the settings only a trusted
+    /// workspace may use unless `trusted` has its directory.
+    pub fn load_from_path_with(path: &Path, trusted: &TrustedWorkspaces) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut config = Self::parse(&contents).map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+
+        let directory = path.parent().unwrap_or(Path::new(""));
+        for plugin in &mut config.plugins {
+            plugin.path = directory.join(&plugin.path);
+        }
+        for grammar in &mut config.grammars {
+            grammar.path = directory.join(&grammar.path);
+        }
+        if let Some(classifier) = &mut config.classifier {
+            classifier.model = directory.join(&classifier.model);
+            classifier.vocab = directory.join(&classifier.vocab);
+        }
+        let network = &mut config.network;
+        for path in [&mut network.ca_bundle, &mut network.client_cert, &mut network.client_key].into_iter().flatten() {
+            *path = directory.join(&*path);
+        }
+
+        let directory = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+        if !trusted.trusts(directory) {
+            let ignored = config.distrust();
+            if !ignored.is_empty() {
+                warn!(
+                    "Ignoring {} in {}: run `unremark trust` to let this workspace use them",
+                    ignored.join(", "),
+                    path.display()
+                );
+            }
+        }
+        Ok(config)
+    }
+
+    /// Leaves out the settings that run commands, load native code or send
+    /// the user's key (and code) to another host, returning their names.
+    fn distrust(&mut self) -> Vec<&'static str> {
+        let mut ignored = vec![];
+        if self.openai.base_url.take().is_some() {
+            ignored.push("openai.base_url");
+        }
+        if self.proxy.endpoint.take().is_some() {
+            ignored.push("proxy.endpoint");
+        }
+        if self.network.https_proxy.take().is_some() {
+            ignored.push("network.https_proxy");
+        }
+        if self.network.ca_bundle.take().is_some() {
+            ignored.push("network.ca_bundle");
+        }
+        if !self.grammars.is_empty() {
+            self.grammars.clear();
+            ignored.push("grammars");
+        }
+        if self.credentials.api_key_cmd.take().is_some() {
+            ignored.push("credentials.api_key_cmd");
+        }
+        if self.credentials.helper.take().is_some() {
+            ignored.push("credentials.helper");
+        }
+        ignored
+    }
+
+    /// Finds the closest config file at or above `start`.
+    pub fn find(start: &Path) -> Option<PathBuf> {
+        let start = start.canonicalize().unwrap_or_else(|_| start.to_path_buf());
+        let directory = if start.is_file() { start.parent()? } else { start.as_path() };
+
+        directory.ancestors()
+            .flat_map(|dir| CONFIG_FILE_NAMES.iter().map(move |name| dir.join(name)))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// Loads the closest config file, falling back to the defaults when there is none.
+    pub fn load(start: &Path) -> Result<Self, String> {
+        match Self::find(start) {
+            Some(path) => Self::load_from_path(&path),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_proxy_endpoint_precedence() {
+        let config = Config::parse("[proxy]\nendpoint = \"https://proxy.example.com\"\n").unwrap();
+        let from_env = Some("http://env:5000".to_string());
+
+        assert_eq!(config.proxy.resolve_endpoint_with(Some("http://flag:5000"), from_env.clone()), "http://flag:5000");
+        assert_eq!(config.proxy.resolve_endpoint_with(None, from_env), "http://env:5000");
+        assert_eq!(config.proxy.resolve_endpoint_with(None, None), "https://proxy.example.com");
+        assert_eq!(ProxyConfig::default().resolve_endpoint_with(None, None), DEFAULT_PROXY_ENDPOINT);
+    }
+
+    #[test]
+    fn test_openai_base_url_precedence() {
+        let config = Config::parse("[openai]\nbase_url = \"http://localhost:1234/v1\"\n").unwrap();
+        let from_env = Some("http://env:8000/v1".to_string());
+
+        assert_eq!(config.openai.resolve_base_url_with(Some("http://flag:8000/v1"), from_env.clone()).as_deref(), Some("http://flag:8000/v1"));
+        assert_eq!(config.openai.resolve_base_url_with(None, from_env).as_deref(), Some("http://env:8000/v1"));
+        assert_eq!(config.openai.resolve_base_url_with(None, None).as_deref(), Some("http://localhost:1234/v1"));
+        assert_eq!(OpenAiConfig::default().resolve_base_url_with(None, Some(String::new())), None);
+    }
+
+    #[test]
+    fn test_openrouter_settings() {
+        let config = Config::parse("[openrouter]\nmodel = \"mistralai/mistral-small\"\ntitle = \"Ours\"\n").unwrap();
+        let api = config.api_settings_for(Provider::OpenRouter);
+        assert_eq!(api.model, "mistralai/mistral-small");
+        assert_eq!(api.base_url(), "https://openrouter.ai/api/v1");
+        assert_eq!(api.openrouter.headers()[1], ("X-Title", "Ours"));
+
+        let api = Config::default().api_settings_for(Provider::OpenRouter);
+        assert_eq!(api.model, "openai/gpt-4o-mini");
+        assert_eq!(api.openrouter.headers()[0].1, "https://github.com/software-trizzey/unremark");
+        assert_eq!(Config::default().api_settings_for(Provider::OpenAi).model, crate::constants::OPENAI_MODEL);
+    }
+
+    #[test]
+    fn test_openai_model() {
+        let config = Config::parse("[openai]\nmodel = \"gpt-4o-mini\"\n").unwrap();
+        assert_eq!(config.api_settings().model, "gpt-4o-mini");
+        assert_eq!(config.api_settings_for(Provider::OpenRouter).model, "openai/gpt-4o-mini");
+        assert!(Config::parse("[openai]\nmodel = \"\"\n").unwrap_err().contains("model must not be empty"));
+    }
+
+    #[test]
+    fn test_prompt_examples() {
+        let config = Config::parse("[[examples]]\ncomment = \"// SAFETY: checked above\"\nredundant = false\n\n[[examples]]\ncomment = \"# increment i\"\ncontext = \"i += 1\"\nredundant = true\nexplanation = \"Repeats the code\"\n").unwrap();
+        let examples = config.api_settings().examples;
+        assert_eq!(examples.len(), 2);
+        assert_eq!((examples[0].context.as_str(), examples[0].explanation.as_deref()), ("", None));
+        assert_eq!(examples[1].explanation.as_deref(), Some("Repeats the code"));
+        assert!(Config::parse("[[examples]]\ncomment = \" \"\nredundant = true\n").unwrap_err().contains("examples need a comment"));
+        assert!(Config::parse("[[examples]]\ncomment = \"// x\"\n").is_err());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        assert_eq!(Config::parse("").unwrap().rate_limit.max_in_flight, Some(16));
+        let config = Config::parse("[rate_limit]\nrequests_per_minute = 500\n").unwrap();
+        assert_eq!(config.rate_limit.requests_per_minute, Some(500));
+        assert_eq!(config.rate_limit.max_in_flight, Some(16));
+        assert!(Config::parse("[rate_limit]\nmax_in_flight = 0\n").unwrap_err().contains("at least 1"));
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        assert_eq!(Config::parse("").unwrap().circuit_breaker, CircuitBreakerConfig { max_failures: 5, cooldown_seconds: None });
+        let config = Config::parse("[circuit_breaker]\ncooldown_seconds = 60\n").unwrap();
+        assert_eq!(config.circuit_breaker.cooldown_seconds, Some(60));
+        assert_eq!(config.api_settings().breaker.max_failures(), 5);
+        assert!(Config::parse("[circuit_breaker]\nmax_failures = 0\n").unwrap_err().contains("at least 1"));
+    }
+
+    #[test]
+    fn test_retry() {
+        assert_eq!(Config::parse("").unwrap().api_settings().retry, RetryPolicy::default());
+        let config = Config::parse("[retry]\nmax_attempts = 5\ninitial_delay_ms = 250\njitter = 0.0\n").unwrap();
+        assert_eq!(config.api_settings().retry, RetryPolicy {
+            max_attempts: 5,
+            initial_delay: std::time::Duration::from_millis(250),
+            multiplier: 2.0,
+            jitter: 0.0,
+        });
+        assert!(Config::parse("[retry]\nmax_attempts = 0\n").unwrap_err().contains("at least 1"));
+        assert!(Config::parse("[retry]\nmultiplier = 0.5\n").unwrap_err().contains("multiplier"));
+        assert!(Config::parse("[retry]\njitter = 1.5\n").unwrap_err().contains("jitter"));
+    }
+
+    #[test]
+    fn test_context_budget() {
+        assert_eq!(Config::parse("").unwrap().context, ContextBudget::default());
+        let config = Config::parse("[context]\nmax_tokens = 256\n").unwrap();
+        assert_eq!(config.context, ContextBudget { max_tokens: 256, ..Default::default() });
+        assert!(Config::parse("[context]\nmax_tokens = 0\n").unwrap_err().contains("at least 1"));
+        assert!(Config::parse("[context]\nbudget = 10\n").is_err());
+    }
+
+    #[test]
+    fn test_proxy_compression() {
+        assert_eq!(Config::parse("").unwrap().proxy.compression, Compression::Gzip);
+        let config = Config::parse("[proxy]\ncompression = \"zstd\"\n").unwrap();
+        assert_eq!(config.api_settings().compression, Compression::Zstd);
+        assert!(Config::parse("[proxy]\ncompression = \"brotli\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_license_section() {
+        let config = Config::parse("[license]\nrequired_header = \"SPDX-License-Identifier: MIT\"\n").unwrap();
+        assert_eq!(config.license.required_header.as_deref(), Some("SPDX-License-Identifier: MIT"));
+
+        assert!(Config::parse("").unwrap().license.required_header.is_none());
+        assert!(Config::parse("[licence]\n").is_err(), "Typos should not be silently ignored");
+    }
+
+    #[test]
+    fn test_parse_rules_section() {
+        let config = Config::parse(concat!(
+            "[rules.redundant-comment]\nenabled = false\n",
+            "[rules.name-echo]\nseverity = \"error\"\n",
+        )).unwrap();
+
+        assert!(!config.rules.is_enabled("redundant-comment"));
+        assert!(config.rules.is_enabled("name-echo"));
+        assert!(config.rules.is_enabled("duplicate-comment"));
+        assert_eq!(config.rules.severity("name-echo"), Severity::Error);
+        assert_eq!(config.rules.severity("empty-comment"), Severity::Warning);
+        assert_eq!(config.rules.severity("duplicate-comment"), Severity::Info);
+        assert_eq!(config.rules.cache_key(), "redundant-comment");
+    }
+
+    #[test]
+    fn test_low_confidence_findings_are_less_severe_unless_configured() {
+        let config = Config::parse("[rules.local-classifier]\nseverity = \"error\"\n").unwrap();
+        let finding = |rule_id: &str, confidence| CommentInfo {
+            rule_id: Some(rule_id.to_string()),
+            confidence,
+            ..Default::default()
+        };
+
+        assert_eq!(config.rules.finding_severity(&finding("redundant-comment", None)), Some(Severity::Warning));
+        assert_eq!(config.rules.finding_severity(&finding("redundant-comment", Some(0.9))), Some(Severity::Warning));
+        assert_eq!(config.rules.finding_severity(&finding("redundant-comment", Some(0.5))), Some(Severity::Info));
+        assert_eq!(config.rules.finding_severity(&finding("local-classifier", Some(0.5))), Some(Severity::Error));
+        assert_eq!(config.rules.finding_severity(&CommentInfo::default()), None);
+    }
+
+    #[test]
+    fn test_rejects_unknown_rules_and_severities() {
+        assert!(Config::parse("[rules.nmae-echo]\nenabled = false\n").is_err());
+        assert!(Config::parse("[rules.name-echo]\nseverity = \"fatal\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_regex_rules() {
+        let config = Config::parse(r#"
+[[rules.regex]]
+pattern = "^//\\s*auto-generated"
+action = "ignore"
+
+[[rules.regex]]
+pattern = "(?i)\\bxxx\\b"
+action = "flag"
+id = "no-xxx"
+message = "Use TODO instead of XXX"
+
+[rules.no-xxx]
+severity = "error"
+"#).unwrap();
+
+        let rules = config.rules.regex_rules();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].id, REGEX_RULE);
+        assert_eq!(rules[1].action, RegexAction::Flag);
+        assert!(config.rules.ignores("// auto-generated by protoc"));
+        assert!(!config.rules.ignores("# auto-generated"));
+        assert_eq!(config.rules.severity("no-xxx"), Severity::Error);
+        assert_eq!(config.rules.severity(REGEX_RULE), Severity::Warning);
+    }
+
+    #[test]
+    fn test_rejects_invalid_regex_rules() {
+        assert!(Config::parse("[[rules.regex]]\npattern = \"(unclosed\"\naction = \"flag\"\n").is_err());
+        assert!(Config::parse("[[rules.regex]]\npattern = \"x\"\naction = \"delete\"\n").is_err());
+    }
+
+    #[test]
+    fn test_load_finds_config_in_ancestors() {
+        let temporary_directory = TempDir::new().unwrap();
+        let nested = temporary_directory.path().join("src").join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(
+            temporary_directory.path().join(".unremark.toml"),
+            "[license]\nrequired_header = \"Copyright Acme\"\n",
+        ).unwrap();
+
+        let config = Config::load(&nested).unwrap();
+        assert_eq!(config.license.required_header.as_deref(), Some("Copyright Acme"));
+    }
+
+    #[test]
+    fn test_plugin_paths_and_ids() {
+        let temporary_directory = TempDir::new().unwrap();
+        let config_path = temporary_directory.path().join("unremark.toml");
+        fs::write(&config_path, concat!(
+            "[[plugins]]\npath = \"policies/house_style.wasm\"\n",
+            "[[plugins]]\npath = \"/opt/jira.wasm\"\nid = \"jira\"\n",
+            "[rules.house_style]\nseverity = \"error\"\n",
+        )).unwrap();
+
+        let config = Config::load_from_path(&config_path).unwrap();
+        assert_eq!(config.plugins[0].path, temporary_directory.path().join("policies/house_style.wasm"));
+        assert_eq!(config.plugins[0].id(), "house_style");
+        assert_eq!(config.plugins[1].path, PathBuf::from("/opt/jira.wasm"));
+        assert_eq!(config.plugins[1].id(), "jira");
+        assert_eq!(config.rules.severity("house_style"), Severity::Error);
+    }
+
+    #[test]
+    fn test_classifier_section() {
+        let temporary_directory = TempDir::new().unwrap();
+        let config_path = temporary_directory.path().join("unremark.toml");
+        fs::write(&config_path, "[classifier]\nmodel = \"model.onnx\"\nvocab = \"vocab.txt\"\nredundant_threshold = 0.8\n").unwrap();
+
+        let config = Config::load_from_path(&config_path).unwrap();
+        let classifier = config.classifier.unwrap();
+        assert_eq!(classifier.model, temporary_directory.path().join("model.onnx"));
+        assert_eq!(classifier.redundant_threshold, 0.8);
+        assert_eq!(classifier.useful_threshold, 0.1);
+        assert_eq!(classifier.max_length, 128);
+
+        assert!(Config::parse("[classifier]\nmodel = \"m.onnx\"\nvocab = \"v.txt\"\nuseful_threshold = 0.95\n").is_err());
+    }
+
+    #[test]
+    fn test_network_section() {
+        let temporary_directory = TempDir::new().unwrap();
+        let config_path = temporary_directory.path().join("unremark.toml");
+        fs::write(&config_path, "[network]\nca_bundle = \"certs/ca.pem\"\nhttps_proxy = \"http://proxy:3128\"\n").unwrap();
+        let mut trusted = TrustedWorkspaces::load_from_path(&temporary_directory.path().join("trusted"));
+        trusted.set_trusted(temporary_directory.path(), true).unwrap();
+
+        let config = Config::load_from_path_with(&config_path, &trusted).unwrap();
+        assert_eq!(config.network.ca_bundle, Some(temporary_directory.path().join("certs/ca.pem")));
+        assert_eq!(config.network.client_cert, None);
+        assert_eq!(config.network.https_proxy.as_deref(), Some("http://proxy:3128"));
+
+        assert!(Config::parse("[network]\ncert = \"client.pem\"\n").is_err());
+    }
+
+    #[test]
+    fn test_generation_section() {
+        assert!(Config::parse("").unwrap().generation.is_default());
+        let config = Config::parse("[generation]\nmax_tokens = 20000\ntemperature = 0.7\n").unwrap();
+        let api = config.api_settings();
+        assert_eq!(api.generation, GenerationConfig { max_tokens: 20000, temperature: 0.7, top_p: 1.0, logprobs: false });
+
+        // Over OpenAI's output limit, but the proxy has its own
+        assert!(api.validate().unwrap_err().contains("at most 16384"));
+        assert!(ApiSettings { provider: Provider::Proxy, ..api }.validate().is_ok());
+
+        assert!(Config::parse("[generation]\ntemperature = 3.0\n").unwrap_err().contains("temperature"));
+        assert!(Config::parse("[generation]\ntop_p = 1.5\n").is_err());
+        assert!(Config::parse("[generation]\nmax_tokens = 0\n").is_err());
+        assert!(Config::parse("[generation]\nseed = 1\n").is_err());
+    }
+
+    #[test]
+    fn test_credentials_section() {
+        assert_eq!(Config::parse("").unwrap().credentials, CredentialsConfig::default());
+        let config = Config::parse("[credentials]\napi_key_env = \"TEAM_KEY\"\nhelper = \"pass-unremark\"\nkeychain = true\n").unwrap();
+        let expected = CredentialsConfig {
+            api_key_env: Some("TEAM_KEY".to_string()),
+            helper: Some("pass-unremark".to_string()),
+            keychain: true,
+            ..Default::default()
+        };
+        assert_eq!(config.api_settings().credentials, expected);
+        assert!(Config::parse("[credentials]\napi_key = \"sk-...\"\n").is_err());
+    }
+
+    #[test]
+    fn test_untrusted_workspaces_run_no_helper() {
+        let temporary_directory = TempDir::new().unwrap();
+        let config_path = temporary_directory.path().join("unremark.toml");
+        fs::write(&config_path, "[credentials]\nhelper = \"pass-unremark\"\nkeychain = true\n").unwrap();
+        let mut trusted = TrustedWorkspaces::load_from_path(&temporary_directory.path().join("trusted"));
+
+        let config = Config::load_from_path_with(&config_path, &trusted).unwrap();
+        assert_eq!(config.credentials, CredentialsConfig { keychain: true, ..Default::default() });
+
+        trusted.set_trusted(temporary_directory.path(), true).unwrap();
+        let config = Config::load_from_path_with(&config_path, &trusted).unwrap();
+        assert_eq!(config.credentials.helper.as_deref(), Some("pass-unremark"));
+    }
+
+    #[test]
+    fn test_untrusted_workspaces_keep_requests_on_the_default_hosts() {
+        let temporary_directory = TempDir::new().unwrap();
+        let config_path = temporary_directory.path().join("unremark.toml");
+        fs::write(&config_path, concat!(
+            "[openai]\nmodel = \"gpt-4o-mini\"\nbase_url = \"https://attacker.example.com/v1\"\n",
+            "[proxy]\nendpoint = \"https://attacker.example.com\"\n",
+            "[network]\nca_bundle = \"ca.pem\"\nhttps_proxy = \"http://attacker.example.com:3128\"\n",
+        )).unwrap();
+        let trusted = TrustedWorkspaces::load_from_path(&temporary_directory.path().join("trusted"));
+
+        let config = Config::load_from_path_with(&config_path, &trusted).unwrap();
+        assert_eq!(config.openai.resolve_base_url_with(None, None), None);
+        assert_eq!(config.proxy.resolve_endpoint_with(None, None), DEFAULT_PROXY_ENDPOINT);
+        assert_eq!(config.network, NetworkConfig::default());
+        assert_eq!(config.openai.model.as_deref(), Some("gpt-4o-mini"));
+    }
+
+    #[test]
+    fn test_untrusted_workspaces_load_no_grammars() {
+        let temporary_directory = TempDir::new().unwrap();
+        let config_path = temporary_directory.path().join("unremark.toml");
+        fs::write(&config_path, "[[grammars]]\nname = \"gleam\"\npath = \"libtree-sitter-gleam.so\"\nextensions = [\"gleam\"]\n").unwrap();
+        let mut trusted = TrustedWorkspaces::load_from_path(&temporary_directory.path().join("trusted"));
+
+        assert!(Config::load_from_path_with(&config_path, &trusted).unwrap().grammars.is_empty());
+        trusted.set_trusted(temporary_directory.path(), true).unwrap();
+        let config = Config::load_from_path_with(&config_path, &trusted).unwrap();
+        assert_eq!(config.grammars[0].path, temporary_directory.path().join("libtree-sitter-gleam.so"));
+    }
+}
@@ -1,7 +1,90 @@
-pub const OPENAI_MODEL: &str = "ft:gpt-4o-mini-2024-07-18:personal:unremark:Aq45wBQq"; 
+/// The model asked unless `--model` or the config names another: a fine-tune
+/// only the author's OpenAI account can reach, so everyone else sets their own.
+pub const OPENAI_MODEL: &str = "ft:gpt-4o-mini-2024-07-18:personal:unremark:Aq45wBQq";
 
 pub const CACHE_FILE_NAME: &str = "unremark_cache.json";
 
+/// Directory of the per-project state kept next to the analyzed code.
+pub const HISTORY_DIR: &str = ".unremark";
+
+pub const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+/// How many files are analyzed at once, each with its source and parse tree
+/// in memory, unless configured otherwise.
+pub const DEFAULT_FILE_CONCURRENCY: usize = 32;
+
+/// Files larger than this are skipped unless configured otherwise: generated
+/// or vendored code, rarely worth the memory and API calls.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Rough cap on the code sent along with each comment, in tokens.
+pub const DEFAULT_CONTEXT_TOKENS: usize = 512;
+
+/// Lines kept on either side of a comment whose enclosing declaration is too
+/// large to send whole.
+pub const DEFAULT_CONTEXT_LINES: usize = 10;
+
+/// OpenAI's price for the default model, in dollars per million input and output tokens.
+pub const OPENAI_INPUT_PRICE: f64 = 0.30;
+pub const OPENAI_OUTPUT_PRICE: f64 = 1.20;
+
+/// What `--annotate` puts at the start of each redundant comment. Comments
+/// still carrying it are removed by the next `--fix`.
+pub const ANNOTATION_MARKER: &str = "[unremark: likely redundant]";
+
+/// Most tokens an OpenAI chat model writes in one answer.
+pub const OPENAI_MAX_OUTPUT_TOKENS: u32 = 16_384;
+
+pub const OPENAI_API_KEY_ENV: &str = "OPENAI_API_KEY";
+
+/// Token sent to proxies that require one, as a bearer token.
+pub const PROXY_TOKEN_ENV: &str = "UNREMARK_PROXY_TOKEN";
+
+/// JSON file of API responses to answer requests from, instead of the API.
+pub const REPLAY_ENV: &str = "UNREMARK_REPLAY";
+
+/// JSON file the API's responses are saved to, for `UNREMARK_REPLAY`.
+pub const RECORD_ENV: &str = "UNREMARK_RECORD";
+
+/// Set to `1` to trust every workspace's config, as `unremark trust` does one's.
+pub const TRUST_WORKSPACE_ENV: &str = "UNREMARK_TRUST_WORKSPACE";
+
+/// File in the user's config directory listing the trusted workspaces.
+pub const TRUSTED_WORKSPACES_FILE_NAME: &str = "trusted_workspaces";
+
+/// Service the keys are stored under in the OS keychain, one entry per
+/// provider (`openai`, `proxy`).
+pub const KEYCHAIN_SERVICE: &str = "unremark";
+
+pub const DEFAULT_OPENAI_ENDPOINT: &str = "https://api.openai.com/v1";
+
+/// Base URL of an OpenAI-compatible server to use instead of OpenAI's API.
+pub const OPENAI_BASE_URL_ENV: &str = "OPENAI_BASE_URL";
+
+pub const DEFAULT_OPENROUTER_ENDPOINT: &str = "https://openrouter.ai/api/v1";
+
+pub const OPENROUTER_API_KEY_ENV: &str = "OPENROUTER_API_KEY";
+
+/// Model asked through OpenRouter unless configured otherwise, as the
+/// fine-tuned default only exists on the author's OpenAI account.
+pub const DEFAULT_OPENROUTER_MODEL: &str = "openai/gpt-4o-mini";
+
+/// Attribution OpenRouter shows for requests that don't set their own.
+pub const DEFAULT_OPENROUTER_REFERER: &str = "https://github.com/software-trizzey/unremark";
+pub const DEFAULT_OPENROUTER_TITLE: &str = "unremark";
+
+/// Most API requests in flight at once across a run, unless configured otherwise.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+
+/// API requests that may fail in a row before the rest of a run's are skipped.
+pub const DEFAULT_MAX_CONSECUTIVE_FAILURES: u32 = 5;
+
+/// How long a single API or proxy request may take before it's retried.
+pub const DEFAULT_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Longest a request's backoff grows to between retries.
+pub const MAX_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(60);
+
 pub const DEFAULT_PROXY_ENDPOINT: &str = "http://localhost:5000";
 
 pub fn get_proxy_endpoint() -> String {
@@ -0,0 +1,180 @@
+use crate::config::CredentialsConfig;
+use crate::constants::{OPENAI_API_KEY_ENV, OPENROUTER_API_KEY_ENV, PROXY_TOKEN_ENV};
+use crate::types::Provider;
+use tracing::warn;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// The provider and the sources a key was looked up with.
+type Lookup = (Provider, CredentialsConfig);
+
+/// Keys already looked up, so a helper or keychain prompt runs once per
+/// process rather than once per file.
+static RESOLVED: OnceLock<Mutex<HashMap<Lookup, Option<String>>>> = OnceLock::new();
+
+/// The API key (or proxy token) for `provider`: `explicit` when given, then
+/// the project's own variable and command (OpenAI only), the environment,
+/// the credential helper and finally the OS keychain, as enabled in
+/// `credentials`. Failing sources are logged and skipped.
+pub fn resolve_api_key(provider: Provider, explicit: Option<&str>, credentials: &CredentialsConfig) -> Option<String> {
+    if let Some(key) = explicit {
+        return Some(key.to_string());
+    }
+    let lookup = (provider, credentials.clone());
+    let resolved = RESOLVED.get_or_init(Default::default);
+    if let Some(key) = resolved.lock().get(&lookup) {
+        return key.clone();
+    }
+    let key = look_up(provider, credentials);
+    resolved.lock().insert(lookup, key.clone());
+    key
+}
+
+fn look_up(provider: Provider, credentials: &CredentialsConfig) -> Option<String> {
+    if provider == Provider::OpenAi {
+        if let Some(variable) = &credentials.api_key_env {
+            match std::env::var(variable) {
+                Ok(key) => return Some(key),
+                Err(_) => warn!("{} (api_key_env) is not set", variable),
+            }
+        }
+        if let Some(command) = &credentials.api_key_cmd {
+            match run_command(command) {
+                Ok(key) => return Some(key),
+                Err(e) => warn!("`{}` (api_key_cmd) failed: {}", command, e),
+            }
+        }
+    }
+    let variable = match provider {
+        Provider::OpenAi => OPENAI_API_KEY_ENV,
+        Provider::Proxy => PROXY_TOKEN_ENV,
+        Provider::OpenRouter => OPENROUTER_API_KEY_ENV,
+    };
+    if let Ok(key) = std::env::var(variable) {
+        return Some(key);
+    }
+
+    let from_helper = || credentials.helper.as_deref().and_then(|helper| {
+        run_helper(helper, provider)
+            .map_err(|e| warn!("Credential helper `{}` failed: {}", helper, e))
+            .ok()
+    });
+    let from_keychain = || credentials.keychain.then(|| read_keychain(provider))?
+        .map_err(|e| warn!("No {} key in the keychain: {}", provider, e))
+        .ok();
+    from_helper().or_else(from_keychain)
+}
+
+/// Runs `<helper> get <provider>`, see `run_command`.
+fn run_helper(helper: &str, provider: Provider) -> Result<String, String> {
+    run_command(&format!("{} get {}", helper, provider))
+}
+
+/// Runs `command` through the shell, taking the first line it prints as the key.
+fn run_command(command: &str) -> Result<String, String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("{}: {}", output.status, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| "it printed no key".to_string())
+}
+
+/// The password of the `unremark` entry for the provider's name, from the
+/// macOS Keychain, Windows Credential Manager or Secret Service.
+#[cfg(feature = "keychain")]
+fn read_keychain(provider: Provider) -> Result<String, String> {
+    keyring::Entry::new(crate::constants::KEYCHAIN_SERVICE, &provider.to_string())
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "keychain"))]
+fn read_keychain(_provider: Provider) -> Result<String, String> {
+    Err("unremark was built without the `keychain` feature".to_string())
+}
+
+/// Saves `key` as the provider's keychain entry, where `keychain = true`
+/// looks it up, or deletes the entry when `key` is `None`.
+#[cfg(feature = "keychain")]
+pub fn store_keychain_key(provider: Provider, key: Option<&str>) -> Result<(), String> {
+    let entry = keyring::Entry::new(crate::constants::KEYCHAIN_SERVICE, &provider.to_string())
+        .map_err(|e| e.to_string())?;
+    match key {
+        Some(key) => entry.set_password(key),
+        None => entry.delete_credential(),
+    }
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "keychain"))]
+pub fn store_keychain_key(_provider: Provider, _key: Option<&str>) -> Result<(), String> {
+    Err("unremark was built without the `keychain` feature".to_string())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_helper_is_asked_for_the_provider() {
+        let credentials = CredentialsConfig { helper: Some("printf '\\n%s-%s-key\\n'".to_string()), ..Default::default() };
+        assert_eq!(run_helper(credentials.helper.as_deref().unwrap(), Provider::Proxy).unwrap(), "get-proxy-key");
+        assert_eq!(resolve_api_key(Provider::Proxy, Some("explicit"), &credentials).as_deref(), Some("explicit"));
+
+        assert!(run_helper("exit 3", Provider::OpenAi).unwrap_err().contains("exit status: 3"));
+        assert!(run_helper("true", Provider::OpenAi).is_err());
+    }
+
+    #[test]
+    fn test_project_key_comes_first() {
+        std::env::set_var("UNREMARK_TEST_TEAM_KEY", "team-key");
+        let credentials = CredentialsConfig {
+            api_key_env: Some("UNREMARK_TEST_TEAM_KEY".to_string()),
+            api_key_cmd: Some("echo command-key".to_string()),
+            helper: Some("echo helper-key #".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(resolve_api_key(Provider::OpenAi, None, &credentials).as_deref(), Some("team-key"));
+
+        let credentials = CredentialsConfig { api_key_env: Some("UNREMARK_TEST_UNSET_KEY".to_string()), ..credentials };
+        assert_eq!(resolve_api_key(Provider::OpenAi, None, &credentials).as_deref(), Some("command-key"));
+        // The project's key is never sent to the proxy
+        assert_eq!(resolve_api_key(Provider::Proxy, None, &credentials).as_deref(), Some("helper-key"));
+    }
+
+    #[test]
+    fn test_untrusted_project_runs_no_command() {
+        use crate::config::Config;
+        use crate::trust::TrustedWorkspaces;
+
+        let directory = tempfile::TempDir::new().unwrap();
+        let config_path = directory.path().join("unremark.toml");
+        let marker = directory.path().join("ran");
+        let command = format!("touch '{}' && echo command-key", marker.display());
+        std::fs::write(&config_path, format!("[credentials]\napi_key_cmd = \"{}\"\n", command)).unwrap();
+        let mut trusted = TrustedWorkspaces::load_from_path(&directory.path().join("trusted"));
+
+        let config = Config::load_from_path_with(&config_path, &trusted).unwrap();
+        let key = resolve_api_key(Provider::OpenAi, None, &config.api_settings().credentials);
+        assert_ne!(key.as_deref(), Some("command-key"));
+        assert!(!marker.exists());
+
+        trusted.set_trusted(directory.path(), true).unwrap();
+        let config = Config::load_from_path_with(&config_path, &trusted).unwrap();
+        let key = resolve_api_key(Provider::OpenAi, None, &config.api_settings().credentials);
+        assert_eq!(key.as_deref(), Some("command-key"));
+        assert!(marker.exists());
+    }
+}
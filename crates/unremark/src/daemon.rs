@@ -0,0 +1,501 @@
+use crate::analysis::{analyze_files, api_client};
+use crate::config::Config;
+use crate::telemetry::{metrics, Server};
+use crate::types::{AnalysisOptions, AnalysisResult, ApiSettings, Cache, Provider};
+use crate::utils::ContextBudget;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::{info, warn};
+
+/// Environment variable overriding where the daemon listens.
+pub const DAEMON_SOCKET_ENV: &str = "UNREMARK_SOCKET";
+
+/// An analysis run handed to the daemon by the CLI.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonRequest {
+    /// Directory the client runs in, relative paths are resolved against it
+    pub cwd: PathBuf,
+    /// Where the closest `unremark.toml` is looked up from
+    pub config_start: PathBuf,
+    pub files: Vec<PathBuf>,
+    pub fix: bool,
+    pub offline: bool,
+    pub name_echo_threshold: f32,
+    pub min_duplicate_occurrences: usize,
+    pub prefilter: bool,
+    pub check_accuracy: bool,
+    pub blame: bool,
+    /// Analyze through the unremark proxy server at this URL instead of the API
+    #[serde(default)]
+    pub proxy_endpoint: Option<String>,
+    /// Analyze with the OpenAI-compatible API at this URL, the config's when unset
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Model asked instead of the config's
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Most comments asked about in one request, the daemon's default when unset
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+    /// Most files analyzed at once, the daemon's default when unset
+    #[serde(default)]
+    pub file_concurrency: Option<usize>,
+    /// Largest file analyzed, in bytes, the daemon's default when unset
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+    /// Rough cap on the code sent along with each comment, the daemon's default when unset
+    #[serde(default)]
+    pub max_context_tokens: Option<usize>,
+    /// Mark redundant comments in the files instead of removing them
+    #[serde(default)]
+    pub annotate: bool,
+    /// Score every analyzed comment, not just report the redundant ones
+    #[serde(default)]
+    pub report_all: bool,
+    /// Check each fix before writing it
+    #[serde(default)]
+    pub verify: bool,
+    /// Also analyze the fenced code blocks of Markdown files
+    #[serde(default)]
+    pub markdown: bool,
+    /// Send each file's source back with its result, for quoting the findings
+    #[serde(default)]
+    pub keep_source: bool,
+}
+
+impl DaemonRequest {
+    /// The request's settings on top of the options loaded from its config.
+    fn options(&self, base: &AnalysisOptions) -> AnalysisOptions {
+        let mut api = match &self.proxy_endpoint {
+            Some(endpoint) => ApiSettings {
+                provider: Provider::Proxy,
+                endpoint: Some(endpoint.clone()),
+                ..base.api.clone()
+            },
+            None => ApiSettings {
+                endpoint: self.base_url.clone().or_else(|| base.api.endpoint.clone()),
+                ..base.api.clone()
+            },
+        };
+        if let Some(model) = &self.model {
+            api.model = model.clone();
+        }
+        api.batch_size = self.batch_size.unwrap_or(api.batch_size).max(1);
+        // Each request is a run of its own, for failures and token usage alike
+        api.breaker = std::sync::Arc::new(api.breaker.restarted());
+        api.usage = Default::default();
+        AnalysisOptions {
+            offline: self.offline,
+            name_echo_threshold: self.name_echo_threshold,
+            min_duplicate_occurrences: self.min_duplicate_occurrences,
+            prefilter: self.prefilter,
+            check_accuracy: self.check_accuracy,
+            blame: self.blame,
+            annotate: self.annotate,
+            report_all: self.report_all,
+            verify: self.verify,
+            markdown: self.markdown,
+            keep_source: self.keep_source,
+            api,
+            file_concurrency: self.file_concurrency.unwrap_or(base.file_concurrency),
+            max_file_size: self.max_file_size.or(base.max_file_size),
+            context_budget: ContextBudget {
+                max_tokens: self.max_context_tokens.unwrap_or(base.context_budget.max_tokens),
+                ..base.context_budget
+            },
+            ..base.clone()
+        }
+    }
+}
+
+/// One line of the daemon's reply: a result per file as soon as it's
+/// analyzed, or an error ending the reply.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum DaemonMessage {
+    Result(AnalysisResult),
+    Error { message: String },
+}
+
+/// `$UNREMARK_SOCKET`, or `unremark.sock` in the user's runtime directory.
+#[cfg(unix)]
+pub fn default_daemon_socket() -> PathBuf {
+    if let Some(socket) = std::env::var_os(DAEMON_SOCKET_ENV) {
+        return PathBuf::from(socket);
+    }
+    dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("unremark.sock")
+}
+
+/// `$UNREMARK_SOCKET`, or a named pipe of the user's own.
+#[cfg(windows)]
+pub fn default_daemon_socket() -> PathBuf {
+    if let Some(socket) = std::env::var_os(DAEMON_SOCKET_ENV) {
+        return PathBuf::from(socket);
+    }
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    PathBuf::from(format!(r"\\.\pipe\unremark-{}", user))
+}
+
+type LoadOptions = dyn Fn(&Path) -> Result<AnalysisOptions, String> + Send + Sync;
+/// Options loaded for a config file, with its modification time back then
+type LoadedOptions = (Option<SystemTime>, Arc<AnalysisOptions>);
+
+/// What the daemon keeps warm between requests.
+struct DaemonState {
+    cache: RwLock<Cache>,
+    /// Options per config file, reloaded when the file changes
+    options: Mutex<HashMap<Option<PathBuf>, LoadedOptions>>,
+    load_options: Box<LoadOptions>,
+}
+
+impl DaemonState {
+    fn options_for(&self, start: &Path) -> Result<Arc<AnalysisOptions>, String> {
+        let config = Config::find(start);
+        let modified = config.as_ref()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .and_then(|metadata| metadata.modified().ok());
+
+        let mut loaded = self.options.lock();
+        if let Some((loaded_modified, options)) = loaded.get(&config) {
+            if *loaded_modified == modified {
+                return Ok(Arc::clone(options));
+            }
+        }
+        let mut options = (self.load_options)(start)?;
+        let client = api_client(&options.api.network).map_err(|e| e.to_string())?;
+        options.api.client = Some(client);
+        let options = Arc::new(options);
+        loaded.insert(config, (modified, Arc::clone(&options)));
+        Ok(options)
+    }
+}
+
+/// Listens on `socket` until interrupted, answering analysis requests with
+/// the cache, plugins, classifier and HTTP connections kept warm in between.
+/// `load_options` builds the options for the config found from a start path.
+pub async fn serve_daemon<F>(socket: &Path, load_options: F) -> Result<(), String>
+where
+    F: Fn(&Path) -> Result<AnalysisOptions, String> + Send + Sync + 'static,
+{
+    let mut listener = transport::bind(socket).await?;
+    let state = Arc::new(DaemonState {
+        cache: RwLock::new(Cache::load()),
+        options: Mutex::new(HashMap::new()),
+        load_options: Box::new(load_options),
+    });
+    info!("Daemon listening on {}", socket.display());
+
+    let served = loop {
+        tokio::select! {
+            connection = listener.accept() => match connection {
+                Ok(stream) => {
+                    let state = Arc::clone(&state);
+                    tokio::spawn(async move {
+                        if let Err(e) = handle(stream, &state).await {
+                            warn!("Daemon request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => break Err(format!("Daemon stopped accepting connections: {}", e)),
+            },
+            _ = tokio::signal::ctrl_c() => break Ok(()),
+        }
+    };
+    listener.close();
+    state.cache.read().save();
+    served
+}
+
+async fn handle<S>(stream: S, state: &DaemonState) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Send,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await.map_err(|e| e.to_string())?;
+
+    let request: DaemonRequest = match serde_json::from_str(&line) {
+        Ok(request) => request,
+        Err(e) => return send(&mut writer, &DaemonMessage::Error { message: format!("invalid request: {}", e) }).await,
+    };
+    metrics().record_request(Server::Daemon);
+    let options = match state.options_for(&request.cwd.join(&request.config_start)) {
+        Ok(base) => request.options(&base),
+        Err(message) => return send(&mut writer, &DaemonMessage::Error { message }).await,
+    };
+
+    // Results carry the paths as the client sent them
+    let resolved: Vec<PathBuf> = request.files.iter().map(|path| request.cwd.join(path)).collect();
+    let original: HashMap<&PathBuf, &PathBuf> = resolved.iter().zip(&request.files).collect();
+    let mut results = analyze_files(&resolved, request.fix, &state.cache, &options);
+    while let Some(mut result) = results.next().await {
+        if let Some(path) = original.get(&result.path) {
+            result.path = (*path).clone();
+        }
+        send(&mut writer, &DaemonMessage::Result(result)).await?;
+    }
+
+    if !options.offline {
+        state.cache.read().save();
+    }
+    Ok(())
+}
+
+async fn send(writer: &mut (impl AsyncWrite + Unpin), message: &DaemonMessage) -> Result<(), String> {
+    let mut line = serde_json::to_string(message).map_err(|e| e.to_string())?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await.map_err(|e| e.to_string())
+}
+
+/// Connection to a running daemon.
+pub struct DaemonClient {
+    stream: transport::Connection,
+}
+
+impl DaemonClient {
+    /// Connects to the daemon, or `None` when none is listening on `socket`.
+    pub async fn connect(socket: &Path) -> Option<Self> {
+        transport::connect(socket).await.ok().map(|stream| Self { stream })
+    }
+
+    /// Sends the request, yielding each file's result as the daemon finishes it.
+    pub async fn analyze(self, request: &DaemonRequest) -> Result<BoxStream<'static, Result<AnalysisResult, String>>, String> {
+        let (reader, mut writer) = tokio::io::split(self.stream);
+        let mut line = serde_json::to_string(request).map_err(|e| e.to_string())?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await.map_err(|e| format!("cannot reach the daemon: {}", e))?;
+
+        let lines = BufReader::new(reader).lines();
+        Ok(futures::stream::unfold(Some(lines), |lines| async move {
+            let mut lines = lines?;
+            let message = match lines.next_line().await {
+                Ok(Some(line)) => serde_json::from_str(&line).map_err(|e| format!("invalid reply from the daemon: {}", e)),
+                Ok(None) => return None,
+                Err(e) => Err(format!("lost the daemon: {}", e)),
+            };
+            match message {
+                Ok(DaemonMessage::Result(result)) => Some((Ok(result), Some(lines))),
+                Ok(DaemonMessage::Error { message }) => Some((Err(message), None)),
+                Err(e) => Some((Err(e), None)),
+            }
+        }).boxed())
+    }
+}
+
+/// The daemon listens on a unix domain socket.
+#[cfg(unix)]
+mod transport {
+    use std::io;
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::path::{Path, PathBuf};
+    use tokio::net::{UnixListener, UnixStream};
+
+    pub type Connection = UnixStream;
+
+    pub struct Listener {
+        listener: UnixListener,
+        socket: PathBuf,
+    }
+
+    impl Listener {
+        pub async fn accept(&mut self) -> io::Result<UnixStream> {
+            self.listener.accept().await.map(|(stream, _)| stream)
+        }
+
+        pub fn close(self) {
+            let _ = std::fs::remove_file(&self.socket);
+        }
+    }
+
+    /// Binds the socket, replacing a stale one left by a daemon that died.
+    pub async fn bind(socket: &Path) -> Result<Listener, String> {
+        if socket.exists() {
+            if UnixStream::connect(socket).await.is_ok() {
+                return Err(format!("a daemon is already listening on {}", socket.display()));
+            }
+            std::fs::remove_file(socket)
+                .map_err(|e| format!("cannot remove stale socket {}: {}", socket.display(), e))?;
+        }
+
+        // Other users must not be able to make the daemon read or fix files,
+        // so the socket is bound in a directory only we can enter and only
+        // moved into place once it's private
+        let parent = socket.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let private = parent.join(format!(".unremark-daemon.{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&private);
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&private)
+            .map_err(|e| format!("cannot create {}: {}", private.display(), e))?;
+        let bound = private.join("unremark.sock");
+        let listener = UnixListener::bind(&bound)
+            .and_then(|listener| {
+                std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+                std::fs::rename(&bound, socket)?;
+                Ok(listener)
+            })
+            .map_err(|e| format!("cannot listen on {}: {}", socket.display(), e));
+        let _ = std::fs::remove_dir_all(&private);
+        Ok(Listener { listener: listener?, socket: socket.to_path_buf() })
+    }
+
+    pub async fn connect(socket: &Path) -> io::Result<UnixStream> {
+        UnixStream::connect(socket).await
+    }
+}
+
+/// The daemon listens on a named pipe, accepting one client per pipe instance.
+#[cfg(windows)]
+mod transport {
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient, NamedPipeServer, ServerOptions};
+
+    /// Returned while every instance of the pipe is busy with a client.
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    pub type Connection = NamedPipeClient;
+
+    pub struct Listener {
+        pipe: PathBuf,
+        /// The instance the next client connects to
+        next: NamedPipeServer,
+    }
+
+    impl Listener {
+        pub async fn accept(&mut self) -> io::Result<NamedPipeServer> {
+            self.next.connect().await?;
+            let next = ServerOptions::new().reject_remote_clients(true).create(&self.pipe)?;
+            Ok(std::mem::replace(&mut self.next, next))
+        }
+
+        pub fn close(self) {}
+    }
+
+    /// Creates the pipe's first instance, which fails while another daemon owns it.
+    pub async fn bind(pipe: &Path) -> Result<Listener, String> {
+        let next = ServerOptions::new()
+            .first_pipe_instance(true)
+            .reject_remote_clients(true)
+            .create(pipe)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::PermissionDenied => format!("a daemon is already listening on {}", pipe.display()),
+                _ => format!("cannot listen on {}: {}", pipe.display(), e),
+            })?;
+        Ok(Listener { pipe: pipe.to_path_buf(), next })
+    }
+
+    pub async fn connect(pipe: &Path) -> io::Result<NamedPipeClient> {
+        loop {
+            match ClientOptions::new().open(pipe) {
+                Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY) => tokio::time::sleep(Duration::from_millis(10)).await,
+                opened => return opened,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn request(cwd: &Path, files: Vec<PathBuf>) -> DaemonRequest {
+        DaemonRequest {
+            cwd: cwd.to_path_buf(),
+            config_start: PathBuf::from("."),
+            files,
+            fix: false,
+            offline: true,
+            name_echo_threshold: crate::heuristics::DEFAULT_NAME_ECHO_THRESHOLD,
+            min_duplicate_occurrences: crate::heuristics::DEFAULT_MIN_DUPLICATES,
+            prefilter: true,
+            check_accuracy: false,
+            blame: false,
+            proxy_endpoint: None,
+            base_url: None,
+            model: None,
+            batch_size: None,
+            file_concurrency: None,
+            max_file_size: None,
+            max_context_tokens: None,
+            annotate: false,
+            report_all: false,
+            verify: false,
+            markdown: false,
+            keep_source: false,
+        }
+    }
+
+    /// A socket of the test's own, a named pipe on Windows.
+    fn socket_in(dir: &Path) -> PathBuf {
+        if cfg!(windows) {
+            PathBuf::from(format!(r"\\.\pipe\unremark-test-{}", dir.file_name().unwrap().to_string_lossy()))
+        } else {
+            dir.join("unremark.sock")
+        }
+    }
+
+    async fn start_daemon(socket: &Path) {
+        let served = socket.to_path_buf();
+        tokio::spawn(async move { serve_daemon(&served, |_| Ok(AnalysisOptions::default())).await });
+        while DaemonClient::connect(socket).await.is_none() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_daemon_answers_with_client_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {\n    // Constructor\n    let x = 1;\n}\n").unwrap();
+        let socket = socket_in(dir.path());
+        start_daemon(&socket).await;
+
+        let client = DaemonClient::connect(&socket).await.unwrap();
+        let results: Vec<_> = client.analyze(&request(dir.path(), vec![PathBuf::from("main.rs")]))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        assert_eq!(results.len(), 1);
+        let result = results[0].as_ref().unwrap();
+        assert_eq!(result.path, PathBuf::from("main.rs"));
+        assert_eq!(result.redundant_comments[0].rule_id.as_deref(), Some("boilerplate-comment"));
+    }
+
+    #[tokio::test]
+    async fn test_second_daemon_and_missing_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = socket_in(dir.path());
+        assert!(DaemonClient::connect(&socket).await.is_none());
+
+        start_daemon(&socket).await;
+        let error = serve_daemon(&socket, |_| Ok(AnalysisOptions::default())).await.unwrap_err();
+        assert!(error.contains("already listening"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_socket_is_private_to_the_user() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let socket = socket_in(dir.path());
+        start_daemon(&socket).await;
+
+        assert_eq!(std::fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o600);
+        let entries: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(entries, vec![std::ffi::OsString::from("unremark.sock")]);
+    }
+}
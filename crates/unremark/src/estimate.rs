@@ -0,0 +1,164 @@
+use crate::analysis::{analyze_locally, is_analyzable};
+use crate::api::{accuracy_prompt, batch_redundancy_prompt, chat_messages, redundancy_prompt};
+use crate::heuristics::{MISLEADING_COMMENT_RULE, REDUNDANT_COMMENT_RULE};
+use crate::types::{AnalysisOptions, CommentInfo, Language};
+use crate::utils::{decode_source, estimate_tokens};
+use serde::Serialize;
+use std::path::Path;
+
+/// Tokens the chat format adds around each prompt.
+const MESSAGE_OVERHEAD_TOKENS: usize = 7;
+
+/// Typical length of the model's JSON answer, well under its `max_tokens`.
+const RESPONSE_TOKENS: usize = 60;
+
+/// What analyzing files would send to the API, counted without calling it.
+/// Cached files cost nothing, so a run can come in under the estimate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct UsageEstimate {
+    pub files: usize,
+    pub comments: usize,
+    /// One request per comment (or batch of comments) the local checks leave
+    /// undecided, plus one per comment checked for accuracy
+    pub api_calls: usize,
+    /// Requests to a proxy, which takes each file's undecided comments at once
+    /// and doesn't check accuracy
+    pub proxy_requests: usize,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+}
+
+impl UsageEstimate {
+    pub fn add(&mut self, other: &UsageEstimate) {
+        self.files += other.files;
+        self.comments += other.comments;
+        self.api_calls += other.api_calls;
+        self.proxy_requests += other.proxy_requests;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+
+    /// Dollar cost at the given prices per million input and output tokens.
+    pub fn cost(&self, input_price: f64, output_price: f64) -> f64 {
+        (self.input_tokens as f64 * input_price + self.output_tokens as f64 * output_price) / 1_000_000.0
+    }
+}
+
+/// Runs the local checks on the file and counts the requests and tokens the
+/// rest would take. Files over `max_file_size` are skipped, as in a real run.
+pub fn estimate_file(path: &Path, options: &AnalysisOptions) -> Result<UsageEstimate, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("failed to read the file: {}", e))?;
+    if options.max_file_size.is_some_and(|limit| bytes.len() as u64 > limit) {
+        return Ok(UsageEstimate::default());
+    }
+    let (source_code, _) = decode_source(&bytes)?;
+    let language = Language::detect(path, &source_code).ok_or_else(|| "unsupported file type".to_string())?;
+    estimate_source(&source_code, language, options)
+}
+
+/// `estimate_file` for a source already in memory.
+pub fn estimate_source(source_code: &str, language: Language, options: &AnalysisOptions) -> Result<UsageEstimate, String> {
+    let local = analyze_locally(source_code, language, options)?;
+    let mut estimate = UsageEstimate { files: 1, comments: local.comments.len(), ..Default::default() };
+    if options.offline {
+        return Ok(estimate);
+    }
+
+    if options.rules.is_enabled(REDUNDANT_COMMENT_RULE) && !local.undecided.is_empty() {
+        estimate.proxy_requests = 1;
+        // Each request repeats the examples ahead of its own question
+        let examples: usize = chat_messages("", &options.api.examples).iter()
+            .take(options.api.examples.len() * 2)
+            .filter_map(|message| message["content"].as_str())
+            .map(|content| estimate_tokens(content) + MESSAGE_OVERHEAD_TOKENS)
+            .sum();
+        if options.api.batch_size > 1 {
+            for batch in local.undecided.chunks(options.api.batch_size) {
+                estimate.api_calls += 1;
+                estimate.input_tokens += estimate_tokens(&batch_redundancy_prompt(batch)) + MESSAGE_OVERHEAD_TOKENS + examples;
+                estimate.output_tokens += RESPONSE_TOKENS * batch.len();
+            }
+        } else {
+            count_requests(&mut estimate, &local.undecided, redundancy_prompt, examples);
+        }
+    }
+    if options.check_accuracy && options.rules.is_enabled(MISLEADING_COMMENT_RULE) {
+        // Comments the API finds redundant aren't checked, but that can't be known up front
+        let candidates: Vec<CommentInfo> = local.comments.iter()
+            .filter(|comment| is_analyzable(comment, options))
+            .filter(|comment| !local.redundant_comments.iter().any(|r| r.line_number == comment.line_number))
+            .cloned()
+            .collect();
+        count_requests(&mut estimate, &candidates, accuracy_prompt, 0);
+    }
+    Ok(estimate)
+}
+
+fn count_requests(estimate: &mut UsageEstimate, comments: &[CommentInfo], prompt: fn(&CommentInfo) -> String, examples_tokens: usize) {
+    for comment in comments {
+        estimate.api_calls += 1;
+        estimate.input_tokens += estimate_tokens(&prompt(comment)) + MESSAGE_OVERHEAD_TOKENS + examples_tokens;
+        estimate.output_tokens += RESPONSE_TOKENS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_undecided_comments_are_counted() {
+        let source = "fn main() {\n    // Constructor\n    let x = 1;\n    // Keep this in step with the server\n    call(x);\n}\n";
+        let estimate = estimate_source(source, Language::Rust, &AnalysisOptions::default()).unwrap();
+
+        assert_eq!(estimate.comments, 2);
+        assert_eq!(estimate.api_calls, 1);
+        assert_eq!(estimate.proxy_requests, 1);
+        assert!(estimate.input_tokens > MESSAGE_OVERHEAD_TOKENS);
+        assert_eq!(estimate.output_tokens, RESPONSE_TOKENS);
+
+        let options = AnalysisOptions { check_accuracy: true, ..Default::default() };
+        let estimate = estimate_source(source, Language::Rust, &options).unwrap();
+        assert_eq!(estimate.api_calls, 2);
+        assert_eq!(estimate.proxy_requests, 1);
+
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+        assert_eq!(estimate_source(source, Language::Rust, &options).unwrap().api_calls, 0);
+    }
+
+    #[test]
+    fn test_examples_are_counted_with_each_request() {
+        let source = "fn main() {\n    // Keep this in step with the server\n    call(x);\n}\n";
+        let without = estimate_source(source, Language::Rust, &AnalysisOptions::default()).unwrap();
+        let mut options = AnalysisOptions::default();
+        options.api.examples = vec![crate::config::PromptExample {
+            comment: "// increment i".to_string(),
+            context: "i += 1;".to_string(),
+            redundant: true,
+            explanation: None,
+        }];
+        let with = estimate_source(source, Language::Rust, &options).unwrap();
+        assert_eq!(with.api_calls, without.api_calls);
+        assert!(with.input_tokens > without.input_tokens + 2 * MESSAGE_OVERHEAD_TOKENS);
+    }
+
+    #[test]
+    fn test_batches_are_one_request_each() {
+        let source = "fn main() {\n    // Keep this in step with the server\n    call(x);\n    // Retry once, the socket may be stale\n    call(y);\n    // Some clients send this twice\n    call(z);\n}\n";
+        let single = estimate_source(source, Language::Rust, &AnalysisOptions::default()).unwrap();
+        assert_eq!(single.api_calls, 3);
+
+        let mut options = AnalysisOptions::default();
+        options.api.batch_size = 2;
+        let batched = estimate_source(source, Language::Rust, &options).unwrap();
+        assert_eq!(batched.api_calls, 2);
+        assert_eq!(batched.output_tokens, single.output_tokens);
+        assert!(batched.input_tokens < single.input_tokens);
+    }
+
+    #[test]
+    fn test_cost() {
+        let estimate = UsageEstimate { input_tokens: 2_000_000, output_tokens: 500_000, ..Default::default() };
+        assert_eq!(estimate.cost(0.30, 1.20), 1.2);
+    }
+}
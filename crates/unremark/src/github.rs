@@ -0,0 +1,233 @@
+use crate::changes::ChangedLines;
+use crate::patch::fix_edit;
+use crate::types::{AnalysisResult, CommentInfo};
+use tracing::debug;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// Used when `GITHUB_API_URL` isn't set, i.e. outside GitHub Actions.
+pub const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+
+/// An inline comment on a pull request, in the shape the reviews API expects.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReviewComment {
+    /// Path relative to the repository root, with forward slashes
+    pub path: String,
+    /// Last line the comment applies to, in the PR's version of the file
+    pub line: usize,
+    /// First line of a multi-line comment
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_line: Option<usize>,
+    pub side: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_side: Option<&'static str>,
+    pub body: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Review<'a> {
+    body: String,
+    event: &'static str,
+    comments: &'a [ReviewComment],
+}
+
+fn review_comment(path: &str, first: usize, last: usize, body: String) -> ReviewComment {
+    let multi_line = first < last;
+    ReviewComment {
+        path: path.to_string(),
+        line: last,
+        start_line: multi_line.then_some(first),
+        side: "RIGHT",
+        start_side: multi_line.then_some("RIGHT"),
+        body,
+    }
+}
+
+fn finding_body(comment: &CommentInfo) -> String {
+    let rule = comment.rule_id.as_deref().unwrap_or("unremark");
+    let severity = comment.severity.map(|severity| severity.to_string()).unwrap_or_default();
+    match &comment.explanation {
+        Some(explanation) => format!("**{}[{}]** {}", severity, rule, explanation),
+        None => format!("**{}[{}]**", severity, rule),
+    }
+}
+
+/// Turns a file's findings into review comments, leaving out the ones outside
+/// the PR's diff since GitHub rejects those. Redundant comments come with a
+/// suggested change that removes them.
+pub fn review_comments(
+    result: &AnalysisResult,
+    source: &str,
+    repo_root: &Path,
+    diff: &ChangedLines,
+) -> Vec<ReviewComment> {
+    let canonical_path = result.path.canonicalize().unwrap_or_else(|_| result.path.clone());
+    let Ok(relative) = canonical_path.strip_prefix(repo_root) else {
+        return vec![];
+    };
+    let path = relative.to_string_lossy().replace('\\', "/");
+    let in_diff = |first: usize, last: usize| diff.contains_lines(&canonical_path, first, last);
+
+    let redundant = result.redundant_comments.iter().filter_map(|comment| {
+        let (first, last, replacement) = fix_edit(source, comment)?;
+        if !in_diff(first, last) {
+            return None;
+        }
+        let suggestion = replacement.iter().map(|line| format!("{}\n", line)).collect::<String>();
+        let body = format!("{}\n\n```suggestion\n{}```", finding_body(comment), suggestion);
+        Some(review_comment(&path, first, last, body))
+    });
+
+    let flagged = result.flagged_comments.iter().filter_map(|comment| {
+        let last = comment.line_number + comment.text.lines().count().saturating_sub(1);
+        in_diff(comment.line_number, last).then(|| review_comment(&path, comment.line_number, last, finding_body(comment)))
+    });
+
+    redundant.chain(flagged).collect()
+}
+
+fn client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .user_agent(concat!("unremark/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30))
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Fetches the pull request's unified diff.
+pub async fn fetch_pull_request_diff(api_url: &str, repo: &str, pr: u64, token: &str) -> Result<String, String> {
+    let response = client()?
+        .get(format!("{}/repos/{}/pulls/{}", api_url.trim_end_matches('/'), repo, pr))
+        .bearer_auth(token)
+        .header("Accept", "application/vnd.github.diff")
+        .send()
+        .await
+        .map_err(|e| format!("Failed to fetch pull request #{}: {}", pr, e))?;
+
+    let status = response.status();
+    let body = response.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("Failed to fetch pull request #{}: {} {}", pr, status, body.trim()));
+    }
+    Ok(body)
+}
+
+/// Posts the comments as a single review on the pull request.
+pub async fn post_review(
+    api_url: &str,
+    repo: &str,
+    pr: u64,
+    token: &str,
+    comments: &[ReviewComment],
+) -> Result<(), String> {
+    let review = Review {
+        body: format!("unremark found {} comments to look at in this pull request.", comments.len()),
+        event: "COMMENT",
+        comments,
+    };
+    debug!("Posting {} review comments to {}#{}", comments.len(), repo, pr);
+
+    let response = client()?
+        .post(format!("{}/repos/{}/pulls/{}/reviews", api_url.trim_end_matches('/'), repo, pr))
+        .bearer_auth(token)
+        .header("Accept", "application/vnd.github+json")
+        .json(&review)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to post review: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to post review: {} {}", status, body.trim()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn comment(text: &str, line_number: usize, rule_id: &str) -> CommentInfo {
+        CommentInfo {
+            text: text.to_string(),
+            line_number,
+            rule_id: Some(rule_id.to_string()),
+            explanation: Some("Says what the code says".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fix_edit_of_own_line_and_trailing_comments() {
+        let source = "fn main() {\n    // Set x\n    let x = 1; // one\n}\n";
+
+        let (first, last, replacement) = fix_edit(source, &comment("// Set x", 2, "name-echo")).unwrap();
+        assert_eq!((first, last), (2, 2));
+        assert!(replacement.is_empty());
+
+        let (first, last, replacement) = fix_edit(source, &comment("// one", 3, "name-echo")).unwrap();
+        assert_eq!((first, last), (3, 3));
+        assert_eq!(replacement, vec!["    let x = 1;".to_string()]);
+    }
+
+    #[test]
+    fn test_review_comments_only_cover_the_diff() {
+        let temporary_directory = TempDir::new().unwrap();
+        let root = temporary_directory.path().canonicalize().unwrap();
+        let source = "fn main() {\n    // Set x\n    let x = 1;\n    // see JIRA-1\n    let y = 2;\n}\n";
+        std::fs::create_dir(root.join("src")).unwrap();
+        std::fs::write(root.join("src/main.rs"), source).unwrap();
+
+        let diff = ChangedLines::from_diff("+++ b/src/main.rs\n@@ -1,3 +1,3 @@\n", &root);
+        let result = AnalysisResult {
+            path: root.join("src/main.rs"),
+            redundant_comments: vec![comment("// Set x", 2, "name-echo")],
+            flagged_comments: vec![comment("// see JIRA-1", 4, "no-jira")],
+            ..Default::default()
+        };
+
+        let comments = review_comments(&result, source, &root, &diff);
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].path, "src/main.rs");
+        assert_eq!(comments[0].line, 2);
+        assert!(comments[0].body.starts_with("**[name-echo]** Says what the code says"));
+        assert!(comments[0].body.ends_with("```suggestion\n```"));
+    }
+
+    #[tokio::test]
+    async fn test_post_review() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/repos/acme/app/pulls/7/reviews"))
+            .and(header("authorization", "Bearer secret"))
+            .and(body_partial_json(serde_json::json!({
+                "event": "COMMENT",
+                "comments": [{ "path": "src/main.rs", "line": 2, "side": "RIGHT" }],
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let comments = vec![review_comment("src/main.rs", 2, 2, "body".to_string())];
+        post_review(&server.uri(), "acme/app", 7, "secret", &comments).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_post_review_reports_api_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(422).set_body_string("line must be part of the diff"))
+            .mount(&server)
+            .await;
+
+        let error = post_review(&server.uri(), "acme/app", 7, "secret", &[]).await.unwrap_err();
+        assert!(error.contains("422"));
+        assert!(error.contains("part of the diff"));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::config::{Config, GrammarConfig};
+use crate::types::Language;
+use parking_lot::{const_rwlock, RwLock};
+use std::collections::{HashMap, HashSet};
+use tree_sitter::{Node, Query, QueryCursor, StreamingIterator};
+
+/// A grammar loaded from the config's `[[grammars]]`.
+#[cfg_attr(not(feature = "grammars"), allow(dead_code))]
+struct ExternalGrammar {
+    name: &'static str,
+    extensions: Vec<&'static str>,
+    language: tree_sitter::Language,
+    comment_query: Option<Query>,
+}
+
+/// The loaded grammars, indexed by `Language::External`. They're never
+/// unloaded, as their languages point into the libraries.
+static GRAMMARS: RwLock<Vec<ExternalGrammar>> = const_rwlock(Vec::new());
+
+/// Extensions the config maps to a language, ahead of the built-in ones.
+static EXTENSIONS: RwLock<Option<HashMap<String, Language>>> = const_rwlock(None);
+
+/// Loads the config's grammars, then its `[extensions]`, which may name
+/// them, replacing the extensions of any config loaded before:
+///
+/// ```toml
+/// [extensions]
+/// mjs = "javascript"
+/// pyi = "python"
+/// h = "c"
+/// ```
+pub fn load_languages(config: &Config) -> Result<(), String> {
+    load_grammars(&config.grammars)?;
+    let extensions = config.extensions.iter()
+        .map(|(extension, name)| match Language::from_name(name) {
+            Some(language) => Ok((extension.trim_start_matches('.').to_string(), language)),
+            None => Err(format!("unknown language '{}' for .{} files in [extensions]", name, extension.trim_start_matches('.'))),
+        })
+        .collect::<Result<HashMap<String, Language>, String>>()?;
+    *EXTENSIONS.write() = Some(extensions);
+    Ok(())
+}
+
+pub(crate) fn mapped_extension(extension: &str) -> Option<Language> {
+    EXTENSIONS.read().as_ref()?.get(extension).copied()
+}
+
+/// Loads the grammars listed in the config, skipping those already loaded.
+#[cfg(feature = "grammars")]
+pub fn load_grammars(grammars: &[GrammarConfig]) -> Result<(), String> {
+    for grammar in grammars {
+        if GRAMMARS.read().iter().any(|loaded| loaded.name == grammar.name) {
+            continue;
+        }
+        let loaded = load_grammar(grammar)?;
+        GRAMMARS.write().push(loaded);
+    }
+    Ok(())
+}
+
+/// Loads the grammars listed in the config.
+#[cfg(not(feature = "grammars"))]
+pub fn load_grammars(grammars: &[GrammarConfig]) -> Result<(), String> {
+    match grammars.first() {
+        Some(grammar) => Err(format!(
+            "cannot load grammar {}: unremark was built without the `grammars` feature",
+            grammar.path.display()
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "grammars")]
+fn load_grammar(grammar: &GrammarConfig) -> Result<ExternalGrammar, String> {
+    let fail = |reason: String| format!("cannot load grammar {}: {}", grammar.path.display(), reason);
+    if grammar.path.extension().is_some_and(|ext| ext == "wasm") {
+        return Err(fail("WASM grammars aren't supported, build the grammar as a shared library".to_string()));
+    }
+    if let Some((extension, language)) = grammar.extensions.iter()
+        .find_map(|extension| Language::from_extension(extension).map(|language| (extension, language))) {
+        return Err(fail(format!("`.{}` files are already analyzed as {}", extension, language)));
+    }
+
+    // The library is trusted like a plugin, and stays loaded for as long as the process
+    let library = unsafe { libloading::Library::new(&grammar.path) }.map_err(|e| fail(e.to_string()))?;
+    let symbol = grammar.symbol();
+    let language_fn = *unsafe { library.get::<unsafe extern "C" fn() -> *const ()>(symbol.as_bytes()) }
+        .map_err(|e| fail(format!("no `{}` function: {}", symbol, e)))?;
+    let language = tree_sitter::Language::new(unsafe { tree_sitter_language::LanguageFn::from_raw(language_fn) });
+    std::mem::forget(library);
+
+    let abi = language.abi_version();
+    if !(tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION..=tree_sitter::LANGUAGE_VERSION).contains(&abi) {
+        return Err(fail(format!(
+            "ABI version {} isn't supported, expected {} to {}",
+            abi, tree_sitter::MIN_COMPATIBLE_LANGUAGE_VERSION, tree_sitter::LANGUAGE_VERSION,
+        )));
+    }
+    let comment_query = grammar.comment_query.as_deref()
+        .map(|query| Query::new(&language, query))
+        .transpose()
+        .map_err(|e| fail(format!("invalid comment_query: {}", e)))?;
+
+    Ok(ExternalGrammar {
+        name: Box::leak(grammar.name.clone().into_boxed_str()),
+        extensions: grammar.extensions.iter().map(|extension| &*Box::leak(extension.clone().into_boxed_str())).collect(),
+        language,
+        comment_query,
+    })
+}
+
+pub(crate) fn from_extension(extension: &str) -> Option<Language> {
+    GRAMMARS.read().iter()
+        .position(|grammar| grammar.extensions.contains(&extension))
+        .map(Language::External)
+}
+
+pub(crate) fn name(index: usize) -> &'static str {
+    GRAMMARS.read()[index].name
+}
+
+pub(crate) fn extension(index: usize) -> &'static str {
+    GRAMMARS.read()[index].extensions.first().copied().unwrap_or_default()
+}
+
+pub(crate) fn tree_sitter_language(index: usize) -> tree_sitter::Language {
+    GRAMMARS.read()[index].language.clone()
+}
+
+/// The IDs of the nodes the grammar's comment query captures in the tree,
+/// when the language was loaded with one.
+pub(crate) fn queried_comments(language: Language, root: Node, code: &str) -> Option<HashSet<usize>> {
+    let Language::External(index) = language else {
+        return None;
+    };
+    let grammars = GRAMMARS.read();
+    let query = grammars[index].comment_query.as_ref()?;
+    let mut ids = HashSet::new();
+    let mut cursor = QueryCursor::new();
+    let mut matches = cursor.matches(query, root, code.as_bytes());
+    while let Some(found) = matches.next() {
+        ids.extend(found.captures.iter().map(|capture| capture.node.id()));
+    }
+    Some(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn grammar(path: &str, extensions: &[&str]) -> GrammarConfig {
+        GrammarConfig {
+            name: "gleam".to_string(),
+            path: PathBuf::from(path),
+            extensions: extensions.iter().map(|extension| extension.to_string()).collect(),
+            symbol: None,
+            comment_query: None,
+        }
+    }
+
+    #[test]
+    fn test_bad_grammars_are_reported() {
+        assert!(load_grammars(&[]).is_ok());
+        let error = load_grammars(&[grammar("missing/libtree-sitter-gleam.so", &["gleam"])]).unwrap_err();
+        assert!(error.contains("missing/libtree-sitter-gleam.so"), "{}", error);
+        assert_eq!(from_extension("gleam"), None);
+
+        #[cfg(feature = "grammars")]
+        {
+            let error = load_grammars(&[grammar("grammars/gleam.wasm", &["gleam"])]).unwrap_err();
+            assert!(error.contains("WASM"), "{}", error);
+            let error = load_grammars(&[grammar("grammars/libtree-sitter-gleam.so", &["gleam", "rs"])]).unwrap_err();
+            assert!(error.contains("already analyzed as rust"), "{}", error);
+        }
+        #[cfg(not(feature = "grammars"))]
+        assert!(error.contains("`grammars` feature"), "{}", error);
+    }
+
+    #[test]
+    fn test_extensions_map_to_languages() {
+        let config = Config::parse("[extensions]\nmjs = \"javascript\"\n\".pyi\" = \"py\"\nh = \"c\"\n").unwrap();
+        load_languages(&config).unwrap();
+        assert_eq!(Language::from_extension("mjs"), Some(Language::JavaScript));
+        assert_eq!(Language::from_extension("pyi"), Some(Language::Python));
+        assert_eq!(Language::from_extension("h"), Some(Language::C));
+
+        // A bad mapping keeps the ones loaded before
+        let config = Config::parse("[extensions]\nmjs = \"cobol\"\n").unwrap();
+        assert!(load_languages(&config).unwrap_err().contains("unknown language 'cobol' for .mjs files"));
+        assert_eq!(Language::from_extension("mjs"), Some(Language::JavaScript));
+    }
+}
@@ -0,0 +1,27 @@
+use crate::types::AnalysisOptions;
+use std::net::SocketAddr;
+
+#[cfg(feature = "grpc")]
+mod service;
+
+#[cfg(feature = "grpc")]
+pub use service::*;
+
+/// Serves comment detection and analysis over gRPC until the process is
+/// stopped, as described by `proto/unremark.proto`.
+#[cfg(feature = "grpc")]
+pub async fn serve_grpc(address: SocketAddr, options: AnalysisOptions) -> Result<(), String> {
+    tracing::info!("Serving gRPC on {}", address);
+    tonic::transport::Server::builder()
+        .add_service(UnremarkServer::new(UnremarkService::new(options)))
+        .serve(address)
+        .await
+        .map_err(|e| format!("gRPC server failed: {}", e))
+}
+
+/// Serves comment detection and analysis over gRPC until the process is
+/// stopped, as described by `proto/unremark.proto`.
+#[cfg(not(feature = "grpc"))]
+pub async fn serve_grpc(address: SocketAddr, _options: AnalysisOptions) -> Result<(), String> {
+    Err(format!("cannot serve gRPC on {}: unremark was built without the `grpc` feature", address))
+}
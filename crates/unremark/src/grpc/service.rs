@@ -0,0 +1,215 @@
+use crate::analysis::analyze_source;
+use crate::comment_detection::detect_comments;
+use crate::telemetry::{metrics, Server};
+use crate::types::{AnalysisOptions, AnalysisResult, CommentInfo, Language};
+use futures::stream::StreamExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+include!(concat!(env!("OUT_DIR"), "/unremark.v1.Unremark.rs"));
+
+pub use unremark_client::UnremarkClient;
+pub use unremark_server::{Unremark, UnremarkServer};
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SourceFile {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(string, tag = "2")]
+    pub source: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Comment {
+    #[prost(string, tag = "1")]
+    pub text: String,
+    #[prost(uint64, tag = "2")]
+    pub line_number: u64,
+    #[prost(string, tag = "3")]
+    pub context: String,
+    #[prost(string, optional, tag = "4")]
+    pub explanation: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    pub rule_id: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub severity: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DetectCommentsResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AnalyzeRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub files: Vec<SourceFile>,
+    #[prost(bool, tag = "2")]
+    pub offline: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FileResult {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(message, repeated, tag = "2")]
+    pub redundant_comments: Vec<Comment>,
+    #[prost(message, repeated, tag = "3")]
+    pub flagged_comments: Vec<Comment>,
+    #[prost(string, repeated, tag = "4")]
+    pub errors: Vec<String>,
+}
+
+impl From<CommentInfo> for Comment {
+    fn from(comment: CommentInfo) -> Self {
+        Self {
+            text: comment.text,
+            line_number: comment.line_number as u64,
+            context: comment.context,
+            explanation: comment.explanation,
+            rule_id: comment.rule_id,
+            severity: comment.severity.map(|severity| severity.to_string()),
+        }
+    }
+}
+
+impl From<AnalysisResult> for FileResult {
+    fn from(result: AnalysisResult) -> Self {
+        Self {
+            path: result.path.display().to_string(),
+            redundant_comments: result.redundant_comments.into_iter().map(Comment::from).collect(),
+            flagged_comments: result.flagged_comments.into_iter().map(Comment::from).collect(),
+            errors: result.errors,
+        }
+    }
+}
+
+/// Results buffered for a slow client before analysis waits for it.
+const STREAM_BUFFER: usize = 16;
+
+fn language_of(file: &SourceFile) -> Option<Language> {
+    Language::detect(Path::new(&file.path), &file.source)
+}
+
+fn unsupported(path: &str) -> Status {
+    Status::invalid_argument(format!("Unsupported file type: {}", path))
+}
+
+/// The `Unremark` gRPC service, analyzing with the options it was started with.
+#[derive(Debug, Clone)]
+pub struct UnremarkService {
+    options: Arc<AnalysisOptions>,
+}
+
+impl UnremarkService {
+    pub fn new(options: AnalysisOptions) -> Self {
+        Self { options: Arc::new(options) }
+    }
+}
+
+#[tonic::async_trait]
+impl Unremark for UnremarkService {
+    async fn detect_comments(&self, request: Request<SourceFile>) -> Result<Response<DetectCommentsResponse>, Status> {
+        metrics().record_request(Server::Grpc);
+        let file = request.into_inner();
+        let language = language_of(&file).ok_or_else(|| unsupported(&file.path))?;
+        let comments = detect_comments(&file.source, language).map_err(Status::invalid_argument)?;
+        Ok(Response::new(DetectCommentsResponse {
+            comments: comments.into_iter().map(Comment::from).collect(),
+        }))
+    }
+
+    type AnalyzeSourcesStream = ReceiverStream<Result<FileResult, Status>>;
+
+    async fn analyze_sources(&self, request: Request<AnalyzeRequest>) -> Result<Response<Self::AnalyzeSourcesStream>, Status> {
+        metrics().record_request(Server::Grpc);
+        let request = request.into_inner();
+        if let Some(file) = request.files.iter().find(|file| language_of(file).is_none()) {
+            return Err(unsupported(&file.path));
+        }
+        let options = if request.offline {
+            Arc::new(AnalysisOptions { offline: true, ..(*self.options).clone() })
+        } else {
+            Arc::clone(&self.options)
+        };
+
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let analyses: Vec<_> = request.files.iter()
+                .map(|file| {
+                    metrics().record_file();
+                    analyze_source(file.source.as_str().into(), Path::new(&file.path), &options)
+                })
+                .collect();
+            let mut results = futures::stream::iter(analyses).buffer_unordered(options.file_concurrency.max(1));
+            while let Some(result) = results.next().await {
+                // The client hung up, the rest would go nowhere
+                if sender.send(Ok(FileResult::from(result))).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    async fn client() -> UnremarkClient<tonic::transport::Channel> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let service = UnremarkService::new(AnalysisOptions { offline: true, ..Default::default() });
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(UnremarkServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        UnremarkClient::connect(format!("http://{}", address)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_detect_comments() {
+        let mut client = client().await;
+        let response = client.detect_comments(SourceFile {
+            path: "main.rs".to_string(),
+            source: "fn main() {\n    // Constructor\n    let x = 1;\n}\n".to_string(),
+        }).await.unwrap().into_inner();
+
+        assert_eq!(response.comments.len(), 1);
+        assert_eq!(response.comments[0].text, "// Constructor");
+        assert_eq!(response.comments[0].line_number, 2);
+
+        let error = client.detect_comments(SourceFile { path: "notes.txt".to_string(), source: String::new() })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_sources_streams_each_file() {
+        let mut client = client().await;
+        let files = vec![
+            SourceFile { path: "a.rs".to_string(), source: "fn main() {\n    // Constructor\n    let x = 1;\n}\n".to_string() },
+            SourceFile { path: "b.py".to_string(), source: "def f():\n    return 1\n".to_string() },
+        ];
+        let mut stream = client.analyze_sources(AnalyzeRequest { files, offline: true }).await.unwrap().into_inner();
+
+        let mut results = Vec::new();
+        while let Some(result) = stream.message().await.unwrap() {
+            results.push(result);
+        }
+        results.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].redundant_comments[0].rule_id.as_deref(), Some("boilerplate-comment"));
+        assert!(results[1].redundant_comments.is_empty());
+    }
+}
@@ -0,0 +1,86 @@
+use super::{comment_body, tokenize, CommentSite, Rule};
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+/// Words a comment can use to narrate an assignment without adding information.
+const FILLER_WORDS: &[&str] = &[
+    "a", "an", "the", "to", "of", "by", "with", "as", "and", "it", "its", "is", "be",
+    "set", "sets", "assign", "assigns", "assigned", "value", "variable", "new",
+    "increment", "increments", "decrement", "decrements", "initialize", "initialise",
+    "init", "update", "updates", "store", "stores", "save", "equal", "equals",
+    "add", "subtract", "declare", "create", "reset",
+];
+
+fn assignment_regex() -> &'static Regex {
+    static ASSIGNMENT: OnceLock<Regex> = OnceLock::new();
+    ASSIGNMENT.get_or_init(|| {
+        Regex::new(r"^(?:let\s+(?:mut\s+)?|const\s+|var\s+)?([A-Za-z_][A-Za-z0-9_.]*)\s*(?::\s*[^=]+)?\s*[-+*/]?=\s*([^=].*?)\s*;?$")
+            .unwrap()
+    })
+}
+
+fn number_word(token: &str) -> &str {
+    match token {
+        "zero" => "0",
+        "one" => "1",
+        "two" => "2",
+        _ => token,
+    }
+}
+
+/// Flags comments like `x += 1  // increment x` that narrate a simple assignment.
+pub struct RestatesAssignmentRule;
+
+impl Rule for RestatesAssignmentRule {
+    fn id(&self) -> &'static str {
+        "comment-restates-assignment"
+    }
+
+    fn check(&self, site: &CommentSite) -> Option<String> {
+        let code = site.described_code()?;
+        let captures = assignment_regex().captures(code)?;
+        let target = captures.get(1)?.as_str();
+
+        let target_tokens: HashSet<String> = tokenize(target).into_iter().collect();
+        let code_tokens: HashSet<String> = tokenize(code).into_iter().collect();
+        let comment_tokens: Vec<String> = tokenize(&comment_body(&site.comment.text))
+            .into_iter()
+            .filter(|token| !FILLER_WORDS.contains(&token.as_str()))
+            .map(|token| number_word(&token).to_string())
+            .collect();
+
+        let mentions_target = comment_tokens.iter().any(|token| target_tokens.contains(token));
+        let only_restates_code = comment_tokens.iter().all(|token| code_tokens.contains(token));
+
+        (mentions_target && only_restates_code)
+            .then(|| format!("Comment restates the assignment to '{}'", target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CommentInfo;
+
+    fn check(source: &str, comment: &str, line_number: usize) -> Option<String> {
+        let comment = CommentInfo { text: comment.to_string(), line_number, ..Default::default() };
+        let lines: Vec<&str> = source.lines().collect();
+        RestatesAssignmentRule.check(&CommentSite::new(&comment, &lines))
+    }
+
+    #[test]
+    fn test_flags_narrated_assignments() {
+        assert!(check("count += 1  # increment count", "# increment count", 1).is_some());
+        assert!(check("// set retries to zero\nlet mut retries = 0;", "// set retries to zero", 1).is_some());
+        assert!(check("this.name = name; // set the name", "// set the name", 1).is_some());
+    }
+
+    #[test]
+    fn test_ignores_comments_explaining_why() {
+        assert!(check("count += 1  # skip the header row", "# skip the header row", 1).is_none());
+        assert!(check("// retries start at zero\nlet mut retries = 0;", "// retries start at zero", 1).is_none());
+        assert!(check("// set name\nif a == b {", "// set name", 1).is_none());
+        assert!(check("total = price * quantity; // total equals price times quantity", "// total equals price times quantity", 1).is_none());
+    }
+}
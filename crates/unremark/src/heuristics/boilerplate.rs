@@ -0,0 +1,82 @@
+use super::{comment_body, tokenize, CommentSite, Rule};
+
+/// Comments that only name the kind of code they sit above.
+const BOILERPLATE: &[&str] = &[
+    "constructor",
+    "default constructor",
+    "destructor",
+    "getter",
+    "setter",
+    "getters",
+    "setters",
+    "getters and setters",
+    "imports",
+    "import statements",
+    "constants",
+    "variables",
+    "fields",
+    "properties",
+    "methods",
+    "main",
+    "main function",
+    "main method",
+    "entry point",
+    "end",
+    "end if",
+    "end for",
+    "end while",
+    "end function",
+    "end of function",
+    "end class",
+    "end of class",
+    "return",
+    "return result",
+];
+
+/// Flags stock comments such as `// Constructor` or `# Getter for name`.
+pub struct BoilerplateRule;
+
+impl Rule for BoilerplateRule {
+    fn id(&self) -> &'static str {
+        "boilerplate-comment"
+    }
+
+    fn check(&self, site: &CommentSite) -> Option<String> {
+        let body = comment_body(&site.comment.text);
+        let tokens = tokenize(&body);
+        let normalized = tokens.join(" ");
+
+        let is_boilerplate = BOILERPLATE.contains(&normalized.as_str())
+            || (tokens.len() <= 4
+                && matches!(tokens.first().map(String::as_str), Some("getter" | "setter" | "constructor" | "destructor"))
+                && matches!(tokens.get(1).map(String::as_str), Some("for" | "of")));
+
+        is_boilerplate.then(|| format!("'{}' is boilerplate that the code already makes obvious", body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CommentInfo;
+
+    fn check(text: &str) -> Option<String> {
+        let comment = CommentInfo { text: text.to_string(), line_number: 1, ..Default::default() };
+        BoilerplateRule.check(&CommentSite::new(&comment, &[text]))
+    }
+
+    #[test]
+    fn test_flags_boilerplate() {
+        assert!(check("// Constructor").is_some());
+        assert!(check("# Getter for name").is_some());
+        assert!(check("/* Setter of the width */").is_some());
+        assert!(check("// end if").is_some());
+        assert!(check("# Imports").is_some());
+    }
+
+    #[test]
+    fn test_ignores_descriptive_comments() {
+        assert!(check("// Constructor validates the config before connecting").is_none());
+        assert!(check("# Getter is lazy because the value is expensive to compute").is_none());
+    }
+}
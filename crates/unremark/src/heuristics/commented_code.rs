@@ -0,0 +1,81 @@
+use super::{comment_body, CommentSite, Rule};
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// Rule ID for comments that are disabled code rather than prose.
+pub const COMMENTED_OUT_CODE_RULE: &str = "commented-out-code";
+
+fn code_line_regex() -> &'static Regex {
+    static CODE_LINE: OnceLock<Regex> = OnceLock::new();
+    CODE_LINE.get_or_init(|| {
+        Regex::new(concat!(
+            r"^(?:",
+            r"(?:let|const|var)\s+(?:mut\s+)?[A-Za-z_][A-Za-z0-9_]*\s*(?::[^=]+)?=\s*\S.*",
+            r"|(?:fn|def|function)\s+[A-Za-z_][A-Za-z0-9_]*\s*\(.*",
+            r"|import\s+[A-Za-z_][A-Za-z0-9_.]*;?",
+            r"|from\s+[A-Za-z_][A-Za-z0-9_.]*\s+import\s+.+",
+            r"|use\s+[A-Za-z_][A-Za-z0-9_:]*(?:::\{.*\})?;",
+            r"|return\b.*;",
+            r"|[A-Za-z_][A-Za-z0-9_.]*(?:\[[^\]]*\])?\s*[-+*/]?=\s*[^=\s].*",
+            r"|[A-Za-z_][A-Za-z0-9_.:]*\(.*\)\s*;?",
+            r"|.*[;{]|\}",
+            r")$",
+        ))
+        .unwrap()
+    })
+}
+
+/// Flags comments whose every line reads as code, e.g. `// let x = compute();`.
+pub struct CommentedOutCodeRule;
+
+impl Rule for CommentedOutCodeRule {
+    fn id(&self) -> &'static str {
+        COMMENTED_OUT_CODE_RULE
+    }
+
+    fn check(&self, site: &CommentSite) -> Option<String> {
+        let text = site.comment.text.trim();
+        let text = text.strip_prefix("/*").and_then(|t| t.strip_suffix("*/")).unwrap_or(text);
+        let lines: Vec<String> = text.lines()
+            .map(comment_body)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if lines.is_empty() {
+            return None;
+        }
+
+        lines.iter()
+            .all(|line| code_line_regex().is_match(line))
+            .then(|| "Comment contains commented-out code; version control keeps the history".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CommentInfo;
+
+    fn check(comment: &str) -> Option<String> {
+        let comment = CommentInfo { text: comment.to_string(), line_number: 1, ..Default::default() };
+        CommentedOutCodeRule.check(&CommentSite::new(&comment, &[]))
+    }
+
+    #[test]
+    fn test_flags_commented_out_code() {
+        assert!(check("// let total = compute(items);").is_some());
+        assert!(check("# print(response.json())").is_some());
+        assert!(check("// console.log(user);").is_some());
+        assert!(check("# result = fetch(url)").is_some());
+        assert!(check("/* if (ready) {\n   start();\n} */").is_some());
+    }
+
+    #[test]
+    fn test_ignores_prose() {
+        assert!(check("// Retry once, the API drops the first request after idling").is_none());
+        assert!(check("# Uses the cached value when available").is_none());
+        assert!(check("// See parse() for the format").is_none());
+        assert!(check("// ------").is_none());
+        assert!(check("// use the cached value when possible").is_none());
+        assert!(check("# For example:").is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use super::comment_body;
+use crate::comment_detection::detect_comments;
+use crate::types::{CommentInfo, Language};
+use crate::utils::read_source;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Rule ID for comment text repeated many times.
+pub const DUPLICATE_COMMENT_RULE: &str = "duplicate-comment";
+
+/// How many copies of a comment it takes to report it by default.
+pub const DEFAULT_MIN_DUPLICATES: usize = 3;
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CommentLocation {
+    pub path: PathBuf,
+    pub line_number: usize,
+}
+
+/// One comment text and every place it was found.
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DuplicateComment {
+    pub text: String,
+    pub count: usize,
+    pub locations: Vec<CommentLocation>,
+}
+
+/// Case- and whitespace-insensitive key, so reformatted copies still match.
+fn normalized_key(text: &str) -> Option<String> {
+    let body = comment_body(text).to_lowercase();
+    let key = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    (key.chars().any(|c| c.is_alphanumeric())).then_some(key)
+}
+
+/// Groups comments by their normalized text, keeping groups with at least
+/// `min_occurrences` members, most repeated first.
+pub fn find_duplicate_comments<'a, I>(comments: I, min_occurrences: usize) -> Vec<DuplicateComment>
+where
+    I: IntoIterator<Item = (&'a Path, &'a CommentInfo)>,
+{
+    let mut groups: HashMap<String, DuplicateComment> = HashMap::new();
+    let mut order = Vec::new();
+
+    for (path, comment) in comments {
+        // The same license banner in every file is intended
+        if comment.is_license() {
+            continue;
+        }
+        let Some(key) = normalized_key(&comment.text) else {
+            continue;
+        };
+        let group = groups.entry(key.clone()).or_insert_with(|| {
+            order.push(key);
+            DuplicateComment {
+                text: comment.text.clone(),
+                count: 0,
+                locations: Vec::new(),
+            }
+        });
+        group.count += 1;
+        group.locations.push(CommentLocation {
+            path: path.to_path_buf(),
+            line_number: comment.line_number,
+        });
+    }
+
+    let mut duplicates: Vec<DuplicateComment> = order.into_iter()
+        .filter_map(|key| groups.remove(&key))
+        .filter(|group| group.count >= min_occurrences.max(2))
+        .collect();
+    duplicates.sort_by_key(|group| std::cmp::Reverse(group.count));
+    duplicates
+}
+
+/// Flags every copy of a comment repeated at least `min_occurrences` times in one file.
+pub fn flag_duplicate_comments(comments: &[CommentInfo], min_occurrences: usize) -> Vec<CommentInfo> {
+    let path = Path::new("");
+    let duplicates = find_duplicate_comments(comments.iter().map(|c| (path, c)), min_occurrences);
+
+    let mut flagged: Vec<CommentInfo> = duplicates.iter()
+        .flat_map(|duplicate| {
+            let lines: Vec<String> = duplicate.locations.iter()
+                .map(|location| location.line_number.to_string())
+                .collect();
+            let explanation = format!(
+                "Comment is repeated {} times in this file (lines {})",
+                duplicate.count,
+                lines.join(", ")
+            );
+            comments.iter()
+                .filter(|comment| duplicate.locations.iter().any(|l| l.line_number == comment.line_number))
+                .map(move |comment| CommentInfo {
+                    rule_id: Some(DUPLICATE_COMMENT_RULE.to_string()),
+                    explanation: Some(explanation.clone()),
+                    ..comment.clone()
+                })
+        })
+        .collect();
+    flagged.sort_by_key(|comment| comment.line_number);
+    flagged
+}
+
+/// Reads and parses each file, returning comments repeated at least
+/// `min_occurrences` times across all of them.
+pub fn find_duplicate_comments_in_files(paths: &[PathBuf], min_occurrences: usize) -> Vec<DuplicateComment> {
+    let detected: Vec<(&Path, Vec<CommentInfo>)> = paths.iter()
+        .filter_map(|path| {
+            let source_code = read_source(path).ok()?;
+            let language = Language::detect(path, &source_code)?;
+            let comments = detect_comments(&source_code, language).ok()?;
+            Some((path.as_path(), comments))
+        })
+        .collect();
+
+    find_duplicate_comments(
+        detected.iter().flat_map(|(path, comments)| comments.iter().map(move |c| (*path, c))),
+        min_occurrences,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(text: &str, line_number: usize) -> CommentInfo {
+        CommentInfo { text: text.to_string(), line_number, ..Default::default() }
+    }
+
+    #[test]
+    fn test_groups_repeated_comments() {
+        let a = Path::new("a.rs");
+        let b = Path::new("b.rs");
+        let comments = [
+            comment("// Helper function", 1),
+            comment("//   helper   FUNCTION", 5),
+            comment("// Parses the header", 7),
+            comment("// Helper function", 2),
+        ];
+        let duplicates = find_duplicate_comments(
+            vec![(a, &comments[0]), (a, &comments[1]), (a, &comments[2]), (b, &comments[3])],
+            3,
+        );
+
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].count, 3);
+        assert_eq!(duplicates[0].locations.len(), 3);
+        assert_eq!(duplicates[0].locations[2].path, b);
+    }
+
+    #[test]
+    fn test_flags_duplicates_within_a_file() {
+        let comments = vec![
+            comment("# Auto-generated, do not edit", 1),
+            comment("# Auto-generated, do not edit", 10),
+            comment("# ------", 11),
+            comment("# ------", 12),
+            comment("# Validates input", 20),
+        ];
+
+        let flagged = flag_duplicate_comments(&comments, 2);
+
+        assert_eq!(flagged.len(), 2, "Dividers have no text to compare and are left to other rules");
+        assert!(flagged.iter().all(|c| c.rule_id.as_deref() == Some(DUPLICATE_COMMENT_RULE)));
+        assert!(flagged[0].explanation.as_deref().unwrap().contains("repeated 2 times"));
+        assert!(flagged[0].explanation.as_deref().unwrap().contains("lines 1, 10"));
+    }
+
+    #[test]
+    fn test_respects_minimum_occurrences() {
+        let comments = vec![comment("// same", 1), comment("// same", 2)];
+        assert!(flag_duplicate_comments(&comments, 3).is_empty());
+    }
+}
@@ -0,0 +1,49 @@
+use super::{comment_body, CommentSite, Rule};
+
+/// Flags comments with no words in them: empty markers and ASCII dividers like `// -----`.
+pub struct EmptyCommentRule;
+
+impl Rule for EmptyCommentRule {
+    fn id(&self) -> &'static str {
+        "empty-comment"
+    }
+
+    fn check(&self, site: &CommentSite) -> Option<String> {
+        let body = comment_body(&site.comment.text);
+        if body.chars().any(|c| c.is_alphanumeric()) {
+            return None;
+        }
+
+        if body.is_empty() && site.comment.text.chars().filter(|c| !c.is_whitespace()).count() <= 4 {
+            Some("Empty comment".to_string())
+        } else {
+            Some("Divider comment adds visual noise without information".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CommentInfo;
+
+    fn check(text: &str) -> Option<String> {
+        let comment = CommentInfo { text: text.to_string(), line_number: 1, ..Default::default() };
+        EmptyCommentRule.check(&CommentSite::new(&comment, &[text]))
+    }
+
+    #[test]
+    fn test_flags_empty_and_divider_comments() {
+        assert_eq!(check("//").as_deref(), Some("Empty comment"));
+        assert_eq!(check("#").as_deref(), Some("Empty comment"));
+        assert!(check("// ==========").is_some());
+        assert!(check("/*************/").is_some());
+        assert!(check("# -*-*-*-").is_some());
+    }
+
+    #[test]
+    fn test_ignores_comments_with_words() {
+        assert!(check("// x").is_none());
+        assert!(check("# --- Helpers ---").is_none());
+    }
+}
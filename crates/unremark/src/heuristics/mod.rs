@@ -0,0 +1,276 @@
+use crate::types::{AnalysisOptions, CommentInfo, Severity};
+use tracing::debug;
+
+mod assignment;
+mod boilerplate;
+mod commented_code;
+mod duplicates;
+mod empty;
+mod name_echo;
+mod prefilter;
+mod regex_rules;
+mod stale_reference;
+
+pub use assignment::RestatesAssignmentRule;
+pub use boilerplate::BoilerplateRule;
+pub use commented_code::{CommentedOutCodeRule, COMMENTED_OUT_CODE_RULE};
+pub use duplicates::{
+    find_duplicate_comments, find_duplicate_comments_in_files, flag_duplicate_comments,
+    CommentLocation, DuplicateComment, DEFAULT_MIN_DUPLICATES, DUPLICATE_COMMENT_RULE,
+};
+pub use empty::EmptyCommentRule;
+pub use name_echo::{NameEchoRule, DEFAULT_NAME_ECHO_THRESHOLD};
+pub use prefilter::{prefilter_comments, useful_comment_reason, PREFILTER_MAX_CODE_OVERLAP, PREFILTER_MIN_SENTENCES};
+pub use regex_rules::{flag_regex_comments, REGEX_RULE};
+pub use stale_reference::{find_outdated_comments, OUTDATED_COMMENT_RULE};
+
+use crate::classifier::CLASSIFIER_RULE;
+
+/// Rule ID reported for comments the LLM (or proxy) judged redundant.
+pub const REDUNDANT_COMMENT_RULE: &str = "redundant-comment";
+
+/// Rule ID reported for comments the LLM found to contradict their code.
+pub const MISLEADING_COMMENT_RULE: &str = "misleading-comment";
+
+/// Every rule ID unremark can report, in the order rules run.
+pub const BUILTIN_RULE_IDS: &[&str] = &[
+    "empty-comment",
+    "boilerplate-comment",
+    "name-echo",
+    "comment-restates-assignment",
+    COMMENTED_OUT_CODE_RULE,
+    REDUNDANT_COMMENT_RULE,
+    MISLEADING_COMMENT_RULE,
+    OUTDATED_COMMENT_RULE,
+    DUPLICATE_COMMENT_RULE,
+    REGEX_RULE,
+    CLASSIFIER_RULE,
+];
+
+/// Severity of a rule's findings unless `unremark.toml` overrides it: comments
+/// `--fix` would remove are warnings, ones that only need a second look are info.
+pub fn default_severity(rule_id: &str) -> Severity {
+    match rule_id {
+        OUTDATED_COMMENT_RULE | DUPLICATE_COMMENT_RULE => Severity::Info,
+        _ => Severity::Warning,
+    }
+}
+
+/// Findings reported with less confidence than this are a level less serious
+/// than their rule, unless `unremark.toml` sets the rule's severity.
+pub const LOW_CONFIDENCE: f32 = 0.7;
+
+/// Severity of a finding from its rule's default and how sure the check
+/// that reported it was.
+pub fn finding_severity(rule_id: &str, confidence: Option<f32>) -> Severity {
+    let severity = default_severity(rule_id);
+    match confidence {
+        Some(confidence) if confidence < LOW_CONFIDENCE => severity.lower(),
+        _ => severity,
+    }
+}
+
+/// A comment together with the code around it, as seen by a heuristic rule.
+#[derive(Debug, Clone, Copy)]
+pub struct CommentSite<'a> {
+    pub comment: &'a CommentInfo,
+    /// Code preceding the comment on its own line, empty for line-start comments
+    pub code_before: &'a str,
+    /// The first non-blank, non-comment line following the comment
+    pub next_code_line: Option<&'a str>,
+}
+
+impl<'a> CommentSite<'a> {
+    pub fn new(comment: &'a CommentInfo, lines: &[&'a str]) -> Self {
+        let index = comment.line_number.saturating_sub(1);
+        let code_before = lines.get(index)
+            .and_then(|line| line.find(comment.text.lines().next().unwrap_or_default()).map(|pos| line[..pos].trim()))
+            .unwrap_or_default();
+        let comment_lines = comment.text.lines().count().max(1);
+        let next_code_line = lines.iter()
+            .skip(index + comment_lines)
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !is_comment_line(line));
+
+        Self { comment, code_before, next_code_line }
+    }
+
+    /// The code the comment most likely describes: the code it trails, or the line below it.
+    pub fn described_code(&self) -> Option<&'a str> {
+        if !self.code_before.is_empty() {
+            Some(self.code_before)
+        } else {
+            self.next_code_line
+        }
+    }
+}
+
+/// A local, deterministic check that can flag a comment without calling the API.
+pub trait Rule: Send + Sync {
+    fn id(&self) -> &'static str;
+
+    /// Returns an explanation when the comment is considered redundant.
+    fn check(&self, site: &CommentSite) -> Option<String>;
+}
+
+pub fn default_rules() -> Vec<Box<dyn Rule>> {
+    rules_for(&AnalysisOptions::default())
+}
+
+/// The built-in rules enabled in the analysis options, configured from them.
+pub fn rules_for(options: &AnalysisOptions) -> Vec<Box<dyn Rule>> {
+    let rules: Vec<Box<dyn Rule>> = vec![
+        Box::new(EmptyCommentRule),
+        Box::new(BoilerplateRule),
+        Box::new(NameEchoRule::new(options.name_echo_threshold)),
+        Box::new(RestatesAssignmentRule),
+        Box::new(CommentedOutCodeRule),
+    ];
+    rules.into_iter()
+        .filter(|rule| options.rules.is_enabled(rule.id()))
+        .collect()
+}
+
+/// Runs the rules over the comments, returning the flagged comments (with their
+/// rule ID and explanation set) and the comments no rule matched.
+pub fn apply_rules(source: &str, comments: Vec<CommentInfo>, rules: &[Box<dyn Rule>]) -> (Vec<CommentInfo>, Vec<CommentInfo>) {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut flagged = Vec::new();
+    let mut remaining = Vec::new();
+
+    for comment in comments {
+        let site = CommentSite::new(&comment, &lines);
+        let verdict = rules.iter()
+            .find_map(|rule| rule.check(&site).map(|explanation| (rule.id(), explanation)));
+
+        match verdict {
+            Some((rule_id, explanation)) => {
+                debug!("Rule {} flagged comment on line {}: {}", rule_id, comment.line_number, comment.text);
+                let mut comment = comment;
+                comment.rule_id = Some(rule_id.to_string());
+                comment.explanation = Some(explanation);
+                flagged.push(comment);
+            }
+            None => remaining.push(comment),
+        }
+    }
+
+    (flagged, remaining)
+}
+
+/// Strips comment delimiters, returning just the prose of the comment.
+pub fn comment_body(text: &str) -> String {
+    let text = text.trim();
+    let text = text.strip_prefix("/*").map(|t| t.strip_suffix("*/").unwrap_or(t))
+        .or_else(|| text.strip_prefix("{-").map(|t| t.strip_suffix("-}").unwrap_or(t)))
+        .unwrap_or(text);
+
+    text.lines()
+        .map(|line| {
+            line.trim()
+                .trim_start_matches(['/', '#', '*', '-', '!', ' ', '\t'])
+                .trim()
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Splits text into lowercase word tokens, breaking identifiers on
+/// snake_case, kebab-case and camelCase boundaries.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        let mut current = String::new();
+        let mut previous_lower = false;
+        for c in word.chars() {
+            if c.is_uppercase() && previous_lower && !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            previous_lower = c.is_lowercase() || c.is_numeric();
+            current.extend(c.to_lowercase());
+        }
+        if !current.is_empty() {
+            tokens.push(current);
+        }
+    }
+    tokens
+}
+
+fn is_comment_line(line: &str) -> bool {
+    ["//", "#", "/*", "*", "--"].iter().any(|prefix| line.starts_with(prefix))
+        && !line.starts_with("#[")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(text: &str, line_number: usize) -> CommentInfo {
+        CommentInfo {
+            text: text.to_string(),
+            line_number,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_comment_body_strips_delimiters() {
+        assert_eq!(comment_body("// Adds two numbers"), "Adds two numbers");
+        assert_eq!(comment_body("# Adds two numbers"), "Adds two numbers");
+        assert_eq!(comment_body("/* Adds two numbers */"), "Adds two numbers");
+        assert_eq!(comment_body("/*\n * Adds two\n * numbers\n */"), "Adds two numbers");
+        assert_eq!(comment_body("{- Adds two numbers -}"), "Adds two numbers");
+        assert_eq!(comment_body("// ------"), "");
+    }
+
+    #[test]
+    fn test_tokenize_splits_identifiers() {
+        assert_eq!(tokenize("getUserName"), vec!["get", "user", "name"]);
+        assert_eq!(tokenize("get_user_name"), vec!["get", "user", "name"]);
+        assert_eq!(tokenize("HTTPServer v2"), vec!["httpserver", "v2"]);
+    }
+
+    #[test]
+    fn test_comment_site_finds_surrounding_code() {
+        let source = "fn main() {\n    // Set x\n\n    let x = 1; // one\n}";
+        let lines: Vec<&str> = source.lines().collect();
+
+        let leading = comment("// Set x", 2);
+        let site = CommentSite::new(&leading, &lines);
+        assert_eq!(site.code_before, "");
+        assert_eq!(site.next_code_line, Some("let x = 1; // one"));
+
+        let trailing = comment("// one", 4);
+        let site = CommentSite::new(&trailing, &lines);
+        assert_eq!(site.code_before, "let x = 1;");
+        assert_eq!(site.described_code(), Some("let x = 1;"));
+    }
+
+    #[test]
+    fn test_apply_rules_partitions_comments() {
+        let source = "# ------\ndef calculate_sum(a, b):\n    # Uses Kahan summation to limit rounding error\n    return a + b";
+        let comments = vec![
+            comment("# ------", 1),
+            comment("# Uses Kahan summation to limit rounding error", 3),
+        ];
+
+        let (flagged, remaining) = apply_rules(source, comments, &default_rules());
+
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].rule_id.as_deref(), Some("empty-comment"));
+        assert!(flagged[0].explanation.is_some());
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].rule_id.is_none());
+    }
+
+    #[test]
+    fn test_rules_for_skips_disabled_rules() {
+        let mut options = AnalysisOptions::default();
+        options.rules.set("empty-comment", crate::config::RuleConfig { enabled: false, severity: None });
+
+        let rules = rules_for(&options);
+        assert!(rules.iter().all(|rule| rule.id() != "empty-comment"));
+        assert!(rules.iter().all(|rule| BUILTIN_RULE_IDS.contains(&rule.id())));
+    }
+}
@@ -0,0 +1,136 @@
+use super::{comment_body, tokenize, CommentSite, Rule};
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+/// Share of the comment's words that must appear in the identifier by default.
+pub const DEFAULT_NAME_ECHO_THRESHOLD: f32 = 1.0;
+
+/// Words that describe what kind of thing is declared rather than what it does.
+const KIND_WORDS: &[&str] = &[
+    "a", "an", "the", "this", "function", "method", "class", "struct", "enum",
+    "interface", "trait", "type", "constant", "variable", "helper", "definition",
+];
+
+fn declaration_regex() -> &'static Regex {
+    static DECLARATION: OnceLock<Regex> = OnceLock::new();
+    DECLARATION.get_or_init(|| {
+        Regex::new(r"\b(?:fn|def|function|class|struct|enum|trait|interface|type|const|let|var|static|mod)\s+(?:mut\s+)?([A-Za-z_][A-Za-z0-9_]*)")
+            .unwrap()
+    })
+}
+
+/// Returns the identifier declared on a line of code, if any.
+pub(crate) fn declared_identifier(code: &str) -> Option<&str> {
+    declaration_regex()
+        .captures(code)
+        .and_then(|captures| captures.get(1))
+        .map(|m| m.as_str())
+}
+
+/// Reduces simple plurals and third-person verbs so "gets users" matches `get_user`.
+fn normalize(token: String) -> String {
+    match token.strip_suffix('s') {
+        Some(stem) if stem.len() >= 3 && !stem.ends_with('s') => stem.to_string(),
+        _ => token,
+    }
+}
+
+fn token_set(text: &str) -> HashSet<String> {
+    tokenize(text)
+        .into_iter()
+        .filter(|token| !KIND_WORDS.contains(&token.as_str()))
+        .map(normalize)
+        .collect()
+}
+
+/// Flags comments whose words are (almost) all taken from the name of the
+/// adjacent declaration, e.g. `// get user name` above `fn get_user_name`.
+pub struct NameEchoRule {
+    threshold: f32,
+}
+
+impl NameEchoRule {
+    /// `threshold` is the fraction (0.0-1.0) of the comment's words that must
+    /// appear in the identifier for the comment to be flagged.
+    pub fn new(threshold: f32) -> Self {
+        Self { threshold: threshold.clamp(0.0, 1.0) }
+    }
+
+    /// Fraction of the comment's words found in the identifier.
+    fn similarity(comment_tokens: &HashSet<String>, identifier_tokens: &HashSet<String>) -> f32 {
+        if comment_tokens.is_empty() {
+            return 0.0;
+        }
+        let shared = comment_tokens.intersection(identifier_tokens).count();
+        shared as f32 / comment_tokens.len() as f32
+    }
+}
+
+impl Default for NameEchoRule {
+    fn default() -> Self {
+        Self::new(DEFAULT_NAME_ECHO_THRESHOLD)
+    }
+}
+
+impl Rule for NameEchoRule {
+    fn id(&self) -> &'static str {
+        "name-echo"
+    }
+
+    fn check(&self, site: &CommentSite) -> Option<String> {
+        let identifier = declared_identifier(site.described_code()?)?;
+
+        let comment_tokens = token_set(&comment_body(&site.comment.text));
+        let identifier_tokens = token_set(identifier);
+
+        (Self::similarity(&comment_tokens, &identifier_tokens) >= self.threshold.max(f32::EPSILON))
+            .then(|| format!("Comment only repeats the name '{}'", identifier))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::CommentInfo;
+
+    fn check_with(rule: &NameEchoRule, comment: &str, code: &str) -> Option<String> {
+        let comment = CommentInfo { text: comment.to_string(), line_number: 1, ..Default::default() };
+        let source = format!("{}\n{}", comment.text, code);
+        let lines: Vec<&str> = source.lines().collect();
+        rule.check(&CommentSite::new(&comment, &lines))
+    }
+
+    fn check(comment: &str, code: &str) -> Option<String> {
+        check_with(&NameEchoRule::default(), comment, code)
+    }
+
+    #[test]
+    fn test_flags_comments_echoing_the_declaration() {
+        assert!(check("// get user name", "fn get_user_name(&self) -> &str {").is_some());
+        assert!(check("# Calculate sum", "def calculate_sum(a, b):").is_some());
+        assert!(check("// The UserRepository class", "class UserRepository {").is_some());
+        assert!(check("// max retries", "const MAX_RETRIES: u32 = 3;").is_some());
+    }
+
+    #[test]
+    fn test_flags_subsets_of_the_identifier() {
+        assert!(check("// get name", "fn get_user_name(&self) -> &str {").is_some());
+        assert!(check("// Gets users", "function getUsers() {").is_some());
+    }
+
+    #[test]
+    fn test_ignores_comments_adding_information() {
+        assert!(check("// get user name from the session cache", "fn get_user_name(&self) -> &str {").is_none());
+        assert!(check("// get user name", "let name = user.name();").is_none());
+        assert!(check("// get user name", "return name").is_none());
+        assert!(check("// ---", "fn get_user_name(&self) -> &str {").is_none());
+    }
+
+    #[test]
+    fn test_threshold_allows_extra_words() {
+        let lenient = NameEchoRule::new(0.6);
+        assert!(check_with(&lenient, "// get user name quickly", "fn get_user_name(&self) -> &str {").is_some());
+        assert!(check_with(&lenient, "// look up the display label", "fn get_user_name(&self) -> &str {").is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use super::{comment_body, tokenize, CommentSite};
+use crate::types::CommentInfo;
+use tracing::debug;
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+/// Sentences a comment needs before it can count as an explanation.
+pub const PREFILTER_MIN_SENTENCES: usize = 3;
+/// Highest share of an explanation's words that may also appear in the nearby code.
+pub const PREFILTER_MAX_CODE_OVERLAP: f32 = 0.3;
+
+fn useful_marker_regex() -> &'static Regex {
+    static USEFUL_MARKER: OnceLock<Regex> = OnceLock::new();
+    USEFUL_MARKER.get_or_init(|| {
+        Regex::new(concat!(
+            r"https?://\S+",
+            r"|(?:^|[\s(])#\d+\b",                 // #1234
+            r"|\b[A-Z][A-Z0-9]+-\d+\b",            // JIRA-123
+            r"|\b(?:SAFETY|PANICS|INVARIANT|WARNING|IMPORTANT):",
+        ))
+        .unwrap()
+    })
+}
+
+fn sentence_count(text: &str) -> usize {
+    text.split(['.', '!', '?'])
+        .filter(|sentence| sentence.chars().filter(|c| c.is_alphabetic()).count() > 1)
+        .count()
+}
+
+/// Why a comment is almost certainly worth keeping, if it is: it links
+/// somewhere, references an issue, carries a safety marker, or is a
+/// multi-sentence explanation that mostly talks about things the code doesn't say.
+pub fn useful_comment_reason(site: &CommentSite) -> Option<&'static str> {
+    let body = comment_body(&site.comment.text);
+    if useful_marker_regex().is_match(&body) {
+        return Some("references a link, issue or safety marker");
+    }
+
+    if sentence_count(&body) < PREFILTER_MIN_SENTENCES {
+        return None;
+    }
+    let code_tokens: HashSet<String> = tokenize(site.described_code().unwrap_or_default())
+        .into_iter()
+        .chain(tokenize(&site.comment.context))
+        .collect();
+    let comment_tokens = tokenize(&body);
+    let overlap = comment_tokens.iter().filter(|token| code_tokens.contains(*token)).count() as f32
+        / comment_tokens.len().max(1) as f32;
+
+    (overlap <= PREFILTER_MAX_CODE_OVERLAP).then_some("explains more than the code shows")
+}
+
+/// Splits off the comments not worth an API call, returning the ones to
+/// send and the ones skipped.
+pub fn prefilter_comments(source: &str, comments: Vec<CommentInfo>) -> (Vec<CommentInfo>, Vec<CommentInfo>) {
+    let lines: Vec<&str> = source.lines().collect();
+    let (skipped, kept): (Vec<CommentInfo>, Vec<CommentInfo>) = comments.into_iter()
+        .partition(|comment| match useful_comment_reason(&CommentSite::new(comment, &lines)) {
+            Some(reason) => {
+                debug!("Not sending comment on line {} to the API, it {}", comment.line_number, reason);
+                true
+            }
+            None => false,
+        });
+    (kept, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(source: &str, comment: &str, line_number: usize) -> Option<&'static str> {
+        let comment = CommentInfo { text: comment.to_string(), line_number, ..Default::default() };
+        let lines: Vec<&str> = source.lines().collect();
+        useful_comment_reason(&CommentSite::new(&comment, &lines))
+    }
+
+    #[test]
+    fn test_skips_links_issues_and_markers() {
+        assert!(reason("", "// See https://github.com/rust-lang/rust/issues/1", 1).is_some());
+        assert!(reason("", "# Workaround for #1234", 1).is_some());
+        assert!(reason("", "// Remove once PROJ-42 ships", 1).is_some());
+        assert!(reason("", "// SAFETY: the index was bounds-checked above", 1).is_some());
+        assert!(reason("", "// Increment the counter", 1).is_none());
+        assert!(reason("", "// Use a color like #fff", 1).is_none());
+    }
+
+    #[test]
+    fn test_skips_long_explanations_only_when_they_add_information() {
+        let source = "// The upstream API drops the first request after idling. Retrying once hides that. Don't raise the limit, it trips their abuse detection.\nretry(1);";
+        assert!(reason(source, source.lines().next().unwrap(), 1).is_some());
+
+        let source = "// Get the user. Get the user name. Return the user name.\nlet user_name = get_user().name;";
+        assert!(reason(source, source.lines().next().unwrap(), 1).is_none());
+    }
+
+    #[test]
+    fn test_prefilter_partitions_comments() {
+        let comments = vec![
+            CommentInfo { text: "// see #12".to_string(), line_number: 1, ..Default::default() },
+            CommentInfo { text: "// add one".to_string(), line_number: 2, ..Default::default() },
+        ];
+        let (kept, skipped) = prefilter_comments("// see #12\n// add one\nx += 1;", comments);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(skipped[0].line_number, 1);
+    }
+}
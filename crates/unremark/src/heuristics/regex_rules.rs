@@ -0,0 +1,60 @@
+use crate::config::{RegexAction, RulesConfig};
+use crate::types::CommentInfo;
+
+/// Default rule ID for comments flagged by a user-defined regex rule.
+pub const REGEX_RULE: &str = "custom-regex";
+
+/// Comments matched by the config's `action = "flag"` regex rules, each tagged
+/// with the first matching rule that is enabled.
+pub fn flag_regex_comments(comments: &[CommentInfo], rules: &RulesConfig) -> Vec<CommentInfo> {
+    comments.iter()
+        .filter_map(|comment| {
+            let rule = rules.regex_rules().iter().find(|rule| {
+                rule.action == RegexAction::Flag
+                    && rules.is_enabled(&rule.id)
+                    && rule.pattern.is_match(&comment.text)
+            })?;
+
+            let mut comment = comment.clone();
+            comment.rule_id = Some(rule.id.clone());
+            comment.explanation = Some(rule.message.clone().unwrap_or_else(|| {
+                format!("Comment matches the custom rule `{}`", rule.pattern.as_str())
+            }));
+            Some(comment)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn comment(text: &str, line_number: usize) -> CommentInfo {
+        CommentInfo { text: text.to_string(), line_number, ..Default::default() }
+    }
+
+    #[test]
+    fn test_flags_matching_comments() {
+        let config = Config::parse(concat!(
+            "[[rules.regex]]\npattern = \"XXX\"\naction = \"flag\"\nid = \"no-xxx\"\nmessage = \"Use TODO\"\n",
+            "[[rules.regex]]\npattern = \"generated\"\naction = \"ignore\"\n",
+        )).unwrap();
+        let comments = vec![comment("// XXX fix later", 1), comment("// generated", 2), comment("// fine", 3)];
+
+        let flagged = flag_regex_comments(&comments, &config.rules);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].rule_id.as_deref(), Some("no-xxx"));
+        assert_eq!(flagged[0].explanation.as_deref(), Some("Use TODO"));
+    }
+
+    #[test]
+    fn test_skips_disabled_regex_rules() {
+        let config = Config::parse(concat!(
+            "[[rules.regex]]\npattern = \"XXX\"\naction = \"flag\"\n",
+            "[rules.custom-regex]\nenabled = false\n",
+        )).unwrap();
+
+        assert!(flag_regex_comments(&[comment("// XXX", 1)], &config.rules).is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::comment_detection::detect_comments;
+use crate::types::{CommentInfo, Language};
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use tree_sitter::{Node, Parser};
+
+/// Rule ID for comments that mention symbols which no longer exist.
+pub const OUTDATED_COMMENT_RULE: &str = "possibly-outdated-comment";
+
+fn code_reference_regex() -> &'static Regex {
+    static CODE_REFERENCE: OnceLock<Regex> = OnceLock::new();
+    CODE_REFERENCE.get_or_init(|| {
+        Regex::new(concat!(
+            r"`(?:[A-Za-z_][A-Za-z0-9_]*(?:::|\.))*([A-Za-z_][A-Za-z0-9_]*)(?:\(\))?`", // `name`, `mod::name`, `obj.name()`
+            r"|\b([A-Za-z_][A-Za-z0-9_]*)\(\)",                                        // name()
+            r"|\b([a-z][a-z0-9]*(?:_[a-z0-9]+)+)\b",                                    // snake_case
+            r"|\b([a-z]+(?:[A-Z][a-z0-9]*)+)\b",                                        // camelCase
+        ))
+        .unwrap()
+    })
+}
+
+/// Identifiers a comment refers to in a code-like way (backticks, calls,
+/// snake_case or camelCase words), as opposed to ordinary prose.
+pub(crate) fn referenced_identifiers(comment_text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    code_reference_regex()
+        .captures_iter(comment_text)
+        .filter_map(|captures| captures.iter().skip(1).flatten().next().map(|m| m.as_str().to_string()))
+        .filter(|name| seen.insert(name.clone()))
+        .collect()
+}
+
+fn collect_identifiers(node: Node, code: &[u8], identifiers: &mut HashSet<String>) {
+    if node.kind().contains("identifier") {
+        if let Ok(text) = node.utf8_text(code) {
+            identifiers.insert(text.to_string());
+        }
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_identifiers(child, code, identifiers);
+    }
+}
+
+/// Node kinds whose names stay visible outside the scope that declares them.
+const DECLARATION_KINDS: &[&str] = &[
+    "function", "method", "class", "struct", "enum", "trait", "interface",
+    "type", "mod", "module", "const", "static", "field", "property",
+];
+
+/// Names declared anywhere in the file (functions, classes, fields, ...), which
+/// are visible from any nested scope. Local variables are deliberately left out.
+fn collect_declarations(node: Node, code: &[u8], declarations: &mut HashSet<String>) {
+    let kind = node.kind();
+    let is_declaration = DECLARATION_KINDS.iter().any(|declaration| kind.contains(declaration));
+    if let Some(name) = node.child_by_field_name("name").filter(|_| is_declaration) {
+        if let Ok(text) = name.utf8_text(code) {
+            declarations.insert(text.to_string());
+        }
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_declarations(child, code, declarations);
+    }
+}
+
+fn enclosing_scope<'tree>(root: Node<'tree>, byte_offset: usize) -> Node<'tree> {
+    let mut node = root.descendant_for_byte_range(byte_offset, byte_offset).unwrap_or(root);
+    while let Some(parent) = node.parent() {
+        let kind = parent.kind();
+        if kind.contains("function") || kind.contains("class") || kind.contains("method") || kind.contains("impl") {
+            return parent;
+        }
+        node = parent;
+    }
+    root
+}
+
+fn comment_offset(source_code: &str, comment: &CommentInfo) -> Option<usize> {
+    let line_start: usize = source_code
+        .split_inclusive('\n')
+        .take(comment.line_number.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let line = source_code[line_start..].lines().next()?;
+    let first_line = comment.text.lines().next()?;
+    line.find(first_line).map(|column| line_start + column)
+}
+
+/// Flags comments that mention identifiers which appear neither in the
+/// comment's enclosing scope nor among the file's declarations.
+pub fn find_outdated_comments(source_code: &str, language: Language) -> Vec<CommentInfo> {
+    let mut parser = Parser::new();
+    if parser.set_language(&language.get_tree_sitter_language()).is_err() {
+        return vec![];
+    }
+    let tree = match crate::sfc::parse(&mut parser, source_code, language) {
+        Some(tree) => tree,
+        None => return vec![],
+    };
+
+    let root = tree.root_node();
+    let code = source_code.as_bytes();
+    let mut declarations = HashSet::new();
+    collect_declarations(root, code, &mut declarations);
+
+    detect_comments(source_code, language)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|comment| !comment.is_license())
+        .filter_map(|comment| {
+            let references = referenced_identifiers(&comment.text);
+            if references.is_empty() {
+                return None;
+            }
+
+            let scope = comment_offset(source_code, &comment)
+                .map(|offset| enclosing_scope(root, offset))
+                .unwrap_or(root);
+            let mut visible = declarations.clone();
+            collect_identifiers(scope, code, &mut visible);
+
+            let missing: Vec<String> = references.into_iter()
+                .filter(|name| !visible.contains(name))
+                .map(|name| format!("`{}`", name))
+                .collect();
+            if missing.is_empty() {
+                return None;
+            }
+
+            let mut comment = comment;
+            comment.rule_id = Some(OUTDATED_COMMENT_RULE.to_string());
+            comment.explanation = Some(format!(
+                "Comment mentions {}, which no longer exists in this scope; it may be outdated",
+                missing.join(", ")
+            ));
+            Some(comment)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_referenced_identifiers() {
+        assert_eq!(referenced_identifiers("// Calls `compute_total` then save()"), vec!["compute_total", "save"]);
+        assert_eq!(referenced_identifiers("// see `config::load_from_path`"), vec!["load_from_path"]);
+        assert_eq!(referenced_identifiers("# uses parseConfig internally"), vec!["parseConfig"]);
+        assert!(referenced_identifiers("// Adds two numbers together").is_empty());
+    }
+
+    #[test]
+    fn test_flags_comments_mentioning_missing_symbols() {
+        let source = r#"
+fn total(items: &[u32]) -> u32 {
+    // Delegates to `sum_items` for the actual work
+    items.iter().sum()
+}
+"#;
+        let outdated = find_outdated_comments(source, Language::Rust);
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated[0].rule_id.as_deref(), Some(OUTDATED_COMMENT_RULE));
+        assert!(outdated[0].explanation.as_deref().unwrap().contains("`sum_items`"));
+    }
+
+    #[test]
+    fn test_ignores_comments_mentioning_existing_symbols() {
+        let source = r#"
+def load_config(path):
+    return parse_config(open(path).read())
+
+def parse_config(text):
+    # Called from load_config(), keep the signature stable
+    return text
+"#;
+        assert!(find_outdated_comments(source, Language::Python).is_empty());
+    }
+
+    #[test]
+    fn test_uses_enclosing_scope_for_locals() {
+        let source = r#"
+function first() {
+    let retryCount = 0;
+    return retryCount;
+}
+
+function second() {
+    // resets retryCount before looping
+    return 1;
+}
+"#;
+        let outdated = find_outdated_comments(source, Language::JavaScript);
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated[0].line_number, 8);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::constants::{HISTORY_DIR, HISTORY_FILE_NAME};
+use crate::metrics::CommentMetrics;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The aggregate metrics of one `report --history` run, a line of the history file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When the run finished, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub files: usize,
+    pub code_lines: usize,
+    pub comments: usize,
+    pub redundant_comments: usize,
+    #[serde(default)]
+    pub todos: usize,
+}
+
+impl HistoryEntry {
+    /// The totals of a run finishing now.
+    pub fn from_metrics(total: &CommentMetrics) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        Self {
+            timestamp,
+            files: total.files,
+            code_lines: total.code_lines,
+            comments: total.comments,
+            redundant_comments: total.redundant_comments,
+            todos: total.todos,
+        }
+    }
+
+    /// The run's date as YYYY-MM-DD, in UTC.
+    pub fn date(&self) -> String {
+        let (year, month, day) = civil_from_days((self.timestamp / 86_400) as i64);
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+/// Where the history of the project at `root` is kept.
+pub fn history_path(root: &Path) -> PathBuf {
+    root.join(HISTORY_DIR).join(HISTORY_FILE_NAME)
+}
+
+/// Adds the entry to the end of the history file, creating it (and its
+/// directory) on the first run.
+pub fn append_history(path: &Path, entry: &HistoryEntry) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)
+            .map_err(|e| format!("failed to create {}: {}", directory.display(), e))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("failed to open {}: {}", path.display(), e))?;
+    let line = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    writeln!(file, "{}", line).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+/// Every entry of the history file, oldest first; none when there's no file yet.
+pub fn load_history(path: &Path) -> Result<Vec<HistoryEntry>, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+    };
+    contents.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| serde_json::from_str(line)
+            .map_err(|e| format!("{}:{}: invalid history entry: {}", path.display(), index + 1, e)))
+        .collect()
+}
+
+/// Year, month and day of the date `days` after 1970-01-01, from Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_history_round_trips_in_order() {
+        let temporary_directory = TempDir::new().unwrap();
+        let path = history_path(temporary_directory.path());
+        assert!(load_history(&path).unwrap().is_empty());
+
+        let first = HistoryEntry { timestamp: 1_700_000_000, comments: 40, redundant_comments: 12, ..Default::default() };
+        let second = HistoryEntry { timestamp: 1_700_604_800, comments: 35, redundant_comments: 5, ..Default::default() };
+        append_history(&path, &first).unwrap();
+        append_history(&path, &second).unwrap();
+        assert_eq!(load_history(&path).unwrap(), vec![first.clone(), second]);
+
+        std::fs::write(&path, format!("{}\nnot json\n", serde_json::to_string(&first).unwrap())).unwrap();
+        let error = load_history(&path).unwrap_err();
+        assert!(error.starts_with(&format!("{}:2: invalid history entry", path.display())), "{}", error);
+    }
+
+    #[test]
+    fn test_date() {
+        let date = |timestamp| HistoryEntry { timestamp, ..Default::default() }.date();
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(1_700_000_000), "2023-11-14");
+        assert_eq!(date(951_782_400), "2000-02-29");
+    }
+}
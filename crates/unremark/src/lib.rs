@@ -1,17 +1,80 @@
 pub use crate::types::{
     Language,
     CommentInfo,
+    CommentCategory,
+    Severity,
     CommentAnalysis,
+    CommentExplanation,
     AnalysisResult,
+    AnalysisOptions,
+    ApiSettings,
+    Provider,
     ApiError,
     Cache,
     CacheEntry,
 };
-pub use crate::analysis::{analyze_file, analyze_comments, analyze_current_file};
-pub use crate::utils::{find_context, remove_redundant_comments};
-pub use crate::comment_detection::detect_comments;
-pub use crate::constants::{OPENAI_MODEL, CACHE_FILE_NAME, get_proxy_endpoint};
-pub use services::proxy::{ProxyAnalysisService, AnalysisService, create_analysis_service};
+pub use crate::analysis::{analyze_file, analyze_file_with_options, analyze_files, analyze_comments, check_comment_accuracy, analyze_comments_with, try_analyze_comments, check_comment_accuracy_with, analyze_detected_comments, analyze_current_file, analyze_source, explain_comment};
+pub use crate::heuristics::{
+    Rule, CommentSite, NameEchoRule, DEFAULT_NAME_ECHO_THRESHOLD,
+    apply_rules, default_rules, rules_for, default_severity, finding_severity, LOW_CONFIDENCE, BUILTIN_RULE_IDS, MISLEADING_COMMENT_RULE, REDUNDANT_COMMENT_RULE,
+    CommentedOutCodeRule, COMMENTED_OUT_CODE_RULE, flag_regex_comments, REGEX_RULE,
+    prefilter_comments, useful_comment_reason, PREFILTER_MAX_CODE_OVERLAP, PREFILTER_MIN_SENTENCES,
+    find_outdated_comments, OUTDATED_COMMENT_RULE,
+    find_duplicate_comments, find_duplicate_comments_in_files, flag_duplicate_comments,
+    CommentLocation, DuplicateComment, DEFAULT_MIN_DUPLICATES, DUPLICATE_COMMENT_RULE,
+};
+pub use crate::config::{
+    CircuitBreakerConfig, ClassifierConfig, RetryConfig, Config, CredentialsConfig, GenerationConfig, LicenseConfig, NetworkConfig, GrammarConfig, OpenAiConfig, OpenRouterConfig, PluginConfig, PromptExample, RateLimitConfig, ProxyConfig, RegexAction, RegexRule, RuleConfig, RulesConfig, CONFIG_FILE_NAMES,
+};
+pub use crate::grammars::{load_grammars, load_languages};
+pub use crate::plugins::{apply_policies, flag_policy_comments, load_plugins, CommentPolicy, Verdict};
+#[cfg(feature = "plugins")]
+pub use crate::plugins::WasmPlugin;
+pub use crate::classifier::{load_classifier, LocalClassifier, RedundancyScorer, CLASSIFIER_RULE};
+#[cfg(feature = "classifier")]
+pub use crate::classifier::{OnnxClassifier, WordPiece};
+pub use crate::license::{find_files_missing_license, has_license_header, is_license_text};
+pub use crate::metrics::{
+    aggregate_by_directory, aggregate_by_language, compute_file_metrics, find_comment_blocks, total_metrics, CommentBlock,
+    CommentMetrics,
+};
+pub use crate::history::{append_history, history_path, load_history, HistoryEntry};
+pub use crate::telemetry::serve_metrics;
+pub use crate::changes::{repository_root, ChangedLines};
+pub use crate::blame::{annotate_blame, CommentBlame};
+pub use crate::baseline::{subtract_findings, MergeBase};
+pub use crate::github::{
+    fetch_pull_request_diff, post_review, review_comments, ReviewComment, DEFAULT_GITHUB_API_URL,
+};
+pub use crate::todos::{find_todo_comments, find_todos_in_file, is_todo_comment, parse_age, todo_tag, TodoComment};
+pub use crate::utils::{
+    annotate_redundant_comments, decode_source, encode_source, find_context, get_cache_path, is_annotated, read_source,
+    remove_redundant_comments, ContextBudget,
+};
+pub use crate::patch::generate_patch;
+pub use crate::verify::verify_fix;
+pub use crate::estimate::{estimate_file, estimate_source, UsageEstimate};
+pub use crate::timings::{Phase, PhaseTimings, Timings};
+pub use crate::usage::{TokenUsage, UsageReport};
+pub use crate::grpc::serve_grpc;
+#[cfg(feature = "grpc")]
+pub use crate::grpc::{
+    AnalyzeRequest, Comment, DetectCommentsResponse, FileResult, SourceFile, Unremark, UnremarkClient, UnremarkServer,
+    UnremarkService,
+};
+pub use crate::mcp::serve_mcp;
+pub use crate::daemon::{default_daemon_socket, serve_daemon, DaemonClient, DaemonRequest, DAEMON_SOCKET_ENV};
+#[cfg(feature = "mcp")]
+pub use crate::mcp::{FileFindings, ToolRequest, ToolResponse, UnremarkMcpServer};
+pub use crate::comment_detection::{detect_comments, detect_comments_with};
+pub use crate::api::{check_connection, RateLimitSender, RetryPolicy};
+pub use crate::rate_limit::RateLimiter;
+pub use crate::circuit_breaker::CircuitBreaker;
+pub use crate::replay::{FixtureMode, Fixtures};
+pub use crate::credentials::{resolve_api_key, store_keychain_key};
+pub use crate::trust::{trusted_workspaces_path, TrustedWorkspaces};
+pub use crate::constants::{OPENAI_MODEL, CACHE_FILE_NAME, DEFAULT_REQUEST_TIMEOUT, DEFAULT_FILE_CONCURRENCY, DEFAULT_MAX_FILE_SIZE, DEFAULT_CONTEXT_TOKENS, DEFAULT_CONTEXT_LINES, ANNOTATION_MARKER, OPENAI_INPUT_PRICE, OPENAI_OUTPUT_PRICE, OPENAI_API_KEY_ENV, OPENAI_BASE_URL_ENV, OPENROUTER_API_KEY_ENV, DEFAULT_OPENROUTER_MODEL, PROXY_TOKEN_ENV, TRUST_WORKSPACE_ENV, KEYCHAIN_SERVICE, get_proxy_endpoint};
+pub use services::proxy::{ProxyAnalysisService, AnalysisService, Compression, create_analysis_service};
 
 // Internal modules
 mod types;
@@ -19,14 +82,46 @@ mod constants;
 mod analysis;
 mod utils;
 mod api;
+mod rate_limit;
+mod circuit_breaker;
+mod replay;
+mod credentials;
+mod trust;
 mod comment_detection;
+mod heuristics;
+mod todos;
+mod changes;
+mod blame;
+mod baseline;
+mod github;
+mod patch;
+mod verify;
+mod sfc;
+mod markdown;
+mod grammars;
+mod timings;
+mod usage;
+mod grpc;
+mod mcp;
+mod daemon;
+mod config;
+mod license;
+mod metrics;
+mod history;
+mod telemetry;
+mod estimate;
+mod plugins;
+mod classifier;
 mod bindings;
 mod services;
 
 
 // Python bindings (only when python feature is enabled)
 #[cfg(feature = "python")]
-pub use bindings::python::{py_analyze_comments, PyCommentInfo};
+pub use bindings::python::{
+    py_analyze_comments, py_analyze_directory, py_analyze_file, py_generate_patch, py_iter_analyze_directory,
+    py_remove_redundant_comments, register_errors, PyAnalysisResult, PyCommentInfo, PyConfig, PyDirectoryAnalysis,
+};
 
 #[cfg(feature = "python")]
 use pyo3::prelude::*;
@@ -35,6 +130,15 @@ use pyo3::prelude::*;
 #[pymodule]
 fn unremark(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PyCommentInfo>()?;
+    m.add_class::<PyAnalysisResult>()?;
+    m.add_class::<PyConfig>()?;
+    m.add_class::<PyDirectoryAnalysis>()?;
+    register_errors(m)?;
     m.add_function(wrap_pyfunction!(py_analyze_comments, m)?)?;
+    m.add_function(wrap_pyfunction!(py_analyze_file, m)?)?;
+    m.add_function(wrap_pyfunction!(py_analyze_directory, m)?)?;
+    m.add_function(wrap_pyfunction!(py_iter_analyze_directory, m)?)?;
+    m.add_function(wrap_pyfunction!(py_remove_redundant_comments, m)?)?;
+    m.add_function(wrap_pyfunction!(py_generate_patch, m)?)?;
     Ok(())
 }
@@ -0,0 +1,135 @@
+use crate::comment_detection::detect_comments;
+use crate::config::LicenseConfig;
+use crate::types::{CommentCategory, CommentInfo, Language};
+use crate::utils::read_source;
+use regex::Regex;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+fn license_regex() -> &'static Regex {
+    static LICENSE: OnceLock<Regex> = OnceLock::new();
+    LICENSE.get_or_init(|| {
+        Regex::new(concat!(
+            r"(?i)SPDX-License-Identifier:",
+            r"|\bcopyright\s+(\(c\)|©|\d{4})",
+            r"|Licensed under the Apache License",
+            r"|Permission is hereby granted, free of charge",
+            r"|GNU (Lesser |Affero )?General Public License",
+            r"|Mozilla Public License",
+            r"|\bMIT License\b",
+            r"|\bBSD-\d-Clause\b",
+        ))
+        .unwrap()
+    })
+}
+
+/// Whether the comment text looks like (part of) a license notice.
+pub fn is_license_text(comment_text: &str) -> bool {
+    license_regex().is_match(comment_text)
+}
+
+fn last_line(comment: &CommentInfo) -> usize {
+    comment.line_number + comment.text.lines().count().max(1) - 1
+}
+
+/// Marks the license header of a file: any block of adjacent comments above
+/// `first_code_line` that contains license text.
+pub fn mark_license_headers(comments: &mut [CommentInfo], first_code_line: Option<usize>) {
+    let header_len = comments.iter()
+        .take_while(|comment| first_code_line.is_none_or(|line| comment.line_number < line))
+        .count();
+
+    let mut block_start = 0;
+    for index in 0..header_len {
+        let block_ends = index + 1 == header_len
+            || comments[index + 1].line_number > last_line(&comments[index]) + 1;
+        if !block_ends {
+            continue;
+        }
+
+        let block = &mut comments[block_start..=index];
+        if block.iter().any(|comment| is_license_text(&comment.text)) {
+            for comment in block {
+                comment.category = CommentCategory::License;
+            }
+        }
+        block_start = index + 1;
+    }
+}
+
+/// Whether the source starts with a license header that satisfies the config.
+pub fn has_license_header(source_code: &str, language: Language, config: &LicenseConfig) -> bool {
+    let headers: Vec<CommentInfo> = detect_comments(source_code, language)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(CommentInfo::is_license)
+        .collect();
+
+    match &config.required_header {
+        Some(required) => headers.iter().any(|comment| comment.text.contains(required.as_str())),
+        None => !headers.is_empty(),
+    }
+}
+
+/// Files whose license header is missing or lacks the configured text.
+pub fn find_files_missing_license(paths: &[PathBuf], config: &LicenseConfig) -> Vec<PathBuf> {
+    paths.iter()
+        .filter(|path| {
+            let Ok(source_code) = read_source(path) else {
+                return false;
+            };
+            Language::detect(path, &source_code)
+                .is_some_and(|language| !has_license_header(&source_code, language, config))
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_license_text() {
+        assert!(is_license_text("// SPDX-License-Identifier: Apache-2.0"));
+        assert!(is_license_text("# Copyright (c) 2024 Acme Corp."));
+        assert!(is_license_text("/* Licensed under the Apache License, Version 2.0 */"));
+        assert!(!is_license_text("// Copy the right half of the buffer"));
+        assert!(!is_license_text("// Parses the license key from the request"));
+    }
+
+    #[test]
+    fn test_marks_whole_header_block() {
+        let source = "\
+// Copyright 2024 Acme Corp.
+//
+// Licensed under the Apache License, Version 2.0
+
+// Entry point for the worker
+fn main() {
+    // SPDX-License-Identifier: MIT
+}
+";
+        let comments = detect_comments(source, Language::Rust).unwrap();
+
+        let categories: Vec<CommentCategory> = comments.iter().map(|c| c.category).collect();
+        assert_eq!(categories, vec![
+            CommentCategory::License,
+            CommentCategory::License,
+            CommentCategory::License,
+            CommentCategory::Regular,
+            CommentCategory::Regular,
+        ], "Only the leading banner counts as a header");
+    }
+
+    #[test]
+    fn test_has_license_header_respects_config() {
+        let source = "# SPDX-License-Identifier: MIT\nimport os\n";
+        let any = LicenseConfig::default();
+        let apache = LicenseConfig { required_header: Some("SPDX-License-Identifier: Apache-2.0".to_string()) };
+
+        assert!(has_license_header(source, Language::Python, &any));
+        assert!(!has_license_header(source, Language::Python, &apache));
+        assert!(!has_license_header("import os\n", Language::Python, &any));
+    }
+}
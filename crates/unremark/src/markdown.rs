@@ -0,0 +1,110 @@
+use crate::types::Language;
+use std::ops::Range;
+
+/// The code of a Markdown file's fenced blocks, one source per language: the
+/// blocks in that language with everything around them blanked out, so the
+/// lines and byte ranges found in it are the Markdown file's. Blocks without
+/// a recognized language in their info string are left out.
+pub(crate) fn fenced_sources(markdown: &str) -> Vec<(Language, String)> {
+    let mut blocks: Vec<(Language, Vec<Range<usize>>)> = Vec::new();
+    for (language, range) in fenced_blocks(markdown) {
+        match blocks.iter_mut().find(|(other, _)| *other == language) {
+            Some((_, ranges)) => ranges.push(range),
+            None => blocks.push((language, vec![range])),
+        }
+    }
+    blocks.into_iter()
+        .map(|(language, ranges)| {
+            let bytes: Vec<u8> = markdown.bytes().enumerate()
+                .map(|(i, byte)| match byte {
+                    b'\n' => b'\n',
+                    _ if ranges.iter().any(|range| range.contains(&i)) => byte,
+                    _ => b' ',
+                })
+                .collect();
+            // Whole characters are kept or blanked, so the bytes stay UTF-8
+            (language, String::from_utf8(bytes).unwrap_or_default())
+        })
+        .collect()
+}
+
+/// The language and the byte range of the contents of each fenced code block.
+fn fenced_blocks(markdown: &str) -> Vec<(Language, Range<usize>)> {
+    let mut blocks = Vec::new();
+    // The fence character, its length, the block's language and where its contents start
+    let mut open: Option<(char, usize, Option<Language>, usize)> = None;
+    let mut offset = 0;
+    for line in markdown.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let trimmed = line.trim_start_matches(' ');
+        if line.len() - trimmed.len() > 3 {
+            continue;
+        }
+        let Some(fence) = trimmed.chars().next().filter(|c| *c == '`' || *c == '~') else {
+            continue;
+        };
+        let length = trimmed.chars().take_while(|c| *c == fence).count();
+        if length < 3 {
+            continue;
+        }
+        let info = trimmed[length..].trim();
+        match open {
+            Some((open_fence, open_length, language, contents)) => {
+                if fence == open_fence && length >= open_length && info.is_empty() {
+                    if let Some(language) = language {
+                        blocks.push((language, contents..start));
+                    }
+                    open = None;
+                }
+            }
+            // Backtick fences can't have backticks in their info string
+            None if fence == '`' && info.contains('`') => {}
+            None => open = Some((fence, length, fence_language(info), offset)),
+        }
+    }
+    // A block left open runs to the end of the file
+    if let Some((_, _, Some(language), contents)) = open {
+        blocks.push((language, contents..markdown.len()));
+    }
+    blocks
+}
+
+/// The language named by the first word of a fence's info string, by its
+/// name or file extension, e.g. `rust` or `rs`.
+fn fence_language(info: &str) -> Option<Language> {
+    Language::from_name(info.split(|c: char| c.is_whitespace() || c == ',' || c == '{').next()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fenced_blocks_keep_their_positions() {
+        let markdown = "# Usage\n\n```rust\n// Make one\nlet x = 1;\n```\n\nSome `code` and text é.\n\n~~~ python title=\"x\"\n# Print it\n````\nprint(x)\n~~~\n\n```\nplain\n```\n\n```rs\nlet y = 2; // two\n```\n";
+        let sources = fenced_sources(markdown);
+        let languages: Vec<Language> = sources.iter().map(|(language, _)| *language).collect();
+        assert_eq!(languages, vec![Language::Rust, Language::Python]);
+
+        let (_, rust) = &sources[0];
+        assert_eq!(rust.len(), markdown.len());
+        assert_eq!(rust.lines().count(), markdown.lines().count());
+        assert_eq!(rust.lines().nth(3), Some("// Make one"));
+        assert_eq!(rust.lines().nth(20), Some("let y = 2; // two"));
+        assert_eq!(rust.lines().nth(7).map(str::trim), Some(""));
+
+        let (_, python) = &sources[1];
+        assert_eq!(python.split_whitespace().collect::<Vec<&str>>(), vec!["#", "Print", "it", "````", "print(x)"]);
+    }
+
+    #[test]
+    fn test_fence_languages() {
+        assert_eq!(fence_language("rust"), Some(Language::Rust));
+        assert_eq!(fence_language("TypeScript"), Some(Language::TypeScript));
+        assert_eq!(fence_language("py {linenos=true}"), Some(Language::Python));
+        assert_eq!(fence_language("c++"), Some(Language::Cpp));
+        assert_eq!(fence_language("console"), None);
+        assert_eq!(fence_language(""), None);
+    }
+}
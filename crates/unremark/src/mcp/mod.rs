@@ -0,0 +1,31 @@
+use crate::types::AnalysisOptions;
+use std::path::Path;
+
+#[cfg(feature = "mcp")]
+mod server;
+
+#[cfg(feature = "mcp")]
+pub use server::*;
+
+/// Serves the `detect_redundant_comments` and `apply_fixes` tools over the
+/// Model Context Protocol on stdin/stdout, confined to the workspace at `root`.
+#[cfg(feature = "mcp")]
+pub async fn serve_mcp(root: &Path, options: AnalysisOptions) -> Result<(), String> {
+    use rmcp::ServiceExt;
+
+    let server = UnremarkMcpServer::new(root, options)?;
+    let service = server.serve(rmcp::transport::stdio())
+        .await
+        .map_err(|e| format!("MCP server failed to start: {}", e))?;
+    service.waiting()
+        .await
+        .map_err(|e| format!("MCP server failed: {}", e))?;
+    Ok(())
+}
+
+/// Serves the `detect_redundant_comments` and `apply_fixes` tools over the
+/// Model Context Protocol on stdin/stdout, confined to the workspace at `root`.
+#[cfg(not(feature = "mcp"))]
+pub async fn serve_mcp(root: &Path, _options: AnalysisOptions) -> Result<(), String> {
+    Err(format!("cannot serve MCP for {}: unremark was built without the `mcp` feature", root.display()))
+}
@@ -0,0 +1,203 @@
+use crate::analysis::analyze_file_with_options;
+use crate::types::{AnalysisOptions, Cache, CommentInfo, Language};
+use futures::StreamExt;
+use rmcp::handler::server::{tool::ToolRouter, wrapper::Parameters};
+use rmcp::model::{Implementation, ServerCapabilities, ServerInfo};
+use rmcp::{tool, tool_handler, tool_router, ErrorData as McpError, Json, ServerHandler};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Arguments shared by both tools.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct ToolRequest {
+    /// File or directory relative to the workspace root, the whole workspace if omitted
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Only run the local heuristic rules, without calling the API
+    #[serde(default)]
+    pub offline: bool,
+}
+
+/// Findings in one file.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FileFindings {
+    /// Path relative to the workspace root
+    pub path: String,
+    pub redundant_comments: Vec<CommentInfo>,
+    /// Comments worth a second look that `apply_fixes` never removes
+    pub flagged_comments: Vec<CommentInfo>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ToolResponse {
+    pub files_analyzed: usize,
+    /// Only the files with findings or errors
+    pub files: Vec<FileFindings>,
+}
+
+/// MCP server letting coding agents analyze and fix the files of one workspace.
+#[derive(Debug, Clone)]
+pub struct UnremarkMcpServer {
+    root: PathBuf,
+    options: Arc<AnalysisOptions>,
+    tool_router: ToolRouter<Self>,
+}
+
+#[tool_router]
+impl UnremarkMcpServer {
+    pub fn new(root: &Path, options: AnalysisOptions) -> Result<Self, String> {
+        let root = root.canonicalize()
+            .map_err(|e| format!("cannot open workspace {}: {}", root.display(), e))?;
+        Ok(Self { root, options: Arc::new(options), tool_router: Self::tool_router() })
+    }
+
+    #[tool(description = "Find comments that only restate the code next to them, plus comments worth a second \
+        look (e.g. possibly outdated), in a file or directory of the workspace. Nothing is changed.")]
+    pub async fn detect_redundant_comments(
+        &self,
+        Parameters(request): Parameters<ToolRequest>,
+    ) -> Result<Json<ToolResponse>, McpError> {
+        self.analyze(request, false).await.map(Json)
+    }
+
+    #[tool(description = "Remove the redundant comments from a file or directory of the workspace, returning the \
+        comments that were removed. Flagged comments are reported but left in place.")]
+    pub async fn apply_fixes(
+        &self,
+        Parameters(request): Parameters<ToolRequest>,
+    ) -> Result<Json<ToolResponse>, McpError> {
+        self.analyze(request, true).await.map(Json)
+    }
+}
+
+impl UnremarkMcpServer {
+    /// Resolves a tool's path argument, refusing anything outside the workspace.
+    fn resolve(&self, path: Option<&str>) -> Result<PathBuf, McpError> {
+        let Some(path) = path else {
+            return Ok(self.root.clone());
+        };
+        let resolved = self.root.join(path).canonicalize()
+            .map_err(|e| McpError::invalid_params(format!("cannot open {}: {}", path, e), None))?;
+        if !resolved.starts_with(&self.root) {
+            return Err(McpError::invalid_params(format!("{} is outside the workspace", path), None));
+        }
+        Ok(resolved)
+    }
+
+    async fn analyze(&self, request: ToolRequest, fix: bool) -> Result<ToolResponse, McpError> {
+        let target = self.resolve(request.path.as_deref())?;
+        let files = source_files(&target);
+        let options = AnalysisOptions {
+            offline: request.offline || self.options.offline,
+            ..(*self.options).clone()
+        };
+
+        let cache = parking_lot::RwLock::new(Cache::load());
+        let analyses: Vec<_> = files.iter().map(|path| analyze_file_with_options(path, fix, &cache, &options)).collect();
+        let results: Vec<_> = futures::stream::iter(analyses)
+            .buffered(options.file_concurrency.max(1))
+            .collect()
+            .await;
+        if !options.offline {
+            cache.read().save();
+        }
+
+        let findings = results.into_iter()
+            .filter(|result| !result.redundant_comments.is_empty() || !result.flagged_comments.is_empty() || !result.errors.is_empty())
+            .map(|result| FileFindings {
+                path: result.path.strip_prefix(&self.root).unwrap_or(&result.path).display().to_string(),
+                redundant_comments: result.redundant_comments,
+                flagged_comments: result.flagged_comments,
+                errors: result.errors,
+            })
+            .collect();
+        Ok(ToolResponse { files_analyzed: files.len(), files: findings })
+    }
+}
+
+#[tool_handler]
+impl ServerHandler for UnremarkMcpServer {
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            instructions: Some(
+                "Finds and removes redundant code comments. Call detect_redundant_comments to review them, \
+                 then apply_fixes to remove them."
+                    .into(),
+            ),
+            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            server_info: Implementation {
+                name: "unremark".into(),
+                version: env!("CARGO_PKG_VERSION").into(),
+                ..Implementation::from_build_env()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+/// Source files under `target` (or `target` itself), honoring `.gitignore`.
+fn source_files(target: &Path) -> Vec<PathBuf> {
+    ignore::WalkBuilder::new(target)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+        .map(|entry| entry.into_path())
+        .filter(|path| Language::of_file(path).is_some())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/main.rs"), "fn main() {\n    // Constructor\n    let x = 1;\n}\n").unwrap();
+        std::fs::write(dir.path().join("src/lib.py"), "def f():\n    return 1\n").unwrap();
+        dir
+    }
+
+    fn server(root: &Path) -> UnremarkMcpServer {
+        UnremarkMcpServer::new(root, AnalysisOptions { offline: true, ..Default::default() }).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_detect_redundant_comments_reports_without_changing_files() {
+        let dir = workspace();
+        let Json(response) = server(dir.path())
+            .detect_redundant_comments(Parameters(ToolRequest::default()))
+            .await
+            .unwrap();
+
+        assert_eq!(response.files_analyzed, 2);
+        assert_eq!(response.files.len(), 1);
+        assert_eq!(response.files[0].path, Path::new("src").join("main.rs").display().to_string());
+        assert_eq!(response.files[0].redundant_comments[0].rule_id.as_deref(), Some("boilerplate-comment"));
+        assert!(std::fs::read_to_string(dir.path().join("src/main.rs")).unwrap().contains("// Constructor"));
+    }
+
+    #[tokio::test]
+    async fn test_apply_fixes_removes_comments() {
+        let dir = workspace();
+        let request = ToolRequest { path: Some("src/main.rs".to_string()), offline: true };
+        let Json(response) = server(dir.path()).apply_fixes(Parameters(request)).await.unwrap();
+
+        assert_eq!(response.files_analyzed, 1);
+        assert_eq!(response.files[0].redundant_comments.len(), 1);
+        assert!(!std::fs::read_to_string(dir.path().join("src/main.rs")).unwrap().contains("// Constructor"));
+    }
+
+    #[tokio::test]
+    async fn test_paths_outside_workspace_are_refused() {
+        let dir = workspace();
+        let request = ToolRequest { path: Some("..".to_string()), offline: true };
+        let Err(error) = server(&dir.path().join("src")).detect_redundant_comments(Parameters(request)).await else {
+            panic!("expected the parent directory to be refused");
+        };
+        assert!(error.message.contains("outside the workspace"));
+    }
+}
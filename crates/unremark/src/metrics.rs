@@ -0,0 +1,361 @@
+use crate::comment_detection::{is_comment_node, is_doc_attribute, is_docstring};
+use crate::types::Language;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use tree_sitter::{Node, Parser};
+
+/// Comment counts for a file, or summed over a directory.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommentMetrics {
+    pub path: PathBuf,
+    /// Set on per-language totals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<Language>,
+    pub files: usize,
+    /// Non-blank lines with code on them
+    pub code_lines: usize,
+    /// Lines holding only comments (including doc comments and docstrings)
+    pub comment_lines: usize,
+    pub comments: usize,
+    pub redundant_comments: usize,
+    /// Comments tagged TODO, FIXME, HACK or XXX
+    pub todos: usize,
+    pub public_functions: usize,
+    pub documented_public_functions: usize,
+}
+
+impl CommentMetrics {
+    /// Share of non-blank lines that are comments.
+    pub fn comment_density(&self) -> f64 {
+        ratio(self.comment_lines, self.code_lines + self.comment_lines)
+    }
+
+    /// Comments per thousand lines of code.
+    pub fn comments_per_kloc(&self) -> f64 {
+        ratio(self.comments * 1000, self.code_lines)
+    }
+
+    /// Share of analyzed comments found redundant.
+    pub fn redundant_ratio(&self) -> f64 {
+        ratio(self.redundant_comments, self.comments)
+    }
+
+    /// Share of public functions with a doc comment; 1.0 when there are none.
+    pub fn doc_coverage(&self) -> f64 {
+        if self.public_functions == 0 {
+            return 1.0;
+        }
+        ratio(self.documented_public_functions, self.public_functions)
+    }
+
+    fn add(&mut self, other: &CommentMetrics) {
+        self.files += other.files;
+        self.code_lines += other.code_lines;
+        self.comment_lines += other.comment_lines;
+        self.comments += other.comments;
+        self.redundant_comments += other.redundant_comments;
+        self.todos += other.todos;
+        self.public_functions += other.public_functions;
+        self.documented_public_functions += other.documented_public_functions;
+    }
+}
+
+fn ratio(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// Line counts and doc coverage for one file. `comments` and
+/// `redundant_comments` are left for the caller to fill in from an analysis.
+pub fn compute_file_metrics(source_code: &str, language: Language, path: &Path) -> CommentMetrics {
+    let mut metrics = CommentMetrics {
+        path: path.to_path_buf(),
+        language: Some(language),
+        files: 1,
+        ..Default::default()
+    };
+
+    let Some(tree) = parse(source_code, language) else {
+        return metrics;
+    };
+
+    let lines: Vec<&str> = source_code.lines().collect();
+    let mut comment_only_lines = HashSet::new();
+    collect_comment_lines(tree.root_node(), source_code, &lines, language, &mut comment_only_lines);
+
+    let non_blank = lines.iter().filter(|line| !line.trim().is_empty()).count();
+    metrics.comment_lines = comment_only_lines.len();
+    metrics.code_lines = non_blank.saturating_sub(comment_only_lines.len());
+
+    count_public_functions(tree.root_node(), source_code, language, &mut metrics);
+    metrics
+}
+
+/// A run of consecutive comment-only lines.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommentBlock {
+    pub path: PathBuf,
+    /// 1-based line the block starts on
+    pub line: usize,
+    pub lines: usize,
+}
+
+/// Every run of consecutive comment-only lines in a file, in source order.
+/// A blank line ends a run.
+pub fn find_comment_blocks(source_code: &str, language: Language, path: &Path) -> Vec<CommentBlock> {
+    let Some(tree) = parse(source_code, language) else {
+        return Vec::new();
+    };
+    let lines: Vec<&str> = source_code.lines().collect();
+    let mut rows = HashSet::new();
+    collect_comment_lines(tree.root_node(), source_code, &lines, language, &mut rows);
+
+    let mut rows: Vec<usize> = rows.into_iter().collect();
+    rows.sort_unstable();
+    let mut blocks: Vec<CommentBlock> = Vec::new();
+    for row in rows {
+        match blocks.last_mut() {
+            Some(block) if block.line - 1 + block.lines == row => block.lines += 1,
+            _ => blocks.push(CommentBlock { path: path.to_path_buf(), line: row + 1, lines: 1 }),
+        }
+    }
+    blocks
+}
+
+fn parse(source_code: &str, language: Language) -> Option<tree_sitter::Tree> {
+    let mut parser = Parser::new();
+    parser.set_language(&language.get_tree_sitter_language()).ok()?;
+    crate::sfc::parse(&mut parser, source_code, language)
+}
+
+/// Rows made up of nothing but a comment (or a Python docstring, an Elixir
+/// doc attribute or a Haddock comment).
+fn collect_comment_lines(node: Node, code: &str, lines: &[&str], language: Language, rows: &mut HashSet<usize>) {
+    let is_comment = is_comment_node(node)
+        || (matches!(language, Language::Python) && is_docstring(node))
+        || (matches!(language, Language::Elixir) && is_doc_attribute(node, code))
+        || node.kind() == "haddock";
+    if is_comment {
+        let start = node.start_position();
+        let end = node.end_position();
+        let alone_on_start = lines.get(start.row).is_some_and(|line| line[..start.column.min(line.len())].trim().is_empty());
+        let alone_on_end = lines.get(end.row).is_some_and(|line| line[end.column.min(line.len())..].trim().is_empty());
+
+        for row in start.row..=end.row {
+            let shares_line = (row == start.row && !alone_on_start) || (row == end.row && !alone_on_end);
+            if !shares_line && lines.get(row).is_some_and(|line| !line.trim().is_empty()) {
+                rows.insert(row);
+            }
+        }
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_comment_lines(child, code, lines, language, rows);
+    }
+}
+
+fn is_doc_comment(node: Node, code: &str) -> bool {
+    let text = node.utf8_text(code.as_bytes()).unwrap_or_default();
+    is_comment_node(node) && (text.starts_with("///") || text.starts_with("/**"))
+}
+
+/// The doc comment directly above `node`, skipping attributes and decorators.
+fn has_leading_doc_comment(node: Node, code: &str) -> bool {
+    let mut sibling = node.prev_sibling();
+    while let Some(previous) = sibling {
+        if previous.kind() == "attribute_item" || previous.kind() == "decorator" {
+            sibling = previous.prev_sibling();
+            continue;
+        }
+        return is_doc_comment(previous, code);
+    }
+    false
+}
+
+fn count_public_functions(node: Node, code: &str, language: Language, metrics: &mut CommentMetrics) {
+    let documented = match (language, node.kind()) {
+        (Language::Rust, "function_item") => {
+            let mut cursor = node.walk();
+            let is_public = node.children(&mut cursor).any(|child| child.kind() == "visibility_modifier");
+            is_public.then(|| has_leading_doc_comment(node, code))
+        }
+        (Language::Python, "function_definition") => {
+            let name = node.child_by_field_name("name")
+                .and_then(|name| name.utf8_text(code.as_bytes()).ok())
+                .unwrap_or_default();
+            let has_docstring = node.child_by_field_name("body")
+                .and_then(|body| body.named_child(0))
+                .is_some_and(is_docstring);
+            (!name.starts_with('_')).then_some(has_docstring)
+        }
+        (Language::JavaScript | Language::TypeScript, "function_declaration") => node.parent()
+            .filter(|parent| parent.kind() == "export_statement")
+            .map(|export| has_leading_doc_comment(export, code)),
+        // `def` is public, `defp` private; `@doc` goes right above
+        (Language::Elixir, "call") => node.child_by_field_name("target")
+            .filter(|target| target.utf8_text(code.as_bytes()) == Ok("def"))
+            .map(|_| node.prev_named_sibling().is_some_and(|previous| is_doc_attribute(previous, code))),
+        _ => None,
+    };
+
+    if let Some(documented) = documented {
+        metrics.public_functions += 1;
+        if documented {
+            metrics.documented_public_functions += 1;
+        }
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        count_public_functions(child, code, language, metrics);
+    }
+}
+
+/// Sums file metrics into one entry per directory, sorted by path.
+pub fn aggregate_by_directory(files: &[CommentMetrics]) -> Vec<CommentMetrics> {
+    let mut directories: BTreeMap<PathBuf, CommentMetrics> = BTreeMap::new();
+    for file in files {
+        let directory = file.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        directories.entry(directory.clone())
+            .or_insert_with(|| CommentMetrics { path: directory, ..Default::default() })
+            .add(file);
+    }
+    directories.into_values().collect()
+}
+
+/// Sums file metrics into one entry per language, sorted by language.
+pub fn aggregate_by_language(files: &[CommentMetrics]) -> Vec<CommentMetrics> {
+    let mut languages: BTreeMap<Language, CommentMetrics> = BTreeMap::new();
+    for file in files {
+        let Some(language) = file.language else { continue };
+        languages.entry(language)
+            .or_insert_with(|| CommentMetrics { language: Some(language), ..Default::default() })
+            .add(file);
+    }
+    languages.into_values().collect()
+}
+
+/// Sums every file into a single project-wide entry.
+pub fn total_metrics(files: &[CommentMetrics]) -> CommentMetrics {
+    let mut total = CommentMetrics::default();
+    for file in files {
+        total.add(file);
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_metrics() {
+        let source = r#"
+// Helpers for math
+use std::fmt;
+
+/// Adds two numbers
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b // trailing comments don't make a comment line
+}
+
+#[inline]
+pub fn sub(a: i32, b: i32) -> i32 {
+    /* block
+       comment */
+    a - b
+}
+
+fn private() {}
+"#;
+        let metrics = compute_file_metrics(source, Language::Rust, Path::new("src/math.rs"));
+
+        assert_eq!(metrics.comment_lines, 4);
+        assert_eq!(metrics.code_lines, 9);
+        assert_eq!(metrics.public_functions, 2);
+        assert_eq!(metrics.documented_public_functions, 1);
+        assert_eq!(metrics.doc_coverage(), 0.5);
+    }
+
+    #[test]
+    fn test_python_docstrings_count_as_docs() {
+        let source = "def public():\n    \"\"\"Does things.\"\"\"\n    return 1\n\ndef other():\n    return 2\n\ndef _helper():\n    pass\n";
+        let metrics = compute_file_metrics(source, Language::Python, Path::new("app.py"));
+
+        assert_eq!(metrics.public_functions, 2);
+        assert_eq!(metrics.documented_public_functions, 1);
+        assert_eq!(metrics.comment_lines, 1);
+    }
+
+    #[test]
+    fn test_elixir_doc_attributes_count_as_docs() {
+        let source = "defmodule Math do\n  @moduledoc \"\"\"\n  Math helpers.\n  \"\"\"\n\n  @doc \"Adds.\"\n  def add(a, b), do: a + b\n\n  def mul(a, b), do: a * b\n\n  defp sub(a, b), do: a - b\nend\n";
+        let metrics = compute_file_metrics(source, Language::Elixir, Path::new("math.ex"));
+
+        assert_eq!(metrics.public_functions, 2);
+        assert_eq!(metrics.documented_public_functions, 1);
+        assert_eq!(metrics.comment_lines, 4);
+    }
+
+    #[test]
+    fn test_javascript_exported_functions() {
+        let source = "/** Documented */\nexport function a() {}\nexport function b() {}\nfunction c() {}\n";
+        let metrics = compute_file_metrics(source, Language::JavaScript, Path::new("index.js"));
+
+        assert_eq!(metrics.public_functions, 2);
+        assert_eq!(metrics.documented_public_functions, 1);
+    }
+
+    #[test]
+    fn test_aggregate_by_directory() {
+        let file = |path: &str, comments, redundant_comments| CommentMetrics {
+            path: PathBuf::from(path),
+            files: 1,
+            comments,
+            redundant_comments,
+            ..Default::default()
+        };
+        let files = vec![file("src/a.rs", 4, 1), file("src/b.rs", 6, 4), file("tests/c.rs", 2, 0)];
+
+        let directories = aggregate_by_directory(&files);
+        assert_eq!(directories.len(), 2);
+        assert_eq!(directories[0].path, PathBuf::from("src"));
+        assert_eq!(directories[0].files, 2);
+        assert_eq!(directories[0].redundant_ratio(), 0.5);
+        assert_eq!(total_metrics(&files).comments, 12);
+    }
+
+    #[test]
+    fn test_aggregate_by_language() {
+        let file = |path: &str, language, comments, code_lines| CommentMetrics {
+            path: PathBuf::from(path),
+            language: Some(language),
+            files: 1,
+            comments,
+            code_lines,
+            ..Default::default()
+        };
+        let files = vec![file("a.rs", Language::Rust, 3, 100), file("b.py", Language::Python, 1, 50), file("c.rs", Language::Rust, 2, 150)];
+
+        let languages = aggregate_by_language(&files);
+        assert_eq!(languages.len(), 2);
+        assert_eq!(languages[0].language, Some(Language::Python));
+        assert_eq!(languages[1].files, 2);
+        assert_eq!(languages[1].comments_per_kloc(), 20.0);
+    }
+
+    #[test]
+    fn test_comment_blocks() {
+        let source = "// one\n// two\n// three\nfn a() {}\n\n// four\n\n/* five\n   six */\nfn b() {} // trailing\n";
+        let blocks = find_comment_blocks(source, Language::Rust, Path::new("lib.rs"));
+
+        let spans: Vec<_> = blocks.iter().map(|block| (block.line, block.lines)).collect();
+        assert_eq!(spans, vec![(1, 3), (6, 1), (8, 2)]);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::types::CommentInfo;
+use crate::utils::remove_redundant_comments;
+use similar::TextDiff;
+use std::path::Path;
+
+/// The lines `--fix` would rewrite to remove the comment, and what it would
+/// put in their place, as (first line, last line, replacement). The fix engine
+/// runs on the comment's lines alone, since on a whole file it also tidies up
+/// blank lines elsewhere.
+pub(crate) fn fix_edit(source: &str, comment: &CommentInfo) -> Option<(usize, usize, Vec<String>)> {
+    let first = comment.line_number;
+    let last = first + comment.text.lines().count().saturating_sub(1);
+    let line_start: usize = source.split_inclusive('\n').take(first.checked_sub(1)?).map(str::len).sum();
+    let lines: Vec<&str> = source[line_start..].split_inclusive('\n').take(last - first + 1).collect();
+    if lines.len() < last - first + 1 {
+        return None;
+    }
+    // The lines as they are, so CRLF endings inside block comments still match
+    let original = lines.concat();
+    let original = original.strip_suffix('\n').map_or(original.as_str(), |rest| rest.strip_suffix('\r').unwrap_or(rest));
+
+    // The span moves along with the comment's lines
+    let comment = CommentInfo {
+        line_number: 1,
+        byte_range: comment.byte_range.as_ref()
+            .and_then(|range| Some(range.start.checked_sub(line_start)?..range.end.checked_sub(line_start)?)),
+        ..comment.clone()
+    };
+    let fixed = remove_redundant_comments(original, std::slice::from_ref(&comment));
+    let replacement: Vec<String> = fixed.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect();
+    (fixed != original).then_some((first, last, replacement))
+}
+
+/// A unified diff of `path` that removes the comments, for previewing a fix
+/// or applying it with `git apply`. Empty when there is nothing to remove.
+pub fn generate_patch(path: &Path, source: &str, redundant_comments: &[CommentInfo]) -> String {
+    let mut edits: Vec<_> = redundant_comments.iter()
+        .filter_map(|comment| fix_edit(source, comment))
+        .collect();
+    edits.sort_by_key(|(first, _, _)| std::cmp::Reverse(*first));
+    edits.dedup_by_key(|(first, _, _)| *first);
+
+    // Each line keeps its own ending, LF or CRLF
+    let mut lines: Vec<String> = source.split_inclusive('\n').map(str::to_string).collect();
+    for (first, last, replacement) in edits {
+        let ending = line_ending(&lines[last - 1]);
+        lines.splice(first - 1..last, replacement.into_iter().map(|line| line + ending));
+    }
+    let fixed = lines.concat();
+    if fixed == source {
+        return String::new();
+    }
+
+    let path = path.to_string_lossy().replace('\\', "/");
+    TextDiff::from_lines(source, &fixed)
+        .unified_diff()
+        .header(&format!("a/{}", path), &format!("b/{}", path))
+        .to_string()
+}
+
+fn line_ending(line: &str) -> &'static str {
+    if line.ends_with("\r\n") {
+        "\r\n"
+    } else if line.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(text: &str, line_number: usize) -> CommentInfo {
+        CommentInfo { text: text.to_string(), line_number, ..Default::default() }
+    }
+
+    #[test]
+    fn test_patch_removes_own_line_and_trailing_comments() {
+        let source = "fn main() {\n    // Set x\n    let x = 1; // one\n\n    let y = 2;\n}\n";
+        let patch = generate_patch(
+            Path::new("src/main.rs"),
+            source,
+            &[comment("// Set x", 2), comment("// one", 3)],
+        );
+
+        assert!(patch.starts_with("--- a/src/main.rs\n+++ b/src/main.rs\n"));
+        assert!(patch.contains("\n-    // Set x\n"));
+        assert!(patch.contains("\n-    let x = 1; // one\n+    let x = 1;\n"));
+        // Blank lines elsewhere are left alone
+        assert!(!patch.contains("\n-\n"));
+    }
+
+    #[test]
+    fn test_patch_keeps_crlf_line_endings() {
+        let source = "fn main() {\r\n    // Set x\r\n    let x = 1; // one\r\n}\r\n";
+        let patch = generate_patch(Path::new("src/main.rs"), source, &[comment("// Set x", 2), comment("// one", 3)]);
+
+        assert!(patch.contains("\n-    // Set x\r\n"), "{:?}", patch);
+        assert!(patch.contains("\n+    let x = 1;\r\n"), "{:?}", patch);
+        assert!(!patch.contains("\n-fn main"), "{:?}", patch);
+    }
+
+    #[test]
+    fn test_patch_is_empty_without_changes() {
+        let source = "x = 1  # not in the file\n";
+        assert_eq!(generate_patch(Path::new("a.py"), source, &[comment("# Missing", 1)]), "");
+        assert_eq!(generate_patch(Path::new("a.py"), source, &[]), "");
+    }
+}
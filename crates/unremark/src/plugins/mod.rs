@@ -0,0 +1,164 @@
+use crate::config::{PluginConfig, RulesConfig};
+use crate::types::CommentInfo;
+use tracing::debug;
+use serde::Serialize;
+use std::sync::Arc;
+
+#[cfg(feature = "plugins")]
+mod wasm;
+
+#[cfg(feature = "plugins")]
+pub use wasm::WasmPlugin;
+
+/// What a comment policy decided about a comment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    Keep,
+    /// The comment is redundant and may be removed by `--fix`
+    Redundant,
+    /// The comment should be reported for review but never removed
+    Flag,
+}
+
+/// A comment policy that runs inside the analysis pipeline, such as a WASM
+/// plugin. Its ID doubles as the rule ID of its findings.
+pub trait CommentPolicy: Send + Sync + std::fmt::Debug {
+    fn id(&self) -> &str;
+
+    /// Judges a comment given the code around it.
+    fn check(&self, comment: &CommentInfo, context: &str) -> Verdict;
+}
+
+/// Loads the plugins listed in the config.
+#[cfg(feature = "plugins")]
+pub fn load_plugins(plugins: &[PluginConfig]) -> Result<Vec<Arc<dyn CommentPolicy>>, String> {
+    plugins.iter()
+        .map(|plugin| {
+            WasmPlugin::load(plugin.id(), &plugin.path).map(|plugin| Arc::new(plugin) as Arc<dyn CommentPolicy>)
+        })
+        .collect()
+}
+
+/// Loads the plugins listed in the config.
+#[cfg(not(feature = "plugins"))]
+pub fn load_plugins(plugins: &[PluginConfig]) -> Result<Vec<Arc<dyn CommentPolicy>>, String> {
+    match plugins.first() {
+        Some(plugin) => Err(format!(
+            "cannot load plugin {}: unremark was built without the `plugins` feature",
+            plugin.path.display()
+        )),
+        None => Ok(vec![]),
+    }
+}
+
+fn verdict_of<'a>(
+    comment: &CommentInfo,
+    policies: &'a [Arc<dyn CommentPolicy>],
+    rules: &RulesConfig,
+    wanted: Verdict,
+) -> Option<&'a dyn CommentPolicy> {
+    policies.iter()
+        .filter(|policy| rules.is_enabled(policy.id()))
+        .find(|policy| policy.check(comment, &comment.context) == wanted)
+        .map(|policy| policy.as_ref())
+}
+
+/// Splits off the comments a policy judged redundant, returning them (tagged
+/// with the policy's ID) and the comments left for the other rules.
+pub fn apply_policies(
+    comments: Vec<CommentInfo>,
+    policies: &[Arc<dyn CommentPolicy>],
+    rules: &RulesConfig,
+) -> (Vec<CommentInfo>, Vec<CommentInfo>) {
+    if policies.is_empty() {
+        return (vec![], comments);
+    }
+
+    let mut redundant = Vec::new();
+    let mut remaining = Vec::new();
+
+    for comment in comments {
+        match verdict_of(&comment, policies, rules, Verdict::Redundant) {
+            Some(policy) => {
+                debug!("Plugin {} flagged comment on line {}: {}", policy.id(), comment.line_number, comment.text);
+                let mut comment = comment;
+                comment.rule_id = Some(policy.id().to_string());
+                comment.explanation = Some(format!("Plugin `{}` reported the comment as redundant", policy.id()));
+                redundant.push(comment);
+            }
+            None => remaining.push(comment),
+        }
+    }
+
+    (redundant, remaining)
+}
+
+/// Comments a policy wants reviewed, tagged with the policy's ID.
+pub fn flag_policy_comments(
+    comments: &[CommentInfo],
+    policies: &[Arc<dyn CommentPolicy>],
+    rules: &RulesConfig,
+) -> Vec<CommentInfo> {
+    comments.iter()
+        .filter_map(|comment| {
+            let policy = verdict_of(comment, policies, rules, Verdict::Flag)?;
+            let mut comment = comment.clone();
+            comment.rule_id = Some(policy.id().to_string());
+            comment.explanation = Some(format!("Plugin `{}` flagged the comment for review", policy.id()));
+            Some(comment)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct KeywordPolicy;
+
+    impl CommentPolicy for KeywordPolicy {
+        fn id(&self) -> &str {
+            "keyword-policy"
+        }
+
+        fn check(&self, comment: &CommentInfo, _context: &str) -> Verdict {
+            if comment.text.contains("JIRA") {
+                Verdict::Flag
+            } else if comment.text.contains("obvious") {
+                Verdict::Redundant
+            } else {
+                Verdict::Keep
+            }
+        }
+    }
+
+    fn comment(text: &str, line_number: usize) -> CommentInfo {
+        CommentInfo { text: text.to_string(), line_number, ..Default::default() }
+    }
+
+    #[test]
+    fn test_policies_split_redundant_and_flagged_comments() {
+        let policies: Vec<Arc<dyn CommentPolicy>> = vec![Arc::new(KeywordPolicy)];
+        let rules = RulesConfig::default();
+        let comments = vec![comment("// obvious", 1), comment("// see JIRA-12", 2), comment("// why", 3)];
+
+        let (redundant, remaining) = apply_policies(comments.clone(), &policies, &rules);
+        assert_eq!(redundant.len(), 1);
+        assert_eq!(redundant[0].rule_id.as_deref(), Some("keyword-policy"));
+        assert_eq!(remaining.len(), 2);
+
+        let flagged = flag_policy_comments(&comments, &policies, &rules);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].line_number, 2);
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    #[test]
+    fn test_loading_plugins_requires_feature() {
+        let plugin = PluginConfig { path: "policy.wasm".into(), id: None };
+        assert!(load_plugins(&[]).unwrap().is_empty());
+        assert!(load_plugins(&[plugin]).unwrap_err().contains("plugins"));
+    }
+}
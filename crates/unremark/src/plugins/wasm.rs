@@ -0,0 +1,163 @@
+//! Host for comment policies compiled to WebAssembly.
+//!
+//! A plugin module must export:
+//! - `memory`
+//! - `alloc(len: i32) -> i32`, returning a buffer the host writes input into
+//! - `check(comment_ptr: i32, comment_len: i32, context_ptr: i32, context_len: i32) -> i32`,
+//!   returning 0 to keep the comment, 1 if it is redundant, 2 to flag it for review
+//!
+//! Strings are passed as UTF-8. Each call gets a fixed fuel budget, so a
+//! misbehaving plugin can't hang the analysis.
+
+use super::{CommentPolicy, Verdict};
+use crate::types::CommentInfo;
+use tracing::warn;
+use parking_lot::Mutex;
+use std::path::Path;
+use wasmi::{Engine, Linker, Memory, Module, Store, TypedFunc};
+
+const FUEL_PER_CHECK: u64 = 10_000_000;
+
+struct Instance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    check: TypedFunc<(i32, i32, i32, i32), i32>,
+}
+
+pub struct WasmPlugin {
+    id: String,
+    instance: Mutex<Instance>,
+}
+
+impl std::fmt::Debug for WasmPlugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmPlugin").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+impl WasmPlugin {
+    pub fn load(id: &str, path: &Path) -> Result<Self, String> {
+        let wasm = std::fs::read(path).map_err(|e| format!("Failed to read plugin {}: {}", path.display(), e))?;
+        Self::from_bytes(id, &wasm).map_err(|e| format!("Invalid plugin {}: {}", path.display(), e))
+    }
+
+    pub fn from_bytes(id: &str, wasm: &[u8]) -> Result<Self, String> {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(|e| e.to_string())?;
+
+        let mut store = Store::new(&engine, ());
+        let instance = Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| e.to_string())?;
+
+        let memory = instance.get_memory(&store, "memory").ok_or("plugin does not export `memory`")?;
+        let alloc = instance.get_typed_func(&store, "alloc").map_err(|e| format!("`alloc`: {}", e))?;
+        let check = instance.get_typed_func(&store, "check").map_err(|e| format!("`check`: {}", e))?;
+
+        Ok(Self {
+            id: id.to_string(),
+            instance: Mutex::new(Instance { store, memory, alloc, check }),
+        })
+    }
+
+    fn call(&self, comment: &str, context: &str) -> Result<i32, String> {
+        let mut instance = self.instance.lock();
+        let Instance { store, memory, alloc, check } = &mut *instance;
+        store.set_fuel(FUEL_PER_CHECK).map_err(|e| e.to_string())?;
+
+        let mut pass = |text: &str| -> Result<(i32, i32), String> {
+            let len = i32::try_from(text.len()).map_err(|_| "input too large".to_string())?;
+            let ptr = alloc.call(&mut *store, len).map_err(|e| e.to_string())?;
+            memory.write(&mut *store, ptr as usize, text.as_bytes()).map_err(|e| e.to_string())?;
+            Ok((ptr, len))
+        };
+        let (comment_ptr, comment_len) = pass(comment)?;
+        let (context_ptr, context_len) = pass(context)?;
+
+        check.call(&mut *store, (comment_ptr, comment_len, context_ptr, context_len))
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl CommentPolicy for WasmPlugin {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn check(&self, comment: &CommentInfo, context: &str) -> Verdict {
+        match self.call(&comment.text, context) {
+            Ok(0) => Verdict::Keep,
+            Ok(1) => Verdict::Redundant,
+            Ok(2) => Verdict::Flag,
+            Ok(other) => {
+                warn!("Plugin {} returned unknown verdict {} for line {}", self.id, other, comment.line_number);
+                Verdict::Keep
+            }
+            Err(e) => {
+                warn!("Plugin {} failed on line {}: {}", self.id, comment.line_number, e);
+                Verdict::Keep
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bump allocator; flags comments whose first byte after `//` is `!`,
+    // calls them redundant when the context is empty.
+    const POLICY: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $next (mut i32) (i32.const 1024))
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $ptr))
+  (func (export "check") (param $c i32) (param $cl i32) (param $x i32) (param $xl i32) (result i32)
+    (if (result i32) (i32.eq (i32.load8_u (i32.add (local.get $c) (i32.const 2))) (i32.const 33))
+      (then (i32.const 2))
+      (else (if (result i32) (i32.eqz (local.get $xl)) (then (i32.const 1)) (else (i32.const 0)))))))
+"#;
+
+    const LOOPS_FOREVER: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "check") (param i32 i32 i32 i32) (result i32)
+    (loop $forever (br $forever))
+    (i32.const 1)))
+"#;
+
+    fn comment(text: &str) -> CommentInfo {
+        CommentInfo { text: text.to_string(), line_number: 1, ..Default::default() }
+    }
+
+    #[test]
+    fn test_runs_wasm_policy() {
+        let plugin = WasmPlugin::from_bytes("house-style", &wat::parse_str(POLICY).unwrap()).unwrap();
+
+        assert_eq!(plugin.id(), "house-style");
+        assert_eq!(plugin.check(&comment("//! important"), "fn main() {}"), Verdict::Flag);
+        assert_eq!(plugin.check(&comment("// loose"), ""), Verdict::Redundant);
+        assert_eq!(plugin.check(&comment("// kept"), "fn main() {}"), Verdict::Keep);
+    }
+
+    #[test]
+    fn test_runaway_plugin_is_stopped() {
+        let plugin = WasmPlugin::from_bytes("stuck", &wat::parse_str(LOOPS_FOREVER).unwrap()).unwrap();
+        assert_eq!(plugin.check(&comment("// anything"), ""), Verdict::Keep);
+    }
+
+    #[test]
+    fn test_rejects_modules_missing_exports() {
+        let wasm = wat::parse_str("(module (memory (export \"memory\") 1))").unwrap();
+        assert!(WasmPlugin::from_bytes("empty", &wasm).unwrap_err().contains("alloc"));
+    }
+}
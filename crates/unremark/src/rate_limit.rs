@@ -0,0 +1,112 @@
+use crate::config::RateLimitConfig;
+use parking_lot::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Paces the requests to the API across every file of a run: at most
+/// `max_in_flight` at once, and within the requests and tokens per minute
+/// the account allows, so a large repository doesn't run into 429s. Clones
+/// of the `ApiSettings` holding it share it.
+#[derive(Debug)]
+pub struct RateLimiter {
+    in_flight: Option<Semaphore>,
+    requests: Option<Mutex<Bucket>>,
+    tokens: Option<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            in_flight: config.max_in_flight.map(|max| Semaphore::new(max.max(1))),
+            requests: config.requests_per_minute.map(|limit| Mutex::new(Bucket::per_minute(limit))),
+            tokens: config.tokens_per_minute.map(|limit| Mutex::new(Bucket::per_minute(limit))),
+        }
+    }
+
+    /// Waits until a request of about `tokens` tokens may be sent. It counts
+    /// as in flight until the permit is dropped.
+    pub(crate) async fn acquire(&self, tokens: usize) -> Option<SemaphorePermit<'_>> {
+        let wait = [(&self.requests, 1), (&self.tokens, tokens)].into_iter()
+            .filter_map(|(bucket, amount)| Some(bucket.as_ref()?.lock().take(amount as f64, Instant::now())))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        match &self.in_flight {
+            Some(semaphore) => semaphore.acquire().await.ok(),
+            None => None,
+        }
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(&RateLimitConfig::default())
+    }
+}
+
+/// A token bucket refilled continuously up to a minute's worth. Takes that
+/// can't be covered yet still reserve their amount, going into debt, so
+/// waiting requests are let through in the order they asked.
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    per_second: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn per_minute(limit: u32) -> Self {
+        let capacity = f64::from(limit.max(1));
+        Self { capacity, per_second: capacity / 60.0, available: capacity, updated: Instant::now() }
+    }
+
+    /// Takes `amount` (at most the capacity) from the bucket, returning how
+    /// long to wait until it's covered.
+    fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.per_second).min(self.capacity);
+        self.updated = now;
+        self.available -= amount.min(self.capacity);
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / self.per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_waits_once_a_minutes_worth_is_spent() {
+        let start = Instant::now();
+        let mut bucket = Bucket::per_minute(60);
+        assert_eq!(bucket.take(59.0, start), Duration::ZERO);
+        assert_eq!(bucket.take(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.take(1.0, start), Duration::from_secs(1));
+        assert_eq!(bucket.take(1.0, start), Duration::from_secs(2));
+        // Three seconds later the debt is paid off and one more is available
+        assert_eq!(bucket.take(1.0, start + Duration::from_secs(3)), Duration::ZERO);
+        // Requests larger than the bucket wait for a full one
+        assert_eq!(bucket.take(1000.0, start + Duration::from_secs(3)), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_requests_are_capped() {
+        let config = RateLimitConfig { max_in_flight: Some(2), ..Default::default() };
+        let limiter = RateLimiter::new(&config);
+        let first = limiter.acquire(10).await;
+        let _second = limiter.acquire(10).await;
+        assert!(tokio::time::timeout(Duration::from_millis(20), limiter.acquire(10)).await.is_err());
+        drop(first);
+        assert!(tokio::time::timeout(Duration::from_millis(20), limiter.acquire(10)).await.is_ok());
+
+        let unlimited = RateLimiter::new(&RateLimitConfig { max_in_flight: None, ..Default::default() });
+        assert!(unlimited.acquire(1_000_000).await.is_none());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::constants::{RECORD_ENV, REPLAY_ENV};
+use crate::types::ApiError;
+use crate::utils::write_atomically;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tracing::warn;
+
+/// The fixtures named by the environment, loaded once per process.
+static FROM_ENV: OnceLock<Option<Arc<Fixtures>>> = OnceLock::new();
+
+/// Whether requests are answered from the fixture file or sent and saved to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureMode {
+    Record,
+    Replay,
+}
+
+/// A request to the chat completions API and the response it got.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Fixture {
+    request: serde_json::Value,
+    response: serde_json::Value,
+}
+
+/// Responses of the API kept in a JSON file, so analyses (and the tests that
+/// run them) can be repeated without a key or a network, with the same
+/// answers each time. Requests are matched by their whole body: a change to
+/// a prompt or setting needs recording again.
+#[derive(Debug)]
+pub struct Fixtures {
+    path: PathBuf,
+    mode: FixtureMode,
+    entries: Mutex<Vec<Fixture>>,
+}
+
+impl Fixtures {
+    /// Replays `$UNREMARK_REPLAY`, or records to `$UNREMARK_RECORD`, keeping
+    /// the responses already there.
+    pub fn from_env() -> Option<Arc<Self>> {
+        FROM_ENV.get_or_init(|| {
+            let (path, mode) = std::env::var_os(REPLAY_ENV).map(|path| (path, FixtureMode::Replay))
+                .or_else(|| std::env::var_os(RECORD_ENV).map(|path| (path, FixtureMode::Record)))?;
+            let path = PathBuf::from(path);
+            let fixtures = Self::load(&path, mode).unwrap_or_else(|e| {
+                warn!("Failed to load the API fixtures: {}", e);
+                Self::new(&path, mode, vec![])
+            });
+            Some(Arc::new(fixtures))
+        }).clone()
+    }
+
+    /// The fixtures at `path`, none when the file doesn't exist yet.
+    pub fn load(path: &Path, mode: FixtureMode) -> Result<Self, String> {
+        let entries = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("{} isn't a fixture file: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && mode == FixtureMode::Record => vec![],
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+        Ok(Self::new(path, mode, entries))
+    }
+
+    fn new(path: &Path, mode: FixtureMode, entries: Vec<Fixture>) -> Self {
+        Self { path: path.to_path_buf(), mode, entries: Mutex::new(entries) }
+    }
+
+    pub fn mode(&self) -> FixtureMode {
+        self.mode
+    }
+
+    /// The recorded response to `request` when replaying, an error when there
+    /// is none, and `None` when recording.
+    pub(crate) fn replay(&self, request: &serde_json::Value) -> Option<Result<serde_json::Value, ApiError>> {
+        if self.mode != FixtureMode::Replay {
+            return None;
+        }
+        let response = self.entries.lock().iter()
+            .find(|fixture| fixture.request == *request)
+            .map(|fixture| fixture.response.clone());
+        Some(response.ok_or_else(|| {
+            ApiError::Other(format!("no recorded response to this request in {}", self.path.display()))
+        }))
+    }
+
+    /// Saves the response to `request` when recording, replacing an earlier one.
+    pub(crate) fn record(&self, request: &serde_json::Value, response: &serde_json::Value) {
+        if self.mode != FixtureMode::Record {
+            return;
+        }
+        let mut entries = self.entries.lock();
+        let fixture = Fixture { request: request.clone(), response: response.clone() };
+        match entries.iter_mut().find(|entry| entry.request == *request) {
+            Some(entry) => *entry = fixture,
+            None => entries.push(fixture),
+        }
+        let contents = serde_json::to_vec_pretty(&*entries).unwrap_or_default();
+        if let Err(e) = write_atomically(&self.path, &contents) {
+            warn!("Failed to save the API fixtures to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The responses the tests' requests got, from `tests/fixtures/api.json`,
+/// so they run without keys or a network. With `UNREMARK_RECORD_TESTS` set,
+/// the tests ask the API and the proxy and save their responses there
+/// instead.
+#[cfg(test)]
+pub(crate) fn test_fixtures() -> Arc<Fixtures> {
+    static FIXTURES: OnceLock<Arc<Fixtures>> = OnceLock::new();
+    FIXTURES.get_or_init(|| {
+        let mode = match std::env::var_os("UNREMARK_RECORD_TESTS") {
+            Some(_) => FixtureMode::Record,
+            None => FixtureMode::Replay,
+        };
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/api.json");
+        Arc::new(Fixtures::load(&path, mode).unwrap())
+    }).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_recorded_responses_are_replayed() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("fixtures.json");
+        let (request, response) = (json!({ "model": "gpt-test", "messages": [] }), json!({ "choices": [] }));
+
+        let recording = Fixtures::load(&path, FixtureMode::Record).unwrap();
+        assert!(recording.replay(&request).is_none());
+        recording.record(&request, &json!({ "choices": null }));
+        recording.record(&request, &response);
+
+        let replaying = Fixtures::load(&path, FixtureMode::Replay).unwrap();
+        assert_eq!(replaying.replay(&request).unwrap().unwrap(), response);
+        let err = replaying.replay(&json!({ "model": "other" })).unwrap().unwrap_err();
+        assert!(err.to_string().contains("no recorded response"), "{}", err);
+
+        assert!(Fixtures::load(&directory.path().join("missing.json"), FixtureMode::Replay).is_err());
+    }
+}
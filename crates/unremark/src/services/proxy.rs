@@ -1,12 +1,24 @@
 use async_trait::async_trait;
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
 use serde::{Serialize, Deserialize};
-use reqwest::Client;
-use crate::types::CommentInfo;
-use crate::constants::get_proxy_endpoint;
+use reqwest::header::{ACCEPT, CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{Client, Response};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::api::{configure_network, send_with_retry, RateLimitSender, RetryPolicy};
+use crate::config::{GenerationConfig, NetworkConfig};
+use crate::credentials::resolve_api_key;
+use crate::replay::{FixtureMode, Fixtures};
+use crate::types::{ApiError, ApiSettings, CommentInfo, Provider};
+use crate::constants::{get_proxy_endpoint, DEFAULT_REQUEST_TIMEOUT};
 
 #[derive(Debug, Serialize)]
 struct ProxyRequest {
     comments: Vec<CommentInfo>,
+    /// Left out unless configured, for proxies that predate it
+    #[serde(skip_serializing_if = "GenerationConfig::is_default")]
+    generation: GenerationConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -14,57 +26,253 @@ struct ProxyResponse {
     comments: Vec<CommentInfo>, 
 }
 
+/// How request bodies sent to the proxy are compressed. Code context for
+/// hundreds of comments adds up, and compresses well.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    #[default]
+    Gzip,
+    Zstd,
+}
+
+/// Bodies smaller than this are sent as they are, compressing them saves next to nothing.
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+impl Compression {
+    /// `body` compressed this way, with its `Content-Encoding`, or as it is
+    /// when uncompressed or too small to bother.
+    fn encode(self, body: Vec<u8>) -> Result<(Vec<u8>, Option<&'static str>), ApiError> {
+        if body.len() < MIN_COMPRESSED_SIZE {
+            return Ok((body, None));
+        }
+        let encoded = match self {
+            Compression::None => return Ok((body, None)),
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&body)
+                    .and_then(|_| encoder.finish())
+                    .map(|body| (body, Some("gzip")))
+            }
+            Compression::Zstd => zstd::encode_all(body.as_slice(), 0).map(|body| (body, Some("zstd"))),
+        };
+        encoded.map_err(|e| ApiError::Other(format!("Failed to compress proxy request: {}", e)))
+    }
+}
+
+/// Verdicts from the proxy, each redundant comment as soon as it arrives.
+pub type VerdictStream = BoxStream<'static, Result<CommentInfo, ApiError>>;
+
 #[async_trait]
 pub trait AnalysisService: Send + Sync {
-    async fn analyze_comments_with_proxy(&self, comments: Vec<CommentInfo>) -> Result<Vec<CommentInfo>, String>;
+    async fn analyze_comments_with_proxy(&self, comments: Vec<CommentInfo>) -> Result<Vec<CommentInfo>, ApiError>;
+
+    /// Like `analyze_comments_with_proxy`, but yields each redundant comment
+    /// as the proxy reports it, for callers that show findings incrementally.
+    async fn stream_comments_with_proxy(&self, comments: Vec<CommentInfo>) -> Result<VerdictStream, ApiError> {
+        let comments = self.analyze_comments_with_proxy(comments).await?;
+        Ok(stream::iter(comments.into_iter().map(Ok)).boxed())
+    }
 }
 
 pub struct ProxyAnalysisService {
     pub endpoint: String,
+    /// How long connecting, or waiting for the next part of the response, may take
+    pub timeout: Duration,
+    pub retry: RetryPolicy,
+    pub network: NetworkConfig,
+    pub compression: Compression,
+    pub rate_limited: Option<RateLimitSender>,
+    pub generation: GenerationConfig,
+    /// Sent as a bearer token, for proxies that require one
+    pub token: Option<String>,
+    /// Recorded responses answering the requests, or saving them
+    pub fixtures: Option<Arc<Fixtures>>,
+}
+
+impl ProxyAnalysisService {
+    pub fn new(endpoint: String) -> Self {
+        Self {
+            endpoint,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            retry: RetryPolicy::default(),
+            network: NetworkConfig::default(),
+            compression: Compression::default(),
+            rate_limited: None,
+            generation: GenerationConfig::default(),
+            token: None,
+            fixtures: Fixtures::from_env(),
+        }
+    }
+
+    /// The proxy at `api.endpoint`, or `PROXY_ENDPOINT`, reached with `api`'s
+    /// timeout, retries, network settings and compression.
+    pub fn from_settings(api: &ApiSettings) -> Self {
+        Self {
+            endpoint: api.endpoint.clone().unwrap_or_else(get_proxy_endpoint),
+            timeout: api.timeout,
+            retry: api.retry,
+            network: api.network.clone(),
+            compression: api.compression,
+            rate_limited: api.rate_limited.clone(),
+            generation: api.generation,
+            // An explicit key is only the proxy's token when the proxy is the provider
+            token: resolve_api_key(
+                Provider::Proxy,
+                api.api_key.as_deref().filter(|_| api.provider == Provider::Proxy),
+                &api.credentials,
+            ),
+            fixtures: api.fixtures.clone(),
+        }
+    }
 }
 
 #[async_trait]
 impl AnalysisService for ProxyAnalysisService {
-    async fn analyze_comments_with_proxy(&self, comments: Vec<CommentInfo>) -> Result<Vec<CommentInfo>, String> {
-        let client = Client::new();
+    async fn analyze_comments_with_proxy(&self, comments: Vec<CommentInfo>) -> Result<Vec<CommentInfo>, ApiError> {
+        self.stream_comments_with_proxy(comments).await?.try_collect().await
+    }
+
+    async fn stream_comments_with_proxy(&self, comments: Vec<CommentInfo>) -> Result<VerdictStream, ApiError> {
+        // A streamed response may rightly take long overall, so only gaps in it time out
+        let builder = Client::builder()
+            .connect_timeout(self.timeout)
+            .read_timeout(self.timeout);
+        let client = configure_network(builder, &self.network)?
+            .build()
+            .map_err(|e| ApiError::Other(format!("Failed to build proxy client: {}", e)))?;
         
-        let request = ProxyRequest { comments };
+        let request = serde_json::to_value(ProxyRequest { comments, generation: self.generation })
+            .map_err(|e| ApiError::Other(format!("Failed to serialize proxy request: {}", e)))?;
+        if let Some(replayed) = self.fixtures.as_ref().and_then(|fixtures| fixtures.replay(&request)) {
+            let response: ProxyResponse = serde_json::from_value(replayed?)
+                .map_err(|e| ApiError::Other(format!("Failed to parse proxy response: {}", e)))?;
+            return Ok(stream::iter(response.comments.into_iter().map(Ok)).boxed());
+        }
+        let (body, encoding) = self.compression.encode(request.to_string().into_bytes())?;
 
-        let response = client
-            .post(&format!("{}/api/analyze/", self.endpoint))
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| format!("Proxy request failed: {}", e))?;
+        let response = send_with_retry(&self.retry, self.rate_limited.as_ref(), || {
+            let request = client
+                .post(format!("{}/api/analyze/", self.endpoint))
+                .header(ACCEPT, "application/x-ndjson, text/event-stream, application/json;q=0.5")
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            let request = match &self.token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            };
+            match encoding {
+                Some(encoding) => request.header(CONTENT_ENCODING, encoding),
+                None => request,
+            }
+        }).await?;
 
-        if !response.status().is_success() {
-            return Err(format!("Proxy error: {}", response.status()));
+        match &self.fixtures {
+            // Recorded whole, so the verdicts come once they've all arrived
+            Some(fixtures) if fixtures.mode() == FixtureMode::Record => {
+                let comments: Vec<CommentInfo> = verdicts(response).try_collect().await?;
+                fixtures.record(&request, &serde_json::json!({ "comments": comments }));
+                Ok(stream::iter(comments.into_iter().map(Ok)).boxed())
+            }
+            _ => Ok(verdicts(response)),
         }
+    }
+}
+
+/// Reads the proxy's verdicts: one per NDJSON line or SSE `data:` event as
+/// they arrive, or all at once from a proxy answering with plain JSON.
+fn verdicts(response: Response) -> VerdictStream {
+    let content_type = response.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let sse = content_type.starts_with("text/event-stream");
 
-        let analysis: ProxyResponse = response
-            .json()
+    if sse || content_type.starts_with("application/x-ndjson") {
+        return lines(response)
+            .filter_map(move |line| async move {
+                line.and_then(|line| parse_verdict(&line, sse)).transpose()
+            })
+            .boxed();
+    }
+
+    stream::once(async move {
+        response.json::<ProxyResponse>()
             .await
-            .map_err(|e| format!("Failed to parse proxy response: {}", e))?;
+            .map_err(|e| ApiError::Other(format!("Failed to parse proxy response: {}", e)))
+    })
+    .map(|analysis| match analysis {
+        Ok(analysis) => stream::iter(analysis.comments.into_iter().map(Ok)).left_stream(),
+        Err(e) => stream::iter(vec![Err(e)]).right_stream(),
+    })
+    .flatten()
+    .boxed()
+}
 
-        Ok(analysis.comments)
+/// Parses one line of a streamed response, skipping blank lines, SSE
+/// fields other than `data:` and the `[DONE]` marker.
+fn parse_verdict(line: &str, sse: bool) -> Result<Option<CommentInfo>, ApiError> {
+    let line = line.trim_end_matches('\r');
+    let payload = match (sse, line.strip_prefix("data:")) {
+        (true, Some(data)) => data.trim_start(),
+        (true, None) => return Ok(None),
+        (false, _) => line,
+    };
+    if payload.trim().is_empty() || payload == "[DONE]" {
+        return Ok(None);
     }
+    serde_json::from_str(payload)
+        .map(Some)
+        .map_err(|e| ApiError::Other(format!("Failed to parse proxy verdict: {}", e)))
 }
 
-pub fn create_analysis_service() -> Box<dyn AnalysisService + Send + Sync> {
-    Box::new(ProxyAnalysisService {
-        endpoint: get_proxy_endpoint(),
+/// Splits the response body into lines as its chunks arrive.
+fn lines(response: Response) -> impl Stream<Item = Result<String, ApiError>> {
+    stream::unfold((Some(response), Vec::new()), |(mut response, mut buffer)| async move {
+        loop {
+            if let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                return Some((Ok(String::from_utf8_lossy(&line[..end]).into_owned()), (response, buffer)));
+            }
+            let Some(body) = response.as_mut() else {
+                // The last line needn't end with a newline
+                if buffer.is_empty() {
+                    return None;
+                }
+                return Some((Ok(String::from_utf8_lossy(&buffer).into_owned()), (None, Vec::new())));
+            };
+            match body.chunk().await {
+                Ok(Some(chunk)) => buffer.extend_from_slice(&chunk),
+                Ok(None) => response = None,
+                Err(e) => {
+                    let error = if e.is_timeout() {
+                        ApiError::Timeout(format!("Proxy stream stalled: {}", e))
+                    } else {
+                        ApiError::Network(format!("Proxy stream broke off: {}", e))
+                    };
+                    return Some((Err(error), (None, Vec::new())));
+                }
+            }
+        }
     })
 }
 
-// FIXME: This should be an integration test as it depends on the proxy server
+pub fn create_analysis_service() -> Box<dyn AnalysisService + Send + Sync> {
+    Box::new(ProxyAnalysisService::new(get_proxy_endpoint()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::replay::test_fixtures;
 
     #[tokio::test]
     async fn test_proxy_service() {
         let service = ProxyAnalysisService {
-            endpoint: get_proxy_endpoint(),
+            fixtures: Some(test_fixtures()),
+            ..ProxyAnalysisService::new(get_proxy_endpoint())
         };
 
         let comments = vec![
@@ -72,17 +280,203 @@ mod tests {
                 text: "// Adds two numbers".to_string(),
                 line_number: 1,
                 context: "fn add(a: i32, b: i32) -> i32 { a + b }".to_string(),
-                explanation: None,
+                ..Default::default()
             },
             CommentInfo {
                 text: "// Returns the sum".to_string(),
                 line_number: 2,
                 context: "a + b".to_string(),
-                explanation: None,
+                ..Default::default()
             },
         ];
 
-        let result = service.analyze_comments_with_proxy(comments).await;
-        assert!(result.is_ok());
+        let redundant = service.analyze_comments_with_proxy(comments).await.unwrap();
+        let lines: Vec<usize> = redundant.iter().map(|comment| comment.line_number).collect();
+        assert_eq!(lines, vec![1, 2]);
     }
-} 
\ No newline at end of file
+
+    fn fast_service(endpoint: String) -> ProxyAnalysisService {
+        ProxyAnalysisService {
+            timeout: Duration::from_millis(200),
+            retry: RetryPolicy { max_attempts: 2, initial_delay: Duration::from_millis(10), ..Default::default() },
+            ..ProxyAnalysisService::new(endpoint)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_retries_server_errors() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/analyze/"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/analyze/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "comments": [{ "text": "// Adds", "line_number": 1, "context": "", "explanation": "Restates the code" }]
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let comments = vec![CommentInfo { text: "// Adds".to_string(), line_number: 1, ..Default::default() }];
+        let result = fast_service(mock_server.uri()).analyze_comments_with_proxy(comments).await.unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].explanation.as_deref(), Some("Restates the code"));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_failures_map_to_api_errors() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+            .mount(&mock_server)
+            .await;
+        let error = fast_service(mock_server.uri()).analyze_comments_with_proxy(vec![]).await.unwrap_err();
+        assert!(matches!(error, ApiError::Timeout(_)), "{:?}", error);
+
+        let limited = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429))
+            .expect(2)
+            .mount(&limited)
+            .await;
+        let error = fast_service(limited.uri()).analyze_comments_with_proxy(vec![]).await.unwrap_err();
+        assert!(matches!(error, ApiError::RateLimit(_)), "{:?}", error);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_streams_ndjson_and_sse_verdicts() {
+        use wiremock::matchers::{header_regex, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let ndjson = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header_regex("accept", "application/x-ndjson"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                "{\"text\": \"// Adds\", \"line_number\": 1, \"context\": \"\"}\n\n{\"text\": \"// Sums\", \"line_number\": 2, \"context\": \"\"}",
+                "application/x-ndjson",
+            ))
+            .mount(&ndjson)
+            .await;
+        let verdicts: Vec<_> = fast_service(ndjson.uri()).stream_comments_with_proxy(vec![])
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let lines: Vec<usize> = verdicts.into_iter().map(|verdict| verdict.unwrap().line_number).collect();
+        assert_eq!(lines, vec![1, 2]);
+
+        let sse = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                ": keep-alive\n\nevent: verdict\ndata: {\"text\": \"// Adds\", \"line_number\": 1, \"context\": \"\"}\r\n\ndata: [DONE]\n\n",
+                "text/event-stream",
+            ))
+            .mount(&sse)
+            .await;
+        let comments = fast_service(sse.uri()).analyze_comments_with_proxy(vec![]).await.unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].text, "// Adds");
+    }
+
+    #[test]
+    fn test_malformed_verdict_is_an_error() {
+        assert!(parse_verdict("data: {not json", true).is_err());
+        assert!(parse_verdict("id: 7", true).unwrap().is_none());
+        assert!(parse_verdict("", false).unwrap().is_none());
+    }
+
+    fn large_comments() -> Vec<CommentInfo> {
+        (1..=20).map(|line_number| CommentInfo {
+            text: format!("// Adds the numbers on line {}", line_number),
+            line_number,
+            context: "fn add(a: i32, b: i32) -> i32 { a + b }".repeat(4),
+            ..Default::default()
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn test_proxy_requests_are_compressed() {
+        use std::io::Read;
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "comments": [] })))
+            .mount(&mock_server)
+            .await;
+
+        fast_service(mock_server.uri()).analyze_comments_with_proxy(large_comments()).await.unwrap();
+        let requests = mock_server.received_requests().await.unwrap();
+        assert_eq!(requests[0].headers.get("content-encoding").unwrap(), "gzip");
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(requests[0].body.as_slice()).read_to_string(&mut body).unwrap();
+        let request: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(request["comments"].as_array().unwrap().len(), 20);
+
+        let zstd_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("content-encoding", "zstd"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "comments": [] })))
+            .expect(1)
+            .mount(&zstd_server)
+            .await;
+        let service = ProxyAnalysisService { compression: Compression::Zstd, ..fast_service(zstd_server.uri()) };
+        service.analyze_comments_with_proxy(large_comments()).await.unwrap();
+
+        // Small requests aren't worth compressing
+        let (body, encoding) = Compression::Gzip.encode(b"{}".to_vec()).unwrap();
+        assert_eq!((body.as_slice(), encoding), (&b"{}"[..], None));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_requests_carry_configured_generation() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({ "comments": [] })))
+            .mount(&mock_server)
+            .await;
+        let comment = || vec![CommentInfo { text: "// Adds".to_string(), line_number: 1, ..Default::default() }];
+
+        fast_service(mock_server.uri()).analyze_comments_with_proxy(comment()).await.unwrap();
+        let generation = GenerationConfig { temperature: 0.5, ..Default::default() };
+        let service = ProxyAnalysisService { generation, ..fast_service(mock_server.uri()) };
+        service.analyze_comments_with_proxy(comment()).await.unwrap();
+
+        let requests = mock_server.received_requests().await.unwrap();
+        let bodies: Vec<serde_json::Value> = requests.iter().map(|request| request.body_json().unwrap()).collect();
+        assert!(bodies[0].get("generation").is_none());
+        assert_eq!(bodies[1]["generation"], serde_json::json!({ "max_tokens": 500, "temperature": 0.5, "top_p": 1.0 }));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_accepts_compressed_responses() {
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let verdict = "{\"text\": \"// Adds\", \"line_number\": 1, \"context\": \"\"}\n";
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("content-encoding", "zstd")
+                .set_body_raw(zstd::encode_all(verdict.as_bytes(), 0).unwrap(), "application/x-ndjson"))
+            .mount(&mock_server)
+            .await;
+
+        let comments = fast_service(mock_server.uri()).analyze_comments_with_proxy(vec![]).await.unwrap();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].text, "// Adds");
+    }
+}
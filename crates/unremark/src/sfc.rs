@@ -0,0 +1,74 @@
+use crate::types::Language;
+use tree_sitter::{Parser, Point, Range, Tree};
+
+/// Parses the source with the parser, set to the language's grammar. Only
+/// the `<script>` blocks of Vue and Svelte components are parsed, as the
+/// JavaScript or TypeScript they are; the tree keeps the positions of the
+/// whole file, so lines and byte ranges need no mapping back.
+pub(crate) fn parse(parser: &mut Parser, source: &str, language: Language) -> Option<Tree> {
+    if matches!(language, Language::Vue | Language::Svelte) {
+        let mut ranges = script_ranges(source);
+        // No ranges at all would parse the whole file
+        if ranges.is_empty() {
+            ranges.push(Range { start_byte: 0, end_byte: 0, start_point: Point::new(0, 0), end_point: Point::new(0, 0) });
+        }
+        parser.set_included_ranges(&ranges).ok()?;
+    }
+    parser.parse(source, None)
+}
+
+/// The contents of each `<script>` element of a single-file component.
+fn script_ranges(source: &str) -> Vec<Range> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = source[offset..].find("<script").map(|start| offset + start) {
+        let after_name = start + "<script".len();
+        if !source[after_name..].starts_with(|c: char| c == '>' || c.is_whitespace()) {
+            offset = after_name;
+            continue;
+        }
+        let Some(content_start) = source[after_name..].find('>').map(|end| after_name + end + 1) else {
+            break;
+        };
+        let content_end = source[content_start..].find("</script").map_or(source.len(), |end| content_start + end);
+        ranges.push(Range {
+            start_byte: content_start,
+            end_byte: content_end,
+            start_point: point_at(source, content_start),
+            end_point: point_at(source, content_end),
+        });
+        offset = content_end;
+    }
+    ranges
+}
+
+fn point_at(source: &str, byte: usize) -> Point {
+    let before = &source[..byte];
+    let row = before.matches('\n').count();
+    let column = before.rfind('\n').map_or(byte, |newline| byte - newline - 1);
+    Point::new(row, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_ranges() {
+        let source = "<template>\n  <div>{{ x }}</div>\n</template>\n\n<script setup lang=\"ts\">\nconst x = 1\n</script>\n<scripts></scripts>\n<script>export default {}</script>\n";
+        let ranges = script_ranges(source);
+        let contents: Vec<&str> = ranges.iter().map(|range| &source[range.start_byte..range.end_byte]).collect();
+        assert_eq!(contents, vec!["\nconst x = 1\n", "export default {}"]);
+        assert_eq!((ranges[0].start_point, ranges[0].end_point), (Point::new(4, 24), Point::new(6, 0)));
+        assert_eq!(ranges[1].start_point, Point::new(8, 8));
+    }
+
+    #[test]
+    fn test_components_without_scripts_parse_to_nothing() {
+        let source = "<template>\n  <a href=\"https://example.com\">// not a comment</a>\n</template>\n";
+        let mut parser = Parser::new();
+        parser.set_language(&Language::Vue.get_tree_sitter_language()).unwrap();
+        let tree = parse(&mut parser, source, Language::Vue).unwrap();
+        assert_eq!(tree.root_node().child_count(), 0);
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+/// Upper bounds of the API latency histogram's buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Largest request head read before answering, more than any scraper sends.
+const MAX_REQUEST_HEAD: usize = 8192;
+
+/// The server mode a request came in through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Server {
+    Daemon,
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    Grpc,
+}
+
+/// How an API request attempt ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    RateLimited,
+    Error,
+}
+
+/// Counters of everything this process analyzed, for `/metrics`.
+pub struct ServiceMetrics {
+    daemon_requests: AtomicU64,
+    grpc_requests: AtomicU64,
+    files_analyzed: AtomicU64,
+    cache_hits: AtomicU64,
+    api_requests: [AtomicU64; 3],
+    /// Attempts at or under each bucket's bound, then the total
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_micros: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+}
+
+static METRICS: ServiceMetrics = ServiceMetrics::new();
+
+/// The process's metrics.
+pub fn metrics() -> &'static ServiceMetrics {
+    &METRICS
+}
+
+impl ServiceMetrics {
+    const fn new() -> Self {
+        Self {
+            daemon_requests: AtomicU64::new(0),
+            grpc_requests: AtomicU64::new(0),
+            files_analyzed: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            api_requests: [const { AtomicU64::new(0) }; 3],
+            latency_buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len() + 1],
+            latency_micros: AtomicU64::new(0),
+            prompt_tokens: AtomicU64::new(0),
+            completion_tokens: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_request(&self, server: Server) {
+        let counter = match server {
+            Server::Daemon => &self.daemon_requests,
+            Server::Grpc => &self.grpc_requests,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A file analyzed afresh, rather than answered from the cache.
+    pub fn record_file(&self) {
+        self.files_analyzed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// One attempt at an API or proxy request, retries counting separately.
+    pub fn record_api_request(&self, outcome: Outcome, elapsed: Duration) {
+        self.api_requests[outcome as usize].fetch_add(1, Ordering::Relaxed);
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(LATENCY_BUCKETS.len());
+        for counter in &self.latency_buckets[bucket..] {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The tokens a completion's `usage` reports.
+    pub fn record_tokens(&self, prompt: u64, completion: u64) {
+        self.prompt_tokens.fetch_add(prompt, Ordering::Relaxed);
+        self.completion_tokens.fetch_add(completion, Ordering::Relaxed);
+    }
+
+    /// The metrics in Prometheus's text exposition format.
+    pub fn render(&self) -> String {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut text = String::new();
+
+        text.push_str("# HELP unremark_requests_total Analysis requests received, by server mode.\n");
+        text.push_str("# TYPE unremark_requests_total counter\n");
+        let _ = writeln!(text, "unremark_requests_total{{server=\"daemon\"}} {}", get(&self.daemon_requests));
+        let _ = writeln!(text, "unremark_requests_total{{server=\"grpc\"}} {}", get(&self.grpc_requests));
+
+        text.push_str("# HELP unremark_files_analyzed_total Files analyzed afresh, not answered from the cache.\n");
+        text.push_str("# TYPE unremark_files_analyzed_total counter\n");
+        let _ = writeln!(text, "unremark_files_analyzed_total {}", get(&self.files_analyzed));
+
+        text.push_str("# HELP unremark_cache_hits_total Files answered from the cache.\n");
+        text.push_str("# TYPE unremark_cache_hits_total counter\n");
+        let _ = writeln!(text, "unremark_cache_hits_total {}", get(&self.cache_hits));
+
+        text.push_str("# HELP unremark_api_requests_total API and proxy request attempts, by outcome.\n");
+        text.push_str("# TYPE unremark_api_requests_total counter\n");
+        for (outcome, counter) in ["success", "rate_limited", "error"].iter().zip(&self.api_requests) {
+            let _ = writeln!(text, "unremark_api_requests_total{{outcome=\"{}\"}} {}", outcome, get(counter));
+        }
+
+        text.push_str("# HELP unremark_api_request_duration_seconds How long API and proxy request attempts took.\n");
+        text.push_str("# TYPE unremark_api_request_duration_seconds histogram\n");
+        for (bound, counter) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            let _ = writeln!(text, "unremark_api_request_duration_seconds_bucket{{le=\"{}\"}} {}", bound, get(counter));
+        }
+        let count = get(&self.latency_buckets[LATENCY_BUCKETS.len()]);
+        let _ = writeln!(text, "unremark_api_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let _ = writeln!(text, "unremark_api_request_duration_seconds_sum {}", get(&self.latency_micros) as f64 / 1e6);
+        let _ = writeln!(text, "unremark_api_request_duration_seconds_count {}", count);
+
+        text.push_str("# HELP unremark_tokens_total Tokens the OpenAI API reported using, by kind.\n");
+        text.push_str("# TYPE unremark_tokens_total counter\n");
+        let _ = writeln!(text, "unremark_tokens_total{{kind=\"prompt\"}} {}", get(&self.prompt_tokens));
+        let _ = writeln!(text, "unremark_tokens_total{{kind=\"completion\"}} {}", get(&self.completion_tokens));
+        text
+    }
+}
+
+/// Serves the process's metrics at `http://<address>/metrics` until the
+/// process stops, or fails to listen.
+pub async fn serve_metrics(address: SocketAddr) -> Result<(), String> {
+    let listener = TcpListener::bind(address).await
+        .map_err(|e| format!("cannot serve metrics on {}: {}", address, e))?;
+    info!("Serving metrics on http://{}/metrics", address);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = answer(stream).await {
+                        warn!("Metrics request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => return Err(format!("metrics endpoint stopped accepting connections: {}", e)),
+        }
+    }
+}
+
+async fn answer(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") && head.len() < MAX_REQUEST_HEAD {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    let request_line = String::from_utf8_lossy(&head).lines().next().unwrap_or_default().to_string();
+    stream.write_all(response(&request_line).as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// The HTTP response to a request: the metrics for `GET /metrics`, a 404 otherwise.
+fn response(request_line: &str) -> String {
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", metrics().render()),
+        _ => ("404 Not Found", "text/plain", "Not found, the metrics are at /metrics\n".to_string()),
+    };
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = ServiceMetrics::new();
+        metrics.record_request(Server::Daemon);
+        metrics.record_cache_hit();
+        metrics.record_api_request(Outcome::Success, Duration::from_millis(300));
+        metrics.record_api_request(Outcome::RateLimited, Duration::from_secs(200));
+        metrics.record_tokens(120, 30);
+
+        let text = metrics.render();
+        assert!(text.contains("unremark_requests_total{server=\"daemon\"} 1\n"));
+        assert!(text.contains("unremark_requests_total{server=\"grpc\"} 0\n"));
+        assert!(text.contains("unremark_cache_hits_total 1\n"));
+        assert!(text.contains("unremark_api_requests_total{outcome=\"rate_limited\"} 1\n"));
+        assert!(text.contains("unremark_api_request_duration_seconds_bucket{le=\"0.25\"} 0\n"));
+        assert!(text.contains("unremark_api_request_duration_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(text.contains("unremark_api_request_duration_seconds_bucket{le=\"120\"} 1\n"));
+        assert!(text.contains("unremark_api_request_duration_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("unremark_api_request_duration_seconds_sum 200.3\n"));
+        assert!(text.contains("unremark_tokens_total{kind=\"prompt\"} 120\n"));
+    }
+
+    #[test]
+    fn test_response() {
+        let found = response("GET /metrics HTTP/1.1");
+        assert!(found.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(found.contains("# TYPE unremark_cache_hits_total counter"));
+        assert!(response("GET / HTTP/1.1").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response("").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A stage of a run whose time is reported by `--timings`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Finding the files to analyze
+    Walk,
+    /// Parsing each file
+    Parse,
+    /// Collecting the comments from the parse tree
+    Detect,
+    /// The local checks: plugins, heuristic rules, prefilter and classifier
+    Heuristics,
+    /// Waiting on the API (or proxy)
+    Api,
+    /// Removing redundant comments and writing the files back
+    Fix,
+}
+
+impl Phase {
+    const ALL: [Phase; 6] = [Phase::Walk, Phase::Parse, Phase::Detect, Phase::Heuristics, Phase::Api, Phase::Fix];
+}
+
+/// Time spent in each phase of a run, shared by every file analyzed in it.
+/// Files are analyzed concurrently, so the phases can add up to more than
+/// the run took.
+#[derive(Debug, Default)]
+pub struct Timings {
+    nanos: [AtomicU64; Phase::ALL.len()],
+}
+
+impl Timings {
+    pub fn record(&self, phase: Phase, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.nanos[phase as usize].fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Runs `f`, counting the time it takes towards `phase`.
+    pub fn time<T>(&self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    pub fn elapsed(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.nanos[phase as usize].load(Ordering::Relaxed))
+    }
+
+    /// The time of each phase so far, in a run that has taken `total`.
+    pub fn report(&self, total: Duration) -> PhaseTimings {
+        let [walk, parse, detect, heuristics, api, fix] = Phase::ALL.map(|phase| self.elapsed(phase).as_secs_f64());
+        PhaseTimings { walk, parse, detect, heuristics, api, fix, total: total.as_secs_f64() }
+    }
+}
+
+/// Runs `f`, timing it when the run is timed.
+pub(crate) fn timed<T>(timings: Option<&Timings>, phase: Phase, f: impl FnOnce() -> T) -> T {
+    match timings {
+        Some(timings) => timings.time(phase, f),
+        None => f(),
+    }
+}
+
+/// Seconds spent in each phase of a run, and in the whole run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PhaseTimings {
+    pub walk: f64,
+    pub parse: f64,
+    pub detect: f64,
+    pub heuristics: f64,
+    pub api: f64,
+    pub fix: f64,
+    /// Wall-clock time of the whole run
+    pub total: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phases_add_up_across_files() {
+        let timings = Timings::default();
+        timings.record(Phase::Parse, Duration::from_millis(30));
+        timings.record(Phase::Parse, Duration::from_millis(20));
+        assert_eq!(timed(Some(&timings), Phase::Fix, || 7), 7);
+        assert_eq!(timed(None, Phase::Fix, || 8), 8);
+
+        let report = timings.report(Duration::from_secs(2));
+        assert_eq!(report.parse, 0.05);
+        assert_eq!(report.walk, 0.0);
+        assert!(report.fix > 0.0);
+        assert_eq!(report.total, 2.0);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::blame::{blame_file, now_seconds, SECONDS_PER_DAY};
+use crate::comment_detection::detect_comments;
+use crate::types::{CommentInfo, Language};
+use crate::utils::read_source;
+use regex::Regex;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const TODO_TAGS: &[&str] = &["TODO", "FIXME", "HACK"];
+
+fn todo_regex() -> &'static Regex {
+    static TODO: OnceLock<Regex> = OnceLock::new();
+    TODO.get_or_init(|| Regex::new(&format!(r"\b({})\b", TODO_TAGS.join("|"))).unwrap())
+}
+
+/// Returns the marker (`TODO`, `FIXME` or `HACK`) a comment carries, if any.
+pub fn todo_tag(comment_text: &str) -> Option<&'static str> {
+    let captures = todo_regex().captures(comment_text)?;
+    TODO_TAGS.iter().copied().find(|tag| *tag == &captures[1])
+}
+
+/// TODO-style comments are reminders, not redundant prose, so analysis never
+/// reports (and `--fix` never removes) them.
+pub fn is_todo_comment(comment_text: &str) -> bool {
+    todo_tag(comment_text).is_some()
+}
+
+/// A TODO/FIXME/HACK comment, with the author and age of its line when the
+/// file is tracked by git.
+#[derive(Debug, Clone, Serialize)]
+pub struct TodoComment {
+    pub path: PathBuf,
+    pub line_number: usize,
+    pub tag: String,
+    pub text: String,
+    pub author: Option<String>,
+    pub age_days: Option<u64>,
+}
+
+/// TODO-style comments in a piece of source code, without blame information.
+pub fn find_todo_comments(source_code: &str, language: Language) -> Vec<CommentInfo> {
+    detect_comments(source_code, language)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|comment| is_todo_comment(&comment.text))
+        .collect()
+}
+
+/// Lists the TODO-style comments in a file, enriched with `git blame` author
+/// and age. Files outside a git repository are listed without them.
+pub fn find_todos_in_file(path: &Path) -> Vec<TodoComment> {
+    let Ok(source_code) = read_source(path) else {
+        return vec![];
+    };
+    let Some(language) = Language::detect(path, &source_code) else {
+        return vec![];
+    };
+
+    let comments = find_todo_comments(&source_code, language);
+    if comments.is_empty() {
+        return vec![];
+    }
+
+    let blame = blame_file(path).unwrap_or_default();
+    let now = now_seconds();
+
+    comments.into_iter()
+        .map(|comment| {
+            let blamed = blame.get(&comment.line_number);
+            TodoComment {
+                path: path.to_path_buf(),
+                line_number: comment.line_number,
+                tag: todo_tag(&comment.text).unwrap_or("TODO").to_string(),
+                author: blamed.map(|line| line.author.clone()),
+                age_days: blamed.map(|line| now.saturating_sub(line.author_time) / SECONDS_PER_DAY),
+                text: comment.text,
+            }
+        })
+        .collect()
+}
+
+/// Parses an age such as `180d`, `6w`, `3m` or `1y` (a bare number means days).
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    let age = age.trim();
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (number, unit) = age.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid age '{}', expected e.g. 180d", age))?;
+
+    let days = match unit {
+        "" | "d" => 1,
+        "w" => 7,
+        "m" => 30,
+        "y" => 365,
+        _ => return Err(format!("unknown unit '{}' in '{}', use d, w, m or y", unit, age)),
+    };
+    Ok(Duration::from_secs(number * days * SECONDS_PER_DAY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_todo_tag() {
+        assert_eq!(todo_tag("// TODO: handle retries"), Some("TODO"));
+        assert_eq!(todo_tag("# FIXME(tristan) this leaks"), Some("FIXME"));
+        assert_eq!(todo_tag("/* HACK around a tokio bug */"), Some("HACK"));
+        assert_eq!(todo_tag("// Mastodon handles it"), None);
+        assert_eq!(todo_tag("// todo list widget"), None);
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("180d").unwrap(), Duration::from_secs(180 * SECONDS_PER_DAY));
+        assert_eq!(parse_age("2w").unwrap(), Duration::from_secs(14 * SECONDS_PER_DAY));
+        assert_eq!(parse_age("1y").unwrap(), Duration::from_secs(365 * SECONDS_PER_DAY));
+        assert_eq!(parse_age("30").unwrap(), Duration::from_secs(30 * SECONDS_PER_DAY));
+        assert!(parse_age("soon").is_err());
+        assert!(parse_age("3h").is_err());
+    }
+
+    #[test]
+    fn test_find_todo_comments() {
+        let source = "# TODO: cache this\ndef load():\n    # Reads the file\n    return 1  # FIXME off by one\n";
+        let todos = find_todo_comments(source, Language::Python);
+
+        assert_eq!(todos.len(), 2);
+        assert_eq!(todos[0].line_number, 1);
+        assert_eq!(todos[1].line_number, 4);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::constants::{TRUSTED_WORKSPACES_FILE_NAME, TRUST_WORKSPACE_ENV};
+use crate::utils::write_atomically;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories whose `unremark.toml` may use the settings that run commands
+/// or otherwise act for the user, such as the credential helper. A cloned
+/// repository's config could otherwise use them as soon as unremark runs in
+/// it, or an editor opens it. Kept one directory per line in the user's
+/// config directory and added to with `unremark trust`; setting
+/// `UNREMARK_TRUST_WORKSPACE=1` trusts every workspace.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedWorkspaces {
+    path: PathBuf,
+    directories: Vec<PathBuf>,
+    everything: bool,
+}
+
+impl TrustedWorkspaces {
+    /// The user's trusted workspaces.
+    pub fn load() -> Self {
+        Self {
+            everything: std::env::var_os(TRUST_WORKSPACE_ENV).is_some_and(|value| value == "1"),
+            ..Self::load_from_path(&trusted_workspaces_path())
+        }
+    }
+
+    /// The workspaces listed at `path`, none when the file doesn't exist yet.
+    pub fn load_from_path(path: &Path) -> Self {
+        let directories = fs::read_to_string(path).unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect();
+        Self { path: path.to_path_buf(), directories, everything: false }
+    }
+
+    /// Whether `directory` is trusted, itself or through one of its ancestors.
+    pub fn trusts(&self, directory: &Path) -> bool {
+        let directory = directory.canonicalize().unwrap_or_else(|_| directory.to_path_buf());
+        self.everything || self.directories.iter().any(|trusted| directory.starts_with(trusted))
+    }
+
+    pub fn directories(&self) -> &[PathBuf] {
+        &self.directories
+    }
+
+    /// Trusts `directory` from now on, or stops trusting it.
+    pub fn set_trusted(&mut self, directory: &Path, trusted: bool) -> Result<(), String> {
+        let directory = directory.canonicalize()
+            .map_err(|e| format!("failed to resolve {}: {}", directory.display(), e))?;
+        self.directories.retain(|listed| *listed != directory);
+        if trusted {
+            self.directories.push(directory);
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
+        }
+        let contents: String = self.directories.iter().map(|directory| format!("{}\n", directory.display())).collect();
+        write_atomically(&self.path, contents.as_bytes())
+            .map_err(|e| format!("failed to save {}: {}", self.path.display(), e))
+    }
+}
+
+/// Where the trusted workspaces are listed, in the user's config directory.
+pub fn trusted_workspaces_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("unremark")
+        .join(TRUSTED_WORKSPACES_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_trusted_workspaces_cover_their_subdirectories() {
+        let directory = TempDir::new().unwrap();
+        let (workspace, other) = (directory.path().join("workspace"), directory.path().join("other"));
+        fs::create_dir_all(workspace.join("nested")).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        let path = directory.path().join("config").join("trusted");
+
+        let mut trusted = TrustedWorkspaces::load_from_path(&path);
+        assert!(!trusted.trusts(&workspace));
+        trusted.set_trusted(&workspace, true).unwrap();
+        trusted.set_trusted(&workspace, true).unwrap();
+
+        let trusted = TrustedWorkspaces::load_from_path(&path);
+        assert_eq!(trusted.directories().len(), 1);
+        assert!(trusted.trusts(&workspace.join("nested")));
+        assert!(!trusted.trusts(&other));
+
+        TrustedWorkspaces::load_from_path(&path).set_trusted(&workspace, false).unwrap();
+        assert!(!TrustedWorkspaces::load_from_path(&path).trusts(&workspace));
+    }
+}
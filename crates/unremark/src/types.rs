@@ -1,23 +1,164 @@
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::fs;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Language {
     Python,
     JavaScript,
     TypeScript,
     Rust,
+    C,
+    Cpp,
+    Sql,
+    Hcl,
+    Dart,
+    Elixir,
+    Zig,
+    Haskell,
+    Vue,
+    Svelte,
+    Bash,
+    /// A grammar loaded from the config's `[[grammars]]`, by load order
+    External(usize),
 }
 
 impl Language {
+    pub const ALL: [Language; 15] = [
+        Language::Python,
+        Language::JavaScript,
+        Language::TypeScript,
+        Language::Rust,
+        Language::C,
+        Language::Cpp,
+        Language::Sql,
+        Language::Hcl,
+        Language::Dart,
+        Language::Elixir,
+        Language::Zig,
+        Language::Haskell,
+        Language::Vue,
+        Language::Svelte,
+        Language::Bash,
+    ];
+
+    /// The file extension `from_extension` recognizes the language by.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Language::Python => "py",
+            Language::JavaScript => "js",
+            Language::TypeScript => "ts",
+            Language::Rust => "rs",
+            Language::C => "c",
+            Language::Cpp => "cpp",
+            Language::Sql => "sql",
+            Language::Hcl => "tf",
+            Language::Dart => "dart",
+            Language::Elixir => "ex",
+            Language::Zig => "zig",
+            Language::Haskell => "hs",
+            Language::Vue => "vue",
+            Language::Svelte => "svelte",
+            Language::Bash => "sh",
+            Language::External(index) => crate::grammars::extension(*index),
+        }
+    }
+
     pub fn from_extension(ext: &str) -> Option<Self> {
+        if let Some(language) = crate::grammars::mapped_extension(ext) {
+            return Some(language);
+        }
         match ext {
             "py" => Some(Language::Python),
             "js" => Some(Language::JavaScript),
             "ts" => Some(Language::TypeScript),
             "rs" => Some(Language::Rust),
+            "c" => Some(Language::C),
+            // Headers go to the C++ parser, which reads C headers too but not the other way round
+            "cpp" | "cc" | "cxx" | "h" | "hpp" | "hh" | "hxx" => Some(Language::Cpp),
+            "sql" => Some(Language::Sql),
+            "tf" | "tfvars" | "hcl" => Some(Language::Hcl),
+            "dart" => Some(Language::Dart),
+            "ex" | "exs" => Some(Language::Elixir),
+            "zig" => Some(Language::Zig),
+            "hs" => Some(Language::Haskell),
+            "vue" => Some(Language::Vue),
+            "svelte" => Some(Language::Svelte),
+            "sh" | "bash" => Some(Language::Bash),
+            _ => crate::grammars::from_extension(ext),
+        }
+    }
+
+    /// The language called `name`, by its name or file extension, e.g. in a
+    /// Markdown code fence or an editor modeline.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        match name.as_str() {
+            "c++" => Some(Language::Cpp),
+            "terraform" => Some(Language::Hcl),
+            _ => Language::from_extension(&name)
+                .or_else(|| Language::ALL.into_iter().find(|language| language.to_string() == name)),
+        }
+    }
+
+    /// The language of a file: by its extension, or for files without one,
+    /// like the scripts in `bin/` directories, by its contents.
+    pub fn detect(path: &Path, source: &str) -> Option<Self> {
+        match path.extension() {
+            Some(ext) => ext.to_str().and_then(Language::from_extension),
+            None => Language::from_content(source),
+        }
+    }
+
+    /// Like `detect`, reading only the start of files without an extension.
+    pub fn of_file(path: &Path) -> Option<Self> {
+        if let Some(ext) = path.extension() {
+            return ext.to_str().and_then(Language::from_extension);
+        }
+        let mut head = Vec::new();
+        fs::File::open(path).and_then(|file| file.take(1024).read_to_end(&mut head)).ok()?;
+        Language::from_content(&String::from_utf8_lossy(&head))
+    }
+
+    /// The language named by a shebang (`#!/usr/bin/env python3`) or an Emacs
+    /// or Vim modeline (`-*- mode: python -*-`, `vim: ft=python`) at the start
+    /// of the source.
+    pub fn from_content(source: &str) -> Option<Self> {
+        if let Some(shebang) = source.strip_prefix("#!") {
+            let mut words = shebang.lines().next().unwrap_or_default().split_whitespace();
+            let mut program = words.next()?.rsplit('/').next()?;
+            if program == "env" {
+                program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+            }
+            return Language::from_interpreter(program);
+        }
+        source.lines().take(5).find_map(|line| {
+            let name = if let Some((_, mode)) = line.split_once("-*-") {
+                let mode = mode.split("-*-").next()?.trim();
+                mode.split(';')
+                    .find_map(|setting| setting.trim().strip_prefix("mode:"))
+                    .unwrap_or(mode)
+                    .trim()
+            } else {
+                let (_, settings) = line.split_once("vim:").or_else(|| line.split_once("vi:"))?;
+                settings.split([' ', ':'])
+                    .find_map(|setting| setting.strip_prefix("ft=").or_else(|| setting.strip_prefix("filetype=")))?
+            };
+            Language::from_name(name)
+        })
+    }
+
+    /// The language a script run by the interpreter is in, e.g. `python3.12`.
+    fn from_interpreter(program: &str) -> Option<Self> {
+        match program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+            "python" | "pypy" => Some(Language::Python),
+            "node" | "nodejs" => Some(Language::JavaScript),
+            "deno" | "bun" | "ts-node" | "tsx" => Some(Language::TypeScript),
+            "bash" | "sh" | "dash" => Some(Language::Bash),
+            "elixir" => Some(Language::Elixir),
+            "runghc" | "runhaskell" => Some(Language::Haskell),
             _ => None,
         }
     }
@@ -28,23 +169,449 @@ impl Language {
             Language::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
             Language::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
             Language::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Language::C => tree_sitter_c::LANGUAGE.into(),
+            Language::Cpp => tree_sitter_cpp::LANGUAGE.into(),
+            Language::Sql => tree_sitter_sequel::LANGUAGE.into(),
+            Language::Hcl => tree_sitter_hcl::LANGUAGE.into(),
+            Language::Dart => tree_sitter_dart::LANGUAGE.into(),
+            Language::Elixir => tree_sitter_elixir::LANGUAGE.into(),
+            Language::Zig => tree_sitter_zig::LANGUAGE.into(),
+            Language::Haskell => tree_sitter_haskell::LANGUAGE.into(),
+            // Only their <script> blocks are parsed, as TypeScript, which reads plain JavaScript too
+            Language::Vue | Language::Svelte => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Language::Bash => tree_sitter_bash::LANGUAGE.into(),
+            Language::External(index) => crate::grammars::tree_sitter_language(*index),
+        }
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Language::Python => write!(f, "python"),
+            Language::JavaScript => write!(f, "javascript"),
+            Language::TypeScript => write!(f, "typescript"),
+            Language::Rust => write!(f, "rust"),
+            Language::C => write!(f, "c"),
+            Language::Cpp => write!(f, "cpp"),
+            Language::Sql => write!(f, "sql"),
+            Language::Hcl => write!(f, "hcl"),
+            Language::Dart => write!(f, "dart"),
+            Language::Elixir => write!(f, "elixir"),
+            Language::Zig => write!(f, "zig"),
+            Language::Haskell => write!(f, "haskell"),
+            Language::Vue => write!(f, "vue"),
+            Language::Svelte => write!(f, "svelte"),
+            Language::Bash => write!(f, "bash"),
+            Language::External(index) => write!(f, "{}", crate::grammars::name(*index)),
+        }
+    }
+}
+
+impl Serialize for Language {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Which service answers the redundancy and accuracy prompts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Provider {
+    /// The OpenAI chat completions API, called directly
+    #[default]
+    OpenAi,
+    /// An unremark proxy server, which holds the API key itself
+    Proxy,
+    /// OpenRouter's OpenAI-compatible API, routing to many providers' models
+    OpenRouter,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 3] = [Provider::OpenAi, Provider::Proxy, Provider::OpenRouter];
+
+    /// The model asked when none is configured.
+    pub fn default_model(&self) -> &'static str {
+        match self {
+            Provider::OpenRouter => crate::constants::DEFAULT_OPENROUTER_MODEL,
+            Provider::OpenAi | Provider::Proxy => crate::constants::OPENAI_MODEL,
+        }
+    }
+}
+
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Provider::OpenAi => write!(f, "openai"),
+            Provider::Proxy => write!(f, "proxy"),
+            Provider::OpenRouter => write!(f, "openrouter"),
+        }
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openai" => Ok(Provider::OpenAi),
+            "proxy" => Ok(Provider::Proxy),
+            "openrouter" => Ok(Provider::OpenRouter),
+            _ => Err(format!("Unknown provider '{}', expected 'openai', 'proxy' or 'openrouter'", s)),
+        }
+    }
+}
+
+/// How to reach the model behind the API checks.
+#[derive(Debug, Clone)]
+pub struct ApiSettings {
+    /// Falls back to `OPENAI_API_KEY` (`UNREMARK_PROXY_TOKEN` for the
+    /// proxy, `OPENROUTER_API_KEY` for OpenRouter), then to the sources in `credentials`
+    pub api_key: Option<String>,
+    pub provider: Provider,
+    pub model: String,
+    /// Base URL of the provider's API, when not the default one
+    /// (`https://api.openai.com/v1`, `https://openrouter.ai/api/v1` for
+    /// OpenRouter, or `PROXY_ENDPOINT` for the proxy).
+    /// Any OpenAI-compatible server can stand in for OpenAI's
+    pub endpoint: Option<String>,
+    /// Most requests of a file in flight at once, unlimited when unset
+    pub concurrency: Option<usize>,
+    /// Paces the requests of every file sharing these settings
+    pub limiter: std::sync::Arc<crate::rate_limit::RateLimiter>,
+    /// Stops the requests of every file sharing these settings once the API keeps failing
+    pub breaker: std::sync::Arc<crate::circuit_breaker::CircuitBreaker>,
+    /// Counts the tokens used by every file sharing these settings
+    pub usage: std::sync::Arc<crate::usage::TokenUsage>,
+    /// Most comments of a file asked about in one request. Batches cut the
+    /// prompt's overhead and the request count; 1 asks about each on its own
+    pub batch_size: usize,
+    /// HTTP client to reuse, so long-running processes like the daemon keep
+    /// their connections warm. A new one is built per batch when unset
+    pub client: Option<reqwest::Client>,
+    /// How long each request may take, for the provider's API and the proxy alike
+    pub timeout: std::time::Duration,
+    pub retry: crate::api::RetryPolicy,
+    /// CA bundle, client certificate and proxy for every request, from the
+    /// `UNREMARK_*` environment variables by default
+    pub network: crate::config::NetworkConfig,
+    /// How request bodies sent to the proxy are compressed
+    pub compression: crate::services::proxy::Compression,
+    /// Told when rate-limited requests will be retried
+    pub rate_limited: Option<crate::api::RateLimitSender>,
+    /// Sampling settings sent with each request
+    pub generation: crate::config::GenerationConfig,
+    /// Where the key is looked up when it's not set or in the environment
+    pub credentials: crate::config::CredentialsConfig,
+    /// Fallback models and attribution sent to OpenRouter
+    pub openrouter: crate::config::OpenRouterConfig,
+    /// Labeled comments asked about before each redundancy question
+    pub examples: Vec<crate::config::PromptExample>,
+    /// Recorded responses answering the API's requests, or saving them, from
+    /// `UNREMARK_REPLAY` and `UNREMARK_RECORD` by default
+    pub fixtures: Option<std::sync::Arc<crate::replay::Fixtures>>,
+}
+
+impl ApiSettings {
+    /// Checks the settings are ones the provider accepts.
+    pub fn validate(&self) -> Result<(), String> {
+        self.generation.validate(self.provider)
+    }
+
+    /// The provider's key, from wherever it's configured to come from.
+    pub fn resolved_api_key(&self) -> Option<String> {
+        crate::credentials::resolve_api_key(self.provider, self.api_key.as_deref(), &self.credentials)
+    }
+
+    /// The base URL of the provider's chat completions API.
+    pub fn base_url(&self) -> &str {
+        match (&self.endpoint, self.provider) {
+            (Some(endpoint), _) => endpoint.trim_end_matches('/'),
+            (None, Provider::OpenRouter) => crate::constants::DEFAULT_OPENROUTER_ENDPOINT,
+            (None, _) => crate::constants::DEFAULT_OPENAI_ENDPOINT,
+        }
+    }
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            provider: Provider::OpenAi,
+            model: crate::constants::OPENAI_MODEL.to_string(),
+            endpoint: None,
+            concurrency: None,
+            limiter: Default::default(),
+            breaker: Default::default(),
+            usage: Default::default(),
+            batch_size: 1,
+            client: None,
+            timeout: crate::constants::DEFAULT_REQUEST_TIMEOUT,
+            retry: crate::api::RetryPolicy::default(),
+            network: crate::config::NetworkConfig::default().with_env(),
+            compression: crate::services::proxy::Compression::default(),
+            rate_limited: None,
+            generation: crate::config::GenerationConfig::default(),
+            credentials: crate::config::CredentialsConfig::default(),
+            openrouter: crate::config::OpenRouterConfig::default(),
+            examples: Vec::new(),
+            fixtures: crate::replay::Fixtures::from_env(),
+        }
+    }
+}
+
+/// Knobs that change how comments are analyzed.
+#[derive(Debug, Clone)]
+pub struct AnalysisOptions {
+    /// Only run the local heuristic rules, never call the API
+    pub offline: bool,
+    /// Fraction of a comment's words that must come from the adjacent
+    /// declaration's name for the name-echo rule to flag it
+    pub name_echo_threshold: f32,
+    /// How many copies of the same comment in a file it takes to flag them
+    pub min_duplicate_occurrences: usize,
+    /// Which rules run and how severe their findings are
+    pub rules: crate::config::RulesConfig,
+    /// Keep comments that are almost certainly useful (links, issue references,
+    /// long explanations) from being sent to the API
+    pub prefilter: bool,
+    /// Custom policies (e.g. WASM plugins) run before the built-in rules
+    pub policies: Vec<std::sync::Arc<dyn crate::plugins::CommentPolicy>>,
+    /// Local model settling confident cases before the API is asked
+    pub classifier: Option<crate::classifier::LocalClassifier>,
+    /// Also ask the API whether each comment still matches its code,
+    /// reporting the ones that contradict it for review
+    pub check_accuracy: bool,
+    /// Only report (and fix) comments on these lines, e.g. the ones a PR touched
+    pub changed_lines: Option<std::sync::Arc<crate::changes::ChangedLines>>,
+    /// Annotate findings with the commit, author and age of the comment
+    pub blame: bool,
+    /// Only report (and fix) findings that weren't already there at this commit
+    pub merge_base: Option<std::sync::Arc<crate::baseline::MergeBase>>,
+    /// Where the API checks are sent
+    pub api: ApiSettings,
+    /// Most files analyzed at once by `analyze_files` and the binaries
+    pub file_concurrency: usize,
+    /// Files larger than this many bytes are skipped with a warning, none when unset
+    pub max_file_size: Option<u64>,
+    /// How much code is sent along with each comment
+    pub context_budget: crate::utils::ContextBudget,
+    /// Where the time spent in each phase is added up, when the run is timed
+    pub timings: Option<std::sync::Arc<crate::timings::Timings>>,
+    /// Mark redundant comments with `ANNOTATION_MARKER` instead of leaving
+    /// the files alone, unless they're being fixed
+    pub annotate: bool,
+    /// Score the usefulness of every analyzed comment, not just report the
+    /// redundant ones
+    pub report_all: bool,
+    /// Check each fix with `verify_fix` before writing it, leaving the files
+    /// whose fix fails alone
+    pub verify: bool,
+    /// Also analyze the comments in the fenced code blocks of Markdown files
+    pub markdown: bool,
+    /// Parse every file as this language, whatever its name
+    pub language: Option<Language>,
+    /// Keep each file's source, as analyzed, in its result, for quoting the
+    /// findings after `--fix` changed the file or when it came from stdin
+    pub keep_source: bool,
+}
+
+impl AnalysisOptions {
+    /// Identifies the settings that change which comments get reported, to
+    /// tell cached results computed under different settings apart.
+    pub fn cache_key(&self) -> String {
+        let policies: Vec<&str> = self.policies.iter().map(|policy| policy.id()).collect();
+        let classifier = self.classifier.as_ref()
+            .map(|classifier| format!("{}-{}", classifier.useful_threshold, classifier.redundant_threshold))
+            .unwrap_or_default();
+        let mut key = format!(
+            "{}|{}|{}|{}|{}",
+            self.rules.cache_key(),
+            policies.join(","),
+            self.prefilter,
+            classifier,
+            self.check_accuracy,
+        );
+        // Left out for the default model so existing cache entries stay valid
+        if self.api.model != crate::constants::OPENAI_MODEL {
+            key.push_str(&format!("|{}", self.api.model));
+        }
+        if !self.api.examples.is_empty() {
+            let examples: Vec<String> = self.api.examples.iter()
+                .map(|example| format!("{}:{}:{}", example.redundant, example.comment, example.context))
+                .collect();
+            key.push_str(&format!("|examples-{}", examples.join(",")));
+        }
+        if self.api.batch_size > 1 {
+            key.push_str(&format!("|batch-{}", self.api.batch_size));
+        }
+        if self.api.generation.logprobs {
+            key.push_str("|logprobs");
+        }
+        // The API's verdicts depend on how much code it saw
+        if self.context_budget != crate::utils::ContextBudget::default() {
+            key.push_str(&format!("|context-{}-{}", self.context_budget.lines_around, self.context_budget.max_tokens));
+        }
+        if let Some(language) = self.language {
+            key.push_str(&format!("|{}", language));
+        }
+        // Entries without scores can't answer a run that reports them
+        if self.report_all {
+            key.push_str("|all");
         }
+        key
     }
 }
 
-#[derive(Debug)]
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        Self {
+            offline: false,
+            name_echo_threshold: crate::heuristics::DEFAULT_NAME_ECHO_THRESHOLD,
+            min_duplicate_occurrences: crate::heuristics::DEFAULT_MIN_DUPLICATES,
+            rules: crate::config::RulesConfig::default(),
+            prefilter: true,
+            policies: Vec::new(),
+            classifier: None,
+            check_accuracy: false,
+            changed_lines: None,
+            blame: false,
+            merge_base: None,
+            api: ApiSettings::default(),
+            file_concurrency: crate::constants::DEFAULT_FILE_CONCURRENCY,
+            max_file_size: Some(crate::constants::DEFAULT_MAX_FILE_SIZE),
+            context_budget: crate::utils::ContextBudget::default(),
+            timings: None,
+            annotate: false,
+            report_all: false,
+            verify: false,
+            markdown: false,
+            language: None,
+            keep_source: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct AnalysisResult {
     pub path: PathBuf,
     pub redundant_comments: Vec<CommentInfo>,
+    /// Comments worth a second look (e.g. possibly outdated) that `--fix` never removes
+    pub flagged_comments: Vec<CommentInfo>,
     pub errors: Vec<String>,
+    /// Why the file was skipped, e.g. for being over `max_file_size`
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Every analyzed comment with its `quality_score`, when `report_all` is on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scored_comments: Vec<CommentInfo>,
+    /// Whether the findings came from the cache rather than a fresh analysis
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// The source the line numbers refer to, before any fix, when `keep_source` is on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// How serious a finding is, from a rule's default or `unremark.toml`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Hint,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    /// One level less serious, bottoming out at `Hint`.
+    pub fn lower(self) -> Self {
+        match self {
+            Severity::Error => Severity::Warning,
+            Severity::Warning => Severity::Info,
+            Severity::Info | Severity::Hint => Severity::Hint,
+        }
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hint" => Ok(Severity::Hint),
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(format!("Unknown severity '{}', expected 'hint', 'info', 'warning' or 'error'", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Hint => write!(f, "hint"),
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// What kind of comment this is, as far as can be told without analysis.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum CommentCategory {
+    #[default]
+    Regular,
+    /// Part of a license header (SPDX tag, Apache/MIT/GPL banner, copyright notice)
+    License,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct CommentInfo {
     pub text: String,
     pub line_number: usize,
     pub context: String,
     pub explanation: Option<String>,
+    #[serde(default)]
+    pub rule_id: Option<String>,
+    #[serde(default)]
+    pub category: CommentCategory,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    /// How sure the check that reported the comment is (0.0-1.0), when it can tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f32>,
+    /// Last commit to touch the comment, when blame annotation is on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blame: Option<crate::blame::CommentBlame>,
+    /// Where the comment is in the source, in bytes, telling apart copies of
+    /// the same comment in a file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub byte_range: Option<std::ops::Range<usize>>,
+    /// How useful the comment is (0-100), when `report_all` asked for it and
+    /// a check could tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality_score: Option<u8>,
+}
+
+impl CommentInfo {
+    /// License headers are legal boilerplate, never candidates for removal.
+    pub fn is_license(&self) -> bool {
+        self.category == CommentCategory::License
+    }
+}
+
+/// What the analysis makes of a single comment, and why.
+#[derive(Debug, Clone, Serialize)]
+pub struct CommentExplanation {
+    pub verdict: crate::plugins::Verdict,
+    /// The comment, with the rule that decided it and the explanation
+    pub comment: CommentInfo,
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,12 +619,40 @@ pub struct CommentAnalysis {
     pub is_redundant: bool,
     pub comment_line_number: usize,
     pub explanation: String,
+    /// Left out, or out of range, from models that don't follow the prompt
+    /// to the letter
+    #[serde(default)]
+    pub usefulness_score: Option<f64>,
+    /// How sure the model is of `is_redundant` (0.0-1.0), stated by it or
+    /// from the API's logprobs
+    #[serde(default)]
+    pub confidence: Option<f64>,
+}
+
+/// The model's answers to a batch of comments.
+#[derive(Debug, Deserialize)]
+pub struct BatchAnalysis {
+    pub analyses: Vec<CommentAnalysis>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccuracyAnalysis {
+    pub is_accurate: bool,
+    pub comment_line_number: usize,
+    pub explanation: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CacheEntry {
     pub last_modified: u64,
     pub redundant_comments: Vec<CommentInfo>,
+    #[serde(default)]
+    pub flagged_comments: Vec<CommentInfo>,
+    #[serde(default)]
+    pub scored_comments: Vec<CommentInfo>,
+    /// `AnalysisOptions::cache_key` of the settings the entry was computed with
+    #[serde(default)]
+    pub rules_key: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -77,9 +672,9 @@ impl Cache {
         }
     }
 
-    pub fn save_to_path(&self, cache_path: &PathBuf) {
+    pub fn save_to_path(&self, cache_path: &std::path::Path) {
         if let Ok(contents) = serde_json::to_string(self) {
-            let _ = fs::write(cache_path, contents);
+            let _ = crate::utils::write_atomically(cache_path, contents.as_bytes());
         }
     }
 
@@ -92,11 +687,13 @@ impl Cache {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ApiError {
     RateLimit(String),
     Timeout(String),
     Network(String),
+    /// Not sent, the circuit breaker is open
+    CircuitOpen(String),
     Other(String),
 }
 
@@ -106,7 +703,47 @@ impl std::fmt::Display for ApiError {
             ApiError::RateLimit(msg) => write!(f, "Rate limit exceeded: {}", msg),
             ApiError::Timeout(msg) => write!(f, "Request timeout: {}", msg),
             ApiError::Network(msg) => write!(f, "Network error: {}", msg),
+            ApiError::CircuitOpen(msg) => write!(f, "Circuit breaker open: {}", msg),
             ApiError::Other(msg) => write!(f, "API error: {}", msg),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_languages_are_detected_from_shebangs_and_modelines() {
+        let detect = |source: &str| Language::from_content(source);
+        assert_eq!(detect("#!/usr/bin/env python3\nprint(1)\n"), Some(Language::Python));
+        assert_eq!(detect("#!/usr/bin/python3.12 -u\n"), Some(Language::Python));
+        assert_eq!(detect("#!/bin/bash\nset -e\n"), Some(Language::Bash));
+        assert_eq!(detect("#!/usr/bin/env -S NODE_ENV=production node --no-warnings\n"), Some(Language::JavaScript));
+        assert_eq!(detect("#!/usr/bin/env perl\n"), None);
+        assert_eq!(detect("# -*- coding: utf-8; mode: python -*-\n"), Some(Language::Python));
+        assert_eq!(detect("// -*- c++ -*-\n"), Some(Language::Cpp));
+        assert_eq!(detect("x = 1\n# vim: set ts=4 ft=elixir:\n"), Some(Language::Elixir));
+        assert_eq!(detect("# -*- coding: utf-8 -*-\nx = 1\n"), None);
+
+        // An extension decides, even over a shebang
+        assert_eq!(Language::detect(Path::new("bin/deploy.rs"), "#!/bin/bash\n"), Some(Language::Rust));
+        assert_eq!(Language::detect(Path::new("bin/deploy.txt"), "#!/bin/bash\n"), None);
+        assert_eq!(Language::detect(Path::new("bin/deploy"), "#!/bin/bash\n"), Some(Language::Bash));
+    }
+
+    #[test]
+    fn test_language_of_file_reads_scripts_without_an_extension() {
+        let temporary_directory = TempDir::new().unwrap();
+        let script = temporary_directory.path().join("deploy");
+        fs::write(&script, "#!/usr/bin/env python3\nprint(1)\n").unwrap();
+        let notes = temporary_directory.path().join("NOTES");
+        fs::write(&notes, "Nothing to see here.\n").unwrap();
+
+        assert_eq!(Language::of_file(&script), Some(Language::Python));
+        assert_eq!(Language::of_file(&notes), None);
+        assert_eq!(Language::of_file(&temporary_directory.path().join("missing")), None);
+        assert_eq!(Language::of_file(Path::new("missing.ts")), Some(Language::TypeScript));
+    }
+}
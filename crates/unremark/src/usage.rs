@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Tokens the API reported using, shared by every file analyzed in a run.
+/// Proxy requests aren't counted, the proxy's operator pays for its model.
+#[derive(Debug, Default)]
+pub struct TokenUsage {
+    requests: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+}
+
+impl TokenUsage {
+    /// The tokens a completion's `usage` reports.
+    pub fn record(&self, prompt: u64, completion: u64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.prompt_tokens.fetch_add(prompt, Ordering::Relaxed);
+        self.completion_tokens.fetch_add(completion, Ordering::Relaxed);
+    }
+
+    /// The usage so far, with its cost at the given prices per million input
+    /// and output tokens.
+    pub fn report(&self, input_price: f64, output_price: f64) -> UsageReport {
+        let prompt_tokens = self.prompt_tokens.load(Ordering::Relaxed);
+        let completion_tokens = self.completion_tokens.load(Ordering::Relaxed);
+        UsageReport {
+            requests: self.requests.load(Ordering::Relaxed),
+            prompt_tokens,
+            completion_tokens,
+            cost: (prompt_tokens as f64 * input_price + completion_tokens as f64 * output_price) / 1_000_000.0,
+        }
+    }
+}
+
+/// The requests a run sent to the API, the tokens they took and what they cost.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UsageReport {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// In dollars, at the prices the run was given
+    pub cost: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_adds_up_across_requests() {
+        let usage = TokenUsage::default();
+        usage.record(1_000_000, 100_000);
+        usage.record(500_000, 150_000);
+        assert_eq!(usage.report(0.30, 1.20), UsageReport {
+            requests: 2,
+            prompt_tokens: 1_500_000,
+            completion_tokens: 250_000,
+            cost: 0.75,
+        });
+    }
+}
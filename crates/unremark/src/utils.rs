@@ -1,9 +1,13 @@
 use crate::types::CommentInfo;
+use crate::comment_detection::is_comment_node;
 use tree_sitter::Node;
-use log::debug;
-use std::path::PathBuf;
+use tracing::debug;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
 use std::fs;
-use crate::constants::CACHE_FILE_NAME;
+use crate::constants::{ANNOTATION_MARKER, CACHE_FILE_NAME, DEFAULT_CONTEXT_LINES, DEFAULT_CONTEXT_TOKENS};
 
 pub fn get_cache_path() -> PathBuf {
     let cache_dir = dirs::cache_dir()
@@ -16,108 +20,485 @@ pub fn get_cache_path() -> PathBuf {
     cache_dir.join(CACHE_FILE_NAME)
 }
 
+/// Decodes a source file, from UTF-8 or whichever legacy encoding (Latin-1,
+/// Shift-JIS, ...) it looks like, returning the encoding to write it back in.
+pub fn decode_source(bytes: &[u8]) -> Result<(String, &'static Encoding), String> {
+    if let Ok(source) = std::str::from_utf8(bytes) {
+        return Ok((source.to_string(), UTF_8));
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    let encoding = detector.guess(None, true);
+    encoding.decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|source| (source.into_owned(), encoding))
+        .ok_or_else(|| format!("not valid UTF-8, and not valid {} either", encoding.name()))
+}
 
-pub fn find_context(node: Node, code: &str) -> String {
-    let mut parent = node;
+/// Encodes a source back in the encoding it was read in.
+pub fn encode_source(source: &str, encoding: &'static Encoding) -> Result<Vec<u8>, String> {
+    let (bytes, _, unmappable) = encoding.encode(source);
+    if unmappable {
+        return Err(format!("can't be written back as {}", encoding.name()));
+    }
+    Ok(bytes.into_owned())
+}
+
+/// Reads a source file in whatever encoding it's in.
+pub fn read_source(path: &Path) -> std::io::Result<String> {
+    let bytes = fs::read(path)?;
+    decode_source(&bytes)
+        .map(|(source, _)| source)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Replaces the file's contents all at once, through a temporary file
+/// renamed over it, so an interrupted write leaves the old contents intact.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("unremark");
+    let temporary = path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()));
+    fs::write(&temporary, contents)
+        .and_then(|_| fs::rename(&temporary, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temporary);
+        })
+}
+
+/// Code is about four characters a token, close enough to budget the context.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Rough number of tokens the text encodes to, the way `ContextBudget` counts them.
+pub(crate) fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// How much code goes along with each comment, to keep large functions from
+/// costing thousands of tokens per comment. Read from the `[context]`
+/// section of the config:
+///
+/// ```toml
+/// [context]
+/// max_tokens = 256
+/// lines_around = 10
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContextBudget {
+    /// Lines kept on either side of the comment when its enclosing
+    /// declaration is too large to send whole
+    pub lines_around: usize,
+    /// Rough cap on the context's size
+    pub max_tokens: usize,
+}
+
+impl Default for ContextBudget {
+    fn default() -> Self {
+        Self {
+            lines_around: DEFAULT_CONTEXT_LINES,
+            max_tokens: DEFAULT_CONTEXT_TOKENS,
+        }
+    }
+}
+
+/// The code a comment is judged against: its enclosing function, class or
+/// method when that fits the budget, otherwise the declaration's signature and
+/// the lines around the comment, fewer of them until they fit. Comments outside
+/// any declaration get the code that follows them instead.
+pub fn find_context(node: Node, code: &str, budget: &ContextBudget) -> String {
+    let max_tokens = budget.max_tokens;
+    let Some(declaration) = enclosing_declaration(node) else {
+        return following_code(node, code, budget.lines_around, max_tokens);
+    };
+    let text = declaration.utf8_text(code.as_bytes()).unwrap_or("");
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+
+    let signature = declaration.child_by_field_name("body")
+        .and_then(|body| code.get(declaration.start_byte()..body.start_byte()))
+        .map(str::trim_end)
+        .unwrap_or_else(|| text.lines().next().unwrap_or(""));
+    let lines: Vec<&str> = text.lines().collect();
+    let signature_lines = signature.lines().count().max(1);
+    let first_row = declaration.start_position().row;
+    let comment_rows = (node.start_position().row - first_row, node.end_position().row - first_row);
+
+    let mut lines_around = budget.lines_around;
+    loop {
+        let from = comment_rows.0.saturating_sub(lines_around).max(signature_lines);
+        let to = (comment_rows.1 + lines_around + 1).min(lines.len());
+        let mut context = vec![signature];
+        if from > signature_lines {
+            context.push("...");
+        }
+        context.extend(lines.get(from..to).unwrap_or_default());
+        if to < lines.len() {
+            context.push("...");
+        }
+        let context = context.join("\n");
+        if estimate_tokens(&context) <= max_tokens {
+            return context;
+        }
+        if lines_around == 0 {
+            return truncate_to_tokens(&context, max_tokens).to_string();
+        }
+        lines_around /= 2;
+    }
+}
+
+/// The comment and the statement or declaration after it (past any further
+/// comments), e.g. the function a top-of-file or pre-declaration comment
+/// describes. A trailing comment also keeps the code it trails. When that's
+/// over `max_tokens`, the comment and the lines after it, fewer until they fit.
+fn following_code(node: Node, code: &str, lines_after: usize, max_tokens: usize) -> String {
+    let mut next = node.next_named_sibling();
+    while let Some(sibling) = next.filter(|sibling| is_comment_node(*sibling)) {
+        next = sibling.next_named_sibling();
+    }
+    let Some(next) = next else {
+        return String::new();
+    };
+    let start = node.prev_named_sibling()
+        .filter(|previous| previous.end_position().row == node.start_position().row)
+        .map_or(node.start_byte(), |previous| previous.start_byte());
+    let start = code[..start].rfind('\n').map_or(0, |newline| newline + 1);
+    let text = code.get(start..next.end_byte()).unwrap_or("");
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    let comment_lines = node.end_position().row - node.start_position().row + 1;
+    let mut lines_after = lines_after;
+    loop {
+        let end = (comment_lines + lines_after).min(lines.len());
+        let mut context = lines[..end].join("\n");
+        if end < lines.len() {
+            context.push_str("\n...");
+        }
+        if estimate_tokens(&context) <= max_tokens {
+            return context;
+        }
+        if lines_after == 0 {
+            return truncate_to_tokens(&context, max_tokens).to_string();
+        }
+        lines_after /= 2;
+    }
+}
 
+/// The closest function, class or method around the node.
+fn enclosing_declaration(node: Node) -> Option<Node> {
+    let mut parent = node;
     while let Some(p) = parent.parent() {
         let kind = p.kind();
         if kind.contains("function") || kind.contains("class") || kind.contains("method") {
-            return p.utf8_text(code.as_bytes()).unwrap_or("").to_string();
+            return Some(p);
         }
         parent = p;
     }
+    None
+}
 
-    String::new()
+/// The start of `text` that fits in `max_tokens`, as `estimate_tokens` counts
+/// them, so multi-byte characters count once like in the budget.
+fn truncate_to_tokens(text: &str, max_tokens: usize) -> &str {
+    let max_chars = max_tokens.saturating_mul(CHARS_PER_TOKEN);
+    text.char_indices().nth(max_chars).map_or(text, |(end, _)| &text[..end])
 }
 
+/// Removes the comments from the source, each from the span it was detected
+/// at, so the same text elsewhere (a string literal, a Python docstring,
+/// another copy of the comment) is left alone. Comments without a span, or
+/// whose span no longer holds them, are only looked for on their own lines.
+/// Lines left blank by a removal are dropped.
 pub fn remove_redundant_comments(source: &str, redundant_comments: &[CommentInfo]) -> String {
+    let mut spans: Vec<Range<usize>> = redundant_comments.iter()
+        .filter_map(|comment| {
+            let span = comment_span(source, comment);
+            if span.is_none() {
+                debug!("Comment '{}' is no longer on line {}, left in place", comment.text, comment.line_number);
+            }
+            span
+        })
+        .collect();
+    // From the end, so the spans still to remove keep their offsets
+    spans.sort_by_key(|span| std::cmp::Reverse(span.start));
+    spans.dedup();
+
     let mut updated_source = source.to_string();
+    for span in spans {
+        let removal = removal_range(&updated_source, span);
+        updated_source.replace_range(removal, "");
+    }
+    updated_source
+}
 
-    // First, find all docstring positions to avoid modifying them
-    let docstring_pattern = match std::path::Path::new(&source)
-        .extension()
-        .and_then(|ext| ext.to_str())
-    {
-        Some("py") => r#"(?m)[\t ]*'''[^']*'''|[\t ]*"""[^"]*""""#,
-        _ => r#"(?m)$^"#  // Match nothing for non-Python files
-    };
-    let docstring_regex = regex::Regex::new(docstring_pattern).unwrap();
-    let docstring_positions: Vec<_> = docstring_regex.find_iter(&updated_source)
-        .map(|m| (m.start(), m.end()))
+/// Marks each comment with `ANNOTATION_MARKER` right after its opening
+/// delimiter (`// [unremark: likely redundant] ...`), for review in the code
+/// before anything is removed. Comments already marked are left as they are.
+pub fn annotate_redundant_comments(source: &str, redundant_comments: &[CommentInfo]) -> String {
+    let mut spans: Vec<Range<usize>> = redundant_comments.iter()
+        .filter(|comment| !is_annotated(&comment.text))
+        .filter_map(|comment| comment_span(source, comment))
         .collect();
+    spans.sort_by_key(|span| std::cmp::Reverse(span.start));
+    spans.dedup();
 
-    debug!("Source content:\n{}", updated_source);
-    debug!("Found {} docstrings", docstring_positions.len());
-    for (i, (start, end)) in docstring_positions.iter().enumerate() {
-        let docstring = &updated_source[*start..*end];
-        debug!("Docstring {} at positions {}..{}:\n{}", 
-            i,
-            start,
-            end,
-            docstring
-        );
+    let mut updated_source = source.to_string();
+    for span in spans {
+        let text = &updated_source[span.clone()];
+        let delimiter = text.len() - text.trim_start_matches(['/', '*', '#', '!', '-', '{']).len();
+        let separator = if text[delimiter..].starts_with(char::is_whitespace) { "" } else { " " };
+        let insertion = format!(" {}{}", ANNOTATION_MARKER, separator);
+        updated_source.insert_str(span.start + delimiter, &insertion);
     }
+    updated_source
+}
 
-    for comment in redundant_comments {
-        let comment_text = &comment.text;
-        
-        // Get the position of this comment in the source
-        if let Some(comment_pos) = updated_source.find(comment_text) {
-            debug!("Found comment '{}' at position {}", 
-                comment_text.replace('\n', "\\n"), 
-                comment_pos
-            );
-            
-            // Check if this comment is part of a docstring
-            let is_in_docstring = docstring_positions.iter()
-                .any(|&(start, end)| {
-                    let in_range = comment_pos >= start && comment_pos < end;
-                    if in_range {
-                        debug!("Comment is inside docstring range {}..{}", start, end);
-                    }
-                    in_range
-                });
-            
-            if is_in_docstring {
-                debug!("Skipping comment in docstring: {}", comment_text);
-                continue;
-            }
+/// Whether the comment was marked by `--annotate`.
+pub fn is_annotated(comment_text: &str) -> bool {
+    comment_text.contains(ANNOTATION_MARKER)
+}
 
-            // For single-line comments, ensure we match the exact comment
-            let pattern = if comment_text.starts_with('#') || comment_text.starts_with("//") {
-                if comment_pos > 0 && updated_source[..comment_pos].trim_end().chars().last() != Some('{') {
-                    // Inline comment
-                    format!("[ \t]*{}[ \t]*(?:\r?\n|$)", regex::escape(comment_text))
-                } else {
-                    // Line-start comment
-                    format!("(?m)^[ \t]*{}[ \t]*(?:\r?\n|$)", regex::escape(comment_text))
-                }
-            } else {
-                format!("[ \t]*{}[ \t]*", regex::escape(comment_text))
-            };
-
-            // Use regex to ensure we only replace exact matches
-            if let Ok(regex) = regex::Regex::new(&pattern) {
-                debug!("Removing comment at line {}: {} with pattern {}", 
-                    comment.line_number, 
-                    comment_text,
-                    pattern
-                );
-                updated_source = regex.replace_all(&updated_source, "").to_string();
+/// Where the comment is in the source.
+fn comment_span(source: &str, comment: &CommentInfo) -> Option<Range<usize>> {
+    let detected = comment.byte_range.clone()
+        .filter(|range| source.get(range.clone()) == Some(comment.text.as_str()));
+    if detected.is_some() {
+        return detected;
+    }
+
+    let first = comment.line_number.checked_sub(1)?;
+    let mut lines = source.split_inclusive('\n');
+    let start: usize = lines.by_ref().take(first).map(str::len).sum();
+    let length: usize = lines.take(comment.text.lines().count().max(1)).map(str::len).sum();
+    let segment = &source[start..start + length];
+
+    // A line comment runs to the end of its line, after any string that quotes it
+    let is_line_comment = comment.text.starts_with('#') || comment.text.starts_with("//") || comment.text.starts_with("--");
+    let position = if is_line_comment { segment.rfind(&comment.text) } else { segment.find(&comment.text) }?;
+    Some(start + position..start + position + comment.text.len())
+}
+
+/// What to cut to remove the comment at `span`: its whole lines when nothing
+/// else is on them, otherwise the comment and the spaces that set it apart.
+fn removal_range(source: &str, span: Range<usize>) -> Range<usize> {
+    let line_start = source[..span.start].rfind('\n').map_or(0, |newline| newline + 1);
+    let newline = source[span.end..].find('\n').map(|newline| span.end + newline);
+    // Where the line's text ends, before its `\n` or `\r\n`
+    let line_end = newline.map_or(source.len(), |newline| {
+        if source[span.end..newline].ends_with('\r') { newline - 1 } else { newline }
+    });
+    let before = &source[line_start..span.start];
+    let after = &source[span.end..line_end];
+
+    match (before.trim().is_empty(), after.trim().is_empty(), newline) {
+        (true, true, Some(newline)) => line_start..newline + 1,
+        // The last line goes with the line break before it
+        (true, true, None) => {
+            let previous_end = source[..line_start].strip_suffix('\n')
+                .map_or(line_start, |rest| rest.strip_suffix('\r').unwrap_or(rest).len());
+            previous_end..line_end
+        }
+        (false, true, _) => span.start - (before.len() - before.trim_end().len())..line_end,
+        (_, false, _) => span.start..span.end + (after.len() - after.trim_start().len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comment_detection::{detect_comments, detect_comments_with};
+    use crate::types::Language;
+
+    fn long_function() -> String {
+        let mut source = String::from("fn process(items: &[Item]) -> usize {\n");
+        for i in 0..60 {
+            source.push_str(&format!("    let value_{} = items.len() + {};\n", i, i));
+        }
+        source.push_str("    // Count the items\n    let count = items.len();\n");
+        for i in 60..120 {
+            source.push_str(&format!("    let value_{} = items.len() + {};\n", i, i));
+        }
+        source.push_str("    count\n}\n");
+        source
+    }
+
+    #[test]
+    fn test_small_declarations_are_sent_whole() {
+        let source = "fn add(a: i32, b: i32) -> i32 {\n    // Add them\n    a + b\n}\n";
+        let comments = detect_comments_with(source, Language::Rust, &ContextBudget::default()).unwrap();
+        assert_eq!(comments[0].context, source.trim_end());
+    }
+
+    #[test]
+    fn test_large_declarations_keep_the_signature_and_nearby_lines() {
+        let source = long_function();
+        let budget = ContextBudget { lines_around: 2, max_tokens: 100 };
+        let comments = detect_comments_with(&source, Language::Rust, &budget).unwrap();
+        let context = &comments[0].context;
+
+        assert!(context.starts_with("fn process(items: &[Item]) -> usize\n...\n"), "{}", context);
+        assert!(context.contains("    let value_58 = items.len() + 58;"));
+        assert!(context.contains("// Count the items\n    let count = items.len();"));
+        assert!(!context.contains("value_57 "));
+        assert!(context.ends_with("\n..."));
+        assert!(context.len() <= 100 * CHARS_PER_TOKEN);
+    }
+
+    #[test]
+    fn test_context_shrinks_to_the_token_budget() {
+        let source = long_function();
+        let budget = ContextBudget { lines_around: 50, max_tokens: 40 };
+        let context = &detect_comments_with(&source, Language::Rust, &budget).unwrap()[0].context;
+        assert!(estimate_tokens(context) <= 40, "{} tokens", estimate_tokens(context));
+        assert!(context.contains("// Count the items"));
+
+        assert_eq!(truncate_to_tokens("héllo wörld", 2), "héllo wö");
+        assert_eq!(truncate_to_tokens("héllo", 2), "héllo");
+    }
+
+    #[test]
+    fn test_legacy_encodings_round_trip() {
+        let latin1 = b"# Caf\xe9 au lait\nprint('cr\xe8me')\n";
+        let (source, encoding) = decode_source(latin1).unwrap();
+        assert_eq!(source, "# Café au lait\nprint('crème')\n");
+        assert_eq!(encoding_rs::WINDOWS_1252, encoding);
+        assert_eq!(encode_source(&source, encoding).unwrap(), latin1);
+
+        let (source, encoding) = decode_source("# 日本語\n".as_bytes()).unwrap();
+        assert_eq!((source.as_str(), encoding), ("# 日本語\n", UTF_8));
+        assert!(encode_source("# 日本語", encoding_rs::WINDOWS_1252).is_err());
+    }
+
+    #[test]
+    fn test_comments_outside_declarations_get_the_code_after_them() {
+        let source = "# Greeting helpers\n\n# Says hello\ndef greet(name):\n    return f\"hello {name}\"\n\nRETRIES = 3  # How often to retry\nTIMEOUT = 10\n";
+        let comments = detect_comments_with(source, Language::Python, &ContextBudget::default()).unwrap();
+        let contexts: Vec<&str> = comments.iter().map(|comment| comment.context.as_str()).collect();
+        assert_eq!(contexts, vec![
+            "# Greeting helpers\n\n# Says hello\ndef greet(name):\n    return f\"hello {name}\"",
+            "# Says hello\ndef greet(name):\n    return f\"hello {name}\"",
+            "RETRIES = 3  # How often to retry\nTIMEOUT = 10",
+        ]);
+
+        let budget = ContextBudget { lines_around: 1, max_tokens: 9 };
+        let comments = detect_comments_with(source, Language::Python, &budget).unwrap();
+        assert_eq!(comments[1].context, "# Says hello\ndef greet(name):\n...");
+
+        let comments = detect_comments_with("x = 1\n# The end\n", Language::Python, &budget).unwrap();
+        assert_eq!(comments[0].context, "");
+    }
+
+    #[test]
+    fn test_crlf_sources_keep_their_line_endings() {
+        let fixtures = [
+            (Language::Rust, "fn main() {\r\n    // Set x\r\n    let x = 1; // one\r\n    /* two\r\n       lines */\r\n    x\r\n}\r\n// last"),
+            (Language::Python, "def main():\r\n    # Set x\r\n    x = 1  # one\r\n    return x\r\n# last"),
+            (Language::JavaScript, "function main() {\r\n    // Set x\r\n    let x = 1; // one\r\n    /* two\r\n       lines */\r\n    return x;\r\n}\r\n// last"),
+            (Language::TypeScript, "function main(): number {\r\n    // Set x\r\n    let x = 1; // one\r\n    /* two\r\n       lines */\r\n    return x;\r\n}\r\n// last"),
+            (Language::C, "int main(void) {\r\n    // Set x\r\n    int x = 1; // one\r\n    /* two\r\n       lines */\r\n    return x;\r\n}\r\n// last"),
+            (Language::Cpp, "int main() {\r\n    // Set x\r\n    auto x = 1; // one\r\n    /* two\r\n       lines */\r\n    return x;\r\n}\r\n// last"),
+            (Language::Sql, "CREATE TABLE t (\r\n    -- Set x\r\n    x INT -- one\r\n    /* two\r\n       lines */\r\n);\r\n-- last"),
+            (Language::Hcl, "resource \"a\" \"b\" {\r\n  # Set x\r\n  x = 1 // one\r\n  /* two\r\n     lines */\r\n}\r\n// last"),
+            (Language::Dart, "int main() {\r\n  // Set x\r\n  var x = 1; // one\r\n  /* two\r\n     lines */\r\n  return x;\r\n}\r\n// last"),
+            (Language::Elixir, "def main do\r\n  # Set x\r\n  x = 1 # one\r\n  # two\r\n  x\r\nend\r\n# last"),
+            (Language::Zig, "pub fn main() void {\r\n    // Set x\r\n    const x = 1; // one\r\n    // two\r\n    _ = x;\r\n}\r\n// last"),
+            (Language::Haskell, "main :: IO ()\r\n-- Set x\r\nmain = print x -- one\r\n  where\r\n    {- two -}\r\n    x = 1\r\n-- last"),
+            (Language::Bash, "set -e\r\n# Set x\r\nx=1 # one\r\n# two\r\necho \"$x\"\r\n# last"),
+        ];
+        for (language, source) in fixtures {
+            let comments = detect_comments(source, language).unwrap();
+            for comment in &comments {
+                assert!(!comment.text.ends_with('\r'), "{:?}: {:?}", language, comment.text);
+                assert_eq!(source.get(comment.byte_range.clone().unwrap()), Some(comment.text.as_str()));
             }
+            let lines: Vec<usize> = comments.iter().map(|comment| comment.line_number).collect();
+            let expected_last = source.lines().count();
+            assert_eq!((lines[0], lines[1], *lines.last().unwrap()), (2, 3, expected_last), "{:?}", language);
+
+            let fixed = remove_redundant_comments(source, &comments);
+            assert!(!fixed.contains("//") && !fixed.contains('#') && !fixed.contains("/*"), "{:?}: {:?}", language, fixed);
+            assert_eq!(fixed.matches('\n').count(), fixed.matches("\r\n").count(), "{:?}: {:?}", language, fixed);
+            assert!(!fixed.contains(" \r\n") && !fixed.ends_with('\n'), "{:?}: {:?}", language, fixed);
+            assert_eq!(fixed.lines().count(), source.lines().count() - comments.iter().filter(|c| c.line_number != 3).map(|c| c.text.lines().count()).sum::<usize>());
         }
     }
 
-    // Clean up any empty lines created by comment removal
-    let cleaned = updated_source
-        .lines()
-        .filter(|line| !line.trim().is_empty())
-        .collect::<Vec<&str>>()
-        .join("\n");
+    fn comment(text: &str, line_number: usize) -> CommentInfo {
+        CommentInfo { text: text.to_string(), line_number, ..Default::default() }
+    }
 
-    debug!("Final content:\n{}", cleaned);
+    #[test]
+    fn test_removal_leaves_docstrings_and_strings_alone() {
+        let source = "def f():\n    \"\"\"\n    # Add one\n    \"\"\"\n\n    # Add one\n    return x + 1  # Add one\n\nlabel = \"# Add one\"  # Add one\n";
+        let comments = [comment("# Add one", 6), comment("# Add one", 7), comment("# Add one", 9)];
+        assert_eq!(
+            remove_redundant_comments(source, &comments),
+            "def f():\n    \"\"\"\n    # Add one\n    \"\"\"\n\n    return x + 1\n\nlabel = \"# Add one\"\n"
+        );
+    }
+
+    #[test]
+    fn test_annotation_marks_comments_once() {
+        let source = "fn main() {\n    //Set x\n    let x = 1; // one\n    /* two\n       lines */\n    /// Docs\n    x\n}\n";
+        let comments = detect_comments(source, Language::Rust).unwrap();
+        let annotated = annotate_redundant_comments(source, &comments);
+        assert_eq!(
+            annotated,
+            "fn main() {\n    // [unremark: likely redundant] Set x\n    let x = 1; // [unremark: likely redundant] one\n    \
+             /* [unremark: likely redundant] two\n       lines */\n    /// Docs\n    x\n}\n"
+        );
 
-    // Ensure we end with a newline
-    cleaned + "\n"
-}
\ No newline at end of file
+        let comments = detect_comments(&annotated, Language::Rust).unwrap();
+        assert!(comments.iter().all(|comment| is_annotated(&comment.text)));
+        assert_eq!(annotate_redundant_comments(&annotated, &comments), annotated);
+        assert_eq!(remove_redundant_comments(&annotated, &comments), "fn main() {\n    let x = 1;\n    /// Docs\n    x\n}\n");
+
+        let source = "x = 1  # one\n";
+        let annotated = annotate_redundant_comments(source, &[comment("# one", 1)]);
+        assert_eq!(annotated, "x = 1  # [unremark: likely redundant] one\n");
+
+        let source = "-- add column\nALTER TABLE t ADD c INT;\n";
+        let annotated = annotate_redundant_comments(source, &[comment("-- add column", 1)]);
+        assert_eq!(annotated, "-- [unremark: likely redundant] add column\nALTER TABLE t ADD c INT;\n");
+    }
+
+    #[test]
+    fn test_removal_of_block_comments() {
+        let source = "let a = 1; /* one */ let b = 2;\n/*\n * Two\n */\nlet c = 3;\n";
+        let comments = [comment("/* one */", 1), comment("/*\n * Two\n */", 2)];
+        assert_eq!(remove_redundant_comments(source, &comments), "let a = 1; let b = 2;\nlet c = 3;\n");
+
+        // Comments that moved since they were detected stay where they are
+        assert_eq!(remove_redundant_comments("x = 1\n# Moved\n", &[comment("# Moved", 1)]), "x = 1\n# Moved\n");
+    }
+
+    #[test]
+    fn test_identical_comments_are_told_apart_by_span() {
+        let source = "let a = 1; /* same */ let b = 2; /* same */\n// same\nlet c = 3;\n// same\n";
+        let comments = detect_comments(source, Language::Rust).unwrap();
+        assert_eq!(comments.len(), 4);
+        assert_eq!(comments[1].byte_range, Some(33..43));
+
+        let only_second = [comments[1].clone(), comments[3].clone()];
+        assert_eq!(remove_redundant_comments(source, &only_second), "let a = 1; /* same */ let b = 2;\n// same\nlet c = 3;\n");
+
+        let only_first = [comments[0].clone(), comments[2].clone()];
+        assert_eq!(remove_redundant_comments(source, &only_first), "let a = 1; let b = 2; /* same */\nlet c = 3;\n// same\n");
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        fs::write(&path, "// old\n").unwrap();
+
+        write_atomically(&path, b"// new\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "// new\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1, "no temporary file is left behind");
+
+        // A write that can't finish leaves nothing half-written
+        assert!(write_atomically(&dir.path().join("missing/main.rs"), b"// new\n").is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
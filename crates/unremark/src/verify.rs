@@ -0,0 +1,167 @@
+use crate::comment_detection::{detect_comments, is_comment_node};
+use crate::types::{CommentInfo, Language};
+use std::collections::HashMap;
+use tree_sitter::{Node, Parser, Tree};
+
+/// Checks that `fixed` is `source` with only the `edits` comments removed:
+/// it parses without errors `source` didn't have, its code tokens are the
+/// same as the original's, and none of the removed comments are left.
+/// Returns why the fix isn't safe otherwise.
+pub fn verify_fix(source: &str, fixed: &str, edits: &[CommentInfo], language: Language) -> Result<(), String> {
+    let original_tree = parse(source, language)?;
+    let fixed_tree = parse(fixed, language)?;
+
+    if fixed_tree.root_node().has_error() && !original_tree.root_node().has_error() {
+        let line = first_error(fixed_tree.root_node()).map_or(0, |node| node.start_position().row + 1);
+        return Err(format!("the fixed file has a syntax error on line {}", line));
+    }
+
+    let original_tokens = code_tokens(&original_tree, source);
+    let fixed_tokens = code_tokens(&fixed_tree, fixed);
+    if let Some(position) = original_tokens.iter().zip(&fixed_tokens).position(|(original, fixed)| original.1 != fixed.1) {
+        let (line, token) = original_tokens[position];
+        return Err(format!("the fix changed the code token `{}` on line {}", token, line));
+    }
+    if original_tokens.len() != fixed_tokens.len() {
+        let (line, token) = original_tokens.get(fixed_tokens.len()).or(fixed_tokens.last()).copied().unwrap_or((0, ""));
+        return Err(format!("the fix added or removed code near the token `{}` on line {}", token, line));
+    }
+
+    let before = comment_counts(source, language)?;
+    let after = comment_counts(fixed, language)?;
+    let mut removed: HashMap<&str, usize> = HashMap::new();
+    for edit in edits {
+        *removed.entry(edit.text.as_str()).or_default() += 1;
+    }
+    for (text, count) in removed {
+        let expected = before.get(text).copied().unwrap_or_default().saturating_sub(count);
+        if after.get(text).copied().unwrap_or_default() > expected {
+            return Err(format!("the comment '{}' is still in the fixed file", text));
+        }
+    }
+    Ok(())
+}
+
+fn parse(source: &str, language: Language) -> Result<Tree, String> {
+    let mut parser = Parser::new();
+    parser.set_language(&language.get_tree_sitter_language())
+        .map_err(|e| format!("can't load the {} grammar: {}", language, e))?;
+    crate::sfc::parse(&mut parser, source, language).ok_or_else(|| format!("the {} parser gave up", language))
+}
+
+fn first_error(node: Node) -> Option<Node> {
+    if node.is_error() || node.is_missing() {
+        return Some(node);
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.children(&mut cursor).collect();
+    children.into_iter().filter(|child| child.has_error()).find_map(first_error)
+}
+
+/// The text and line of every token of the tree outside its comments.
+fn code_tokens<'a>(tree: &Tree, source: &'a str) -> Vec<(usize, &'a str)> {
+    let mut tokens = Vec::new();
+    let mut stack = vec![tree.root_node()];
+    while let Some(node) = stack.pop() {
+        if is_comment_node(node) {
+            continue;
+        }
+        if node.child_count() == 0 {
+            let text = &source[node.byte_range()];
+            if !text.trim().is_empty() {
+                tokens.push((node.start_position().row + 1, text));
+            }
+            continue;
+        }
+        let mut cursor = node.walk();
+        let children: Vec<Node> = node.children(&mut cursor).collect();
+        stack.extend(children.into_iter().rev());
+    }
+    tokens
+}
+
+/// How many times each comment appears in the source.
+fn comment_counts(source: &str, language: Language) -> Result<HashMap<String, usize>, String> {
+    let mut counts = HashMap::new();
+    for comment in detect_comments(source, language)? {
+        *counts.entry(comment.text).or_default() += 1;
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::remove_redundant_comments;
+
+    fn comments_of(source: &str, language: Language, texts: &[&str]) -> Vec<CommentInfo> {
+        detect_comments(source, language).unwrap().into_iter()
+            .filter(|comment| texts.contains(&comment.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_removing_any_comments_is_safe() {
+        let sources = [
+            (Language::Rust, "fn main() {\n    // Constructor\n    let x = 1; /* one */\n    // Keep\n    call(x); // Call\n}\n"),
+            (Language::Python, "# Module\ndef f():\n    # Return one\n    return 1  # one\n# End\n"),
+            (Language::JavaScript, "// Add\nconst a = 1;\r\n/*\n * Block\n */\nconst b = 2; // two\r\n"),
+            (Language::TypeScript, "let x: number = 1; // one\n// Last"),
+            (Language::Sql, "-- Users\nCREATE TABLE users (id INT); -- id\n/* end */\n"),
+            (Language::Hcl, "# Bucket\nresource \"s3\" \"b\" {\n  acl = \"private\" // acl\n  /* tags */\n}\n"),
+            (Language::Dart, "// Greet\nvoid greet() {\n  print(\"hi\"); // say hi\n  /* done */\n}\n"),
+            (Language::Elixir, "# Greet\ndef greet do\n  IO.puts(\"hi\") # say hi\nend\n"),
+            (Language::Zig, "// Greet\npub fn greet() void {\n    print(\"hi\"); // say hi\n}\n"),
+            (Language::Haskell, "-- Greet\ngreet :: IO ()\ngreet = putStrLn \"hi\" -- say hi\n{- done -}\n"),
+            (Language::Vue, "<template>\n  <p>{{ n }}</p>\n</template>\n<script>\n// Count\nlet n = 1; // one\n/* end */\n</script>\n"),
+            (Language::Bash, "#!/bin/sh\n# Greet\necho hi # say hi\n"),
+        ];
+        // Every subset of each file's comments, as they could be found redundant
+        for (language, source) in sources {
+            let comments = detect_comments(source, language).unwrap();
+            for subset in 0..1u32 << comments.len() {
+                let edits: Vec<CommentInfo> = comments.iter().enumerate()
+                    .filter(|(i, _)| subset & (1 << i) != 0)
+                    .map(|(_, comment)| comment.clone())
+                    .collect();
+                let fixed = remove_redundant_comments(source, &edits);
+                assert_eq!(verify_fix(source, &fixed, &edits, language), Ok(()), "{:?}", fixed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_code_changes_are_caught() {
+        let source = "fn main() {\n    // Constructor\n    let x = 1;\n}\n";
+        let edits = comments_of(source, Language::Rust, &["// Constructor"]);
+
+        let broken = "fn main() {\n    let x = 1;\n";
+        assert!(verify_fix(source, broken, &edits, Language::Rust).unwrap_err().contains("syntax error"));
+
+        let changed = "fn main() {\n    let y = 1;\n}\n";
+        assert!(verify_fix(source, changed, &edits, Language::Rust).unwrap_err().contains("`x` on line 3"));
+
+        let truncated = "fn main() {\n    let x;\n}\n";
+        assert!(verify_fix(source, truncated, &edits, Language::Rust).is_err());
+
+        let left = "fn main() {\n    // Constructor\n    let x = 1;\n}\n";
+        assert!(verify_fix(source, left, &edits, Language::Rust).unwrap_err().contains("still in the fixed file"));
+    }
+
+    #[test]
+    fn test_other_copies_of_a_removed_comment_may_stay() {
+        let source = "# same\nx = 1\n# same\ny = 2\n";
+        let edits = vec![detect_comments(source, Language::Python).unwrap().remove(0)];
+        let fixed = remove_redundant_comments(source, &edits);
+        assert_eq!(fixed, "x = 1\n# same\ny = 2\n");
+        assert_eq!(verify_fix(source, &fixed, &edits, Language::Python), Ok(()));
+    }
+
+    #[test]
+    fn test_existing_syntax_errors_dont_fail_the_fix() {
+        let source = "def f(:\n    # Return one\n    return 1\n";
+        let edits = comments_of(source, Language::Python, &["# Return one"]);
+        let fixed = remove_redundant_comments(source, &edits);
+        assert_eq!(verify_fix(source, &fixed, &edits, Language::Python), Ok(()));
+    }
+}
@@ -0,0 +1,1604 @@
+use clap::{Parser, Subcommand};
+use colored::*;
+use futures::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use parking_lot::{Mutex, RwLock};
+use serde::Serialize;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use ignore::{WalkBuilder, WalkState};
+
+mod output;
+use output::{
+    fails, print_csv, print_json, print_jsonl_end, print_problems, print_sarif, print_schema, print_summary, print_tap, print_text, print_text_end,
+    print_timings, print_usage, sort_results, init_logging, ColorChoice, FileStream, ReportTemplate, Format, GroupBy, LogFormat, SchemaFormat, SortBy,
+};
+
+use unremark::{
+    aggregate_by_directory, aggregate_by_language, analyze_file_with_options, analyze_source, annotate_redundant_comments, compute_file_metrics, check_connection, default_daemon_socket, detect_comments, estimate_file, explain_comment,
+    fetch_pull_request_diff, find_comment_blocks, find_duplicate_comments_in_files, find_files_missing_license, load_classifier, load_languages,
+    load_plugins, load_history, post_review, append_history, history_path, is_todo_comment, remove_redundant_comments, repository_root, resolve_api_key, review_comments, serve_daemon, store_keychain_key, total_metrics, AnalysisOptions,
+    AnalysisResult, ApiError, ApiSettings, Cache, FixtureMode, Fixtures, CommentBlock, ChangedLines, DaemonClient, DaemonRequest, CommentMetrics, CommentPolicy, Config, DuplicateComment, HistoryEntry, Language,
+    LocalClassifier, MergeBase, Phase, Provider, ReviewComment, Severity, Timings, TodoComment, TrustedWorkspaces, UsageEstimate, Verdict, DEFAULT_GITHUB_API_URL, DUPLICATE_COMMENT_RULE,
+    verify_fix,
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "unremark", version, about = "Find and remove redundant comments in code")]
+#[command(args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// File or directory to analyze, or - to read the source from stdin (needs --language)
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Parse the file, or the source read from stdin, as this language (by name or
+    /// extension, e.g. python or py) whatever its name, e.g. for a Jenkinsfile
+    #[arg(long, value_name = "LANGUAGE")]
+    language: Option<String>,
+
+    /// Remove redundant comments from the analyzed files. Source read from stdin is
+    /// printed fixed instead of its findings
+    #[arg(long)]
+    fix: bool,
+
+    /// Mark redundant comments with "[unremark: likely redundant]" instead of removing
+    /// them, for review in the code. The next --fix removes the comments still marked
+    #[arg(long, conflicts_with = "fix")]
+    annotate: bool,
+
+    /// Check every fix before writing it: the file must still parse, only comments may
+    /// be removed, and none of the removed comments may be left. Files that fail are
+    /// left unchanged and reported
+    #[arg(long, requires = "fix")]
+    verify: bool,
+
+    /// Also analyze the comments in the fenced code blocks of Markdown (.md) files,
+    /// for the blocks whose info string names a supported language
+    #[arg(long)]
+    markdown: bool,
+
+    /// Score the usefulness (0-100) of every analyzed comment, not just the redundant
+    /// ones, listed under scored_comments in the JSON output
+    #[arg(long)]
+    report_all: bool,
+
+    /// Print the results as JSON, short for --format json
+    #[arg(long, conflicts_with = "format")]
+    json: bool,
+
+    /// Count the requests the analysis would send and their cost, without calling
+    /// the API, like the estimate command
+    #[arg(long, conflicts_with_all = ["fix", "offline"])]
+    estimate: bool,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// MiniJinja template to render the results with, for --format template
+    #[arg(long, value_name = "PATH")]
+    template: Option<PathBuf>,
+
+    /// When to color the output
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, global = true)]
+    color: ColorChoice,
+
+    /// Show what unremark is doing: -v for progress notes, -vv for debug details,
+    /// -vvv to include dependencies. RUST_LOG takes precedence when set
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// How to write the logs: text, or json for log pipelines
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    /// Append the logs to this file instead of printing them
+    #[arg(long, value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Exit with 1 when there's a finding at least this severe: error, warning, info or hint
+    #[arg(long, default_value = "warning", value_name = "SEVERITY")]
+    fail_on: Severity,
+
+    /// Only print the summary counts, not the individual findings
+    #[arg(long, conflicts_with_all = ["json", "format"])]
+    summary_only: bool,
+
+    /// Gather findings by rule, file or language, in the text and JSON output.
+    /// With --summary-only, print the number of findings in each group
+    #[arg(long, value_enum)]
+    group_by: Option<GroupBy>,
+
+    /// Order files and findings by path, line, confidence (most confident first)
+    /// or count (files with the most findings first)
+    #[arg(long, value_enum)]
+    sort: Option<SortBy>,
+
+    /// Only run the local heuristic rules, without calling the API
+    #[arg(long)]
+    offline: bool,
+
+    /// Who answers the API checks: openai, openrouter, or proxy for an unremark proxy server
+    /// (which holds the API key itself)
+    #[arg(long, default_value = "openai")]
+    provider: Provider,
+
+    /// URL of the unremark proxy server, overriding PROXY_ENDPOINT and the [proxy]
+    /// section of unremark.toml. Implies --provider proxy
+    #[arg(long, value_name = "URL")]
+    proxy_endpoint: Option<String>,
+
+    /// Base URL of an OpenAI-compatible API (vLLM, LM Studio, LiteLLM...) to use
+    /// instead of OpenAI's, overriding OPENAI_BASE_URL and the [openai] section of unremark.toml
+    #[arg(long, value_name = "URL", conflicts_with = "proxy_endpoint")]
+    base_url: Option<String>,
+
+    /// Model the API is asked, overriding the [openai] (or [openrouter]) section of
+    /// unremark.toml. The default is a fine-tune only its author's OpenAI account can use
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Fraction (0.0-1.0) of a comment's words that must come from the name of the
+    /// declaration below it for the name-echo rule to flag it
+    #[arg(long, default_value_t = unremark::DEFAULT_NAME_ECHO_THRESHOLD)]
+    name_echo_threshold: f32,
+
+    /// How many copies of the same comment it takes to report them as duplicates
+    #[arg(long, default_value_t = unremark::DEFAULT_MIN_DUPLICATES)]
+    min_duplicates: usize,
+
+    /// Send every comment to the API, including ones with links, issue references
+    /// or long explanations that are almost never redundant
+    #[arg(long)]
+    no_prefilter: bool,
+
+    /// Also ask whether each comment still describes its code, reporting the ones
+    /// that contradict it as misleading-comment (never removed by --fix)
+    #[arg(long, conflicts_with = "offline")]
+    check_accuracy: bool,
+
+    /// Only report (and fix) comments on lines added or modified since this git
+    /// revision, e.g. origin/main
+    #[arg(long, value_name = "REV")]
+    changed_since: Option<String>,
+
+    /// Show the commit, author and age of each reported comment (from git blame)
+    #[arg(long)]
+    blame: bool,
+
+    /// Only report (and fix) findings that weren't already present where the current
+    /// branch forked from this git revision, e.g. origin/main
+    #[arg(long, value_name = "REV")]
+    new_since: Option<String>,
+
+    /// Analyze in this process even when an `unremark daemon` is running
+    #[arg(long)]
+    no_daemon: bool,
+
+    /// Most files analyzed at once, each with its source and parse tree in memory
+    #[arg(short, long, value_name = "N", default_value_t = unremark::DEFAULT_FILE_CONCURRENCY)]
+    jobs: usize,
+
+    /// Skip files larger than this many bytes, with a warning (0 for no limit)
+    #[arg(long, value_name = "BYTES", default_value_t = unremark::DEFAULT_MAX_FILE_SIZE)]
+    max_file_size: u64,
+
+    /// Rough cap, in tokens, on the code sent to the API along with each comment,
+    /// by default max_tokens in the config's [context] section or 512
+    #[arg(long, value_name = "TOKENS")]
+    max_context_tokens: Option<usize>,
+
+    /// Ask the API about up to this many comments of a file in one request, for
+    /// fewer requests and tokens on large files
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    batch_size: u16,
+
+    /// Report the time spent walking, parsing, detecting, in the local checks,
+    /// in API calls and writing fixes, after the summary
+    #[arg(long)]
+    timings: bool,
+
+    /// Dollars per million input tokens, for the run's cost with a model other than the default
+    #[arg(long, default_value_t = unremark::OPENAI_INPUT_PRICE, value_name = "DOLLARS")]
+    input_price: f64,
+
+    /// Dollars per million output tokens, for the run's cost with a model other than the default
+    #[arg(long, default_value_t = unremark::OPENAI_OUTPUT_PRICE, value_name = "DOLLARS")]
+    output_price: f64,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List TODO/FIXME/HACK comments with their git author and age
+    Todos(TodosArgs),
+    /// Report files missing the license header required by unremark.toml
+    CheckLicense(CheckLicenseArgs),
+    /// Show comment density, redundant-comment ratio and doc coverage per file and directory
+    Stats(StatsArgs),
+    /// Summarize the redundant comments, recording each run with --history to show the trend
+    Report(ReportArgs),
+    /// Analyze the comment at <file>:<line> and explain the verdict
+    Explain(ExplainArgs),
+    /// Check the config, API connection and cache, suggesting a fix for each problem
+    Doctor(DoctorArgs),
+    /// Save a provider's API key (read from stdin) in the OS keychain (needs the `keychain` feature)
+    Login(LoginArgs),
+    /// Let a workspace's unremark.toml run commands, load grammars and choose where requests go
+    Trust(TrustArgs),
+    /// Estimate the API calls, tokens and cost of analyzing files, without calling the API
+    Estimate(EstimateArgs),
+    /// Post findings on a pull request's changed lines as inline GitHub review comments
+    Review(ReviewArgs),
+    /// Print the JSON Schema of the JSON or JSONL output
+    Schema(SchemaArgs),
+    /// Serve comment detection and analysis over gRPC (needs the `grpc` feature)
+    Grpc(GrpcArgs),
+    /// Serve detection and fixes as tools for AI coding agents over MCP on stdio (needs the `mcp` feature)
+    Mcp(McpArgs),
+    /// Keep parsers, caches and HTTP connections warm for later runs, listening on a unix socket or, on Windows, a named pipe
+    Daemon(DaemonArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct TodosArgs {
+    /// File or directory to search
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Only list comments older than this (e.g. 180d, 6w, 3m, 1y)
+    #[arg(long, value_parser = unremark::parse_age)]
+    older_than: Option<Duration>,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct CheckLicenseArgs {
+    /// File or directory to check
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct StatsArgs {
+    /// File or directory to measure
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Count redundant comments with the local rules only, without calling the API
+    #[arg(long)]
+    offline: bool,
+
+    /// How many of the largest comment blocks to list
+    #[arg(long, default_value_t = 10)]
+    largest: usize,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReportArgs {
+    /// File or directory to report on
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Append this run's totals to .unremark/history.jsonl and show how they changed over time
+    #[arg(long)]
+    history: bool,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Count redundant comments with the local rules only, without calling the API
+    #[arg(long)]
+    offline: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExplainArgs {
+    /// The comment's file and line, as <file>:<line>
+    #[arg(value_parser = parse_location)]
+    location: (PathBuf, usize),
+
+    /// Print the result as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Only run the local rules, without calling the API
+    #[arg(long)]
+    offline: bool,
+
+    /// Also ask the API whether the comment still matches its code
+    #[arg(long)]
+    check_accuracy: bool,
+}
+
+fn parse_location(location: &str) -> Result<(PathBuf, usize), String> {
+    let (path, line) = location.rsplit_once(':').ok_or("expected <file>:<line>")?;
+    match line.parse() {
+        Ok(line) if line > 0 => Ok((PathBuf::from(path), line)),
+        _ => Err(format!("invalid line number '{}'", line)),
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct DoctorArgs {
+    /// Directory whose unremark.toml to check
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Who answers the API checks: openai, openrouter, or proxy for an unremark proxy server
+    #[arg(long, default_value = "openai")]
+    provider: Provider,
+
+    /// URL of the unremark proxy server to check. Implies --provider proxy
+    #[arg(long, value_name = "URL")]
+    proxy_endpoint: Option<String>,
+
+    /// Base URL of the OpenAI-compatible API to check
+    #[arg(long, value_name = "URL", conflicts_with = "proxy_endpoint")]
+    base_url: Option<String>,
+
+    /// Model to check the API with
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Skip the API connection check
+    #[arg(long)]
+    offline: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct EstimateArgs {
+    /// File or directory to estimate
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Print the estimate as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Include the requests of --check-accuracy
+    #[arg(long)]
+    check_accuracy: bool,
+
+    /// Count batches of up to this many comments of a file as one request, as
+    /// with the analysis's --batch-size
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    batch_size: u16,
+
+    /// Dollars per million input tokens, for a model other than the default
+    #[arg(long, default_value_t = unremark::OPENAI_INPUT_PRICE, value_name = "DOLLARS")]
+    input_price: f64,
+
+    /// Dollars per million output tokens, for a model other than the default
+    #[arg(long, default_value_t = unremark::OPENAI_OUTPUT_PRICE, value_name = "DOLLARS")]
+    output_price: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct EstimateOutput {
+    #[serde(flatten)]
+    estimate: UsageEstimate,
+    /// Dollars, at the given prices, when calling OpenAI directly
+    openai_cost: f64,
+}
+
+#[derive(clap::Args, Debug)]
+struct GrpcArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    listen: std::net::SocketAddr,
+
+    /// Directory whose unremark.toml configures the analysis
+    #[arg(long, default_value = ".")]
+    config_dir: PathBuf,
+
+    /// Only run the local heuristic rules, without calling the API
+    #[arg(long)]
+    offline: bool,
+
+    /// Also serve Prometheus metrics at http://<address>/metrics
+    #[arg(long, value_name = "ADDRESS")]
+    metrics: Option<std::net::SocketAddr>,
+}
+
+#[derive(clap::Args, Debug)]
+struct McpArgs {
+    /// Workspace the tools may read and fix, also where unremark.toml is looked up
+    #[arg(default_value = ".")]
+    root: PathBuf,
+
+    /// Only run the local heuristic rules, without calling the API
+    #[arg(long)]
+    offline: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct LoginArgs {
+    /// Provider the key is for: openai, openrouter, or proxy for the proxy's token
+    #[arg(long, default_value = "openai")]
+    provider: Provider,
+
+    /// Delete the saved key instead
+    #[arg(long)]
+    delete: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct TrustArgs {
+    /// Workspace to trust, the directory of its closest unremark.toml
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Stop trusting it instead
+    #[arg(long, conflicts_with = "list")]
+    remove: bool,
+
+    /// List the trusted workspaces
+    #[arg(long)]
+    list: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct DaemonArgs {
+    /// Socket or named pipe to listen on, defaults to $UNREMARK_SOCKET, else unremark.sock in the runtime directory or \\.\pipe\unremark-<user> on Windows
+    #[arg(long)]
+    socket: Option<PathBuf>,
+
+    /// Also serve Prometheus metrics at http://<address>/metrics
+    #[arg(long, value_name = "ADDRESS")]
+    metrics: Option<std::net::SocketAddr>,
+}
+
+#[derive(clap::Args, Debug)]
+struct SchemaArgs {
+    /// Output format to describe
+    #[arg(long, value_enum, default_value_t = SchemaFormat::Json)]
+    format: SchemaFormat,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReviewArgs {
+    /// Checkout of the pull request's head
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Number of the pull request to review
+    #[arg(long)]
+    pr: u64,
+
+    /// Repository as owner/name, defaults to $GITHUB_REPOSITORY
+    #[arg(long)]
+    repo: Option<String>,
+
+    /// Only run the local heuristic rules, without calling the API
+    #[arg(long)]
+    offline: bool,
+
+    /// Print the review comments as JSON instead of posting them
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct StatsRow<'a> {
+    #[serde(flatten)]
+    metrics: &'a CommentMetrics,
+    comment_density: f64,
+    comments_per_kloc: f64,
+    redundant_ratio: f64,
+    doc_coverage: f64,
+}
+
+impl<'a> From<&'a CommentMetrics> for StatsRow<'a> {
+    fn from(metrics: &'a CommentMetrics) -> Self {
+        Self {
+            metrics,
+            comment_density: metrics.comment_density(),
+            comments_per_kloc: metrics.comments_per_kloc(),
+            redundant_ratio: metrics.redundant_ratio(),
+            doc_coverage: metrics.doc_coverage(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatsOutput<'a> {
+    files: Vec<StatsRow<'a>>,
+    directories: Vec<StatsRow<'a>>,
+    languages: Vec<StatsRow<'a>>,
+    total: StatsRow<'a>,
+    largest_comment_blocks: &'a [CommentBlock],
+}
+
+#[derive(Debug, Serialize)]
+struct ReportOutput<'a> {
+    current: &'a HistoryEntry,
+    /// Every recorded run, oldest first, ending with the current one
+    #[serde(skip_serializing_if = "<[HistoryEntry]>::is_empty")]
+    history: &'a [HistoryEntry],
+}
+
+fn is_supported(path: &std::path::Path) -> bool {
+    Language::of_file(path).is_some()
+}
+
+fn is_markdown(path: &std::path::Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "md")
+}
+
+/// The supported source files under `root`, in path order.
+fn collect_files(root: &PathBuf) -> Vec<PathBuf> {
+    collect_files_matching(root, is_supported)
+}
+
+/// The files under `root` that `wanted` accepts, in path order. Directories
+/// are walked on several threads, skipping hidden and gitignored entries
+/// along with `target` and `node_modules`.
+fn collect_files_matching(root: &PathBuf, wanted: impl Fn(&std::path::Path) -> bool + Sync) -> Vec<PathBuf> {
+    if root.is_file() {
+        return vec![root.clone()];
+    }
+
+    let files = Mutex::new(Vec::new());
+    WalkBuilder::new(root)
+        .filter_entry(|entry| {
+            let name = entry.file_name();
+            entry.depth() == 0 || !(name == "target" || name == "node_modules")
+        })
+        .build_parallel()
+        .run(|| {
+            let (files, wanted) = (&files, &wanted);
+            Box::new(move |entry| {
+                if let Ok(entry) = entry {
+                    if entry.file_type().is_some_and(|file_type| file_type.is_file()) && wanted(entry.path()) {
+                        files.lock().push(entry.into_path());
+                    }
+                }
+                WalkState::Continue
+            })
+        });
+
+    let mut files = files.into_inner();
+    files.sort();
+    files
+}
+
+fn run_todos(args: TodosArgs) {
+    let mut todos: Vec<TodoComment> = collect_files(&args.path)
+        .iter()
+        .flat_map(|path| unremark::find_todos_in_file(path))
+        .collect();
+
+    if let Some(older_than) = args.older_than {
+        let min_days = older_than.as_secs() / (24 * 60 * 60);
+        todos.retain(|todo| todo.age_days.is_some_and(|days| days >= min_days));
+    }
+    todos.sort_by_key(|todo| std::cmp::Reverse(todo.age_days));
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&todos).unwrap());
+        return;
+    }
+
+    for todo in &todos {
+        let age = todo.age_days.map(|days| format!("{}d", days)).unwrap_or_else(|| "?".to_string());
+        println!(
+            "{}:{} {} {} {}",
+            todo.path.display(),
+            todo.line_number,
+            format!("{:>5}", age).cyan(),
+            todo.author.as_deref().unwrap_or("unknown").dimmed(),
+            todo.text.yellow()
+        );
+    }
+    println!("\n{} {} TODO comments", "Summary:".bold(), todos.len());
+}
+
+/// Loads the closest config, and the grammars it lists so their files are found.
+fn load_config(path: &std::path::Path) -> Config {
+    Config::load(path)
+        .and_then(|config| load_languages(&config).map(|_| config))
+        .unwrap_or_else(|e| {
+            eprintln!("{} {}", "error:".red().bold(), e);
+            std::process::exit(2);
+        })
+}
+
+fn load_policies(config: &Config) -> Vec<Arc<dyn CommentPolicy>> {
+    load_plugins(&config.plugins).unwrap_or_else(|e| {
+        eprintln!("{} {}", "error:".red().bold(), e);
+        std::process::exit(2);
+    })
+}
+
+fn load_local_classifier(config: &Config) -> Option<LocalClassifier> {
+    load_classifier(config.classifier.as_ref()).unwrap_or_else(|e| {
+        eprintln!("{} {}", "error:".red().bold(), e);
+        std::process::exit(2);
+    })
+}
+
+fn require_api_key(offline: bool, config: &Config) {
+    require_provider_key(offline, Provider::OpenAi, config);
+}
+
+fn require_provider_key(offline: bool, provider: Provider, config: &Config) {
+    // Replayed responses stand in for the API, key and all
+    let replaying = Fixtures::from_env().is_some_and(|fixtures| fixtures.mode() == FixtureMode::Replay);
+    if !offline && !replaying && resolve_api_key(provider, None, &config.credentials).is_none() {
+        let variable = match provider {
+            Provider::OpenRouter => unremark::OPENROUTER_API_KEY_ENV,
+            _ => unremark::OPENAI_API_KEY_ENV,
+        };
+        eprintln!(
+            "{} {} is not set or in the [credentials] sources, use --offline to run only the local rules",
+            "error:".red().bold(),
+            variable
+        );
+        std::process::exit(2);
+    }
+}
+
+/// Options shared by every command that analyzes files.
+fn base_options(config: Config, offline: bool) -> AnalysisOptions {
+    AnalysisOptions {
+        offline,
+        policies: load_policies(&config),
+        classifier: load_local_classifier(&config),
+        api: config.api_settings(),
+        context_budget: config.context,
+        rules: config.rules,
+        ..Default::default()
+    }
+}
+
+fn run_check_license(args: CheckLicenseArgs) {
+    let config = load_config(&args.path);
+
+    let missing = find_files_missing_license(&collect_files(&args.path), &config.license);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&missing).unwrap());
+    } else {
+        let expected = config.license.required_header.as_deref().unwrap_or("a license header");
+        for path in &missing {
+            println!("{} is missing {}", path.display().to_string().bold(), expected.yellow());
+        }
+        println!("\n{} {} files missing a license header", "Summary:".bold(), missing.len());
+    }
+
+    std::process::exit(if missing.is_empty() { 0 } else { 1 });
+}
+
+fn print_stats_table(title: &str, rows: &[CommentMetrics]) {
+    println!("\n{}", title.bold());
+    println!(
+        "  {:<50} {:>6} {:>9} {:>9} {:>6} {:>10} {:>13}",
+        "path", "files", "density", "per KLOC", "todos", "redundant", "doc coverage"
+    );
+    for row in rows {
+        let label = row.language.map_or_else(|| row.path.display().to_string(), |language| language.to_string());
+        println!(
+            "  {:<50} {:>6} {:>8.1}% {:>9.1} {:>6} {:>9.1}% {:>12.1}%",
+            label,
+            row.files,
+            row.comment_density() * 100.0,
+            row.comments_per_kloc(),
+            row.todos,
+            row.redundant_ratio() * 100.0,
+            row.doc_coverage() * 100.0
+        );
+    }
+}
+
+/// The file's comment metrics, with redundant comments counted by analyzing
+/// it, and its comment blocks; none for unsupported or unreadable files.
+async fn measure_file(
+    path: &PathBuf,
+    cache: &RwLock<Cache>,
+    options: &AnalysisOptions,
+) -> Option<(CommentMetrics, Vec<CommentBlock>)> {
+    let source_code = unremark::read_source(path).ok()?;
+    let language = Language::detect(path, &source_code)?;
+    let result = analyze_file_with_options(path, false, cache, options).await;
+
+    let comments = detect_comments(&source_code, language).unwrap_or_default();
+    let mut metrics = compute_file_metrics(&source_code, language, path);
+    metrics.comments = comments.len();
+    metrics.todos = comments.iter().filter(|comment| is_todo_comment(&comment.text)).count();
+    metrics.redundant_comments = result.redundant_comments.len();
+    Some((metrics, find_comment_blocks(&source_code, language, path)))
+}
+
+async fn run_report(args: ReportArgs) {
+    let config = load_config(&args.path);
+    require_api_key(args.offline, &config);
+    let files = collect_files(&args.path);
+    let cache = RwLock::new(Cache::load());
+    let options = base_options(config, args.offline);
+
+    let futures: Vec<_> = files.iter()
+        .map(|path| measure_file(path, &cache, &options))
+        .collect();
+    let analyzed = analyze_bounded(futures, options.file_concurrency).await;
+    cache.read().save();
+    if analyzed.interrupted {
+        exit_interrupted("the cache is saved but this run isn't recorded");
+    }
+    let file_metrics: Vec<CommentMetrics> = analyzed.results
+        .into_iter()
+        .flatten()
+        .map(|(metrics, _)| metrics)
+        .collect();
+    let current = HistoryEntry::from_metrics(&total_metrics(&file_metrics));
+
+    let history = if args.history {
+        let root = if args.path.is_dir() { args.path.as_path() } else { args.path.parent().unwrap_or(Path::new(".")) };
+        let path = history_path(root);
+        append_history(&path, &current).unwrap_or_else(|e| exit_with_error(e));
+        load_history(&path).unwrap_or_else(|e| exit_with_error(e))
+    } else {
+        vec![]
+    };
+
+    if args.json {
+        let output = ReportOutput { current: &current, history: &history };
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return;
+    }
+
+    if history.len() > 1 {
+        print_trend(&history);
+    }
+    println!(
+        "\n{} {} redundant of {} comments in {} files, {} TODOs",
+        "Summary:".bold(),
+        current.redundant_comments,
+        current.comments,
+        current.files,
+        current.todos
+    );
+}
+
+/// Redundant comments per recorded run, with the change from the run before
+/// and a bar scaled to the largest count.
+fn print_trend(history: &[HistoryEntry]) {
+    const BAR_WIDTH: usize = 40;
+    let most = history.iter().map(|entry| entry.redundant_comments).max().unwrap_or(0).max(1);
+
+    println!("{}", "Redundant comments over time".bold());
+    let mut previous: Option<usize> = None;
+    for entry in history {
+        let change = match previous {
+            Some(previous) if entry.redundant_comments < previous => format!("-{}", previous - entry.redundant_comments).green(),
+            Some(previous) if entry.redundant_comments > previous => format!("+{}", entry.redundant_comments - previous).red(),
+            Some(_) => "=".normal(),
+            None => "".normal(),
+        };
+        let bar = "█".repeat(entry.redundant_comments * BAR_WIDTH / most);
+        println!("  {} {:>6} {:>6}  {}", entry.date(), entry.redundant_comments, change, bar);
+        previous = Some(entry.redundant_comments);
+    }
+    let (first, last) = (&history[0], &history[history.len() - 1]);
+    let change = last.redundant_comments as i64 - first.redundant_comments as i64;
+    println!("  {:+} since {} over {} runs", change, first.date(), history.len());
+}
+
+async fn run_stats(args: StatsArgs) {
+    let config = load_config(&args.path);
+    require_api_key(args.offline, &config);
+    let files = collect_files(&args.path);
+    let cache = Arc::new(RwLock::new(Cache::load()));
+    let options = base_options(config, args.offline);
+
+    let futures: Vec<_> = files.iter()
+        .map(|path| measure_file(path, &cache, &options))
+        .collect();
+    let analyzed = analyze_bounded(futures, options.file_concurrency).await;
+    cache.read().save();
+    if analyzed.interrupted {
+        exit_interrupted("the cache is saved");
+    }
+    let (file_metrics, blocks): (Vec<CommentMetrics>, Vec<Vec<CommentBlock>>) =
+        analyzed.results.into_iter().flatten().unzip();
+
+    let directory_metrics = aggregate_by_directory(&file_metrics);
+    let language_metrics = aggregate_by_language(&file_metrics);
+    let total = total_metrics(&file_metrics);
+    let mut largest_blocks: Vec<CommentBlock> = blocks.into_iter().flatten().collect();
+    largest_blocks.sort_by(|a, b| b.lines.cmp(&a.lines).then_with(|| a.path.cmp(&b.path)).then(a.line.cmp(&b.line)));
+    largest_blocks.truncate(args.largest);
+
+    if args.json {
+        let output = StatsOutput {
+            files: file_metrics.iter().map(StatsRow::from).collect(),
+            directories: directory_metrics.iter().map(StatsRow::from).collect(),
+            languages: language_metrics.iter().map(StatsRow::from).collect(),
+            total: StatsRow::from(&total),
+            largest_comment_blocks: &largest_blocks,
+        };
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return;
+    }
+
+    print_stats_table("Files", &file_metrics);
+    print_stats_table("Directories", &directory_metrics);
+    print_stats_table("Languages", &language_metrics);
+    if !largest_blocks.is_empty() {
+        println!("\n{}", "Largest comment blocks".bold());
+        for block in &largest_blocks {
+            println!("  {}:{} {} lines", block.path.display(), block.line, block.lines);
+        }
+    }
+    println!(
+        "\n{} {} files, {:.1}% comment density, {:.1} comments per KLOC, {} TODOs, {:.1}% of comments redundant, {:.1}% of public functions documented",
+        "Summary:".bold(),
+        total.files,
+        total.comment_density() * 100.0,
+        total.comments_per_kloc(),
+        total.todos,
+        total.redundant_ratio() * 100.0,
+        total.doc_coverage() * 100.0
+    );
+}
+
+async fn run_explain(args: ExplainArgs) {
+    let (path, line) = args.location;
+    let config = load_config(&path);
+    require_api_key(args.offline, &config);
+    let source_code = unremark::read_source(&path)
+        .unwrap_or_else(|e| exit_with_error(format!("failed to read {}: {}", path.display(), e)));
+    let Some(language) = Language::detect(&path, &source_code) else {
+        exit_with_error(format!("{} is not a supported file type", path.display()));
+    };
+    let options = AnalysisOptions { check_accuracy: args.check_accuracy, ..base_options(config, args.offline) };
+
+    let explained = explain_comment(&source_code, language, line, &options).await
+        .unwrap_or_else(|e| exit_with_error(format!("{}:{}: {}", path.display(), line, e)));
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&explained).unwrap());
+        return;
+    }
+
+    let comment = &explained.comment;
+    let verdict = match explained.verdict {
+        Verdict::Redundant => "redundant".red().bold(),
+        Verdict::Flag => "flagged".yellow().bold(),
+        Verdict::Keep => "keep".green().bold(),
+    };
+    let mut details: Vec<String> = comment.rule_id.iter().cloned().collect();
+    if let Some(confidence) = comment.confidence {
+        details.push(format!("{:.0}% confidence", confidence * 100.0));
+    }
+    let details = if details.is_empty() { String::new() } else { format!(" ({})", details.join(", ")) };
+    println!("{}:{} {}{}", path.display(), comment.line_number, verdict, details);
+    for text_line in comment.text.lines() {
+        println!("  {}", text_line.trim().dimmed());
+    }
+    if let Some(explanation) = &comment.explanation {
+        println!("  {}", explanation);
+    }
+}
+
+/// Prints the outcome of one `doctor` check, with how to fix it when it failed.
+fn report_check(name: &str, outcome: Result<String, (String, String)>) -> bool {
+    match outcome {
+        Ok(detail) => {
+            println!("{} {:<8} {}", format!("{:<4}", "ok").green().bold(), name, detail);
+            true
+        }
+        Err((problem, fix)) => {
+            println!("{} {:<8} {}", "fail".red().bold(), name, problem);
+            println!("{:14}{} {}", "", "fix:".yellow(), fix);
+            false
+        }
+    }
+}
+
+fn check_config(path: &std::path::Path) -> Result<(Config, String), (String, String)> {
+    let Some(config_path) = Config::find(path) else {
+        return Ok((Config::default(), "no unremark.toml found, using the defaults".to_string()));
+    };
+    let config = Config::load_from_path(&config_path)
+        .map_err(|e| (e, format!("correct {} (see the README for every setting)", config_path.display())))?;
+    load_plugins(&config.plugins)
+        .map_err(|e| (e, format!("check the [[plugins]] paths in {}", config_path.display())))?;
+    load_languages(&config)
+        .map_err(|e| (e, format!("check the [[grammars]] and [extensions] in {}", config_path.display())))?;
+    Ok((config, config_path.display().to_string()))
+}
+
+async fn check_api(api: &ApiSettings, offline: bool) -> Result<String, (String, String)> {
+    if offline {
+        return Ok("skipped (--offline)".to_string());
+    }
+    api.validate().map_err(|e| (e, "change the [generation] section of unremark.toml".to_string()))?;
+    let target = match api.provider {
+        Provider::Proxy => format!("proxy at {}", api.endpoint.as_deref().unwrap_or_default()),
+        Provider::OpenAi => {
+            if api.resolved_api_key().is_none() {
+                return Err((
+                    "OPENAI_API_KEY is not set".to_string(),
+                    "export OPENAI_API_KEY, set up [credentials] in unremark.toml, use --provider proxy, or run with --offline".to_string(),
+                ));
+            }
+            match &api.endpoint {
+                Some(endpoint) => format!("OpenAI-compatible API at {}, model {}", endpoint, api.model),
+                None => format!("OpenAI API, model {}", api.model),
+            }
+        }
+        Provider::OpenRouter => {
+            if api.resolved_api_key().is_none() {
+                return Err((
+                    "OPENROUTER_API_KEY is not set".to_string(),
+                    "export OPENROUTER_API_KEY, set up a [credentials] helper or keychain entry, or run with --offline".to_string(),
+                ));
+            }
+            format!("OpenRouter API, model {}", api.model)
+        }
+    };
+    match check_connection(api).await {
+        Ok(()) => Ok(format!("reached the {}", target)),
+        Err(e) => {
+            let fix = match (&e, api.provider) {
+                (ApiError::Other(message), Provider::OpenAi) if message.contains("401") => {
+                    "OPENAI_API_KEY was rejected, check that it's valid and not revoked".to_string()
+                }
+                (ApiError::Other(message), Provider::OpenRouter) if message.contains("401") => {
+                    "OPENROUTER_API_KEY was rejected, check that it's valid and not revoked".to_string()
+                }
+                (ApiError::RateLimit(_), _) => "wait a moment, or check the account's usage limits".to_string(),
+                (_, Provider::Proxy) => {
+                    "check --proxy-endpoint, PROXY_ENDPOINT or the [proxy] section of unremark.toml".to_string()
+                }
+                _ => "check the network, and the [network] section or UNREMARK_* variables for proxies and certificates".to_string(),
+            };
+            Err((format!("couldn't reach the {}: {}", target, e), fix))
+        }
+    }
+}
+
+fn check_cache() -> Result<String, (String, String)> {
+    let cache_path = unremark::get_cache_path();
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&cache_path)
+        .map(|_| cache_path.display().to_string())
+        .map_err(|e| (
+            format!("can't write {}: {}", cache_path.display(), e),
+            format!("make {} writable, or point XDG_CACHE_HOME elsewhere", cache_path.parent().unwrap_or(&cache_path).display()),
+        ))
+}
+
+/// Saves the key piped in (or pasted) to the keychain, for `keychain = true`
+/// in `[credentials]` to find, so it needn't live in a `.env` file.
+fn run_login(args: LoginArgs) {
+    if args.delete {
+        store_keychain_key(args.provider, None).unwrap_or_else(|e| exit_with_error(format!("failed to delete the key: {}", e)));
+        println!("Deleted the {} key from the keychain", args.provider);
+        return;
+    }
+    if std::io::stdin().is_terminal() {
+        eprintln!("Paste the {} key and press Enter:", args.provider);
+    }
+    let mut key = String::new();
+    std::io::stdin().read_line(&mut key).unwrap_or_else(|e| exit_with_error(format!("failed to read the key: {}", e)));
+    let key = key.trim();
+    if key.is_empty() {
+        exit_with_error("no key given");
+    }
+    store_keychain_key(args.provider, Some(key)).unwrap_or_else(|e| exit_with_error(format!("failed to save the key: {}", e)));
+    println!(
+        "Saved the {} key in the keychain, set keychain = true under [credentials] to use it",
+        args.provider
+    );
+}
+
+fn run_trust(args: TrustArgs) {
+    let mut trusted = TrustedWorkspaces::load();
+    if args.list {
+        for directory in trusted.directories() {
+            println!("{}", directory.display());
+        }
+        return;
+    }
+    let workspace = match Config::find(&args.path) {
+        Some(config_path) => config_path.parent().map(std::path::Path::to_path_buf).unwrap_or_default(),
+        None => args.path,
+    };
+    trusted.set_trusted(&workspace, !args.remove)
+        .unwrap_or_else(|e| exit_with_error(format!("failed to update the trusted workspaces: {}", e)));
+    match args.remove {
+        true => println!("No longer trusting {}", workspace.display()),
+        false => println!("Trusting {}: its unremark.toml may now use every setting", workspace.display()),
+    }
+}
+
+async fn run_doctor(args: DoctorArgs) {
+    let mut healthy = true;
+    let config = match check_config(&args.path) {
+        Ok((config, detail)) => {
+            healthy &= report_check("config", Ok(detail));
+            config
+        }
+        Err(failure) => {
+            healthy &= report_check("config", Err(failure));
+            Config::default()
+        }
+    };
+
+    let provider = if args.proxy_endpoint.is_some() { Provider::Proxy } else { args.provider };
+    let endpoint = match provider {
+        Provider::Proxy => Some(config.proxy.resolve_endpoint(args.proxy_endpoint.as_deref())),
+        Provider::OpenAi => config.openai.resolve_base_url(args.base_url.as_deref()),
+        Provider::OpenRouter => None,
+    };
+    let api = config.api_settings_for(provider);
+    let api = ApiSettings { endpoint, model: args.model.clone().unwrap_or(api.model), ..api };
+    healthy &= report_check("api", check_api(&api, args.offline).await);
+    healthy &= report_check("cache", check_cache());
+
+    let languages: Vec<String> = Language::ALL.iter()
+        .map(|language| format!("{} (.{})", language, language.extension()))
+        .collect();
+    let providers: Vec<String> = Provider::ALL.iter().map(Provider::to_string).collect();
+    println!("\n{} {}", "Languages:".bold(), languages.join(", "));
+    println!("{} {}", "Providers:".bold(), providers.join(", "));
+
+    std::process::exit(if healthy { 0 } else { 1 });
+}
+
+async fn run_estimate(args: EstimateArgs) {
+    let config = load_config(&args.path);
+    let mut options = AnalysisOptions { check_accuracy: args.check_accuracy, ..base_options(config, false) };
+    options.api.batch_size = args.batch_size.into();
+    let options = Arc::new(options);
+    let files = collect_files(&args.path);
+
+    // Only the local checks run, all CPU-bound
+    let futures: Vec<_> = files.into_iter()
+        .map(|path| {
+            let options = Arc::clone(&options);
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    estimate_file(&path, &options).map_err(|e| eprintln!("{} {}: {}", "warning:".yellow().bold(), path.display(), e)).ok()
+                }).await.ok().flatten()
+            }
+        })
+        .collect();
+    let analyzed = analyze_bounded(futures, options.file_concurrency).await;
+    if analyzed.interrupted {
+        exit_interrupted("nothing was estimated");
+    }
+    let mut estimate = UsageEstimate::default();
+    for file in analyzed.results.into_iter().flatten() {
+        estimate.add(&file);
+    }
+    let openai_cost = estimate.cost(args.input_price, args.output_price);
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&EstimateOutput { estimate, openai_cost }).unwrap());
+        return;
+    }
+
+    println!("{} files, {} comments", estimate.files, estimate.comments);
+    println!("\n  {:<8} {:>9} {:>14} {:>14} {:>10}", "provider", "requests", "input tokens", "output tokens", "cost");
+    println!(
+        "  {:<8} {:>9} {:>14} {:>14} {:>10}",
+        "openai", estimate.api_calls, estimate.input_tokens, estimate.output_tokens, format!("${:.4}", openai_cost)
+    );
+    println!("  {:<8} {:>9} {:>14} {:>14} {:>10}", "proxy", estimate.proxy_requests, "-", "-", "-");
+    println!(
+        "\nTokens are estimated at about four characters each. Cached files cost nothing, and the proxy's \
+         operator pays for its model."
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+    let args = Args::parse();
+    init_logging(args.verbose, args.log_format, args.log_file.as_deref(), args.color).unwrap_or_else(|e| exit_with_error(e));
+    args.color.apply();
+    match args.command {
+        Some(Command::Todos(todos_args)) => run_todos(todos_args),
+        Some(Command::CheckLicense(license_args)) => run_check_license(license_args),
+        Some(Command::Stats(stats_args)) => run_stats(stats_args).await,
+        Some(Command::Report(report_args)) => run_report(report_args).await,
+        Some(Command::Explain(explain_args)) => run_explain(explain_args).await,
+        Some(Command::Doctor(doctor_args)) => run_doctor(doctor_args).await,
+        Some(Command::Login(login_args)) => run_login(login_args),
+        Some(Command::Trust(trust_args)) => run_trust(trust_args),
+        Some(Command::Estimate(estimate_args)) => run_estimate(estimate_args).await,
+        Some(Command::Review(review_args)) => run_review(review_args).await,
+        Some(Command::Schema(schema_args)) => print_schema(schema_args.format),
+        Some(Command::Grpc(grpc_args)) => run_grpc(grpc_args).await,
+        Some(Command::Mcp(mcp_args)) => run_mcp(mcp_args).await,
+        Some(Command::Daemon(daemon_args)) => run_daemon(daemon_args).await,
+        None if args.estimate => run_estimate(EstimateArgs {
+            json: args.json || args.format == Format::Json,
+            check_accuracy: args.check_accuracy,
+            batch_size: args.batch_size,
+            input_price: args.input_price,
+            output_price: args.output_price,
+            path: args.path,
+        }).await,
+        None => run_analysis(args).await,
+    }
+}
+
+fn exit_with_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("{} {}", "error:".red().bold(), message);
+    std::process::exit(2);
+}
+
+async fn run_grpc(args: GrpcArgs) {
+    let config = load_config(&args.config_dir);
+    require_api_key(args.offline, &config);
+    let options = base_options(config, args.offline);
+    if let Some(address) = args.metrics {
+        spawn_metrics(address);
+    }
+    if let Err(e) = unremark::serve_grpc(args.listen, options).await {
+        exit_with_error(e);
+    }
+}
+
+/// Serves `/metrics` next to a long-running server, exiting if it can't.
+fn spawn_metrics(address: std::net::SocketAddr) {
+    tokio::spawn(async move {
+        if let Err(e) = unremark::serve_metrics(address).await {
+            exit_with_error(e);
+        }
+    });
+}
+
+async fn run_mcp(args: McpArgs) {
+    let config = load_config(&args.root);
+    require_api_key(args.offline, &config);
+    let options = base_options(config, args.offline);
+    if let Err(e) = unremark::serve_mcp(&args.root, options).await {
+        exit_with_error(e);
+    }
+}
+
+/// Exit code of a run stopped with Ctrl-C, after it saved what it finished.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// The results of the analyses that finished, and whether Ctrl-C stopped the rest.
+struct Analyzed<T> {
+    results: Vec<T>,
+    interrupted: bool,
+}
+
+/// Waits for Ctrl-C, then lets a second one exit at once rather than wait
+/// for the cache and the finished results to be saved.
+async fn interrupted() {
+    let _ = tokio::signal::ctrl_c().await;
+    tokio::spawn(async {
+        let _ = tokio::signal::ctrl_c().await;
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    });
+}
+
+/// Runs the per-file analyses at most `limit` at a time, rather than opening
+/// every file of a big repo at once, keeping their results in order. Ctrl-C
+/// cancels the analyses still running.
+async fn analyze_bounded<F: std::future::Future>(analyses: Vec<F>, limit: usize) -> Analyzed<F::Output> {
+    let mut stream = futures::stream::iter(analyses).buffered(limit.max(1));
+    let mut results = Vec::new();
+    let interrupt = interrupted();
+    tokio::pin!(interrupt);
+    loop {
+        tokio::select! {
+            result = stream.next() => match result {
+                Some(result) => results.push(result),
+                None => return Analyzed { results, interrupted: false },
+            },
+            _ = &mut interrupt => return Analyzed { results, interrupted: true },
+        }
+    }
+}
+
+/// Exits after Ctrl-C stopped a command whose partial results would mislead.
+fn exit_interrupted(saved: &str) -> ! {
+    eprintln!("{} interrupted, {}", "warning:".yellow().bold(), saved);
+    std::process::exit(INTERRUPTED_EXIT_CODE);
+}
+
+/// Streams the results from the daemon, reporting each as it arrives.
+async fn analyze_with_daemon(
+    daemon: DaemonClient,
+    request: &DaemonRequest,
+    report: impl Fn(&AnalysisResult),
+) -> Result<Analyzed<AnalysisResult>, String> {
+    let mut stream = daemon.analyze(request).await?;
+    let mut results = Vec::new();
+    let interrupt = interrupted();
+    tokio::pin!(interrupt);
+    loop {
+        tokio::select! {
+            result = stream.next() => match result {
+                Some(result) => {
+                    let result = result?;
+                    report(&result);
+                    results.push(result);
+                }
+                None => return Ok(Analyzed { results, interrupted: false }),
+            },
+            _ = &mut interrupt => return Ok(Analyzed { results, interrupted: true }),
+        }
+    }
+}
+
+async fn run_daemon(args: DaemonArgs) {
+    let socket = args.socket.unwrap_or_else(default_daemon_socket);
+    if let Some(address) = args.metrics {
+        spawn_metrics(address);
+    }
+    if let Err(e) = serve_daemon(&socket, configured_options).await {
+        exit_with_error(e);
+    }
+}
+
+/// Options from the closest unremark.toml, for the daemon, which must not exit on a bad config.
+fn configured_options(start: &std::path::Path) -> Result<AnalysisOptions, String> {
+    let config = Config::load(start)?;
+    load_languages(&config)?;
+    Ok(AnalysisOptions {
+        policies: load_plugins(&config.plugins)?,
+        classifier: load_classifier(config.classifier.as_ref())?,
+        api: config.api_settings(),
+        context_budget: config.context,
+        rules: config.rules,
+        ..Default::default()
+    })
+}
+
+async fn run_review(args: ReviewArgs) {
+    let config = load_config(&args.path);
+    require_api_key(args.offline, &config);
+    let Some(repo) = args.repo.or_else(|| std::env::var("GITHUB_REPOSITORY").ok()) else {
+        exit_with_error("no repository given, pass --repo owner/name or set GITHUB_REPOSITORY");
+    };
+    let Ok(token) = std::env::var("GITHUB_TOKEN") else {
+        exit_with_error("GITHUB_TOKEN is not set");
+    };
+    let api_url = std::env::var("GITHUB_API_URL").unwrap_or_else(|_| DEFAULT_GITHUB_API_URL.to_string());
+
+    let root = repository_root(&args.path).unwrap_or_else(|e| exit_with_error(e));
+    let diff = fetch_pull_request_diff(&api_url, &repo, args.pr, &token).await.unwrap_or_else(|e| exit_with_error(e));
+    let diff = ChangedLines::from_diff(&diff, &root);
+
+    let options = base_options(config, args.offline);
+    let cache = Arc::new(RwLock::new(Cache::load()));
+    let files: Vec<PathBuf> = collect_files(&args.path).into_iter()
+        .filter(|path| diff.contains_file(path))
+        .collect();
+
+    let futures: Vec<_> = files.iter()
+        .map(|path| {
+            let cache = Arc::clone(&cache);
+            let (options, diff, root) = (&options, &diff, &root);
+            async move {
+                let source_code = unremark::read_source(path).ok()?;
+                let result = analyze_file_with_options(path, false, &cache, options).await;
+                Some(review_comments(&result, &source_code, root, diff))
+            }
+        })
+        .collect();
+    let analyzed = analyze_bounded(futures, options.file_concurrency).await;
+    cache.read().save();
+    if analyzed.interrupted {
+        exit_interrupted("the cache is saved but no review was posted");
+    }
+    let comments: Vec<ReviewComment> = analyzed.results.into_iter().flatten().flatten().collect();
+
+    if args.dry_run {
+        println!("{}", serde_json::to_string_pretty(&comments).unwrap());
+        return;
+    }
+    if comments.is_empty() {
+        println!("No findings on the changed lines of {}#{}", repo, args.pr);
+        return;
+    }
+    post_review(&api_url, &repo, args.pr, &token, &comments).await.unwrap_or_else(|e| exit_with_error(e));
+    println!("Posted {} review comments to {}#{}", comments.len(), repo, args.pr);
+}
+
+async fn run_analysis(args: Args) {
+    let started = Instant::now();
+    let provider = if args.proxy_endpoint.is_some() { Provider::Proxy } else { args.provider };
+    let config = load_config(&args.path);
+    require_provider_key(args.offline || provider == Provider::Proxy, provider, &config);
+    let api_settings = config.api_settings_for(provider);
+    if !args.offline {
+        api_settings.validate().unwrap_or_else(|e| exit_with_error(e));
+    }
+    let breaker = Arc::clone(&api_settings.breaker);
+    let usage = Arc::clone(&api_settings.usage);
+    let proxy_endpoint = (provider == Provider::Proxy)
+        .then(|| config.proxy.resolve_endpoint(args.proxy_endpoint.as_deref()));
+    let base_url = (provider == Provider::OpenAi)
+        .then(|| config.openai.resolve_base_url(args.base_url.as_deref()))
+        .flatten();
+    let changed_lines = args.changed_since.as_deref().map(|revision| {
+        Arc::new(ChangedLines::since(&args.path, revision).unwrap_or_else(|e| exit_with_error(e)))
+    });
+    let merge_base = args.new_since.as_deref().map(|revision| {
+        MergeBase::find(&args.path, revision).unwrap_or_else(|e| exit_with_error(e))
+    });
+    let from_stdin = args.path == Path::new("-");
+    let language = args.language.as_deref().map(|name| {
+        Language::from_name(name).unwrap_or_else(|| exit_with_error(format!("unknown language '{}'", name)))
+    });
+    if from_stdin && language.is_none() {
+        exit_with_error("reading the source from stdin needs --language");
+    }
+    if language.is_some() && !from_stdin && !args.path.is_file() {
+        exit_with_error("--language only applies to a single file or to stdin");
+    }
+    let stdin_source = from_stdin.then(|| {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source)
+            .unwrap_or_else(|e| exit_with_error(format!("failed to read stdin: {}", e)));
+        source
+    });
+    // Fixed or annotated stdin is printed in place of the findings
+    let rewrite_stdin = from_stdin && (args.fix || args.annotate);
+
+    let timings = args.timings.then(|| Arc::new(Timings::default()));
+    let walk_start = Instant::now();
+    let mut files = if from_stdin {
+        vec![PathBuf::from("<stdin>")]
+    } else {
+        collect_files_matching(&args.path, |path| is_supported(path) || (args.markdown && is_markdown(path)))
+    };
+    if let Some(timings) = &timings {
+        timings.record(Phase::Walk, walk_start.elapsed());
+    }
+    if let Some(changed_lines) = &changed_lines {
+        files.retain(|path| changed_lines.contains_file(path));
+    }
+    let rules = config.rules.clone();
+    let max_file_size = (args.max_file_size > 0).then_some(args.max_file_size);
+
+    let format = if args.json { Format::Json } else { args.format };
+    if args.group_by.is_some() && !matches!(format, Format::Text | Format::Json | Format::Template) {
+        exit_with_error("--group-by only applies to the text, JSON and template output");
+    }
+    let template = match (&args.template, format) {
+        (Some(path), Format::Template) => Some(ReportTemplate::load(path).unwrap_or_else(|e| exit_with_error(e))),
+        (None, Format::Template) => exit_with_error("--format template needs a --template file"),
+        (Some(_), _) => exit_with_error("--template only applies to --format template"),
+        (None, _) => None,
+    };
+    let progress = if format != Format::Text || from_stdin {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(files.len() as u64)
+    };
+    progress.set_style(
+        ProgressStyle::with_template("{spinner} [{bar:40}] {pos}/{len} {msg}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    // Sorting and grouping need every file first; JSONL is streamed regardless
+    let streamed = FileStream::streams(format) && !rewrite_stdin
+        && (format == Format::Jsonl || (args.sort.is_none() && args.group_by.is_none() && !args.summary_only));
+    let stream = streamed.then(|| FileStream::start(format, args.fix, args.fail_on, files.len()));
+    let report = |result: &AnalysisResult| {
+        progress.inc(1);
+        if let Some(stream) = &stream {
+            progress.suspend(|| stream.print(result));
+        }
+    };
+
+    // Text output quotes each finding from the source as analyzed
+    let keep_source = format == Format::Text && !args.summary_only && !rewrite_stdin;
+
+    // The daemon can't filter by git revision, time the phases for this
+    // process, take a language or ask OpenRouter, so those runs stay local
+    let daemon = if args.no_daemon || changed_lines.is_some() || merge_base.is_some() || timings.is_some() || language.is_some()
+        || provider == Provider::OpenRouter {
+        None
+    } else {
+        DaemonClient::connect(&default_daemon_socket()).await
+    };
+    let analyzed = match daemon {
+        Some(daemon) => {
+            let request = DaemonRequest {
+                cwd: std::env::current_dir().unwrap_or_else(|e| exit_with_error(e)),
+                config_start: args.path.clone(),
+                files: files.clone(),
+                fix: args.fix,
+                offline: args.offline,
+                name_echo_threshold: args.name_echo_threshold,
+                min_duplicate_occurrences: args.min_duplicates,
+                prefilter: !args.no_prefilter,
+                check_accuracy: args.check_accuracy,
+                blame: args.blame,
+                proxy_endpoint: proxy_endpoint.clone(),
+                base_url: base_url.clone(),
+                model: args.model.clone(),
+                batch_size: Some(args.batch_size.into()),
+                file_concurrency: Some(args.jobs),
+                max_file_size: max_file_size.or(Some(u64::MAX)),
+                max_context_tokens: args.max_context_tokens,
+                annotate: args.annotate,
+                report_all: args.report_all,
+                verify: args.verify,
+                markdown: args.markdown,
+                keep_source,
+            };
+            analyze_with_daemon(daemon, &request, report).await
+                .unwrap_or_else(|e| exit_with_error(format!("{} (use --no-daemon to analyze without it)", e)))
+        }
+        None => {
+            let cache = Arc::new(RwLock::new(Cache::load()));
+            let options = AnalysisOptions {
+                offline: args.offline,
+                changed_lines: changed_lines.clone(),
+                blame: args.blame,
+                merge_base: merge_base.map(Arc::new),
+                name_echo_threshold: args.name_echo_threshold,
+                min_duplicate_occurrences: args.min_duplicates,
+                prefilter: !args.no_prefilter,
+                check_accuracy: args.check_accuracy,
+                api: ApiSettings {
+                    endpoint: proxy_endpoint.or(base_url),
+                    model: args.model.clone().unwrap_or(api_settings.model),
+                    batch_size: args.batch_size.into(),
+                    ..api_settings
+                },
+                file_concurrency: args.jobs,
+                max_file_size,
+                context_budget: unremark::ContextBudget {
+                    max_tokens: args.max_context_tokens.unwrap_or(config.context.max_tokens),
+                    ..config.context
+                },
+                timings: timings.clone(),
+                annotate: args.annotate,
+                report_all: args.report_all,
+                verify: args.verify,
+                markdown: args.markdown,
+                language,
+                keep_source,
+                ..base_options(config, args.offline)
+            };
+            let futures: Vec<_> = files.iter()
+                .map(|path| {
+                    let cache = Arc::clone(&cache);
+                    let (options, report, stdin_source) = (&options, &report, &stdin_source);
+                    async move {
+                        let result = match stdin_source {
+                            Some(source) => analyze_source(source.as_str().into(), path, options).await,
+                            None => analyze_file_with_options(path, args.fix, &cache, options).await,
+                        };
+                        report(&result);
+                        result
+                    }
+                })
+                .collect();
+            let analyzed = analyze_bounded(futures, args.jobs).await;
+            cache.read().save();
+            analyzed
+        }
+    };
+    progress.finish_and_clear();
+    let (mut results, interrupted) = (analyzed.results, analyzed.interrupted);
+    if interrupted {
+        eprintln!(
+            "{} interrupted, reporting the {} of {} files analyzed before Ctrl-C",
+            "warning:".yellow().bold(),
+            results.len(),
+            files.len()
+        );
+    }
+    if breaker.skipped() > 0 {
+        eprintln!(
+            "{} the API failed {} times in a row, so {} requests were skipped; their files are listed with errors",
+            "warning:".yellow().bold(),
+            breaker.max_failures(),
+            breaker.skipped()
+        );
+    }
+
+    // Files skipped for their size aren't read for duplicates either
+    let skipped: std::collections::HashSet<&PathBuf> = results.iter()
+        .filter(|result| result.warnings.iter().any(|warning| warning.starts_with("skipped")))
+        .map(|result| &result.path)
+        .collect();
+    let finished: std::collections::HashSet<&PathBuf> = results.iter().map(|result| &result.path).collect();
+    let analyzed: Vec<PathBuf> = files.iter()
+        .filter(|path| !from_stdin && !skipped.contains(path) && (!interrupted || finished.contains(path)))
+        .cloned()
+        .collect();
+
+    // Repeats within a single file are already flagged per file
+    let duplicates: Vec<DuplicateComment> = if rules.is_enabled(DUPLICATE_COMMENT_RULE) {
+        find_duplicate_comments_in_files(&analyzed, args.min_duplicates)
+            .into_iter()
+            .filter(|duplicate| duplicate.locations.iter().any(|l| l.path != duplicate.locations[0].path))
+            .filter(|duplicate| !rules.ignores(&duplicate.text))
+            .filter(|duplicate| changed_lines.as_ref().is_none_or(|changed_lines| {
+                duplicate.locations.iter().any(|l| changed_lines.touches_line(&l.path, l.line_number))
+            }))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    if let Some(sort) = args.sort {
+        sort_results(&mut results, sort);
+    }
+    let timings = timings.map(|timings| timings.report(started.elapsed()));
+    // Runs that never reached the API, offline or through the proxy, cost nothing to report
+    let usage = Some(usage.report(args.input_price, args.output_price)).filter(|usage| usage.requests > 0);
+    match format {
+        _ if rewrite_stdin => {
+            let source = stdin_source.as_deref().unwrap_or_default();
+            // Interrupted before the analysis finished, the input goes through untouched
+            let Some(result) = results.first() else {
+                print!("{}", source);
+                exit_interrupted("the input is printed unchanged");
+            };
+            results.iter().for_each(print_problems);
+            if args.fix {
+                let fixed = remove_redundant_comments(source, &result.redundant_comments);
+                if args.verify {
+                    let language = language.expect("required for stdin");
+                    verify_fix(source, &fixed, &result.redundant_comments, language)
+                        .unwrap_or_else(|e| exit_with_error(format!("fix failed verification: {}", e)));
+                }
+                print!("{}", fixed);
+            } else {
+                print!("{}", annotate_redundant_comments(source, &result.redundant_comments));
+            }
+        }
+        Format::Text if args.summary_only => print_summary(&results, args.fix, args.group_by, args.sort),
+        Format::Text if streamed => print_text_end(&results, &duplicates, args.fix),
+        Format::Text => print_text(&results, &duplicates, args.fix, args.group_by, args.sort),
+        Format::Json => print_json(&results, &duplicates, args.group_by, args.sort, timings, usage),
+        Format::Jsonl => print_jsonl_end(&results, &duplicates, timings, usage),
+        Format::Csv | Format::Tap if streamed => {}
+        Format::Csv => print_csv(&results),
+        Format::Tap => print_tap(&results, args.fix, args.fail_on),
+        Format::Sarif => print_sarif(&results),
+        Format::Template => {
+            let template = template.expect("loaded for --format template");
+            if let Err(e) = template.print(&results, &duplicates, args.group_by, args.sort, timings, usage) {
+                exit_with_error(e);
+            }
+        }
+    }
+    if let (Format::Text, Some(timings)) = (format, &timings) {
+        print_timings(timings);
+    }
+    if let (Format::Text, Some(usage)) = (format, &usage) {
+        print_usage(usage);
+    }
+
+    if interrupted {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    // Removed comments no longer count
+    let found = results.iter().any(|result| fails(result, args.fix, args.fail_on));
+    std::process::exit(if found { 1 } else { 0 });
+}
@@ -0,0 +1,1014 @@
+use colored::*;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, HashMap};
+use std::io::IsTerminal;
+use std::path::Path;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::EnvFilter;
+use unremark::{AnalysisResult, CommentInfo, DuplicateComment, Language, PhaseTimings, Severity, UsageReport};
+
+/// How analysis results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Human-readable report
+    Text,
+    /// One JSON document once every file is analyzed
+    Json,
+    /// One JSON object per line: each file as soon as it's analyzed, then
+    /// cross-file duplicates and the summary
+    Jsonl,
+    /// One row per finding, for spreadsheets
+    Csv,
+    /// Test Anything Protocol, one test point per analyzed file
+    Tap,
+    /// The JSON document rendered through the MiniJinja template given with --template
+    Template,
+    /// SARIF 2.1.0, for code scanning dashboards, with each finding's severity as its level
+    Sarif,
+}
+
+/// What `--group-by` gathers findings under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// The finding's rule ID
+    Rule,
+    /// The file the comment is in
+    File,
+    /// The language of that file
+    Language,
+}
+
+/// How `--sort` orders files and findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortBy {
+    /// By file path, then line
+    Path,
+    /// By line, then file path (across files with --group-by)
+    Line,
+    /// Most confident findings first, and the files that have them
+    Confidence,
+    /// Files with the most findings first, and the largest groups with --group-by
+    Count,
+}
+
+/// Findings without a confidence score sort after every scored one.
+fn confidence(comment: &CommentInfo) -> f32 {
+    comment.confidence.unwrap_or(f32::NEG_INFINITY)
+}
+
+/// The order of two findings, each with the path of its file. Without
+/// `--sort`, and for `count`, findings stay in file order, by line.
+fn compare_findings(sort: Option<SortBy>, a: (&str, &CommentInfo), b: (&str, &CommentInfo)) -> Ordering {
+    let (a_path, a) = a;
+    let (b_path, b) = b;
+    match sort {
+        Some(SortBy::Path) => a_path.cmp(b_path).then(a.line_number.cmp(&b.line_number)),
+        Some(SortBy::Line) => a.line_number.cmp(&b.line_number).then(a_path.cmp(b_path)),
+        Some(SortBy::Confidence) => confidence(b).total_cmp(&confidence(a))
+            .then(a_path.cmp(b_path))
+            .then(a.line_number.cmp(&b.line_number)),
+        Some(SortBy::Count) | None if a_path == b_path => a.line_number.cmp(&b.line_number),
+        Some(SortBy::Count) | None => Ordering::Equal,
+    }
+}
+
+fn findings(result: &AnalysisResult) -> impl Iterator<Item = &CommentInfo> {
+    result.redundant_comments.iter().chain(&result.flagged_comments)
+}
+
+/// Orders the files for `--sort`, and the findings within each of them.
+pub fn sort_results(results: &mut [AnalysisResult], sort: SortBy) {
+    for result in results.iter_mut() {
+        let path = result.path.display().to_string();
+        let by_sort = |a: &CommentInfo, b: &CommentInfo| compare_findings(Some(sort), (&path, a), (&path, b));
+        result.redundant_comments.sort_by(by_sort);
+        result.flagged_comments.sort_by(by_sort);
+    }
+
+    match sort {
+        SortBy::Path | SortBy::Line => results.sort_by(|a, b| a.path.cmp(&b.path)),
+        SortBy::Confidence => {
+            let top = |result: &AnalysisResult| findings(result).map(confidence).fold(f32::NEG_INFINITY, f32::max);
+            results.sort_by(|a, b| top(b).total_cmp(&top(a)).then(a.path.cmp(&b.path)));
+        }
+        SortBy::Count => results.sort_by_key(|result| (Reverse(findings(result).count()), result.path.clone())),
+    }
+}
+
+/// A finding as listed in a group, with the file it's in.
+#[derive(Debug, Serialize, JsonSchema)]
+struct Finding<'a> {
+    path: String,
+    /// `redundant` for comments `--fix` removes, `review` for the rest
+    kind: &'static str,
+    #[serde(flatten)]
+    comment: &'a CommentInfo,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct FindingGroup<'a> {
+    /// The rule ID, path or language the findings share
+    key: String,
+    count: usize,
+    findings: Vec<Finding<'a>>,
+}
+
+/// Gathers every finding under its group, ordered by key, or largest first
+/// when sorting by count. Within a group, findings follow `sort`.
+fn group_findings(results: &[AnalysisResult], group_by: GroupBy, sort: Option<SortBy>) -> Vec<FindingGroup<'_>> {
+    let mut groups: BTreeMap<String, Vec<Finding>> = BTreeMap::new();
+    for result in results {
+        let path = result.path.display().to_string();
+        let redundant = result.redundant_comments.iter().map(|comment| (comment, "redundant"));
+        let flagged = result.flagged_comments.iter().map(|comment| (comment, "review"));
+        let mut findings: Vec<_> = redundant.chain(flagged).collect();
+        findings.sort_by(|(a, _), (b, _)| compare_findings(sort, (&path, a), (&path, b)));
+
+        for (comment, kind) in findings {
+            let key = match group_by {
+                GroupBy::Rule => comment.rule_id.clone().unwrap_or_else(|| "unknown".to_string()),
+                GroupBy::File => path.clone(),
+                GroupBy::Language => Language::of_file(&result.path)
+                    .map_or_else(|| "unknown".to_string(), |language| language.to_string()),
+            };
+            groups.entry(key).or_default().push(Finding { path: path.clone(), kind, comment });
+        }
+    }
+
+    let mut groups: Vec<FindingGroup> = groups.into_iter()
+        .map(|(key, mut findings)| {
+            findings.sort_by(|a, b| compare_findings(sort, (&a.path, a.comment), (&b.path, b.comment)));
+            FindingGroup { key, count: findings.len(), findings }
+        })
+        .collect();
+    if sort == Some(SortBy::Count) {
+        groups.sort_by_key(|group| Reverse(group.count));
+    }
+    groups
+}
+
+/// Whether the file has a finding that fails the run: one at least as severe
+/// as `fail_on`, except for redundant comments `--fix` already removed.
+pub fn fails(result: &AnalysisResult, fix: bool, fail_on: Severity) -> bool {
+    let fails = |comment: &CommentInfo| comment.severity.unwrap_or(Severity::Warning) >= fail_on;
+    // A file with errors and findings is one whose fix failed verification
+    let unfixed = !fix || !result.errors.is_empty();
+    (unfixed && result.redundant_comments.iter().any(fails)) || result.flagged_comments.iter().any(fails)
+}
+
+/// Version of the JSON and JSONL output structures, bumped on breaking changes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct FileReport<'a> {
+    path: String,
+    redundant_comments: &'a [CommentInfo],
+    flagged_comments: &'a [CommentInfo],
+    errors: &'a [String],
+    /// Why the file was skipped, e.g. for being over --max-file-size
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    warnings: &'a [String],
+    /// Every analyzed comment with its usefulness score, for --report-all
+    #[serde(skip_serializing_if = "<[CommentInfo]>::is_empty")]
+    scored_comments: &'a [CommentInfo],
+}
+
+impl<'a> From<&'a AnalysisResult> for FileReport<'a> {
+    fn from(result: &'a AnalysisResult) -> Self {
+        Self {
+            path: result.path.display().to_string(),
+            redundant_comments: &result.redundant_comments,
+            flagged_comments: &result.flagged_comments,
+            errors: &result.errors,
+            warnings: &result.warnings,
+            scored_comments: &result.scored_comments,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct Summary {
+    files_analyzed: usize,
+    files_with_errors: usize,
+    redundant_comments: usize,
+    flagged_comments: usize,
+    /// Seconds spent in each phase, with `--timings`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings: Option<PhaseTimings>,
+    /// The requests sent to the API, their tokens and cost, when any were
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<UsageReport>,
+}
+
+/// The `--format json` document.
+#[derive(Debug, Serialize, JsonSchema)]
+struct JsonOutput<'a> {
+    schema_version: u32,
+    results: Vec<FileReport<'a>>,
+    /// Every finding again, grouped, with `--group-by`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<FindingGroup<'a>>>,
+    duplicates: &'a [DuplicateComment],
+    summary: Summary,
+}
+
+fn summarize(results: &[AnalysisResult], timings: Option<PhaseTimings>, usage: Option<UsageReport>) -> Summary {
+    Summary {
+        files_analyzed: results.len(),
+        files_with_errors: results.iter().filter(|r| !r.errors.is_empty()).count(),
+        redundant_comments: results.iter().map(|r| r.redundant_comments.len()).sum(),
+        flagged_comments: results.iter().map(|r| r.flagged_comments.len()).sum(),
+        timings,
+        usage,
+    }
+}
+
+fn json_output<'a>(
+    results: &'a [AnalysisResult],
+    duplicates: &'a [DuplicateComment],
+    group_by: Option<GroupBy>,
+    sort: Option<SortBy>,
+    timings: Option<PhaseTimings>,
+    usage: Option<UsageReport>,
+) -> JsonOutput<'a> {
+    JsonOutput {
+        schema_version: SCHEMA_VERSION,
+        results: results.iter().map(FileReport::from).collect(),
+        groups: group_by.map(|group_by| group_findings(results, group_by, sort)),
+        duplicates,
+        summary: summarize(results, timings, usage),
+    }
+}
+
+pub fn print_json(
+    results: &[AnalysisResult],
+    duplicates: &[DuplicateComment],
+    group_by: Option<GroupBy>,
+    sort: Option<SortBy>,
+    timings: Option<PhaseTimings>,
+    usage: Option<UsageReport>,
+) {
+    let output = json_output(results, duplicates, group_by, sort, timings, usage);
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+}
+
+/// A user-provided MiniJinja template for `--format template`, rendered with
+/// the same document `--format json` prints.
+pub struct ReportTemplate {
+    environment: minijinja::Environment<'static>,
+}
+
+impl ReportTemplate {
+    const NAME: &'static str = "report";
+
+    /// Reads and compiles the template, so mistakes in it show up before analysis.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read template {}: {}", path.display(), e))?;
+        let mut environment = minijinja::Environment::new();
+        environment.set_keep_trailing_newline(true);
+        environment.add_template_owned(Self::NAME, source)
+            .map_err(|e| format!("Invalid template {}: {}", path.display(), e))?;
+        Ok(Self { environment })
+    }
+
+    pub fn print(
+        &self,
+        results: &[AnalysisResult],
+        duplicates: &[DuplicateComment],
+        group_by: Option<GroupBy>,
+        sort: Option<SortBy>,
+        timings: Option<PhaseTimings>,
+        usage: Option<UsageReport>,
+    ) -> Result<(), String> {
+        print!("{}", self.render(&json_output(results, duplicates, group_by, sort, timings, usage))?);
+        Ok(())
+    }
+
+    fn render(&self, output: &JsonOutput) -> Result<String, String> {
+        self.environment.get_template(Self::NAME)
+            .and_then(|template| template.render(output))
+            .map_err(|e| format!("Failed to render template: {}", e))
+    }
+}
+
+/// When to color the human-readable output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Color when printing to a terminal and `NO_COLOR` isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn apply(self) {
+        match self {
+            Self::Always => colored::control::set_override(true),
+            Self::Never => colored::control::set_override(false),
+            Self::Auto if !std::io::stdout().is_terminal() => colored::control::set_override(false),
+            // `colored` already honors NO_COLOR and CLICOLOR
+            Self::Auto => {}
+        }
+    }
+}
+
+/// How the `-v` logs are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// One line per event, for people
+    Text,
+    /// One JSON object per event, with the fields of its file and API request spans
+    Json,
+}
+
+/// Sends the logs of the given verbosity (or `RUST_LOG`'s) to `file`, or to
+/// stderr, with how long each file and API request took as its span closes.
+pub fn init_logging(verbose: u8, format: LogFormat, file: Option<&Path>, color: ColorChoice) -> Result<(), String> {
+    let default_filter = match verbose {
+        0 => "warn",
+        1 => "warn,unremark=info",
+        2 => "warn,unremark=debug",
+        _ => "debug",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_filter));
+    let writer = match file {
+        Some(path) => std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(|file| BoxMakeWriter::new(std::sync::Mutex::new(file)))
+            .map_err(|e| format!("failed to open the log file {}: {}", path.display(), e))?,
+        None => BoxMakeWriter::new(std::io::stderr),
+    };
+    let ansi = file.is_none() && match color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(ansi)
+        .with_span_events(FmtSpan::CLOSE);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    Ok(())
+}
+
+/// Lines of a comment shown before the rest is elided.
+const MAX_SNIPPET_LINES: usize = 3;
+
+fn severity_label(severity: Severity) -> ColoredString {
+    let label = severity.to_string();
+    match severity {
+        Severity::Error => label.red().bold(),
+        Severity::Warning => label.yellow().bold(),
+        Severity::Info => label.blue().bold(),
+        Severity::Hint => label.cyan().bold(),
+    }
+}
+
+/// The comment's lines from the source, with the first one underlined, e.g.
+///
+/// ```text
+///    |
+///  2 |     // Constructor
+///    |     ^^^^^^^^^^^^^^
+/// ```
+fn print_snippet(comment: &CommentInfo, source_lines: &[&str], gutter: usize, severity: Severity) {
+    let Some(first_line) = source_lines.get(comment.line_number.wrapping_sub(1)) else {
+        return;
+    };
+    let comment_start = comment.text.lines().next().unwrap_or_default();
+    let Some(column) = first_line.find(comment_start) else {
+        return;
+    };
+
+    let bar = format!("{:>width$} |", "", width = gutter).blue().bold();
+    println!("{}", bar);
+    let line_count = comment.text.lines().count().max(1);
+    for offset in 0..line_count.min(MAX_SNIPPET_LINES) {
+        let Some(line) = source_lines.get(comment.line_number - 1 + offset) else {
+            break;
+        };
+        let number = format!("{:>width$} |", comment.line_number + offset, width = gutter).blue().bold();
+        println!("{} {}", number, line);
+        if offset == 0 {
+            // Keep tabs so the carets line up under the comment
+            let indent: String = first_line[..column].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+            let carets = "^".repeat(comment_start.chars().count().max(1));
+            let carets = match severity {
+                Severity::Error => carets.red().bold(),
+                Severity::Warning => carets.yellow().bold(),
+                Severity::Info => carets.blue().bold(),
+                Severity::Hint => carets.cyan().bold(),
+            };
+            println!("{} {}{}", bar, indent, carets);
+        }
+    }
+    if line_count > MAX_SNIPPET_LINES {
+        println!("{} ...", bar);
+    }
+}
+
+fn print_comment(path: &Path, comment: &CommentInfo, source_lines: Option<&[&str]>, gutter: usize) {
+    let severity = comment.severity.unwrap_or(Severity::Warning);
+    let rule = comment.rule_id.as_deref().map(|rule_id| format!("[{}]", rule_id)).unwrap_or_default();
+    let column = source_lines
+        .and_then(|lines| lines.get(comment.line_number.wrapping_sub(1)))
+        .and_then(|line| line.find(comment.text.lines().next().unwrap_or_default()))
+        .map(|column| format!(":{}", column + 1))
+        .unwrap_or_default();
+
+    println!("{}{}", severity_label(severity), rule.bold());
+    println!(
+        "{}{} {}:{}{}",
+        " ".repeat(gutter),
+        "-->".blue().bold(),
+        path.display(),
+        comment.line_number,
+        column
+    );
+    match source_lines {
+        Some(lines) => print_snippet(comment, lines, gutter, severity),
+        None => println!("{:>width$} {} {}", "", "|".blue().bold(), comment.text.trim(), width = gutter),
+    }
+
+    let note = format!("{:>width$} =", "", width = gutter).blue().bold();
+    if let Some(explanation) = &comment.explanation {
+        println!("{} {}", note, explanation);
+    }
+    if let Some(blame) = &comment.blame {
+        let commit = &blame.commit[..blame.commit.len().min(8)];
+        let age = format!("last changed in {} by {}, {} days ago", commit, blame.author, blame.age_days);
+        println!("{} {}", note, age.dimmed());
+    }
+    println!();
+}
+
+fn print_groups(results: &[AnalysisResult], group_by: GroupBy, sort: Option<SortBy>) {
+    let sources: HashMap<String, Vec<&str>> = results.iter()
+        .filter_map(|result| Some((result.path.display().to_string(), result.source.as_deref()?.lines().collect())))
+        .collect();
+
+    for group in group_findings(results, group_by, sort) {
+        let last_line = group.findings.iter().map(|finding| finding.comment.line_number + 2).max().unwrap_or(1);
+        let gutter = last_line.to_string().len() + 1;
+
+        println!("{} ({} findings)\n", group.key.bold().underline(), group.count);
+        for finding in group.findings {
+            let source_lines = sources.get(&finding.path).map(Vec::as_slice);
+            print_comment(Path::new(&finding.path), finding.comment, source_lines, gutter);
+        }
+    }
+}
+
+fn print_file(result: &AnalysisResult, sort: Option<SortBy>) {
+    if result.redundant_comments.is_empty() && result.flagged_comments.is_empty() {
+        return;
+    }
+
+    // Quoted from the source as analyzed, which `--fix` may have changed since
+    let source_lines: Option<Vec<&str>> = result.source.as_deref().map(|source| source.lines().collect());
+    let mut findings: Vec<&CommentInfo> = result.redundant_comments.iter()
+        .chain(&result.flagged_comments)
+        .collect();
+    let path = result.path.display().to_string();
+    findings.sort_by(|a, b| compare_findings(sort, (&path, a), (&path, b)));
+    let last_line = findings.iter().map(|comment| comment.line_number + 2).max().unwrap_or(1);
+    let gutter = last_line.to_string().len() + 1;
+
+    println!("{}\n", result.path.display().to_string().bold().underline());
+    for comment in findings {
+        print_comment(&result.path, comment, source_lines.as_deref(), gutter);
+    }
+}
+
+pub fn print_problems(result: &AnalysisResult) {
+    for error in &result.errors {
+        eprintln!("{} {}: {}", "error:".red().bold(), result.path.display(), error);
+    }
+    for warning in &result.warnings {
+        eprintln!("{} {}: {}", "warning:".yellow().bold(), result.path.display(), warning);
+    }
+}
+
+pub fn print_text(
+    results: &[AnalysisResult],
+    duplicates: &[DuplicateComment],
+    fix: bool,
+    group_by: Option<GroupBy>,
+    sort: Option<SortBy>,
+) {
+    results.iter().for_each(print_problems);
+    match group_by {
+        Some(group_by) => print_groups(results, group_by, sort),
+        None => results.iter().for_each(|result| print_file(result, sort)),
+    }
+    print_text_end(results, duplicates, fix);
+}
+
+/// Prints what's only known once every file is analyzed, for text output
+/// whose files were already printed as they finished.
+pub fn print_text_end(results: &[AnalysisResult], duplicates: &[DuplicateComment], fix: bool) {
+    if !duplicates.is_empty() {
+        println!("{}", "Comments repeated across files".bold().underline());
+        for duplicate in duplicates {
+            println!("  {} {}", format!("{}x", duplicate.count).cyan(), duplicate.text.blue());
+            for location in &duplicate.locations {
+                println!("    {}:{}", location.path.display(), location.line_number);
+            }
+        }
+        println!();
+    }
+
+    print_summary(results, fix, None, None);
+}
+
+pub fn print_summary(results: &[AnalysisResult], fix: bool, group_by: Option<GroupBy>, sort: Option<SortBy>) {
+    if let Some(group_by) = group_by {
+        for group in group_findings(results, group_by, sort) {
+            println!("{:>6}  {}", group.count, group.key);
+        }
+    }
+    let summary = summarize(results, None, None);
+    println!(
+        "{} {} files analyzed, {} redundant comments {}, {} comments flagged for review, {} files with errors",
+        "Summary:".bold(),
+        summary.files_analyzed,
+        summary.redundant_comments,
+        if fix { "removed" } else { "found" },
+        summary.flagged_comments,
+        summary.files_with_errors
+    );
+}
+
+/// A line of `--format jsonl` output, tagged with its `type`.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonlRecord<'a> {
+    File(FileReport<'a>),
+    Duplicate(&'a DuplicateComment),
+    Summary(Summary),
+}
+
+/// A line of `--format jsonl` output.
+#[derive(Debug, Serialize, JsonSchema)]
+struct JsonlLine<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    record: JsonlRecord<'a>,
+}
+
+fn print_jsonl_record(record: JsonlRecord) {
+    let line = JsonlLine { schema_version: SCHEMA_VERSION, record };
+    println!("{}", serde_json::to_string(&line).unwrap());
+}
+
+/// Prints a file's results right away, for `--format jsonl`.
+pub fn print_jsonl_file(result: &AnalysisResult) {
+    print_jsonl_record(JsonlRecord::File(FileReport::from(result)));
+}
+
+/// Prints what's only known once every file is analyzed, for `--format jsonl`.
+pub fn print_jsonl_end(
+    results: &[AnalysisResult],
+    duplicates: &[DuplicateComment],
+    timings: Option<PhaseTimings>,
+    usage: Option<UsageReport>,
+) {
+    for duplicate in duplicates {
+        print_jsonl_record(JsonlRecord::Duplicate(duplicate));
+    }
+    print_jsonl_record(JsonlRecord::Summary(summarize(results, timings, usage)));
+}
+
+/// Prints where the time went, after the summary, for `--timings`.
+pub fn print_timings(timings: &PhaseTimings) {
+    let phases = [
+        ("walk", timings.walk),
+        ("parse", timings.parse),
+        ("detect", timings.detect),
+        ("heuristics", timings.heuristics),
+        ("api", timings.api),
+        ("fix", timings.fix),
+    ];
+    let phases: Vec<String> = phases.iter().map(|(name, secs)| format!("{} {:.2}s", name, secs)).collect();
+    println!("{} {}, total {:.2}s", "Timings:".bold(), phases.join(", "), timings.total);
+}
+
+/// Prints what the API requests cost, after the summary.
+pub fn print_usage(usage: &UsageReport) {
+    println!(
+        "{} {} API requests, {} prompt and {} completion tokens, about ${:.4}",
+        "Usage:".bold(),
+        usage.requests,
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        usage.cost
+    );
+}
+
+#[derive(Debug, Serialize)]
+struct CsvRow<'a> {
+    path: String,
+    line: usize,
+    comment: &'a str,
+    /// `redundant` for comments `--fix` removes, `review` for the rest
+    category: &'static str,
+    rule: &'a str,
+    severity: String,
+    confidence: Option<f32>,
+    explanation: &'a str,
+}
+
+impl<'a> CsvRow<'a> {
+    fn new(result: &AnalysisResult, comment: &'a CommentInfo, category: &'static str) -> Self {
+        Self {
+            path: result.path.display().to_string(),
+            line: comment.line_number,
+            comment: &comment.text,
+            category,
+            rule: comment.rule_id.as_deref().unwrap_or_default(),
+            severity: comment.severity.map(|severity| severity.to_string()).unwrap_or_default(),
+            confidence: comment.confidence,
+            explanation: comment.explanation.as_deref().unwrap_or_default(),
+        }
+    }
+}
+
+pub fn print_csv(results: &[AnalysisResult]) {
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    for result in results {
+        write_csv_rows(&mut writer, result);
+    }
+    writer.flush().unwrap();
+}
+
+fn write_csv_rows(writer: &mut csv::Writer<impl std::io::Write>, result: &AnalysisResult) {
+    let redundant = result.redundant_comments.iter().map(|comment| CsvRow::new(result, comment, "redundant"));
+    let flagged = result.flagged_comments.iter().map(|comment| CsvRow::new(result, comment, "review"));
+    for row in redundant.chain(flagged) {
+        writer.serialize(row).unwrap();
+    }
+}
+
+/// Prints a TAP version 13 report. A file passes unless it has a finding that
+/// fails the run or couldn't be analyzed; its findings go in a YAML block.
+pub fn print_tap(results: &[AnalysisResult], fix: bool, fail_on: Severity) {
+    print_tap_plan(results.len());
+    for (index, result) in results.iter().enumerate() {
+        print_tap_point(index + 1, result, fix, fail_on);
+    }
+}
+
+fn print_tap_plan(tests: usize) {
+    println!("TAP version 13");
+    println!("1..{}", tests);
+}
+
+fn print_tap_point(number: usize, result: &AnalysisResult, fix: bool, fail_on: Severity) {
+    print!("{}", tap_point(number, result, fix, fail_on));
+}
+
+/// A file's test point, with its findings and errors in a YAML block.
+fn tap_point(number: usize, result: &AnalysisResult, fix: bool, fail_on: Severity) -> String {
+    use std::fmt::Write;
+    // JSON strings are valid YAML scalars
+    let quote = |text: &str| serde_json::to_string(text).unwrap();
+
+    let ok = !fails(result, fix, fail_on) && result.errors.is_empty();
+    let mut point = String::new();
+    writeln!(point, "{} {} - {}", if ok { "ok" } else { "not ok" }, number, result.path.display()).unwrap();
+
+    let findings: Vec<&CommentInfo> = result.redundant_comments.iter().chain(&result.flagged_comments).collect();
+    if findings.is_empty() && result.errors.is_empty() {
+        return point;
+    }
+    writeln!(point, "  ---").unwrap();
+    if !findings.is_empty() {
+        writeln!(point, "  findings:").unwrap();
+    }
+    for comment in findings {
+        writeln!(point, "    - line: {}", comment.line_number).unwrap();
+        writeln!(point, "      rule: {}", quote(comment.rule_id.as_deref().unwrap_or_default())).unwrap();
+        if let Some(severity) = comment.severity {
+            writeln!(point, "      severity: {}", severity).unwrap();
+        }
+        writeln!(point, "      comment: {}", quote(&comment.text)).unwrap();
+        if let Some(explanation) = &comment.explanation {
+            writeln!(point, "      message: {}", quote(explanation)).unwrap();
+        }
+    }
+    if !result.errors.is_empty() {
+        writeln!(point, "  errors:").unwrap();
+        for error in &result.errors {
+            writeln!(point, "    - {}", quote(error)).unwrap();
+        }
+    }
+    writeln!(point, "  ...").unwrap();
+    point
+}
+
+/// SARIF level of a finding: errors and warnings as they are, info and hints as notes.
+fn sarif_level(comment: &CommentInfo) -> &'static str {
+    match comment.severity.unwrap_or(Severity::Warning) {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info | Severity::Hint => "note",
+    }
+}
+
+/// The file's path relative to where unremark ran, with forward slashes.
+fn sarif_uri(path: &std::path::Path) -> String {
+    path.strip_prefix(".").unwrap_or(path).display().to_string().replace('\\', "/")
+}
+
+/// Prints a SARIF 2.1.0 log with one result per finding, for GitHub code
+/// scanning and other dashboards.
+pub fn print_sarif(results: &[AnalysisResult]) {
+    println!("{}", serde_json::to_string_pretty(&sarif_log(results)).unwrap());
+}
+
+/// The SARIF log of `results`, with each comment's blame (when annotated)
+/// in its result's `properties`.
+fn sarif_log(results: &[AnalysisResult]) -> serde_json::Value {
+    let findings: Vec<(&AnalysisResult, &CommentInfo)> = results.iter()
+        .flat_map(|result| result.redundant_comments.iter().chain(&result.flagged_comments).map(move |comment| (result, comment)))
+        .collect();
+    let mut rule_ids: Vec<&str> = findings.iter()
+        .map(|(_, comment)| comment.rule_id.as_deref().unwrap_or(unremark::REDUNDANT_COMMENT_RULE))
+        .collect();
+    rule_ids.sort_unstable();
+    rule_ids.dedup();
+
+    let sarif_results: Vec<serde_json::Value> = findings.iter()
+        .map(|(result, comment)| {
+            let mut sarif_result = serde_json::json!({
+                "ruleId": comment.rule_id.as_deref().unwrap_or(unremark::REDUNDANT_COMMENT_RULE),
+                "level": sarif_level(comment),
+                "message": { "text": comment.explanation.as_deref().unwrap_or("This comment may be redundant") },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": sarif_uri(&result.path) },
+                        "region": { "startLine": comment.line_number, "snippet": { "text": comment.text } },
+                    },
+                }],
+            });
+            if let Some(blame) = &comment.blame {
+                sarif_result["properties"] = serde_json::json!({ "blame": blame });
+            }
+            sarif_result
+        })
+        .collect();
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "unremark",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rule_ids.iter().map(|id| serde_json::json!({ "id": id })).collect::<Vec<_>>(),
+                },
+            },
+            "results": sarif_results,
+        }],
+    })
+}
+
+/// Prints each file's results as soon as it's analyzed, so long runs show
+/// findings early and keep them when interrupted. Only the formats that don't
+/// need every file first stream: text, JSONL, CSV and TAP, and none of them
+/// when the results are sorted or grouped.
+pub struct FileStream {
+    format: Format,
+    fix: bool,
+    fail_on: Severity,
+    csv: RefCell<Option<csv::Writer<std::io::Stdout>>>,
+    tap_points: Cell<usize>,
+}
+
+impl FileStream {
+    /// Starts streaming `files` results, printing a TAP plan up front.
+    pub fn start(format: Format, fix: bool, fail_on: Severity, files: usize) -> Self {
+        if format == Format::Tap {
+            print_tap_plan(files);
+        }
+        Self {
+            format,
+            fix,
+            fail_on,
+            csv: RefCell::new((format == Format::Csv).then(|| csv::Writer::from_writer(std::io::stdout()))),
+            tap_points: Cell::new(0),
+        }
+    }
+
+    pub fn streams(format: Format) -> bool {
+        matches!(format, Format::Text | Format::Jsonl | Format::Csv | Format::Tap)
+    }
+
+    pub fn print(&self, result: &AnalysisResult) {
+        match self.format {
+            Format::Text => {
+                print_problems(result);
+                print_file(result, None);
+            }
+            Format::Jsonl => print_jsonl_file(result),
+            Format::Csv => {
+                if let Some(writer) = self.csv.borrow_mut().as_mut() {
+                    write_csv_rows(writer, result);
+                    writer.flush().unwrap();
+                }
+            }
+            Format::Tap => {
+                self.tap_points.set(self.tap_points.get() + 1);
+                print_tap_point(self.tap_points.get(), result, self.fix, self.fail_on);
+            }
+            Format::Json | Format::Template | Format::Sarif => {}
+        }
+    }
+}
+
+/// Prints the JSON Schema of a machine-readable output format.
+pub fn print_schema(format: SchemaFormat) {
+    let schema = match format {
+        SchemaFormat::Json => schemars::schema_for!(JsonOutput<'static>),
+        SchemaFormat::Jsonl => schemars::schema_for!(JsonlLine<'static>),
+    };
+    println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+}
+
+/// The output formats `unremark schema` describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SchemaFormat {
+    /// The `--format json` document
+    Json,
+    /// A line of `--format jsonl` output
+    Jsonl,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use unremark::CommentBlame;
+
+    fn comment(text: &str, line_number: usize, severity: Option<Severity>) -> CommentInfo {
+        CommentInfo {
+            text: text.to_string(),
+            line_number,
+            rule_id: Some("boilerplate-comment".to_string()),
+            severity,
+            explanation: Some("says what the code says".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// A file with a redundant comment, a flagged one and nothing else.
+    fn result() -> AnalysisResult {
+        AnalysisResult {
+            path: PathBuf::from("src/main.rs"),
+            redundant_comments: vec![comment("// Constructor", 2, Some(Severity::Warning))],
+            flagged_comments: vec![comment("// TODO: \"remove\"", 5, Some(Severity::Info))],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fails_on_severity_unless_fixed() {
+        let result = result();
+        assert!(fails(&result, false, Severity::Warning));
+        assert!(!fails(&result, false, Severity::Error));
+        assert!(fails(&result, false, Severity::Info));
+        // The redundant comment is removed, the flagged one never is
+        assert!(!fails(&result, true, Severity::Warning));
+        assert!(fails(&result, true, Severity::Info));
+
+        let unverified = AnalysisResult { errors: vec!["fix failed verification".to_string()], ..result };
+        assert!(fails(&unverified, true, Severity::Warning));
+
+        // Findings without a severity count as warnings
+        let unrated = AnalysisResult {
+            redundant_comments: vec![comment("// Constructor", 2, None)],
+            ..Default::default()
+        };
+        assert!(fails(&unrated, false, Severity::Warning));
+        assert!(!fails(&unrated, false, Severity::Error));
+    }
+
+    #[test]
+    fn test_csv_has_a_row_per_finding() {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        write_csv_rows(&mut writer, &result());
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        assert_eq!(csv, concat!(
+            "path,line,comment,category,rule,severity,confidence,explanation\n",
+            "src/main.rs,2,// Constructor,redundant,boilerplate-comment,warning,,says what the code says\n",
+            "src/main.rs,5,\"// TODO: \"\"remove\"\"\",review,boilerplate-comment,info,,says what the code says\n",
+        ));
+    }
+
+    #[test]
+    fn test_tap_points_pass_unless_the_file_fails() {
+        let point = tap_point(1, &result(), false, Severity::Warning);
+        assert_eq!(point, concat!(
+            "not ok 1 - src/main.rs\n",
+            "  ---\n",
+            "  findings:\n",
+            "    - line: 2\n",
+            "      rule: \"boilerplate-comment\"\n",
+            "      severity: warning\n",
+            "      comment: \"// Constructor\"\n",
+            "      message: \"says what the code says\"\n",
+            "    - line: 5\n",
+            "      rule: \"boilerplate-comment\"\n",
+            "      severity: info\n",
+            "      comment: \"// TODO: \\\"remove\\\"\"\n",
+            "      message: \"says what the code says\"\n",
+            "  ...\n",
+        ));
+        assert!(tap_point(2, &result(), true, Severity::Warning).starts_with("ok 2 - src/main.rs\n"));
+
+        let clean = AnalysisResult { path: PathBuf::from("lib.rs"), ..Default::default() };
+        assert_eq!(tap_point(3, &clean, false, Severity::Hint), "ok 3 - lib.rs\n");
+        let broken = AnalysisResult { errors: vec!["unreadable".to_string()], ..clean };
+        assert_eq!(tap_point(4, &broken, false, Severity::Error), "not ok 4 - lib.rs\n  ---\n  errors:\n    - \"unreadable\"\n  ...\n");
+    }
+
+    #[test]
+    fn test_json_output_counts_findings() {
+        let results = [result(), AnalysisResult { errors: vec!["unreadable".to_string()], ..Default::default() }];
+        let output = serde_json::to_value(json_output(&results, &[], Some(GroupBy::Rule), None, None, None)).unwrap();
+
+        assert_eq!(output["schema_version"], SCHEMA_VERSION);
+        assert_eq!(output["results"][0]["path"], "src/main.rs");
+        assert_eq!(output["results"][0]["redundant_comments"][0]["line_number"], 2);
+        assert_eq!(output["groups"][0]["key"], "boilerplate-comment");
+        assert_eq!(output["summary"], serde_json::json!({
+            "files_analyzed": 2,
+            "files_with_errors": 1,
+            "redundant_comments": 1,
+            "flagged_comments": 1,
+        }));
+    }
+
+    #[test]
+    fn test_template_renders_the_json_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.j2");
+        std::fs::write(&path, "{% for file in results %}{{ file.path }}: {{ file.redundant_comments | length }}\n{% endfor %}{{ summary.flagged_comments }} flagged\n").unwrap();
+        let template = ReportTemplate::load(&path).unwrap();
+
+        let rendered = template.render(&json_output(&[result()], &[], None, None, None, None)).unwrap();
+        assert_eq!(rendered, "src/main.rs: 1\n1 flagged\n");
+
+        std::fs::write(&path, "{% for file in results %}").unwrap();
+        assert!(ReportTemplate::load(&path).is_err_and(|e| e.starts_with("Invalid template")));
+    }
+
+    #[test]
+    fn test_sarif_levels_follow_severity() {
+        let log = sarif_log(&[result()]);
+        let results = &log["runs"][0]["results"];
+        assert_eq!(log["version"], "2.1.0");
+        assert_eq!(results[0]["level"], "warning");
+        assert_eq!(results[0]["ruleId"], "boilerplate-comment");
+        assert_eq!(results[0]["locations"][0]["physicalLocation"]["region"]["startLine"], 2);
+        assert_eq!(results[1]["level"], "note");
+    }
+
+    #[test]
+    fn test_sarif_results_carry_blame() {
+        let blame = CommentBlame { commit: "0123456789abcdef".to_string(), author: "Ada".to_string(), age_days: 42 };
+        let comment = |line_number: usize, blame: Option<CommentBlame>| CommentInfo {
+            text: "// increment i".to_string(),
+            line_number,
+            blame,
+            ..Default::default()
+        };
+        let result = AnalysisResult {
+            path: std::path::PathBuf::from("./src/main.rs"),
+            redundant_comments: vec![comment(3, Some(blame)), comment(7, None)],
+            ..Default::default()
+        };
+
+        let log = sarif_log(&[result]);
+        let results = &log["runs"][0]["results"];
+        assert_eq!(results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"], "src/main.rs");
+        assert_eq!(results[0]["properties"]["blame"], serde_json::json!({
+            "commit": "0123456789abcdef",
+            "author": "Ada",
+            "age_days": 42,
+        }));
+        assert!(results[1].get("properties").is_none());
+    }
+}
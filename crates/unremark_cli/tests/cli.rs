@@ -0,0 +1,152 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+const SOURCE: &str = "fn main() {\n    // Constructor\n    let x = 1;\n}\n";
+
+/// Runs `unremark` offline in `dir`, away from the user's config, cache and daemon.
+fn unremark(dir: &Path, args: &[&str], stdin: Option<&str>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_unremark"))
+        .args(["--offline", "--no-daemon", "--color", "never"])
+        .args(args)
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env("UNREMARK_SOCKET", dir.join("unremark.sock"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.unwrap_or_default().as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn test_csv_output() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.rs"), SOURCE).unwrap();
+
+    let csv = stdout(&unremark(dir.path(), &["--format", "csv", "main.rs"], None));
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows.len(), 2, "{}", csv);
+    assert!(rows[1].starts_with("main.rs,2,// Constructor,redundant,boilerplate-comment,"), "{}", csv);
+}
+
+#[test]
+fn test_tap_output() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.rs"), SOURCE).unwrap();
+
+    let tap = stdout(&unremark(dir.path(), &["--format", "tap", "main.rs"], None));
+    assert!(tap.starts_with("TAP version 13\n1..1\nnot ok 1 - main.rs\n"), "{}", tap);
+}
+
+#[test]
+fn test_text_output_quotes_the_comment() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = unremark(dir.path(), &["--language", "rust", "--format", "text", "-"], Some(SOURCE));
+
+    let text = stdout(&output);
+    assert!(text.contains("warning[boilerplate-comment]"), "{}", text);
+    assert!(text.contains("--> <stdin>:2:5\n   |\n 2 |     // Constructor\n   |     ^^^^^^^^^^^^^^\n"), "{}", text);
+    assert!(text.contains("1 redundant comments found"), "{}", text);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_text_output_quotes_fixed_comments_with_their_code() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.rs"), "fn main() {\n    let x = 1; // Constructor\n}\n").unwrap();
+    let output = unremark(dir.path(), &["--fix", "main.rs"], None);
+
+    let text = stdout(&output);
+    assert!(text.contains("--> main.rs:2:16\n   |\n 2 |     let x = 1; // Constructor\n   |                ^^^^^^^^^^^^^^\n"), "{}", text);
+    assert_eq!(std::fs::read_to_string(dir.path().join("main.rs")).unwrap(), "fn main() {\n    let x = 1;\n}\n");
+}
+
+#[test]
+fn test_json_output() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.rs"), SOURCE).unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&stdout(&unremark(dir.path(), &["--format", "json", "--group-by", "rule", "main.rs"], None))).unwrap();
+    assert_eq!(json["summary"]["redundant_comments"], 1);
+    assert_eq!(json["results"][0]["redundant_comments"][0]["line_number"], 2);
+    assert_eq!(json["groups"][0]["key"], "boilerplate-comment");
+}
+
+#[test]
+fn test_template_output() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.rs"), SOURCE).unwrap();
+
+    std::fs::write(dir.path().join("report.j2"), "{{ summary.files_analyzed }} files, {{ summary.redundant_comments }} redundant\n").unwrap();
+    let output = unremark(dir.path(), &["--format", "template", "--template", "report.j2", "main.rs"], None);
+    assert_eq!(stdout(&output), "1 files, 1 redundant\n");
+}
+
+#[test]
+fn test_exit_code_follows_fail_on() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.rs"), SOURCE).unwrap();
+
+    assert_eq!(unremark(dir.path(), &["main.rs"], None).status.code(), Some(1));
+    assert_eq!(unremark(dir.path(), &["--fail-on", "error", "main.rs"], None).status.code(), Some(0));
+    assert_eq!(unremark(dir.path(), &["--fail-on", "hint", "main.rs"], None).status.code(), Some(1));
+
+    // Fixed comments no longer fail the run
+    assert_eq!(unremark(dir.path(), &["--fix", "main.rs"], None).status.code(), Some(0));
+    assert!(!std::fs::read_to_string(dir.path().join("main.rs")).unwrap().contains("Constructor"));
+    assert_eq!(unremark(dir.path(), &["main.rs"], None).status.code(), Some(0));
+}
+
+#[test]
+fn test_sarif_output() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.rs"), SOURCE).unwrap();
+
+    let sarif: serde_json::Value = serde_json::from_str(&stdout(&unremark(dir.path(), &["--format", "sarif", "main.rs"], None))).unwrap();
+    assert_eq!(sarif["runs"][0]["results"][0]["ruleId"], "boilerplate-comment");
+    assert_eq!(sarif["runs"][0]["results"][0]["level"], "warning");
+}
+
+#[test]
+fn test_offline_runs_the_heuristic_rules() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("main.rs"), concat!(
+        "//\n",
+        "fn main() {\n",
+        "    // Constructor\n",
+        "    let mut count = 0;\n",
+        "    // -----------------\n",
+        "    // set count to 5\n",
+        "    count = 5;\n",
+        "    // Retry twice because the first request after a deploy often times out\n",
+        "    println!(\"{}\", count);\n",
+        "}\n",
+        "\n",
+        "// get user name\n",
+        "fn get_user_name() -> String {\n",
+        "    String::new()\n",
+        "}\n",
+    )).unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&stdout(&unremark(dir.path(), &["--format", "json", "main.rs"], None))).unwrap();
+    let findings: Vec<(u64, &str)> = json["results"][0]["redundant_comments"].as_array().unwrap().iter()
+        .map(|comment| (comment["line_number"].as_u64().unwrap(), comment["rule_id"].as_str().unwrap()))
+        .collect();
+    assert_eq!(findings, vec![
+        (1, "empty-comment"),
+        (3, "boilerplate-comment"),
+        (5, "empty-comment"),
+        (6, "comment-restates-assignment"),
+        (12, "name-echo"),
+    ]);
+    assert_eq!(json["results"][0]["flagged_comments"], serde_json::json!([]));
+}
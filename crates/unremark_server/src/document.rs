@@ -1,16 +1,17 @@
-/* This file is a WIP and is not yet ready for use. */
-
 use tower_lsp::Client;
 use tower_lsp::lsp_types::*;
 use std::collections::HashMap;
+use futures::StreamExt;
+use ropey::Rope;
 
-use unremark::{Language, detect_comments, CommentInfo, analyze_comments, create_analysis_service};
+use unremark::{Language, detect_comments, CommentInfo, analyze_comments_with, apply_rules, rules_for, AnalysisOptions, ApiSettings, Provider, find_outdated_comments, flag_regex_comments, flag_duplicate_comments, is_todo_comment, prefilter_comments, AnalysisService, ProxyAnalysisService, RulesConfig, Severity, DEFAULT_MIN_DUPLICATES, REDUNDANT_COMMENT_RULE};
 
 #[derive(Debug, Clone)]
 pub struct Document {
-    text: String,
+    text: Rope,
     language: Language,
     comments: Vec<CommentInfo>,
+    flagged_comments: Vec<CommentInfo>,
     analysis: HashMap<usize, Analysis>,
     version: i32,
 }
@@ -19,15 +20,34 @@ pub struct Document {
 struct Analysis {
     is_redundant: bool,
     explanation: Option<String>,
-    version: i32,  // Track when this analysis was performed
+    rule_id: Option<String>,
+    confidence: Option<f32>,
+}
+
+fn diagnostic_severity(severity: Severity) -> DiagnosticSeverity {
+    match severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Info => DiagnosticSeverity::INFORMATION,
+        Severity::Hint => DiagnosticSeverity::HINT,
+    }
+}
+
+/// The diagnostic's message, with how sure the check was when it could tell.
+fn diagnostic_message(message: String, confidence: Option<f32>) -> String {
+    match confidence {
+        Some(confidence) => format!("{} ({:.0}% confidence)", message, confidence * 100.0),
+        None => message,
+    }
 }
 
 impl Document {
     pub fn new(text: String, language: Language, version: i32) -> Self {
         let mut doc = Self {
-            text,
+            text: Rope::from_str(&text),
             language,
             comments: Vec::new(),
+            flagged_comments: Vec::new(),
             analysis: HashMap::new(),
             version,
         };
@@ -37,41 +57,70 @@ impl Document {
 
     pub fn apply_change(&mut self, change: &TextDocumentContentChangeEvent, new_version: i32) {
         if let Some(range) = change.range {
-            let lines: Vec<&str> = self.text.lines().collect();
-            let mut new_lines: Vec<String> = lines.iter().map(|&s| s.to_string()).collect();
-            
-            if range.start.line == range.end.line {
-                if range.start.character == 0 && range.end.character == 0 {
-                    // Line insertion
-                    new_lines.insert(range.start.line as usize, change.text.trim_end().to_string());
-                } else {
-                    // Line replacement
-                    new_lines[range.start.line as usize] = change.text.clone();
-                }
-            }
-            
-            self.text = new_lines.join("\n");
+            let start_idx = self.position_to_char(range.start);
+            let end_idx = self.position_to_char(range.end).max(start_idx);
+            let removed_lines = self.text.char_to_line(end_idx) - self.text.char_to_line(start_idx);
+
+            self.text.remove(start_idx..end_idx);
+            self.text.insert(start_idx, &change.text);
+
+            let added_lines = Rope::from_str(&change.text).len_lines() - 1;
+            self.shift_analysis(range, removed_lines, added_lines);
         } else {
-            self.text = change.text.clone();
+            self.text = Rope::from_str(&change.text);
+            self.analysis.clear();
         }
         self.version = new_version;
         self.detect_comments();
-        self.invalidate_affected_analysis(change.range);
+    }
+
+    /// Converts an LSP position (UTF-16 code units) into a char index, clamping
+    /// positions past the end of a line or document as the spec requires.
+    fn position_to_char(&self, position: Position) -> usize {
+        let line = position.line as usize;
+        if line >= self.text.len_lines() {
+            return self.text.len_chars();
+        }
+
+        let line_start = self.text.line_to_char(line);
+        let line_slice = self.text.line(line);
+        let mut line_len = line_slice.len_chars();
+        while line_len > 0 && matches!(line_slice.char(line_len - 1), '\n' | '\r') {
+            line_len -= 1;
+        }
+
+        let line_start_cu = self.text.char_to_utf16_cu(line_start);
+        let line_end_cu = self.text.char_to_utf16_cu(line_start + line_len);
+        let target_cu = (line_start_cu + position.character as usize).min(line_end_cu);
+        self.text.utf16_cu_to_char(target_cu)
     }
 
     fn detect_comments(&mut self) {
-        self.comments = detect_comments(&self.text, self.language)
+        let text = self.text.to_string();
+        self.comments = detect_comments(&text, self.language)
             .unwrap_or_default();
+        self.flagged_comments = find_outdated_comments(&text, self.language);
+        self.flagged_comments.extend(flag_duplicate_comments(&self.comments, DEFAULT_MIN_DUPLICATES));
     }
 
-    pub async fn get_diagnostics(&mut self, client: &Client) -> Vec<Diagnostic> {
-        client.log_message(MessageType::INFO, format!("Getting diagnostics for document:")).await;
+    /// Analyzes the comments not analyzed yet, with OpenAI or through the
+    /// proxy at `api.endpoint`, as `api.provider` says. Progress is reported
+    /// on `progress` as the proxy's verdicts arrive, if the client sent a token.
+    /// Severities are the workspace's `rules`, as the CLI reports them.
+    pub async fn get_diagnostics(
+        &mut self,
+        client: &Client,
+        api: &ApiSettings,
+        rules: &RulesConfig,
+        progress: Option<&ProgressToken>,
+    ) -> Vec<Diagnostic> {
+        client.log_message(MessageType::INFO, format!("Getting diagnostics for document version {}", self.version)).await;
         let mut diagnostics = Vec::new();
-        
+
         let unanalyzed = self.comments.iter()
             .filter(|c| !self.analysis.contains_key(&c.line_number));
 
-        if let Some(comments) = self.analyze_comments(unanalyzed, client).await {
+        if let Some(comments) = self.analyze_comments(unanalyzed, client, api, rules, progress).await {
             for (comment, analysis) in comments {
                 self.analysis.insert(comment.line_number, analysis);
             }
@@ -82,6 +131,12 @@ impl Document {
         for comment in &self.comments {
             if let Some(analysis) = self.analysis.get(&comment.line_number) {
                 if analysis.is_redundant {
+                    let rule_id = analysis.rule_id.clone().unwrap_or_else(|| REDUNDANT_COMMENT_RULE.to_string());
+                    if !rules.is_enabled(&rule_id) {
+                        continue;
+                    }
+                    let finding = CommentInfo { rule_id: Some(rule_id.clone()), confidence: analysis.confidence, ..comment.clone() };
+                    let severity = rules.finding_severity(&finding).unwrap_or(Severity::Warning);
                     diagnostics.push(Diagnostic {
                         range: Range {
                             start: Position {
@@ -93,11 +148,13 @@ impl Document {
                                 character: comment.text.len() as u32,
                             },
                         },
-                        severity: Some(DiagnosticSeverity::WARNING),
-                        code: Some(NumberOrString::String("redundant-comment".to_string())),
+                        severity: Some(diagnostic_severity(severity)),
+                        code: Some(NumberOrString::String(rule_id)),
                         source: Some("unremark".to_string()),
-                        message: analysis.explanation.clone()
-                            .unwrap_or_else(|| "This comment may be redundant".to_string()),
+                        message: diagnostic_message(
+                            analysis.explanation.clone().unwrap_or_else(|| "This comment may be redundant".to_string()),
+                            analysis.confidence,
+                        ),
                         data: Some(serde_json::to_value(comment).unwrap()),
                         ..Default::default()
                     });
@@ -105,66 +162,221 @@ impl Document {
             }
         }
 
+        // Flagged by the workspace's regex rules, or by checks it didn't disable
+        let flagged = self.flagged_comments.iter()
+            .filter(|comment| comment.rule_id.as_deref().is_none_or(|rule_id| rules.is_enabled(rule_id)))
+            .filter(|comment| !rules.ignores(&comment.text))
+            .cloned()
+            .chain(flag_regex_comments(&self.comments, rules));
+        for comment in flagged {
+            let already_reported = self.analysis.get(&comment.line_number)
+                .is_some_and(|analysis| analysis.is_redundant);
+            if already_reported {
+                continue;
+            }
+            diagnostics.push(Diagnostic {
+                range: Range {
+                    start: Position {
+                        line: (comment.line_number - 1) as u32,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: (comment.line_number - 1) as u32,
+                        character: comment.text.len() as u32,
+                    },
+                },
+                severity: Some(rules.finding_severity(&comment).map_or(DiagnosticSeverity::INFORMATION, diagnostic_severity)),
+                code: comment.rule_id.clone().map(NumberOrString::String),
+                source: Some("unremark".to_string()),
+                message: diagnostic_message(
+                    comment.explanation.clone().unwrap_or_else(|| "This comment needs a second look".to_string()),
+                    comment.confidence,
+                ),
+                data: Some(serde_json::to_value(&comment).unwrap()),
+                ..Default::default()
+            });
+        }
+
         client.log_message(MessageType::INFO, format!("Generated {} diagnostics", diagnostics.len())).await;
 
         diagnostics
     }
 
-    fn invalidate_affected_analysis(&mut self, range: Option<Range>) {
-        if let Some(range) = range {
-            self.analysis.retain(|line, _| {
-                let line = *line as u32;
-                line < range.start.line || line > range.end.line
-            });
-        } else {
-            self.analysis.clear();
-        }
+    /// Drops analysis for lines touched by an edit and moves analysis for the
+    /// lines below it by the number of lines the edit added or removed.
+    fn shift_analysis(&mut self, range: Range, removed_lines: usize, added_lines: usize) {
+        // Analysis is keyed by 1-based line numbers, LSP ranges are 0-based
+        let first_touched = range.start.line as usize + 1;
+        let last_touched = range.start.line as usize + 1 + removed_lines;
+
+        self.analysis = std::mem::take(&mut self.analysis)
+            .into_iter()
+            .filter_map(|(line, analysis)| {
+                if line < first_touched {
+                    Some((line, analysis))
+                } else if line > last_touched {
+                    Some((line + added_lines - removed_lines, analysis))
+                } else {
+                    None
+                }
+            })
+            .collect();
     }
 
-    async fn analyze_comments<'a, I>(&self, comments: I, client: &Client) -> Option<Vec<(CommentInfo, Analysis)>>
+    async fn analyze_comments<'a, I>(
+        &self,
+        comments: I,
+        client: &Client,
+        api: &ApiSettings,
+        rules: &RulesConfig,
+        progress: Option<&ProgressToken>,
+    ) -> Option<Vec<(CommentInfo, Analysis)>>
     where
         I: Iterator<Item = &'a CommentInfo>,
     {
-        let comments: Vec<_> = comments.cloned().collect();
+        let comments: Vec<_> = comments
+            .filter(|comment| !is_todo_comment(&comment.text) && !comment.is_license() && !rules.ignores(&comment.text))
+            .cloned()
+            .collect();
         if comments.is_empty() {
             return None;
         }
 
-        let analyzed = if std::env::var("OPENAI_API_KEY").is_ok() {
+        // Comments caught by the local rules never need a round trip
+        let options = AnalysisOptions { rules: rules.clone(), ..Default::default() };
+        let (flagged, remaining) = apply_rules(&self.text(), comments, &rules_for(&options));
+        let mut results: Vec<(CommentInfo, Analysis)> = flagged.into_iter()
+            .map(|c| {
+                let analysis = Analysis {
+                    is_redundant: true,
+                    explanation: c.explanation.clone(),
+                    rule_id: c.rule_id.clone(),
+                    confidence: c.confidence,
+                };
+                (c, analysis)
+            })
+            .collect();
+
+        // Comments that are almost certainly useful aren't worth a round trip either
+        let (remaining, skipped) = prefilter_comments(&self.text(), remaining);
+        results.extend(skipped.into_iter().map(|c| (c, Analysis {
+            is_redundant: false,
+            explanation: None,
+            rule_id: None,
+            confidence: None,
+        })));
+
+        if remaining.is_empty() || !rules.is_enabled(REDUNDANT_COMMENT_RULE) {
+            return Some(results);
+        }
+
+        let analyzed = if api.provider == Provider::OpenAi {
             client.log_message(MessageType::INFO, "Analyzing comments with OpenAI").await;
-            analyze_comments(comments.clone()).await.unwrap_or_default()
+            analyze_comments_with(remaining.clone(), api).await.unwrap_or_default()
         } else {
-            client.log_message(MessageType::INFO, "Analyzing comments with proxy").await;
-            match create_analysis_service().analyze_comments_with_proxy(comments.clone()).await {
-                Ok(results) => {
-                    client.log_message(MessageType::INFO, 
-                        format!("Successfully received {} analyzed comments from proxy", results.len())).await;
-                    results
-                }
-                Err(e) => {
-                    client.log_message(MessageType::ERROR, 
-                        format!("Failed to analyze comments with proxy: {}", e)).await;
-                    return None;
-                }
+            let proxy = ProxyAnalysisService::from_settings(api);
+            client.log_message(MessageType::INFO, format!("Analyzing comments with proxy at {}", proxy.endpoint)).await;
+            let (analyzed, complete) = stream_from_proxy(remaining.clone(), client, &proxy, progress).await;
+            if !complete {
+                // Whatever didn't get a verdict is analyzed again on the next request
+                results.extend(analyzed.into_iter().filter_map(|found| {
+                    let comment = remaining.iter().find(|c| c.line_number == found.line_number)?;
+                    Some((comment.clone(), Analysis {
+                        is_redundant: true,
+                        explanation: found.explanation,
+                        rule_id: found.rule_id,
+                        confidence: found.confidence,
+                    }))
+                }));
+                return Some(results);
             }
+            analyzed
         };
 
-        if analyzed.is_empty() {
-            client.log_message(MessageType::WARNING, "No comments were analyzed").await;
-            return None;
-        }
+        // Only redundant comments come back, everything else we sent was judged useful
+        let mut redundant: HashMap<usize, CommentInfo> = analyzed.into_iter()
+            .map(|c| (c.line_number, c))
+            .collect();
+
+        results.extend(remaining.into_iter()
+            .map(|c| match redundant.remove(&c.line_number) {
+                Some(found) => (c, Analysis {
+                    is_redundant: true,
+                    explanation: found.explanation,
+                    rule_id: found.rule_id,
+                    confidence: found.confidence,
+                }),
+                None => (c, Analysis {
+                    is_redundant: false,
+                    explanation: None,
+                    rule_id: None,
+                    confidence: None,
+                }),
+            }));
+
+        Some(results)
+    }
 
-        Some(analyzed.into_iter()
-            .map(|c| (c.clone(), Analysis {
-                is_redundant: true,
-                explanation: c.explanation.clone(),
-                version: self.version,
-            }))
-            .collect())
+    pub fn text(&self) -> String {
+        self.text.to_string()
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+}
+
+/// Collects the proxy's verdicts as they stream in, reporting each one as
+/// progress. Returns the redundant comments and whether the stream finished.
+async fn stream_from_proxy(
+    comments: Vec<CommentInfo>,
+    client: &Client,
+    proxy: &ProxyAnalysisService,
+    progress: Option<&ProgressToken>,
+) -> (Vec<CommentInfo>, bool) {
+    let mut verdicts = match proxy.stream_comments_with_proxy(comments).await {
+        Ok(verdicts) => verdicts,
+        Err(e) => {
+            client.log_message(MessageType::ERROR, format!("Failed to analyze comments with proxy: {}", e)).await;
+            return (vec![], false);
+        }
+    };
+
+    report_progress(client, progress, WorkDoneProgress::Begin(WorkDoneProgressBegin {
+        title: "Analyzing comments".to_string(),
+        ..Default::default()
+    })).await;
+    let mut analyzed = Vec::new();
+    let mut complete = true;
+    while let Some(verdict) = verdicts.next().await {
+        match verdict {
+            Ok(comment) => {
+                analyzed.push(comment);
+                report_progress(client, progress, WorkDoneProgress::Report(WorkDoneProgressReport {
+                    message: Some(format!("{} redundant comments found", analyzed.len())),
+                    ..Default::default()
+                })).await;
+            }
+            Err(e) => {
+                client.log_message(MessageType::ERROR, format!("Proxy stream failed: {}", e)).await;
+                complete = false;
+                break;
+            }
+        }
     }
+    report_progress(client, progress, WorkDoneProgress::End(WorkDoneProgressEnd::default())).await;
+
+    client.log_message(MessageType::INFO,
+        format!("Successfully received {} analyzed comments from proxy", analyzed.len())).await;
+    (analyzed, complete)
+}
 
-    pub fn text(&self) -> &str {
-        &self.text
+async fn report_progress(client: &Client, token: Option<&ProgressToken>, value: WorkDoneProgress) {
+    if let Some(token) = token {
+        client.send_notification::<notification::Progress>(ProgressParams {
+            token: token.clone(),
+            value: ProgressParamsValue::WorkDone(value),
+        }).await;
     }
 }
 
@@ -200,12 +412,23 @@ mod tests {
         Document::new(text, Language::Rust, 1)
     }
 
+    fn edit(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position { line: start.0, character: start.1 },
+                end: Position { line: end.0, character: end.1 },
+            }),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
     #[test]
     fn test_document_creation() {
         let doc = create_test_document();
         assert!(!doc.comments.is_empty(), "Should detect comments");
         assert_eq!(doc.version, 1);
-        
+
         let comment = &doc.comments[0];
         assert_eq!(comment.text.trim(), "// Test comment");
         assert_eq!(comment.line_number, 2);
@@ -214,44 +437,122 @@ mod tests {
     #[test]
     fn test_incremental_change() {
         let mut doc = create_test_document();
-        
+
         let comment_text = "    // Updated comment";  // Include indentation
-        doc.apply_change(&TextDocumentContentChangeEvent {
-            range: Some(Range {
-                start: Position { line: 1, character: 0 },  // Start from beginning of line
-                end: Position { line: 1, character: 17 }
-            }),
-            range_length: None,
-            text: comment_text.to_string(),
-        }, 2);
+        doc.apply_change(&edit((1, 0), (1, 19), comment_text), 2);
 
         assert_eq!(doc.version, 2);
         assert_eq!(doc.comments[0].text.trim(), "// Updated comment");  // Still trim for comparison
+        assert_eq!(doc.text(), "fn main() {\n    // Updated comment\n    println!(\"Hello\");\n}");
     }
 
     #[test]
     fn test_line_addition() {
         let mut doc = create_test_document();
         let initial_comment_line = doc.comments[0].line_number;
-        
+
         // Add a line before the comment
-        doc.apply_change(&TextDocumentContentChangeEvent {
-            range: Some(Range {
-                start: Position { line: 0, character: 0 },
-                end: Position { line: 0, character: 0 }
-            }),
-            range_length: None,
-            text: "// Another comment\n".to_string(),
-        }, 2);
+        doc.apply_change(&edit((0, 0), (0, 0), "// Another comment\n"), 2);
 
         // Verify comment position is updated
         assert_eq!(doc.comments[1].line_number, initial_comment_line + 1);
     }
 
+    #[test]
+    fn test_multi_line_change() {
+        let mut doc = create_test_document();
+
+        // Replace from the middle of the first line through the middle of the third
+        doc.apply_change(&edit((0, 9), (2, 22), " {\n    // a\n    // b\n    x();"), 2);
+
+        assert_eq!(doc.text(), "fn main() {\n    // a\n    // b\n    x();\n}");
+        let texts: Vec<_> = doc.comments.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["// a", "// b"]);
+        assert_eq!(doc.comments[1].line_number, 3);
+    }
+
+    #[test]
+    fn test_line_deletion() {
+        let mut doc = create_test_document();
+
+        doc.apply_change(&edit((1, 0), (2, 0), ""), 2);
+
+        assert_eq!(doc.text(), "fn main() {\n    println!(\"Hello\");\n}");
+        assert!(doc.comments.is_empty());
+    }
+
+    #[test]
+    fn test_crlf_change() {
+        let text = "fn main() {\r\n    // Test comment\r\n    let x = 1;\r\n}".to_string();
+        let mut doc = Document::new(text, Language::Rust, 1);
+
+        doc.apply_change(&edit((2, 8), (2, 9), "y"), 2);
+
+        assert_eq!(doc.text(), "fn main() {\r\n    // Test comment\r\n    let y = 1;\r\n}");
+    }
+
+    #[test]
+    fn test_multi_byte_change() {
+        // "é" is one UTF-16 code unit but two bytes, "𝄞" is two code units and four bytes
+        let text = "fn main() {\n    // café 𝄞 note\n}".to_string();
+        let mut doc = Document::new(text, Language::Rust, 1);
+
+        doc.apply_change(&edit((1, 15), (1, 19), "clef"), 2);
+
+        assert_eq!(doc.text(), "fn main() {\n    // café 𝄞 clef\n}");
+        assert_eq!(doc.comments[0].text, "// café 𝄞 clef");
+    }
+
+    #[test]
+    fn test_position_past_line_end_is_clamped() {
+        let mut doc = create_test_document();
+
+        doc.apply_change(&edit((0, 100), (0, 100), " // trailing"), 2);
+
+        assert_eq!(doc.text(), "fn main() { // trailing\n    // Test comment\n    println!(\"Hello\");\n}");
+    }
+
+    #[test]
+    fn test_analysis_shifts_with_edits() {
+        let mut doc = create_test_document();
+        doc.analysis.insert(2, Analysis { is_redundant: true, explanation: None, rule_id: None, confidence: None });
+
+        doc.apply_change(&edit((0, 0), (0, 0), "// header\n// more\n"), 2);
+        assert!(doc.analysis.contains_key(&4), "Analysis should follow the comment down");
+        assert!(!doc.analysis.contains_key(&2));
+
+        doc.apply_change(&edit((3, 4), (3, 19), "// changed"), 3);
+        assert!(doc.analysis.is_empty(), "Edited lines should be re-analyzed");
+    }
+
+    #[test]
+    fn test_outdated_comments_detected() {
+        let text = "fn main() {\n    // Calls `missing_helper` first\n    println!(\"Hello\");\n}".to_string();
+        let doc = Document::new(text, Language::Rust, 1);
+
+        assert_eq!(doc.flagged_comments.len(), 1);
+        assert_eq!(doc.flagged_comments[0].line_number, 2);
+    }
+
+    #[test]
+    fn test_duplicate_comments_detected() {
+        let text = "fn a() {}
+// Helper
+fn b() {}
+// Helper
+fn c() {}
+// Helper
+fn d() {}".to_string();
+        let doc = Document::new(text, Language::Rust, 1);
+
+        assert_eq!(doc.flagged_comments.len(), 3);
+        assert!(doc.flagged_comments.iter().all(|c| c.rule_id.as_deref() == Some("duplicate-comment")));
+    }
+
     #[test]
     fn test_full_document_update() {
         let mut doc = create_test_document();
-        
+
         let comment_text = "// Single comment";
         doc.apply_change(&TextDocumentContentChangeEvent {
             range: None,
@@ -268,8 +569,8 @@ mod tests {
     async fn test_diagnostics_generation() {
         let mut doc = create_test_document();
         let client = create_test_client();
-        let diagnostics = doc.get_diagnostics(&client).await;
-        
+        let diagnostics = doc.get_diagnostics(&client, &proxy_settings(unremark::get_proxy_endpoint()), &RulesConfig::default(), None).await;
+
         if !diagnostics.is_empty() {
             let diagnostic = &diagnostics[0];
             assert_eq!(diagnostic.source, Some(SERVER_ID.to_string()));
@@ -277,4 +578,90 @@ mod tests {
             assert_eq!(diagnostic.code, Some(NumberOrString::String("redundant-comment".to_string())));
         }
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_low_confidence_findings_are_less_severe() {
+        let mut doc = create_test_document();
+        doc.analysis.insert(2, Analysis {
+            is_redundant: true,
+            explanation: None,
+            rule_id: Some("local-classifier".to_string()),
+            confidence: Some(0.5),
+        });
+        let diagnostics = doc.get_diagnostics(&create_test_client(), &proxy_settings(unremark::get_proxy_endpoint()), &RulesConfig::default(), None).await;
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::INFORMATION));
+        assert_eq!(diagnostics[0].message, "This comment may be redundant (50% confidence)");
+    }
+
+    #[tokio::test]
+    async fn test_configured_severities_override_the_defaults() {
+        let mut doc = create_test_document();
+        doc.analysis.insert(2, Analysis {
+            is_redundant: true,
+            explanation: None,
+            rule_id: Some("local-classifier".to_string()),
+            confidence: Some(0.5),
+        });
+        let rules = unremark::Config::parse("[rules.local-classifier]\nseverity = \"error\"\n").unwrap().rules;
+        let diagnostics = doc.get_diagnostics(&create_test_client(), &proxy_settings(unremark::get_proxy_endpoint()), &rules, None).await;
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics_follow_the_configured_rules() {
+        let text = "fn main() {\n    // Constructor\n    let x = 1;\n    // XXX: x is never read\n}".to_string();
+        let mut doc = Document::new(text, Language::Rust, 1);
+        let rules = unremark::Config::parse(concat!(
+            "[rules.boilerplate-comment]\nenabled = false\n",
+            "[rules.redundant-comment]\nenabled = false\n",
+            "[[rules.regex]]\npattern = \"XXX\"\naction = \"flag\"\nid = \"no-xxx\"\nseverity = \"error\"\n",
+        )).unwrap().rules;
+        let diagnostics = doc.get_diagnostics(&create_test_client(), &proxy_settings(unremark::get_proxy_endpoint()), &rules, None).await;
+
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0].code, Some(NumberOrString::String("no-xxx".to_string())));
+        assert_eq!(diagnostics[0].range.start.line, 3);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+    }
+
+    fn proxy_settings(endpoint: String) -> ApiSettings {
+        ApiSettings { provider: Provider::Proxy, endpoint: Some(endpoint), ..Default::default() }
+    }
+
+    /// Answers one request with a verdict for line 2, then drops the
+    /// connection before the promised body is complete.
+    async fn broken_stream_proxy() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 64 * 1024];
+            let _ = stream.read(&mut request).await;
+            let verdict = "{\"text\": \"// keep the retry loop short\", \"line_number\": 2, \"context\": \"\"}\n";
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nContent-Length: 4096\r\n\r\n";
+            stream.write_all(format!("{}{}", head, verdict).as_bytes()).await.unwrap();
+            stream.flush().await.unwrap();
+        });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_broken_proxy_stream_keeps_received_verdicts() {
+        let text = "fn main() {\n    // keep the retry loop short\n    let a = 1;\n    // the vendor caps batches\n    let b = 2;\n}";
+        let mut doc = Document::new(text.to_string(), Language::Rust, 1);
+        let client = create_test_client();
+
+        let diagnostics = doc.get_diagnostics(&client, &proxy_settings(broken_stream_proxy().await), &RulesConfig::default(), None).await;
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range.start.line, 1);
+        assert!(doc.analysis.contains_key(&2));
+        assert!(!doc.analysis.contains_key(&4), "Comments without a verdict are retried later");
+    }
+}
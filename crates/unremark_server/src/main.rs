@@ -2,17 +2,28 @@ use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
 use unremark::{
-    analyze_comments, 
-    detect_comments, 
-    Cache, 
-    Language,
-    create_analysis_service,
+    get_proxy_endpoint, load_languages, AnalysisOptions, ApiSettings, Cache, Config, Provider, Language, RateLimitSender, RulesConfig,
+    DUPLICATE_COMMENT_RULE, OUTDATED_COMMENT_RULE,
 };
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use parking_lot::RwLock;
+use std::time::SystemTime;
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::mpsc::UnboundedReceiver;
 use dashmap::DashMap;
+use tracing_subscriber::EnvFilter;
 use serde_json::Value;
 
+use crate::document::Document;
+use crate::status::{Status, StatusNotification};
+use crate::workspace::{summarize_workspace, AnalyzeWorkspaceParams, WorkspaceSummary, ANALYZE_WORKSPACE_REQUEST};
+
+mod document;
+mod status;
+mod workspace;
+
 const VERSION_COMMAND: &str = "unremark.version";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const SERVER_ID: &str = "unremark";
@@ -20,28 +31,69 @@ const SERVER_ID: &str = "unremark";
 
 #[derive(Debug, Default, serde::Deserialize)]
 struct UnremarkInitializeParams {
+    /// Overrides the sources in the `[credentials]` section of unremark.toml
     openai_api_key: Option<String>,
+    /// Overrides `PROXY_ENDPOINT` and the `[proxy]` section of unremark.toml
+    proxy_endpoint: Option<String>,
+    /// Overrides `OPENAI_BASE_URL` and the `[openai]` section of unremark.toml
+    openai_base_url: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 struct UnremarkLanguageServer {
     client: Client,
-    document_map: DashMap<String, String>,
-    #[allow(dead_code)]
-    cache: Arc<RwLock<Cache>>, // TODO: implement cache logic after we've prototyped the server
+    document_map: DashMap<String, Document>,
+    /// Workspace analyses reuse (and add to) the CLI's cache
+    cache: Arc<RwLock<Cache>>,
+    /// The proxy comments are analyzed with when there's no OpenAI API key,
+    /// and the network settings and key sources for either
+    api: Arc<RwLock<ApiSettings>>,
+    /// The key the client sent, rather than the workspace's own
+    openai_api_key: Arc<RwLock<Option<String>>>,
+    /// The OpenAI-compatible API used with an OpenAI key, OpenAI's own when unset
+    openai_endpoint: Arc<RwLock<Option<String>>>,
+    /// The workspace folder `unremark/analyzeWorkspace` scans, when the client sent one
+    root: Arc<RwLock<Option<PathBuf>>>,
+    /// The rules of the workspace's unremark.toml
+    rules: Arc<RwLock<RulesConfig>>,
+    /// Analyses running, for the `$/unremark/status` notifications
+    in_flight: Arc<AtomicUsize>,
+    /// Where the API announces its rate limits, forwarded to the client as
+    /// statuses once it's initialized
+    rate_limited: RateLimitSender,
+    rate_limits: Arc<Mutex<Option<UnboundedReceiver<SystemTime>>>>,
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for UnremarkLanguageServer {
     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
         // Extract our custom initialization options
-        if let Some(options) = params.initialization_options {
-            if let Ok(unremark_options) = serde_json::from_value::<UnremarkInitializeParams>(options) {
-                if let Some(api_key) = unremark_options.openai_api_key {
-                    std::env::set_var("OPENAI_API_KEY", api_key);
-                }
+        let unremark_options = params.initialization_options.clone()
+            .and_then(|options| serde_json::from_value::<UnremarkInitializeParams>(options).ok())
+            .unwrap_or_default();
+        *self.openai_api_key.write() = unremark_options.openai_api_key;
+
+        let root = workspace_root(&params);
+        let config = match root.as_deref().map(Config::load) {
+            Some(Ok(config)) => config,
+            Some(Err(e)) => {
+                self.client.log_message(MessageType::WARNING, format!("Ignoring unremark.toml: {}", e)).await;
+                Config::default()
             }
+            None => Config::default(),
+        };
+        if let Err(e) = load_languages(&config) {
+            self.client.log_message(MessageType::WARNING, e).await;
         }
+        *self.openai_endpoint.write() = config.openai.resolve_base_url(unremark_options.openai_base_url.as_deref());
+        *self.api.write() = ApiSettings {
+            provider: Provider::Proxy,
+            endpoint: Some(config.proxy.resolve_endpoint(unremark_options.proxy_endpoint.as_deref())),
+            rate_limited: Some(self.rate_limited.clone()),
+            ..config.api_settings()
+        };
+        *self.rules.write() = config.rules;
+        *self.root.write() = root;
 
         self.client.log_message(MessageType::INFO, "Initializing server").await;
         Ok(InitializeResult {
@@ -70,19 +122,35 @@ impl LanguageServer for UnremarkLanguageServer {
 
     async fn initialized(&self, _: InitializedParams) {
         self.client.log_message(MessageType::INFO, "Server initialized").await;
+        if let Some(mut rate_limits) = self.rate_limits.lock().take() {
+            let client = self.client.clone();
+            tokio::spawn(async move {
+                while let Some(until) = rate_limits.recv().await {
+                    let until = until.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+                    client.send_notification::<StatusNotification>(Status::RateLimited { until }).await;
+                }
+            });
+        }
+        self.client.send_notification::<StatusNotification>(Status::Idle).await;
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         self.client.log_message(MessageType::INFO, format!("Document {} opened", params.text_document.uri)).await;
+        let uri = params.text_document.uri;
+        let Some(language) = Language::detect(Path::new(uri.path()), &params.text_document.text) else {
+            self.client.log_message(MessageType::LOG, format!("Ignoring unsupported document {}", uri)).await;
+            return;
+        };
         self.document_map.insert(
-            params.text_document.uri.to_string(),
-            params.text_document.text,
+            uri.to_string(),
+            Document::new(params.text_document.text, language, params.text_document.version),
         );
     }
 
     async fn diagnostic(&self, params: DocumentDiagnosticParams) -> Result<DocumentDiagnosticReportResult> {
         self.client.log_message(MessageType::INFO, format!("Requesting diagnostics for file: {}", params.text_document.uri)).await;
-        let diagnostics = self.analyze_document(&params.text_document.uri).await;
+        let progress = params.work_done_progress_params.work_done_token;
+        let diagnostics = self.tracked(self.analyze_document(&params.text_document.uri, progress.as_ref())).await;
         self.client.log_message(MessageType::INFO, format!("Collected {} diagnostics", diagnostics.len())).await;
         Ok(DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(
             RelatedFullDocumentDiagnosticReport {
@@ -99,40 +167,12 @@ impl LanguageServer for UnremarkLanguageServer {
         self.client.log_message(MessageType::INFO, 
             format!("Document change detected - version: {}", params.text_document.version)).await;
         
-        if let Some(change) = params.content_changes.first() {
-            let uri_str = params.text_document.uri.to_string();
-            let mut current_text = if let Some(text) = self.document_map.get(&uri_str) {
-                text.clone()
-            } else {
-                return;
-            };
-
-            // Apply the change to the document
-            if let Some(range) = change.range {
-                let start_pos = range.start;
-                let end_pos = range.end;
-                
-                // Convert the positions to string indices
-                let lines: Vec<&str> = current_text.lines().collect();
-                let start_idx = lines[..start_pos.line as usize]
-                    .iter()
-                    .map(|l| l.len() + 1)
-                    .sum::<usize>()
-                    + start_pos.character as usize;
-                let end_idx = lines[..end_pos.line as usize]
-                    .iter()
-                    .map(|l| l.len() + 1)
-                    .sum::<usize>()
-                    + end_pos.character as usize;
-
-                // Replace the text in the range
-                current_text.replace_range(start_idx..end_idx, &change.text);
-            } else {
-                // If no range is provided, replace the entire content
-                current_text = change.text.clone();
+        let uri_str = params.text_document.uri.to_string();
+        if let Some(mut document) = self.document_map.get_mut(&uri_str) {
+            // Changes must be applied in order, each one against the result of the previous
+            for change in &params.content_changes {
+                document.apply_change(change, params.text_document.version);
             }
-
-            self.document_map.insert(uri_str, current_text);
         }
     }
 
@@ -144,7 +184,10 @@ impl LanguageServer for UnremarkLanguageServer {
                 Some(data) => data.get("text").unwrap().to_string(),
                 None => diagnostic.message.clone(),
             };
-            if diagnostic.source == Some(SERVER_ID.to_string()) {
+            // Outdated or duplicated comments need a human to look at them, not a removal
+            let needs_review = [OUTDATED_COMMENT_RULE, DUPLICATE_COMMENT_RULE].iter()
+                .any(|rule| diagnostic.code == Some(NumberOrString::String(rule.to_string())));
+            if diagnostic.source == Some(SERVER_ID.to_string()) && !needs_review {
                 actions.push(CodeActionOrCommand::CodeAction(CodeAction {
                     title: format!("Remove redundant comment: {}", title_text),
                     kind: Some(CodeActionKind::QUICKFIX),
@@ -182,84 +225,121 @@ impl LanguageServer for UnremarkLanguageServer {
     }
 }
 
+/// First workspace folder, or the root of a client that only sends `rootUri`.
+fn workspace_root(params: &InitializeParams) -> Option<std::path::PathBuf> {
+    #[allow(deprecated)]
+    let root_uri = params.root_uri.as_ref();
+    params.workspace_folders.as_ref()
+        .and_then(|folders| folders.first())
+        .map(|folder| &folder.uri)
+        .or(root_uri)
+        .and_then(|uri| uri.to_file_path().ok())
+}
+
+/// The proxy at `PROXY_ENDPOINT`, until the workspace config is loaded.
+fn proxy_settings() -> ApiSettings {
+    ApiSettings { provider: Provider::Proxy, endpoint: Some(get_proxy_endpoint()), ..Default::default() }
+}
+
 impl UnremarkLanguageServer {
-    async fn analyze_document(&self, uri: &Url) -> Vec<Diagnostic> {
-        if let Some(text) = self.document_map.get(uri.as_str()) {
-            if let Some(language) = uri.path().rsplit('.').next().and_then(Language::from_extension) {
-                let comments = detect_comments(text.as_str(), language).unwrap_or_default();
-                if comments.is_empty() {
-                    self.client.log_message(MessageType::LOG, "No comments found to analyze").await;
-                    return vec![];
-                }
+    fn new(client: Client) -> Self {
+        let (rate_limited, rate_limits) = tokio::sync::mpsc::unbounded_channel();
+        Self {
+            client,
+            document_map: DashMap::new(),
+            cache: Arc::new(RwLock::new(Cache::load())),
+            api: Arc::new(RwLock::new(proxy_settings())),
+            openai_api_key: Arc::new(RwLock::new(None)),
+            openai_endpoint: Arc::new(RwLock::new(None)),
+            root: Arc::new(RwLock::new(None)),
+            rules: Arc::new(RwLock::new(RulesConfig::default())),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            rate_limited,
+            rate_limits: Arc::new(Mutex::new(Some(rate_limits))),
+        }
+    }
 
-                let redundant_comments = if std::env::var("OPENAI_API_KEY").is_ok() {
-                    self.client.log_message(MessageType::INFO, "Local OpenAI API key found, analyzing comments locally").await;
-                    analyze_comments(comments).await.unwrap_or_default()
-                } else {
-                    self.client.log_message(MessageType::INFO, "No OpenAI API key found, using proxy to analyze comments").await;
-
-                    let proxy_result = create_analysis_service().analyze_comments_with_proxy(comments).await;
-                    match proxy_result {
-                        Ok(comments) => {
-                            self.client.log_message(MessageType::INFO, 
-                                format!("Proxy returned {} redundant comments", comments.len())).await;
-                            comments
-                        }
-                        Err(e) => {
-                            self.client.log_message(MessageType::ERROR, 
-                                format!("Proxy analysis failed: {}", e)).await;
-                            vec![]
-                        }
-                    }
-                };
-
-                self.client.log_message(MessageType::LOG, format!("Found {} redundant comments", redundant_comments.len())).await;
-
-                let diagnostics: Vec<Diagnostic> = redundant_comments
-                    .into_iter()
-                    .map(|comment| Diagnostic {
-                        range: Range {
-                            start: Position {
-                                line: comment.line_number as u32 - 1,
-                                character: 0,
-                            },
-                            end: Position {
-                                line: comment.line_number as u32 - 1,
-                                character: comment.text.len() as u32,
-                            },
-                        },
-                        severity: Some(DiagnosticSeverity::WARNING),
-                        code: Some(NumberOrString::String("redundant-comment".to_string())),
-                        source: Some(SERVER_ID.to_string()),
-                        message: comment.explanation.clone().unwrap_or("This comment may be redundant".to_string()),
-                        data: Some(serde_json::to_value(comment).unwrap()),
-                        ..Default::default()
-                    })
-                    .collect();
-                
-                return diagnostics;
+    /// Runs the analysis, telling the client how many are running when it
+    /// starts and when it's done, and that the server is idle after the last.
+    async fn tracked<T>(&self, analysis: impl Future<Output = T>) -> T {
+        let running = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.client.send_notification::<StatusNotification>(Status::Analyzing { documents: running }).await;
+        let result = analysis.await;
+        let running = self.in_flight.fetch_sub(1, Ordering::SeqCst) - 1;
+        let status = if running == 0 { Status::Idle } else { Status::Analyzing { documents: running } };
+        self.client.send_notification::<StatusNotification>(status).await;
+        result
+    }
+
+    /// OpenAI's settings when there's a key for the workspace, the proxy's otherwise.
+    fn analysis_api(&self) -> ApiSettings {
+        let api = self.api.read().clone();
+        let openai = ApiSettings {
+            provider: Provider::OpenAi,
+            endpoint: self.openai_endpoint.read().clone(),
+            api_key: self.openai_api_key.read().clone(),
+            ..api.clone()
+        };
+        match openai.resolved_api_key() {
+            Some(api_key) => ApiSettings { api_key: Some(api_key), ..openai },
+            None => api,
+        }
+    }
+
+    async fn analyze_document(&self, uri: &Url, progress: Option<&ProgressToken>) -> Vec<Diagnostic> {
+        // Work on a snapshot so the map isn't locked while we wait on the analysis
+        let Some(mut document) = self.document_map.get(uri.as_str()).map(|d| d.clone()) else {
+            return vec![];
+        };
+
+        let api = self.analysis_api();
+        let rules = self.rules.read().clone();
+        let diagnostics = document.get_diagnostics(&self.client, &api, &rules, progress).await;
+
+        // Keep the analysis results unless the document was edited in the meantime
+        if let Some(mut current) = self.document_map.get_mut(uri.as_str()) {
+            if current.version() == document.version() {
+                *current = document;
             }
         }
-        vec![]
+
+        diagnostics
+    }
+
+    /// Handles `unremark/analyzeWorkspace`, analyzing every file of the
+    /// workspace folder rather than only the open documents.
+    async fn analyze_workspace(&self, params: AnalyzeWorkspaceParams) -> Result<WorkspaceSummary> {
+        let Some(root) = self.root.read().clone() else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("the client didn't send a workspace folder"));
+        };
+        let options = AnalysisOptions {
+            offline: params.offline,
+            api: self.analysis_api(),
+            rules: self.rules.read().clone(),
+            ..Default::default()
+        };
+        self.client.log_message(MessageType::INFO, format!("Analyzing workspace {}", root.display())).await;
+        let summary = self.tracked(summarize_workspace(&root, &options, &self.cache)).await;
+        self.cache.read().save();
+        Ok(summary)
     }
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::Builder::from_env(
-        env_logger::Env::default()
-            .default_filter_or("info")
-    )
-    .init();
+    // stdout carries the protocol, so the logs go to stderr
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .init();
 
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(|client| UnremarkLanguageServer {
-        client,
-        document_map: DashMap::new(),
-        cache: Arc::new(RwLock::new(Cache::load())),
-    });
+    let (service, socket) = LspService::build(UnremarkLanguageServer::new)
+    .custom_method(ANALYZE_WORKSPACE_REQUEST, UnremarkLanguageServer::analyze_workspace)
+    .finish();
 
     Server::new(stdin, stdout, socket)
         .serve(service)
@@ -270,16 +350,12 @@ async fn main() {
 mod tests {
     use super::*;
     use tower_lsp::LanguageServer;
-    use std::sync::Arc;
     use futures::executor::block_on;
     use tokio::runtime::Runtime;
     
     fn create_test_server() -> UnremarkLanguageServer {
-        let (service, _socket) = LspService::build(|client| UnremarkLanguageServer {
-            client,
-            document_map: DashMap::new(),
-            cache: Arc::new(RwLock::new(Cache::load())),
-        })
+        let (service, _socket) = LspService::build(UnremarkLanguageServer::new)
+        .custom_method(ANALYZE_WORKSPACE_REQUEST, UnremarkLanguageServer::analyze_workspace)
         .finish();
         service.inner().clone()
     }
@@ -321,6 +397,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_proxy_endpoint_from_initialization_options() {
+        let server = create_test_server();
+        let init_params = InitializeParams {
+            initialization_options: Some(serde_json::json!({ "proxy_endpoint": "https://proxy.example.com" })),
+            ..Default::default()
+        };
+
+        block_on(server.initialize(init_params)).unwrap();
+        assert_eq!(server.api.read().endpoint.as_deref(), Some("https://proxy.example.com"));
+    }
+
+    #[test]
+    fn test_openai_base_url_from_initialization_options() {
+        let server = create_test_server();
+        let init_params = InitializeParams {
+            initialization_options: Some(serde_json::json!({
+                "openai_api_key": "sk-test",
+                "openai_base_url": "http://localhost:1234/v1",
+            })),
+            ..Default::default()
+        };
+
+        block_on(server.initialize(init_params)).unwrap();
+        let api = server.analysis_api();
+        assert_eq!(api.provider, Provider::OpenAi);
+        assert_eq!(api.endpoint.as_deref(), Some("http://localhost:1234/v1"));
+    }
+
     #[test]
     fn test_document_management() {
         let runtime = Runtime::new().unwrap();
@@ -339,7 +444,7 @@ mod tests {
         }));
 
         assert_eq!(
-            server.document_map.get(uri.as_str()).unwrap().as_str(),
+            server.document_map.get(uri.as_str()).unwrap().text(),
             text
         );
 
@@ -358,7 +463,7 @@ mod tests {
         }));
 
         assert_eq!(
-            server.document_map.get(uri.as_str()).unwrap().as_str(),
+            server.document_map.get(uri.as_str()).unwrap().text(),
             new_text
         );
     }
@@ -386,5 +491,7 @@ mod tests {
             },
             _ => panic!("Expected full diagnostic report"),
         }
+        // The analysis is over, so the server is back to idle
+        assert_eq!(server.in_flight.load(Ordering::SeqCst), 0);
     }
 }
\ No newline at end of file
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::notification::Notification;
+
+/// `$/unremark/status`, sent whenever the server starts or finishes an
+/// analysis or has to wait on the API, for the editor's status item.
+pub enum StatusNotification {}
+
+impl Notification for StatusNotification {
+    type Params = Status;
+    const METHOD: &'static str = "$/unremark/status";
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum Status {
+    Idle,
+    /// Documents (or workspace scans) being analyzed
+    Analyzing { documents: usize },
+    /// Waiting on the API's rate limit until `until`, in seconds since the Unix epoch
+    RateLimited { until: u64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_status_json() {
+        assert_eq!(serde_json::to_value(Status::Idle).unwrap(), json!({ "state": "idle" }));
+        assert_eq!(
+            serde_json::to_value(Status::Analyzing { documents: 2 }).unwrap(),
+            json!({ "state": "analyzing", "documents": 2 })
+        );
+        assert_eq!(
+            serde_json::to_value(Status::RateLimited { until: 1_700_000_000 }).unwrap(),
+            json!({ "state": "rateLimited", "until": 1_700_000_000 })
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::{Position, Range, Url};
+use futures::StreamExt;
+use unremark::{
+    analyze_files, estimate_file, AnalysisOptions, AnalysisResult, Cache, CommentInfo, Language, Severity, UsageEstimate,
+    OPENAI_INPUT_PRICE, OPENAI_OUTPUT_PRICE,
+};
+
+pub const ANALYZE_WORKSPACE_REQUEST: &str = "unremark/analyzeWorkspace";
+
+/// Parameters of `unremark/analyzeWorkspace`, `{}` for the defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeWorkspaceParams {
+    /// Only run the local rules, without calling the API
+    #[serde(default)]
+    pub offline: bool,
+}
+
+/// What `unremark/analyzeWorkspace` returns, for the extension's tree view.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceSummary {
+    pub files_scanned: usize,
+    /// Files whose findings came from the cache, costing nothing
+    pub cache_hits: usize,
+    pub findings: usize,
+    /// Requests the files analyzed afresh took, estimated without counting them
+    pub api_calls: usize,
+    /// Dollar cost of those requests at the default model's prices
+    pub estimated_cost: f64,
+    /// Files with findings or errors, in path order
+    pub files: Vec<FileSummary>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileSummary {
+    pub uri: Url,
+    pub findings: Vec<WorkspaceFinding>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceFinding {
+    pub range: Range,
+    pub rule_id: Option<String>,
+    pub severity: Option<Severity>,
+    pub message: String,
+    pub text: String,
+    /// Whether `--fix` would remove the comment, rather than it needing a second look
+    pub redundant: bool,
+}
+
+impl WorkspaceFinding {
+    fn new(comment: &CommentInfo, redundant: bool) -> Self {
+        let line = (comment.line_number - 1) as u32;
+        Self {
+            range: Range {
+                start: Position { line, character: 0 },
+                end: Position { line, character: comment.text.len() as u32 },
+            },
+            rule_id: comment.rule_id.clone(),
+            severity: comment.severity,
+            message: comment.explanation.clone().unwrap_or_else(|| "This comment needs a second look".to_string()),
+            text: comment.text.clone(),
+            redundant,
+        }
+    }
+}
+
+/// Analyzes every supported file under `root`, honoring `.gitignore`, and
+/// sums up the findings, the cache hits and what the rest cost.
+pub async fn summarize_workspace(root: &Path, options: &AnalysisOptions, cache: &RwLock<Cache>) -> WorkspaceSummary {
+    let paths = source_files(root);
+    let mut results: Vec<AnalysisResult> = analyze_files(&paths, false, cache, options).collect().await;
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut usage = UsageEstimate::default();
+    for result in results.iter().filter(|result| !result.cached && result.errors.is_empty()) {
+        if let Ok(estimate) = estimate_file(&result.path, options) {
+            usage.add(&estimate);
+        }
+    }
+
+    let mut summary = WorkspaceSummary {
+        files_scanned: results.len(),
+        cache_hits: results.iter().filter(|result| result.cached).count(),
+        api_calls: usage.api_calls,
+        estimated_cost: usage.cost(OPENAI_INPUT_PRICE, OPENAI_OUTPUT_PRICE),
+        ..Default::default()
+    };
+    for result in results {
+        let mut findings: Vec<WorkspaceFinding> = result.redundant_comments.iter().map(|comment| WorkspaceFinding::new(comment, true))
+            .chain(result.flagged_comments.iter().map(|comment| WorkspaceFinding::new(comment, false)))
+            .collect();
+        if findings.is_empty() && result.errors.is_empty() {
+            continue;
+        }
+        findings.sort_by_key(|finding| finding.range.start.line);
+        let Ok(uri) = Url::from_file_path(std::path::absolute(&result.path).unwrap_or(result.path)) else {
+            continue;
+        };
+        summary.findings += findings.len();
+        summary.files.push(FileSummary { uri, findings, errors: result.errors });
+    }
+    summary
+}
+
+/// Source files under `root`, honoring `.gitignore`.
+fn source_files(root: &Path) -> Vec<PathBuf> {
+    ignore::WalkBuilder::new(root)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|file_type| file_type.is_file()))
+        .map(|entry| entry.into_path())
+        .filter(|path| Language::of_file(path).is_some())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_workspace_summary_counts_findings() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("main.rs"), "fn main() {\n    // Constructor\n    let x = 1;\n}\n").unwrap();
+        std::fs::write(root.path().join("lib.py"), "def f():\n    return 1\n").unwrap();
+        std::fs::write(root.path().join("notes.txt"), "// Constructor\n").unwrap();
+
+        let cache = RwLock::new(Cache { entries: HashMap::new() });
+        let options = AnalysisOptions { offline: true, ..Default::default() };
+        let summary = summarize_workspace(root.path(), &options, &cache).await;
+
+        assert_eq!(summary.files_scanned, 2);
+        assert_eq!(summary.cache_hits, 0);
+        assert_eq!(summary.findings, 1);
+        assert_eq!(summary.api_calls, 0);
+        assert_eq!(summary.files.len(), 1);
+        assert!(summary.files[0].uri.path().ends_with("/main.rs"));
+        let finding = &summary.files[0].findings[0];
+        assert_eq!(finding.range.start, Position { line: 1, character: 0 });
+        assert!(finding.redundant);
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["filesScanned"], 2);
+        assert_eq!(json["files"][0]["findings"][0]["text"], "// Constructor");
+    }
+}